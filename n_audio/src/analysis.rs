@@ -0,0 +1,236 @@
+use crate::fft::{Complex, Fft};
+use std::f32::consts::PI;
+use std::fmt::{Display, Formatter};
+
+/// Rate (approximately) the tracks are downmixed and downsampled to before being analyzed
+pub(crate) const ANALYSIS_RATE: u32 = 11025;
+const ONSET_FRAME: usize = 1024;
+const ONSET_HOP: usize = 128;
+const CHROMA_FRAME: usize = 8192;
+const CHROMA_HOP: usize = 4096;
+/// Range of the tempo that can be detected
+const BPM_RANGE: (f32, f32) = (60.0, 200.0);
+/// Tempo preferred between its multiples, which have similar periodicities
+const BPM_CENTER: f32 = 120.0;
+/// Range of the frequencies used to detect the key, from C2 to C7
+const CHROMA_RANGE: (f32, f32) = (65.4, 2093.0);
+
+/// Key profiles by Krumhansl and Kessler, from the tonic
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+const NOTES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+/// A musical key
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// Pitch class of the tonic, from C (0) to B (11)
+    pub tonic: u8,
+    pub minor: bool,
+}
+
+impl Key {
+    /// Returns the position of the key on the Camelot wheel, as its number (from 1 to 12) and its
+    /// letter (A for minor keys, B for major ones)
+    ///
+    /// Keys with the same number, or with numbers next to each other, mix well together
+    pub fn camelot(&self) -> (u8, char) {
+        // minor keys share the number of their relative major
+        let major = if self.minor {
+            (self.tonic + 3) % 12
+        } else {
+            self.tonic
+        };
+        let number = (major * 7 % 12 + 7) % 12 + 1;
+        (number, if self.minor { 'A' } else { 'B' })
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let suffix = if self.minor { "m" } else { "" };
+        write!(f, "{}{suffix}", NOTES[self.tonic as usize % 12])
+    }
+}
+
+/// Tempo and key of a track, estimated by `MusicTrack::analyze`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Analysis {
+    /// Beats per minute, rounded to 0.1
+    pub bpm: Option<f32>,
+    pub key: Option<Key>,
+}
+
+/// Analyzes the mono `samples`
+pub(crate) fn analyze(samples: &[f32], rate: u32) -> Analysis {
+    Analysis {
+        bpm: tempo(samples, rate),
+        key: key(samples, rate),
+    }
+}
+
+fn hann(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+        .collect()
+}
+
+/// Windows `samples` into `buf` and transforms it
+fn spectrum(fft: &Fft, window: &[f32], samples: &[f32], buf: &mut [Complex]) {
+    for ((value, sample), window) in buf.iter_mut().zip(samples).zip(window) {
+        *value = Complex::new(sample * window, 0.0);
+    }
+    fft.forward(buf);
+}
+
+/// Estimates the tempo from the autocorrelation of the onsets (spectral flux) of the track
+fn tempo(samples: &[f32], rate: u32) -> Option<f32> {
+    if samples.len() < ONSET_FRAME {
+        return None;
+    }
+
+    let fft = Fft::new(ONSET_FRAME);
+    let window = hann(ONSET_FRAME);
+    let mut buf = vec![Complex::default(); ONSET_FRAME];
+    let mut previous = vec![0.0_f32; ONSET_FRAME / 2];
+    let mut onsets = vec![];
+    for start in (0..=samples.len() - ONSET_FRAME).step_by(ONSET_HOP) {
+        spectrum(
+            &fft,
+            &window,
+            &samples[start..start + ONSET_FRAME],
+            &mut buf,
+        );
+        let mut flux = 0.0;
+        for (bin, previous) in buf.iter().zip(previous.iter_mut()) {
+            let magnitude = (1.0 + 100.0 * bin.norm()).ln();
+            flux += (magnitude - *previous).max(0.0);
+            *previous = magnitude;
+        }
+        onsets.push(flux);
+    }
+
+    // only the onsets that stand out from their surroundings are kept
+    let onset_rate = rate as f32 / ONSET_HOP as f32;
+    let radius = (onset_rate / 4.0) as usize;
+    let mut sums = vec![0.0_f64; onsets.len() + 1];
+    for (i, onset) in onsets.iter().enumerate() {
+        sums[i + 1] = sums[i] + *onset as f64;
+    }
+    let onsets = (0..onsets.len())
+        .map(|i| {
+            let (from, to) = (i.saturating_sub(radius), (i + radius + 1).min(onsets.len()));
+            let mean = (sums[to] - sums[from]) / (to - from) as f64;
+            (onsets[i] - mean as f32).max(0.0)
+        })
+        .collect::<Vec<_>>();
+
+    let min_lag = ((onset_rate * 60.0 / BPM_RANGE.1).floor() as usize).max(2);
+    let max_lag = (onset_rate * 60.0 / BPM_RANGE.0).ceil() as usize;
+    if onsets.len() <= max_lag * 2 {
+        return None;
+    }
+    let correlation = |lag: usize| {
+        onsets[lag..]
+            .iter()
+            .zip(&onsets)
+            .map(|(a, b)| a * b)
+            .sum::<f32>()
+            / (onsets.len() - lag) as f32
+    };
+    let bpm = |lag: f32| 60.0 * onset_rate / lag;
+
+    let (lag, score) = (min_lag..=max_lag)
+        .map(|lag| {
+            let weight = (-0.5 * (bpm(lag as f32) / BPM_CENTER).log2().powi(2)).exp();
+            (lag, correlation(lag) * weight)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if score <= 0.0 {
+        return None;
+    }
+
+    // the peak is usually between two lags
+    let (a, b, c) = (correlation(lag - 1), correlation(lag), correlation(lag + 1));
+    let curvature = a - 2.0 * b + c;
+    let offset = if curvature < 0.0 {
+        (0.5 * (a - c) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    Some((bpm(lag as f32 + offset) * 10.0).round() / 10.0)
+}
+
+/// Estimates the key by matching the chroma of the track against the key profiles
+fn key(samples: &[f32], rate: u32) -> Option<Key> {
+    if samples.len() < CHROMA_FRAME {
+        return None;
+    }
+
+    let fft = Fft::new(CHROMA_FRAME);
+    let window = hann(CHROMA_FRAME);
+    let mut buf = vec![Complex::default(); CHROMA_FRAME];
+    let bin_width = rate as f32 / CHROMA_FRAME as f32;
+    let first = (CHROMA_RANGE.0 / bin_width).ceil() as usize;
+    let last = ((CHROMA_RANGE.1 / bin_width) as usize).min(CHROMA_FRAME / 2);
+    // pitch class of every bin, A4 is 440 Hz
+    let classes = (first..last)
+        .map(|bin| {
+            let semitones = (12.0 * (bin as f32 * bin_width / 440.0).log2()).round() as i32;
+            (semitones + 9).rem_euclid(12) as usize
+        })
+        .collect::<Vec<_>>();
+
+    let mut chroma = [0.0_f32; 12];
+    for start in (0..=samples.len() - CHROMA_FRAME).step_by(CHROMA_HOP) {
+        spectrum(
+            &fft,
+            &window,
+            &samples[start..start + CHROMA_FRAME],
+            &mut buf,
+        );
+        for (bin, class) in buf[first..last].iter().zip(&classes) {
+            chroma[*class] += bin.norm();
+        }
+    }
+    if chroma.iter().all(|value| *value == 0.0) {
+        return None;
+    }
+
+    (0..12)
+        .flat_map(|tonic| [(tonic, false), (tonic, true)])
+        .map(|(tonic, minor)| {
+            let profile = if minor {
+                &MINOR_PROFILE
+            } else {
+                &MAJOR_PROFILE
+            };
+            let key = Key { tonic, minor };
+            (key, correlation(&chroma, profile, tonic as usize))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(key, _)| key)
+}
+
+/// Pearson correlation between `chroma` starting from `tonic` and `profile`
+fn correlation(chroma: &[f32; 12], profile: &[f32; 12], tonic: usize) -> f32 {
+    let chroma_mean = chroma.iter().sum::<f32>() / 12.0;
+    let profile_mean = profile.iter().sum::<f32>() / 12.0;
+    let (mut covariance, mut chroma_variance, mut profile_variance) = (0.0, 0.0, 0.0);
+    for (i, profile) in profile.iter().enumerate() {
+        let x = chroma[(tonic + i) % 12] - chroma_mean;
+        let y = profile - profile_mean;
+        covariance += x * y;
+        chroma_variance += x * x;
+        profile_variance += y * y;
+    }
+    covariance
+        / (chroma_variance * profile_variance)
+            .sqrt()
+            .max(f32::EPSILON)
+}
@@ -0,0 +1,739 @@
+use crate::container::Location;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use symphonia::core::formats::{Cue, FormatReader};
+use symphonia::core::units::TimeBase;
+use symphonia_core::meta::{StandardTagKey, Tag};
+
+/// Largest `moov` atom read to find the chapters of an MP4 file
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest `Chapters` element read from a Matroska file
+const MAX_MATROSKA_CHAPTERS_SIZE: u64 = 16 * 1024 * 1024;
+/// Most samples read from a QuickTime chapter track
+const MAX_TEXT_SAMPLES: usize = 10000;
+/// Largest sample of a QuickTime chapter track, which holds a title
+const MAX_TEXT_SAMPLE_SIZE: u64 = 64 * 1024;
+
+// Matroska element IDs, with their length marker
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const CHAPTERS: u32 = 0x1043A770;
+const EDITION_ENTRY: u32 = 0x45B9;
+const EDITION_FLAG_DEFAULT: u32 = 0x45DB;
+const CHAPTER_ATOM: u32 = 0xB6;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_FLAG_HIDDEN: u32 = 0x98;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+
+/// A chapter (or cue point) inside a track
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub title: String,
+    /// Where the chapter starts, in seconds
+    pub start: f64,
+}
+
+impl Chapter {
+    fn from_cue(cue: &Cue, time_base: TimeBase) -> Self {
+        let title = cue
+            .tags
+            .iter()
+            .find(|tag| {
+                tag.std_key == Some(StandardTagKey::TrackTitle)
+                    || tag.key.eq_ignore_ascii_case("title")
+            })
+            .map(|tag| tag.value.to_string())
+            .unwrap_or_else(|| format!("Chapter {}", cue.index));
+        let start = time_base.calc_time(cue.start_ts);
+
+        Self {
+            title,
+            start: start.seconds as f64 + start.frac,
+        }
+    }
+}
+
+/// Parses a `HH:MM:SS.sss` timestamp, as used by the chapter tags
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    timestamp.trim().split(':').try_fold(0.0, |acc, part| {
+        Some(acc * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+/// Reads the chapters from the `CHAPTERxxx`/`CHAPTERxxxNAME` tags (the Vorbis comment convention)
+fn chapters_from_tags(tags: &[Tag]) -> Vec<Chapter> {
+    let mut chapters = vec![];
+    for tag in tags {
+        let key = tag.key.to_ascii_uppercase();
+        let Some(number) = key.strip_prefix("CHAPTER") else {
+            continue;
+        };
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let Some(start) = parse_timestamp(&tag.value.to_string()) else {
+            continue;
+        };
+        let name = format!("{key}NAME");
+        let title = tags
+            .iter()
+            .find(|tag| tag.key.eq_ignore_ascii_case(&name))
+            .map(|tag| tag.value.to_string())
+            .unwrap_or_else(|| format!("Chapter {}", number.parse::<u32>().unwrap_or_default()));
+
+        chapters.push(Chapter { title, start });
+    }
+    chapters
+}
+
+/// Big-endian reader of the content of an MP4 atom
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.0.get(..len)?;
+        self.0 = &self.0[len..];
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+/// Iterates the atoms in `data`, as their type and content
+fn mp4_atoms(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let mut bytes = Bytes(data);
+        let size = bytes.u32()?;
+        let kind = bytes.take(4)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, data.len()),
+            1 => (16, usize::try_from(bytes.u64()?).ok()?),
+            size => (8, size as usize),
+        };
+        if size < header || size > data.len() {
+            return None;
+        }
+        let content = &data[header..size];
+        data = &data[size..];
+        Some((kind, content))
+    })
+}
+
+/// Content of the atom at `path` inside `data`
+fn mp4_atom<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| {
+        mp4_atoms(data).find_map(|(k, content)| (k == **kind).then_some(content))
+    })
+}
+
+/// Reads the `moov` atom of an MP4 file, skipping the others without reading them
+fn read_moov<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut position = 0;
+    while position + 8 <= len {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 16];
+        reader.read_exact(&mut header[..8])?;
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, len - position),
+            1 => {
+                reader.read_exact(&mut header[8..])?;
+                (16, u64::from_be_bytes(header[8..].try_into().unwrap()))
+            }
+            size => (8, size as u64),
+        };
+        if size < header_len {
+            break;
+        }
+        if &header[4..8] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                break;
+            }
+            let mut moov = vec![0; (size - header_len) as usize];
+            reader.read_exact(&mut moov)?;
+            return Ok(Some(moov));
+        }
+        position += size;
+    }
+    Ok(None)
+}
+
+/// Reads the chapters of an MP4 file, from the Nero `chpl` atom or from the QuickTime chapter
+/// track
+fn mp4_chapters<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Chapter>> {
+    let Some(moov) = read_moov(reader)? else {
+        return Ok(vec![]);
+    };
+    let chapters = mp4_atom(&moov, &[b"udta", b"chpl"])
+        .map(parse_chpl)
+        .unwrap_or_default();
+    if !chapters.is_empty() {
+        return Ok(chapters);
+    }
+    quicktime_chapters(reader, &moov)
+}
+
+/// Parses the Nero `chpl` atom: a list of starts, in units of 100 ns, and titles
+fn parse_chpl(chpl: &[u8]) -> Vec<Chapter> {
+    let mut bytes = Bytes(chpl);
+    let Some(version) = bytes.u8() else {
+        return vec![];
+    };
+    let header = if version > 0 { 3 + 4 } else { 3 };
+    let Some(count) = bytes.take(header).and_then(|_| bytes.u8()) else {
+        return vec![];
+    };
+    (0..count)
+        .map_while(|_| {
+            let start = bytes.u64()?;
+            let len = bytes.u8()? as usize;
+            Some(Chapter {
+                title: String::from_utf8_lossy(bytes.take(len)?).into_owned(),
+                start: start as f64 / 10_000_000.0,
+            })
+        })
+        .collect()
+}
+
+/// Id of the track in the `tkhd` atom of `trak`
+fn mp4_track_id(trak: &[u8]) -> Option<u32> {
+    let mut tkhd = Bytes(mp4_atom(trak, &[b"tkhd"])?);
+    // the creation and modification times are 64-bit from version 1
+    let times = if tkhd.u8()? == 0 { 8 } else { 16 };
+    tkhd.take(3 + times)?;
+    tkhd.u32()
+}
+
+/// Start (in seconds), position and size of the samples of `trak`
+fn mp4_samples(trak: &[u8]) -> Option<Vec<(f64, u64, u64)>> {
+    let mut mdhd = Bytes(mp4_atom(trak, &[b"mdia", b"mdhd"])?);
+    let times = if mdhd.u8()? == 0 { 8 } else { 16 };
+    mdhd.take(3 + times)?;
+    let timescale = mdhd.u32().filter(|timescale| *timescale > 0)? as f64;
+    let stbl = mp4_atom(trak, &[b"mdia", b"minf", b"stbl"])?;
+
+    let mut stsz = Bytes(mp4_atom(stbl, &[b"stsz"])?);
+    stsz.take(4)?;
+    let size = stsz.u32()?;
+    let count = (stsz.u32()? as usize).min(MAX_TEXT_SAMPLES);
+    let sizes = if size == 0 {
+        (0..count)
+            .map(|_| stsz.u32().map(u64::from))
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        vec![size as u64; count]
+    };
+
+    let mut stts = Bytes(mp4_atom(stbl, &[b"stts"])?);
+    stts.take(4)?;
+    let mut starts = vec![];
+    let mut time = 0;
+    for _ in 0..stts.u32()? {
+        let (count, delta) = (stts.u32()?, stts.u32()? as u64);
+        for _ in 0..count {
+            if starts.len() == sizes.len() {
+                break;
+            }
+            starts.push(time);
+            time += delta;
+        }
+    }
+
+    let mut stsc = Bytes(mp4_atom(stbl, &[b"stsc"])?);
+    stsc.take(4)?;
+    let per_chunk = (0..stsc.u32()?)
+        .map(|_| {
+            let entry = (stsc.u32()?, stsc.u32()?);
+            stsc.u32()?;
+            Some(entry)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let offsets = if let Some(stco) = mp4_atom(stbl, &[b"stco"]) {
+        let mut stco = Bytes(stco);
+        stco.take(4)?;
+        (0..stco.u32()?)
+            .map(|_| stco.u32().map(u64::from))
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        let mut co64 = Bytes(mp4_atom(stbl, &[b"co64"])?);
+        co64.take(4)?;
+        (0..co64.u32()?)
+            .map(|_| co64.u64())
+            .collect::<Option<Vec<_>>>()?
+    };
+
+    let mut samples = vec![];
+    for (chunk, offset) in offsets.into_iter().enumerate() {
+        // the entries apply from their first chunk, counted from 1, to the next entry
+        let samples_in_chunk = per_chunk
+            .iter()
+            .rev()
+            .find(|(first, _)| *first as usize <= chunk + 1)
+            .map_or(0, |(_, samples)| *samples);
+        let mut offset = offset;
+        for _ in 0..samples_in_chunk {
+            let index = samples.len();
+            let (Some(start), Some(size)) = (starts.get(index), sizes.get(index)) else {
+                return Some(samples);
+            };
+            samples.push((*start as f64 / timescale, offset, *size));
+            offset += size;
+        }
+    }
+    Some(samples)
+}
+
+/// Reads the chapters of the QuickTime chapter track: a text track referenced by the `chap`
+/// reference of another track, whose samples are the titles
+fn quicktime_chapters<R: Read + Seek>(reader: &mut R, moov: &[u8]) -> io::Result<Vec<Chapter>> {
+    let traks = mp4_atoms(moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, trak)| trak)
+        .collect::<Vec<_>>();
+    let chapter_ids = traks
+        .iter()
+        .filter_map(|trak| mp4_atom(trak, &[b"tref", b"chap"]))
+        .flat_map(|chap| chap.chunks_exact(4))
+        .map(|id| u32::from_be_bytes(id.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    let Some(samples) = traks
+        .iter()
+        .find(|trak| mp4_track_id(trak).is_some_and(|id| chapter_ids.contains(&id)))
+        .and_then(|trak| mp4_samples(trak))
+    else {
+        return Ok(vec![]);
+    };
+
+    let mut chapters = vec![];
+    for (start, offset, size) in samples {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut sample = vec![0; size.min(MAX_TEXT_SAMPLE_SIZE) as usize];
+        reader.read_exact(&mut sample)?;
+        // the text is preceded by its length, and may be followed by other atoms
+        let mut bytes = Bytes(&sample);
+        let title = bytes
+            .u16()
+            .and_then(|len| bytes.take(len as usize))
+            .map(|title| String::from_utf8_lossy(title).into_owned())
+            .unwrap_or_default();
+        chapters.push(Chapter { title, start });
+    }
+    Ok(chapters)
+}
+
+/// Reads an EBML variable-length integer, returning it with its length marker and its length
+fn ebml_vint<R: Read>(reader: &mut R) -> io::Result<(u64, u32)> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    let len = byte[0].leading_zeros() + 1;
+    if len > 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid EBML integer",
+        ));
+    }
+    let mut value = byte[0] as u64;
+    for _ in 1..len {
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | byte[0] as u64;
+    }
+    Ok((value, len))
+}
+
+/// Reads the ID of an EBML element, which keeps its length marker
+fn ebml_id<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(ebml_vint(reader)?.0 as u32)
+}
+
+/// Reads the size of an EBML element, `None` if it's unknown
+fn ebml_size<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let (value, len) = ebml_vint(reader)?;
+    let max = (1 << (7 * len)) - 1;
+    // all the bits set mean that the size is unknown
+    Ok(Some(value & max).filter(|size| *size != max))
+}
+
+fn ebml_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .take(8)
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+/// Iterates the elements in `data`, as their ID and content
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let id = ebml_id(&mut data).ok()?;
+        let size = usize::try_from(ebml_size(&mut data).ok()??).ok()?;
+        if size > data.len() {
+            return None;
+        }
+        let (content, rest) = data.split_at(size);
+        data = rest;
+        Some((id, content))
+    })
+}
+
+/// Reads the chapters of a Matroska file from its `Chapters` element
+fn matroska_chapters<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Chapter>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    if ebml_id(reader)? != EBML_HEADER {
+        return Ok(vec![]);
+    }
+    let Some(size) = ebml_size(reader)? else {
+        return Ok(vec![]);
+    };
+    reader.seek(SeekFrom::Current(size as i64))?;
+    if ebml_id(reader)? != SEGMENT {
+        return Ok(vec![]);
+    }
+    let segment_end = match ebml_size(reader)? {
+        Some(size) => reader.stream_position()?.saturating_add(size).min(len),
+        None => len,
+    };
+
+    // the chapters usually come before the clusters, that are skipped without being read
+    while reader.stream_position()? < segment_end {
+        let id = ebml_id(reader)?;
+        // a cluster of unknown size can't be skipped
+        let Some(size) = ebml_size(reader)? else {
+            break;
+        };
+        if id == CHAPTERS {
+            if size > MAX_MATROSKA_CHAPTERS_SIZE {
+                break;
+            }
+            let mut chapters = vec![0; size as usize];
+            reader.read_exact(&mut chapters)?;
+            return Ok(parse_matroska_chapters(&chapters));
+        }
+        let next = reader.stream_position()?.saturating_add(size);
+        reader.seek(SeekFrom::Start(next))?;
+    }
+    Ok(vec![])
+}
+
+/// Parses the content of the `Chapters` element, using its default edition or the first one
+fn parse_matroska_chapters(chapters: &[u8]) -> Vec<Chapter> {
+    let editions = ebml_elements(chapters)
+        .filter(|(id, _)| *id == EDITION_ENTRY)
+        .map(|(_, edition)| edition)
+        .collect::<Vec<_>>();
+    let Some(edition) = editions
+        .iter()
+        .find(|edition| {
+            ebml_elements(edition)
+                .any(|(id, value)| id == EDITION_FLAG_DEFAULT && ebml_uint(value) == 1)
+        })
+        .or(editions.first())
+    else {
+        return vec![];
+    };
+
+    ebml_elements(edition)
+        .filter(|(id, _)| *id == CHAPTER_ATOM)
+        .enumerate()
+        .filter_map(|(i, (_, atom))| {
+            let mut start = None;
+            let mut title = None;
+            let mut hidden = false;
+            for (id, value) in ebml_elements(atom) {
+                match id {
+                    CHAPTER_TIME_START => start = Some(ebml_uint(value)),
+                    CHAPTER_FLAG_HIDDEN => hidden = ebml_uint(value) == 1,
+                    CHAPTER_DISPLAY if title.is_none() => {
+                        title = ebml_elements(value)
+                            .find(|(id, _)| *id == CHAP_STRING)
+                            .map(|(_, title)| String::from_utf8_lossy(title).into_owned());
+                    }
+                    _ => {}
+                }
+            }
+            Some(Chapter {
+                title: title.unwrap_or_else(|| format!("Chapter {}", i + 1)),
+                // in nanoseconds
+                start: start.filter(|_| !hidden)? as f64 / 1e9,
+            })
+        })
+        .collect()
+}
+
+/// Reads the chapters that Symphonia doesn't expose as cues: the ones of MP4 files (Nero `chpl`
+/// atom or QuickTime chapter track) and of Matroska files
+fn container_chapters(path: &str) -> Vec<Chapter> {
+    let location = Location::parse(path);
+    // the entries of the archives are read in memory, without seeking
+    if location.entry.is_some() {
+        return vec![];
+    }
+    let parse: fn(&mut BufReader<File>) -> io::Result<Vec<Chapter>> =
+        match location.extension().map(str::to_ascii_lowercase).as_deref() {
+            Some("mp4" | "m4a" | "m4b" | "m4v" | "mov") => mp4_chapters,
+            Some("mkv" | "mka" | "mk3d" | "webm") => matroska_chapters,
+            _ => return vec![],
+        };
+    match File::open(location.file).and_then(|file| parse(&mut BufReader::new(file))) {
+        Ok(chapters) => chapters,
+        Err(err) => {
+            eprintln!("Couldn't read the chapters of {path}\nError: {err}");
+            vec![]
+        }
+    }
+}
+
+/// Returns the chapters exposed by the format through its cues, sorted by their start
+///
+/// If the format doesn't have any cue, the chapters are read from the tags instead, and then from
+/// the container of the file at `path`, if given
+pub(crate) fn get_chapters(format: &mut dyn FormatReader, path: Option<&str>) -> Vec<Chapter> {
+    let Some(time_base) = format
+        .default_track()
+        .and_then(|track| track.codec_params.time_base)
+    else {
+        return vec![];
+    };
+
+    let mut chapters = format
+        .cues()
+        .iter()
+        .map(|cue| Chapter::from_cue(cue, time_base))
+        .collect::<Vec<_>>();
+    if chapters.is_empty() {
+        if let Some(metadata) = format.metadata().current() {
+            chapters = chapters_from_tags(metadata.tags());
+        }
+    }
+    if let (true, Some(path)) = (chapters.is_empty(), path) {
+        chapters = container_chapters(path);
+    }
+    chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+    chapters
+}
+
+/// Returns the index of the chapter playing at `position` seconds
+pub fn chapter_at(chapters: &[Chapter], position: f64) -> Option<usize> {
+    chapters
+        .iter()
+        .rposition(|chapter| chapter.start <= position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    fn chapter(title: &str, start: f64) -> Chapter {
+        Chapter {
+            title: title.to_string(),
+            start,
+        }
+    }
+
+    fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(content);
+        atom
+    }
+
+    /// Atom with a version and flags, all 0
+    fn full_atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        atom(kind, &[&[0; 4][..], content].concat())
+    }
+
+    fn ftyp() -> Vec<u8> {
+        atom(b"ftyp", b"M4A \0\0\0\0M4A mp42isom")
+    }
+
+    /// MP4 file with the Nero chapters `(start in seconds, title)`
+    fn nero_file(chapters: &[(f64, &str)]) -> Vec<u8> {
+        // version 1 has 4 more bytes before the count
+        let mut chpl = vec![1, 0, 0, 0, 0, 0, 0, 0, chapters.len() as u8];
+        for (start, title) in chapters {
+            chpl.extend_from_slice(&((start * 10_000_000.0) as u64).to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let moov = atom(b"moov", &atom(b"udta", &atom(b"chpl", &chpl)));
+        [ftyp(), moov, atom(b"mdat", b"audio")].concat()
+    }
+
+    fn tkhd(id: u32) -> Vec<u8> {
+        full_atom(b"tkhd", &[&[0; 8][..], &id.to_be_bytes(), &[0; 4]].concat())
+    }
+
+    /// `moov` atom of an audio track and of its chapter track, whose samples are at `offset`
+    fn quicktime_moov(offset: u32, sizes: &[u32]) -> Vec<u8> {
+        let audio = atom(
+            b"trak",
+            &[tkhd(1), atom(b"tref", &atom(b"chap", &2_u32.to_be_bytes()))].concat(),
+        );
+
+        let u32s = |values: &[u32]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect()
+        };
+        let mdhd = full_atom(b"mdhd", &u32s(&[0, 0, 1000, 12000]));
+        let stts = full_atom(b"stts", &u32s(&[2, 1, 5000, 1, 7000]));
+        let stsz = full_atom(
+            b"stsz",
+            &[u32s(&[0, sizes.len() as u32]), u32s(sizes)].concat(),
+        );
+        let stsc = full_atom(b"stsc", &u32s(&[1, 1, sizes.len() as u32, 1]));
+        let stco = full_atom(b"stco", &u32s(&[1, offset]));
+        let stbl = atom(b"stbl", &[stts, stsz, stsc, stco].concat());
+        let text = atom(
+            b"trak",
+            &[
+                tkhd(2),
+                atom(b"mdia", &[mdhd, atom(b"minf", &stbl)].concat()),
+            ]
+            .concat(),
+        );
+        atom(b"moov", &[audio, text].concat())
+    }
+
+    #[test]
+    fn nero_chapters_are_read() {
+        let file = nero_file(&[(0.0, "Intro"), (90.5, "Main part")]);
+        assert_eq!(
+            mp4_chapters(&mut Cursor::new(file)).unwrap(),
+            [chapter("Intro", 0.0), chapter("Main part", 90.5)]
+        );
+    }
+
+    #[test]
+    fn quicktime_chapter_track_is_read() {
+        let samples = [&b"\0\x05First"[..], b"\0\x06Second\0\0\0\x0cencd"].concat();
+        let sizes = [7, samples.len() as u32 - 7];
+        let offset = (ftyp().len() + quicktime_moov(0, &sizes).len() + 8) as u32;
+        let file = [
+            ftyp(),
+            quicktime_moov(offset, &sizes),
+            atom(b"mdat", &samples),
+        ]
+        .concat();
+        assert_eq!(
+            mp4_chapters(&mut Cursor::new(file)).unwrap(),
+            [chapter("First", 0.0), chapter("Second", 5.0)]
+        );
+    }
+
+    /// EBML element with an 8-byte size
+    fn element(id: u32, content: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let skip = id.iter().take_while(|byte| **byte == 0).count();
+        let mut element = id[skip..].to_vec();
+        element.push(0x01);
+        element.extend_from_slice(&(content.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(content);
+        element
+    }
+
+    fn chapter_atom(title: &str, start: u64, hidden: bool) -> Vec<u8> {
+        let display = [
+            element(CHAP_STRING, title.as_bytes()),
+            element(0x437C, b"eng"),
+        ]
+        .concat();
+        let atom = [
+            // ChapterUID
+            element(0x73C4, &[1]),
+            element(CHAPTER_TIME_START, &start.to_be_bytes()),
+            element(CHAPTER_FLAG_HIDDEN, &[hidden as u8]),
+            element(CHAPTER_DISPLAY, &display),
+        ]
+        .concat();
+        element(CHAPTER_ATOM, &atom)
+    }
+
+    #[test]
+    fn matroska_chapters_of_the_default_edition_are_read() {
+        let other = element(EDITION_ENTRY, &chapter_atom("Other", 0, false));
+        let default = element(
+            EDITION_ENTRY,
+            &[
+                element(EDITION_FLAG_DEFAULT, &[1]),
+                chapter_atom("Opening", 0, false),
+                chapter_atom("Hidden", 1_000_000_000, true),
+                chapter_atom("Credits", 125_250_000_000, false),
+            ]
+            .concat(),
+        );
+
+        let mut file = element(EBML_HEADER, &element(0x4282, b"matroska"));
+        // a segment of unknown size, as written while recording
+        file.extend_from_slice(&SEGMENT.to_be_bytes());
+        file.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        // Info, with its TimestampScale
+        file.extend(element(0x1549A966, &element(0x2AD7B1, &[0x0F, 0x42, 0x40])));
+        file.extend(element(CHAPTERS, &[other, default].concat()));
+        // Cluster
+        file.extend(element(0x1F43B675, &[0; 32]));
+
+        assert_eq!(
+            matroska_chapters(&mut Cursor::new(file)).unwrap(),
+            [chapter("Opening", 0.0), chapter("Credits", 125.25)]
+        );
+    }
+
+    #[test]
+    fn files_without_chapters_have_none() {
+        let file = [ftyp(), atom(b"moov", &[]), atom(b"mdat", b"audio")].concat();
+        assert!(mp4_chapters(&mut Cursor::new(file)).unwrap().is_empty());
+        assert!(
+            matroska_chapters(&mut Cursor::new(b"not a matroska file".to_vec()))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn container_chapters_are_read_by_extension() {
+        let dir = TempDir::new().unwrap();
+        let file = nero_file(&[(0.0, "Intro")]);
+        let book = dir.path().join("book.m4b");
+        fs::write(&book, &file).unwrap();
+        let flac = dir.path().join("book.flac");
+        fs::write(&flac, &file).unwrap();
+
+        assert_eq!(
+            container_chapters(book.to_str().unwrap()),
+            [chapter("Intro", 0.0)]
+        );
+        assert!(container_chapters(flac.to_str().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn container_chapters_are_navigable() {
+        let dir = TempDir::new().unwrap();
+        let book = dir.path().join("book.m4b");
+        fs::write(&book, nero_file(&[(0.0, "Intro"), (90.5, "Main part")])).unwrap();
+
+        let chapters = container_chapters(book.to_str().unwrap());
+        assert_eq!(chapter_at(&chapters, 0.0), Some(0));
+        assert_eq!(chapter_at(&chapters, 90.0), Some(0));
+        assert_eq!(chapter_at(&chapters, 90.5), Some(1));
+        assert_eq!(chapter_at(&chapters, 3600.0), Some(1));
+    }
+}
@@ -0,0 +1,408 @@
+//! Tracks stored inside other files: the audio tracks of multi-track containers (Matroska files
+//! with several audio streams, videos) and the audio files of ZIP archives
+//!
+//! They're addressed by a location: `movie.mkv#2` is the track with id 2 of `movie.mkv` and
+//! `album.zip#disc 1/01.flac` is the entry `disc 1/01.flac` of `album.zip`
+
+use crate::{tracker, CODEC_REGISTRY, PROBE};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::errors::{unsupported_error, Result as SymphResult};
+use symphonia::core::formats::{
+    Cue, FormatOptions, FormatReader, Packet, SeekMode, SeekTo, SeekedTo, Track,
+};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{Metadata, MetadataOptions};
+use symphonia::core::probe::Hint;
+use zip::ZipArchive;
+
+/// Separates the file from the track or the entry inside it
+pub const TRACK_SEPARATOR: char = '#';
+
+/// Extensions of the archive entries listed as tracks
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "aac", "aif", "aifc", "aiff", "caf", "dca", "flac", "m4a", "mka", "mp1", "mp2", "mp3", "oga",
+    "ogg", "opus", "rawf32", "rawpcm", "wav", "wave", "webm",
+];
+
+/// Names (without extension) of the images used as the cover of the tracks of an archive, by
+/// priority
+const COVER_NAMES: &[&str] = &["cover", "folder", "front", "album"];
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// Where a track is stored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location<'a> {
+    /// Path of the file on disk
+    pub file: &'a str,
+    /// Path of the entry inside the ZIP archive `file`
+    pub entry: Option<&'a str>,
+    /// Id of the track inside its container
+    pub track_id: Option<u32>,
+}
+
+impl<'a> Location<'a> {
+    /// Parses the path of a track
+    ///
+    /// A path is only split when the part before the separator is a file, so files whose names
+    /// look like a location (`mix #2`, `notes.zip#1`) keep being played as they are
+    pub fn parse(path: &'a str) -> Self {
+        let whole = Self {
+            file: path,
+            entry: None,
+            track_id: None,
+        };
+        if Path::new(path).exists() {
+            return whole;
+        }
+
+        let (rest, track_id) = match path.rsplit_once(TRACK_SEPARATOR) {
+            Some((rest, id)) if !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()) => {
+                match id.parse() {
+                    Ok(id) => (rest, Some(id)),
+                    Err(_) => return whole,
+                }
+            }
+            _ => (path, None),
+        };
+        // lowercasing ASCII keeps the byte indices the same
+        let (file, entry) = match rest.to_ascii_lowercase().find(".zip#") {
+            Some(i) if !Path::new(rest).is_file() => (&rest[..i + 4], Some(&rest[i + 5..])),
+            _ => (rest, None),
+        };
+
+        if Path::new(file).is_file() {
+            Self {
+                file,
+                entry,
+                track_id,
+            }
+        } else {
+            whole
+        }
+    }
+
+    /// Extension of the file holding the track
+    pub fn extension(&self) -> Option<&'a str> {
+        Path::new(self.entry.unwrap_or(self.file))
+            .extension()
+            .and_then(|ext| ext.to_str())
+    }
+}
+
+/// Lists the locations of the tracks held by the file at `path`: the audio files of a ZIP
+/// archive, every audio track of a container with more than one or of a video, otherwise `path`
+/// itself if it can be played
+pub fn list_tracks(path: &str) -> io::Result<Vec<String>> {
+    let is_zip = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if is_zip {
+        return Ok(archive_entries(path)?
+            .into_iter()
+            .map(|entry| format!("{path}{TRACK_SEPARATOR}{entry}"))
+            .collect());
+    }
+
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let media_stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let format = PROBE
+        .format(
+            &hint,
+            media_stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|_| io::Error::from(io::ErrorKind::Unsupported))?
+        .format;
+
+    let tracks = audio_tracks(format.as_ref());
+    let default_id = format.default_track().map(|track| track.id);
+    Ok(match tracks.as_slice() {
+        [] => vec![],
+        [track] if Some(track.id) == default_id => vec![path.to_string()],
+        tracks => tracks
+            .iter()
+            .map(|track| format!("{path}{TRACK_SEPARATOR}{}", track.id))
+            .collect(),
+    })
+}
+
+/// Returns the tracks of `format` that can be decoded
+pub fn audio_tracks(format: &dyn FormatReader) -> Vec<&Track> {
+    format
+        .tracks()
+        .iter()
+        .filter(|track| {
+            track.codec_params.codec != CODEC_TYPE_NULL
+                && CODEC_REGISTRY.get_codec(track.codec_params.codec).is_some()
+        })
+        .collect()
+}
+
+/// Restricts `format` to the track with id `track_id`
+pub fn select_track(format: Box<dyn FormatReader>, track_id: u32) -> io::Result<SingleTrack> {
+    let track = audio_tracks(format.as_ref())
+        .into_iter()
+        .find(|track| track.id == track_id)
+        .cloned()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+    Ok(SingleTrack {
+        inner: format,
+        track: [track],
+    })
+}
+
+/// Returns the paths of the audio files inside the ZIP archive at `path`, sorted, leaving out the
+/// ones with an absolute path or going up with `..`
+pub fn archive_entries(path: &str) -> io::Result<Vec<String>> {
+    let mut archive = ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+    let mut entries = vec![];
+    for i in 0..archive.len() {
+        let file = archive.by_index(i).map_err(io::Error::other)?;
+        if file.is_file() && file.enclosed_name().is_some() && is_audio(file.name()) {
+            entries.push(file.name().to_string());
+        }
+    }
+    entries.sort_unstable();
+
+    Ok(entries)
+}
+
+/// Reads the whole `entry` of the ZIP archive at `path`
+pub fn read_entry(path: &str, entry: &str) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+    let mut file = archive.by_name(entry).map_err(|e| match e {
+        zip::result::ZipError::FileNotFound => io::Error::from(io::ErrorKind::NotFound),
+        e => io::Error::other(e),
+    })?;
+    let mut data = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// Returns the image used as the cover of the archive entry at `location`, looking for the
+/// images in its folder
+pub fn archive_cover(location: &Location) -> Option<Vec<u8>> {
+    let entry = location.entry?;
+    let folder = entry.rfind('/').map_or("", |i| &entry[..=i]);
+    let archive = ZipArchive::new(File::open(location.file).ok()?).ok()?;
+    let images: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            name.strip_prefix(folder)
+                .is_some_and(|name| !name.contains('/') && has_extension(name, IMAGE_EXTENSIONS))
+        })
+        .map(String::from)
+        .collect();
+    let priority = |name: &str| {
+        let stem = Path::new(name).file_stem()?.to_str()?.to_ascii_lowercase();
+        COVER_NAMES.iter().position(|cover| *cover == stem)
+    };
+    let image = images
+        .iter()
+        .min_by_key(|name| priority(name).unwrap_or(COVER_NAMES.len()))?;
+
+    read_entry(location.file, image).ok()
+}
+
+fn is_audio(name: &str) -> bool {
+    has_extension(name, AUDIO_EXTENSIONS)
+        || tracker::ENABLED && has_extension(name, tracker::EXTENSIONS)
+}
+
+fn has_extension(name: &str, extensions: &[&str]) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// A `FormatReader` that shows only one track of another, so that it becomes its default track
+pub struct SingleTrack {
+    inner: Box<dyn FormatReader>,
+    track: [Track; 1],
+}
+
+impl FormatReader for SingleTrack {
+    fn try_new(_source: MediaSourceStream, _options: &FormatOptions) -> SymphResult<Self> {
+        unsupported_error("single track: can only be made from another reader")
+    }
+
+    fn cues(&self) -> &[Cue] {
+        self.inner.cues()
+    }
+
+    fn metadata(&mut self) -> Metadata<'_> {
+        self.inner.metadata()
+    }
+
+    fn seek(&mut self, mode: SeekMode, to: SeekTo) -> SymphResult<SeekedTo> {
+        let track_id = self.track[0].id;
+        let to = match to {
+            SeekTo::Time { time, .. } => SeekTo::Time {
+                time,
+                track_id: Some(track_id),
+            },
+            SeekTo::TimeStamp { ts, .. } => SeekTo::TimeStamp { ts, track_id },
+        };
+        self.inner.seek(mode, to)
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.track
+    }
+
+    fn next_packet(&mut self) -> SymphResult<Packet> {
+        loop {
+            let packet = self.inner.next_packet()?;
+            if packet.track_id() == self.track[0].id {
+                return Ok(packet);
+            }
+        }
+    }
+
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strip_absolute_path;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::TempDir;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    /// Writes a ZIP archive at `path` with the entries `(name, data)`
+    fn archive(path: &Path, entries: &[(&str, &[u8])]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn path(dir: &TempDir, name: &str) -> String {
+        dir.path().join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parse_splits_tracks_and_entries_of_files() {
+        let dir = TempDir::new().unwrap();
+        fs::write(path(&dir, "movie.mkv"), b"").unwrap();
+        fs::write(path(&dir, "album.zip"), b"").unwrap();
+
+        let movie = path(&dir, "movie.mkv");
+        let track = format!("{movie}#2");
+        assert_eq!(
+            Location::parse(&track),
+            Location {
+                file: &movie,
+                entry: None,
+                track_id: Some(2),
+            }
+        );
+
+        let album = path(&dir, "album.zip");
+        let entry = format!("{album}#disc 1/movie.mkv#3");
+        let location = Location::parse(&entry);
+        assert_eq!(location.file, album);
+        assert_eq!(location.entry, Some("disc 1/movie.mkv"));
+        assert_eq!(location.track_id, Some(3));
+        assert_eq!(location.extension(), Some("mkv"));
+    }
+
+    #[test]
+    fn parse_keeps_files_that_look_like_locations() {
+        let dir = TempDir::new().unwrap();
+        for name in ["mix #2", "notes.zip#1.mp3", "notes.zip"] {
+            fs::write(path(&dir, name), b"").unwrap();
+        }
+
+        for name in ["mix #2", "notes.zip#1.mp3"] {
+            let file = path(&dir, name);
+            assert_eq!(Location::parse(&file).file, file);
+            assert_eq!(Location::parse(&file).entry, None);
+            assert_eq!(Location::parse(&file).track_id, None);
+        }
+        // the part before the separator has to exist
+        let missing = path(&dir, "missing.mkv#2");
+        assert_eq!(Location::parse(&missing).file, missing);
+        assert_eq!(Location::parse(&missing).track_id, None);
+    }
+
+    #[test]
+    fn archive_entries_skip_unsafe_names() {
+        let dir = TempDir::new().unwrap();
+        let album = path(&dir, "album.zip");
+        archive(
+            Path::new(&album),
+            &[
+                ("disc 2/01.flac", b"two"),
+                ("disc 1/01.flac", b"one"),
+                ("../outside.mp3", b"up"),
+                ("/absolute.mp3", b"root"),
+                ("disc 1/cover.jpg", b"jpg"),
+                ("notes.txt", b"txt"),
+            ],
+        );
+
+        assert_eq!(
+            archive_entries(&album).unwrap(),
+            vec!["disc 1/01.flac", "disc 2/01.flac"]
+        );
+        assert_eq!(read_entry(&album, "disc 2/01.flac").unwrap(), b"two");
+        assert_eq!(
+            list_tracks(&album).unwrap(),
+            vec![
+                format!("{album}#disc 1/01.flac"),
+                format!("{album}#disc 2/01.flac")
+            ]
+        );
+    }
+
+    #[test]
+    fn archive_cover_prefers_cover_names_in_the_same_folder() {
+        let dir = TempDir::new().unwrap();
+        let album = path(&dir, "album.zip");
+        archive(
+            Path::new(&album),
+            &[
+                ("cover.jpg", b"root"),
+                ("disc 1/01.flac", b""),
+                ("disc 1/booklet.png", b"booklet"),
+                ("disc 1/Folder.jpg", b"folder"),
+            ],
+        );
+
+        let entry = format!("{album}#disc 1/01.flac");
+        assert_eq!(archive_cover(&Location::parse(&entry)).unwrap(), b"folder");
+    }
+
+    #[test]
+    fn strip_absolute_path_keeps_the_folders_of_entries() {
+        let dir = TempDir::new().unwrap();
+        let album = path(&dir, "album.zip");
+        archive(Path::new(&album), &[("disc 1/01.flac", b"")]);
+
+        assert_eq!(
+            strip_absolute_path(format!("{album}#disc 1/01.flac")),
+            "album.zip#disc 1/01.flac"
+        );
+        assert_eq!(strip_absolute_path(path(&dir, "song.flac")), "song.flac");
+    }
+}
@@ -0,0 +1,258 @@
+use crate::effect::{Effect, Parameter};
+use crate::fft::{Complex, Fft};
+use crate::music_track::MusicTrack;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+
+/// Frames processed at once by the `Convolver`, which is also its latency
+const BLOCK_SIZE: usize = 512;
+
+/// An impulse response (room correction, speaker or headphone EQ, ...) to be used by a `Convolver`
+#[derive(Clone, Debug)]
+pub struct ImpulseResponse {
+    rate: u32,
+    /// The samples of every channel
+    channels: Vec<Vec<f32>>,
+}
+
+impl ImpulseResponse {
+    pub fn new(rate: u32, channels: Vec<Vec<f32>>) -> Self {
+        Self { rate, channels }
+    }
+
+    /// Loads an impulse response from an audio file, usually a WAV file
+    pub fn from_file<P: AsRef<Path> + AsRef<OsStr> + Clone + Into<String>>(
+        path: P,
+    ) -> io::Result<Self> {
+        let mut rate = 0;
+        let mut channels: Vec<Vec<f32>> = vec![];
+        MusicTrack::new(path)?.decode(|samples, count, samples_rate| {
+            rate = samples_rate;
+            channels.resize(count, vec![]);
+            for frame in samples.chunks_exact(count) {
+                for (channel, sample) in channels.iter_mut().zip(frame) {
+                    channel.push(*sample);
+                }
+            }
+        })?;
+
+        if channels.iter().all(|channel| channel.is_empty()) {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+        Ok(Self { rate, channels })
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the length of the impulse response, in frames
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, |channel| channel.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the channels resampled (with linear interpolation) to `rate`
+    fn resampled(&self, rate: u32) -> Vec<Vec<f32>> {
+        if rate == self.rate || self.rate == 0 {
+            return self.channels.clone();
+        }
+
+        let ratio = self.rate as f64 / rate as f64;
+        // keeps the overall gain the same
+        let gain = (rate as f64 / self.rate as f64).min(1.0) as f32;
+        let len = (self.len() as f64 / ratio).ceil() as usize;
+        self.channels
+            .iter()
+            .map(|channel| {
+                (0..len)
+                    .map(|i| {
+                        let position = i as f64 * ratio;
+                        let index = position as usize;
+                        let frac = (position - index as f64) as f32;
+                        let a = channel.get(index).copied().unwrap_or(0.0);
+                        let b = channel.get(index + 1).copied().unwrap_or(0.0);
+                        (a + (b - a) * frac) * gain
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// State of a channel of the `Convolver`
+#[derive(Debug)]
+struct ChannelState {
+    /// Which channel of the impulse response is used
+    ir: usize,
+    /// The previous block followed by the current one
+    window: Vec<f32>,
+    /// Spectra of the last windows, one per partition of the impulse response
+    history: Vec<Vec<Complex>>,
+    head: usize,
+    /// The block being played while the next one is collected
+    output: Vec<f32>,
+}
+
+/// Convolves the samples with an [`ImpulseResponse`] using uniformly partitioned FFT convolution,
+/// so that the cost doesn't grow with the length of the impulse response as fast as it would
+/// with a direct convolution
+///
+/// Mono impulse responses are applied to every channel, otherwise every channel uses the channel of
+/// the impulse response with the same index (wrapping around)
+#[derive(Debug)]
+pub struct Convolver {
+    ir: ImpulseResponse,
+    /// Gain in dB
+    gain: f32,
+    fft: Fft,
+    /// Spectra of the partitions of every channel of the impulse response
+    partitions: Vec<Vec<Vec<Complex>>>,
+    states: Vec<ChannelState>,
+    position: usize,
+    spectrum: Vec<Complex>,
+}
+
+impl Convolver {
+    pub fn new(ir: ImpulseResponse) -> Self {
+        Self {
+            ir,
+            gain: 0.0,
+            fft: Fft::new(BLOCK_SIZE * 2),
+            partitions: vec![],
+            states: vec![],
+            position: 0,
+            spectrum: vec![Complex::default(); BLOCK_SIZE * 2],
+        }
+    }
+
+    fn convolve_block(&mut self) {
+        let gain = 10.0_f32.powf(self.gain / 20.0);
+        for state in self.states.iter_mut() {
+            let partitions = &self.partitions[state.ir];
+            let count = partitions.len();
+
+            let input = &mut state.history[state.head];
+            for (value, sample) in input.iter_mut().zip(&state.window) {
+                *value = Complex::new(*sample, 0.0);
+            }
+            self.fft.forward(input);
+
+            self.spectrum.fill(Complex::default());
+            for (lag, partition) in partitions.iter().enumerate() {
+                let input = &state.history[(state.head + count - lag) % count];
+                for ((acc, x), h) in self.spectrum.iter_mut().zip(input).zip(partition) {
+                    *acc += *x * *h;
+                }
+            }
+            self.fft.inverse(&mut self.spectrum);
+
+            // overlap-save: only the second half is free of circular aliasing
+            for (output, value) in state.output.iter_mut().zip(&self.spectrum[BLOCK_SIZE..]) {
+                *output = value.re * gain;
+            }
+            state.window.copy_within(BLOCK_SIZE.., 0);
+            state.head = (state.head + 1) % count;
+        }
+    }
+}
+
+impl Effect for Convolver {
+    fn name(&self) -> &str {
+        "Convolver"
+    }
+
+    fn configure(&mut self, rate: u32, channels: usize) {
+        let fft = &self.fft;
+        self.partitions = self
+            .ir
+            .resampled(rate)
+            .iter()
+            .map(|channel| {
+                channel
+                    .chunks(BLOCK_SIZE)
+                    .map(|chunk| {
+                        let mut partition = vec![Complex::default(); BLOCK_SIZE * 2];
+                        for (value, sample) in partition.iter_mut().zip(chunk) {
+                            *value = Complex::new(*sample, 0.0);
+                        }
+                        fft.forward(&mut partition);
+                        partition
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|partitions| !partitions.is_empty())
+            .collect();
+
+        let count = self.partitions.len();
+        self.states = if count == 0 {
+            vec![]
+        } else {
+            (0..channels)
+                .map(|channel| ChannelState {
+                    ir: channel % count,
+                    window: vec![0.0; BLOCK_SIZE * 2],
+                    history: vec![
+                        vec![Complex::default(); BLOCK_SIZE * 2];
+                        self.partitions[channel % count].len()
+                    ],
+                    head: 0,
+                    output: vec![0.0; BLOCK_SIZE],
+                })
+                .collect()
+        };
+        self.position = 0;
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if self.states.len() != channels {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(self.states.iter_mut()) {
+                state.window[BLOCK_SIZE + self.position] = *sample;
+                *sample = state.output[self.position];
+            }
+            self.position += 1;
+            if self.position == BLOCK_SIZE {
+                self.position = 0;
+                self.convolve_block();
+            }
+        }
+    }
+
+    fn latency(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {
+        for state in self.states.iter_mut() {
+            state.window.fill(0.0);
+            state
+                .history
+                .iter_mut()
+                .for_each(|spectrum| spectrum.fill(Complex::default()));
+            state.output.fill(0.0);
+        }
+        self.position = 0;
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![Parameter::new("gain", self.gain, -24.0, 24.0)]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        if name == "gain" {
+            self.gain = value.clamp(-24.0, 24.0);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::effect::{Effect, Parameter};
+use std::f32::consts::PI;
+
+/// Headphone crossfeed, based on the filters of [bs2b](https://bs2b.sourceforge.net/)
+///
+/// Mixes a low-passed and delayed copy of every channel into the other one, like it happens when
+/// listening to speakers, so that hard-panned recordings are less tiring on headphones
+/// Only stereo tracks are processed
+#[derive(Clone, Debug)]
+pub struct Crossfeed {
+    /// Cut frequency in Hz
+    cutoff: f32,
+    /// Feed level at low frequencies in dB
+    feed: f32,
+    rate: u32,
+    lo: (f32, f32),
+    hi: (f32, f32, f32),
+    gain: f32,
+    /// Last input and filters output of both channels
+    last: [f32; 2],
+    last_lo: [f32; 2],
+    last_hi: [f32; 2],
+}
+
+impl Crossfeed {
+    pub fn new(cutoff: f32, feed: f32) -> Self {
+        let mut crossfeed = Self {
+            cutoff: cutoff.clamp(300.0, 2000.0),
+            feed: feed.clamp(1.0, 15.0),
+            rate: 44100,
+            lo: (0.0, 0.0),
+            hi: (0.0, 0.0, 0.0),
+            gain: 1.0,
+            last: [0.0; 2],
+            last_lo: [0.0; 2],
+            last_hi: [0.0; 2],
+        };
+        crossfeed.update_filters();
+        crossfeed
+    }
+
+    /// The default preset of bs2b, close to the crossfeed described by Benjamin Bauer
+    pub fn bauer() -> Self {
+        Self::new(700.0, 4.5)
+    }
+
+    /// Closer to the Chu Moy crossfeed, with a stronger feed
+    pub fn chu_moy() -> Self {
+        Self::new(700.0, 6.0)
+    }
+
+    /// Closer to the Jan Meier crossfeed, with a lower cut and a much stronger feed
+    pub fn jan_meier() -> Self {
+        Self::new(650.0, 9.5)
+    }
+
+    fn update_filters(&mut self) {
+        let rate = self.rate as f32;
+        let gain_lo_db = self.feed * -5.0 / 6.0 - 3.0;
+        let gain_hi_db = self.feed / 6.0 - 3.0;
+        let gain_lo = 10.0_f32.powf(gain_lo_db / 20.0);
+        let gain_hi = 1.0 - 10.0_f32.powf(gain_hi_db / 20.0);
+        let cutoff_hi = self.cutoff * 2.0_f32.powf((gain_lo_db - 20.0 * gain_hi.log10()) / 12.0);
+
+        let x = (-2.0 * PI * self.cutoff / rate).exp();
+        self.lo = (gain_lo * (1.0 - x), x);
+        let x = (-2.0 * PI * cutoff_hi / rate).exp();
+        self.hi = (1.0 - gain_hi * (1.0 - x), -x, x);
+        self.gain = 1.0 / (1.0 - gain_hi + gain_lo);
+    }
+}
+
+impl Default for Crossfeed {
+    fn default() -> Self {
+        Self::bauer()
+    }
+}
+
+impl Effect for Crossfeed {
+    fn name(&self) -> &str {
+        "Crossfeed"
+    }
+
+    fn configure(&mut self, rate: u32, _channels: usize) {
+        self.rate = rate;
+        self.update_filters();
+        self.reset();
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if channels != 2 {
+            return;
+        }
+
+        let (lo_a0, lo_b1) = self.lo;
+        let (hi_a0, hi_a1, hi_b1) = self.hi;
+        for frame in samples.chunks_exact_mut(2) {
+            for (channel, sample) in frame.iter().copied().enumerate() {
+                self.last_lo[channel] = lo_a0 * sample + lo_b1 * self.last_lo[channel];
+                self.last_hi[channel] =
+                    hi_a0 * sample + hi_a1 * self.last[channel] + hi_b1 * self.last_hi[channel];
+                self.last[channel] = sample;
+            }
+            frame[0] = (self.last_hi[0] + self.last_lo[1]) * self.gain;
+            frame[1] = (self.last_hi[1] + self.last_lo[0]) * self.gain;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last = [0.0; 2];
+        self.last_lo = [0.0; 2];
+        self.last_hi = [0.0; 2];
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::new("cutoff", self.cutoff, 300.0, 2000.0),
+            Parameter::new("feed", self.feed, 1.0, 15.0),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "cutoff" => self.cutoff = value.clamp(300.0, 2000.0),
+            "feed" => self.feed = value.clamp(1.0, 15.0),
+            _ => return,
+        }
+        self.update_filters();
+    }
+}
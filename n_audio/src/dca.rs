@@ -1,6 +1,14 @@
-use audiopus::SampleRate;
-use serde::{Deserialize, Serialize};
-use std::io::{Seek, SeekFrom};
+use crate::export::{base64, downmix};
+use crate::music_track::MusicTrack;
+use crate::resample::Resampler;
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::Path;
 use symphonia::core::{
     codecs::{CodecParameters, CODEC_TYPE_OPUS},
     errors::{self as symph_err, Error as SymphError, Result as SymphResult, SeekErrorKind},
@@ -9,7 +17,7 @@ use symphonia::core::{
     meta::{Metadata as SymphMetadata, MetadataBuilder, MetadataLog, StandardTagKey, Tag, Value},
     probe::{Descriptor, Instantiate, QueryDescriptor},
     sample::SampleFormat,
-    units::TimeStamp,
+    units::{Time, TimeStamp},
 };
 
 // Original code from the Songbird project
@@ -57,6 +65,473 @@ pub struct Info {
     pub comments: Option<String>,
 }
 
+/// A chapter stored in the `chapters` array of the DCA1 `extra` object
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DcaChapter {
+    pub title: Option<String>,
+    /// Where the chapter starts, in seconds
+    pub start: f64,
+}
+
+/// Encoder delay and padding stored in the `gapless` object of the DCA1 `extra` object
+///
+/// DCA1 files without it are assumed to come from libopus, so only its [`DEFAULT_DELAY`] is
+/// trimmed; DCA0 files have no metadata and aren't trimmed
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct DcaGapless {
+    /// Samples added by the encoder at the start of the stream
+    pub delay: u32,
+    /// Samples added by the encoder at the end of the stream, to fill the last frame
+    pub padding: u32,
+}
+
+/// Seek index stored in the `seek_index` object of the DCA1 `extra` object, so that seeking
+/// doesn't need to read all the frames before the position
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DcaSeekIndex {
+    /// Length of the stream in samples, the encoder delay and padding included
+    pub length: u64,
+    /// Timestamp and position of some frames, relative to the first frame, in order
+    pub frames: Vec<(TimeStamp, u64)>,
+}
+
+impl DcaSeekIndex {
+    /// Builds the index reading the frames from `reader`, which must be at the first frame
+    pub fn build<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut index = Self::default();
+        let mut position = 0;
+        let mut buf = vec![];
+        loop {
+            let mut len = [0; 2];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let len = i16::from_le_bytes(len);
+            if len < 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "DCA frame header had a negative length",
+                ));
+            }
+            buf.resize(len as usize, 0);
+            reader.read_exact(&mut buf)?;
+
+            let samples = packet_samples(&buf).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "DCA frame is not an Opus packet",
+                )
+            })?;
+            index.add_frame(position, samples);
+            position += 2 + len as u64;
+        }
+        Ok(index)
+    }
+
+    /// Adds the frame at `position`, of `samples` samples, after the ones already added
+    fn add_frame(&mut self, position: u64, samples: u64) {
+        if self.length >= self.frames.len() as u64 * SEEK_INDEX_INTERVAL {
+            self.frames.push((self.length, position));
+        }
+        self.length += samples;
+    }
+
+    fn is_valid(&self) -> bool {
+        self.frames.first().is_some_and(|frame| *frame == (0, 0))
+            && self
+                .frames
+                .windows(2)
+                .all(|frames| frames[0].0 < frames[1].0 && frames[0].1 < frames[1].1)
+    }
+}
+
+/// Builds the seek index of a DCA1 file and stores it in its `extra` metadata, rewriting the file
+pub fn store_seek_index<P: AsRef<Path>>(path: P) -> io::Result<DcaSeekIndex> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"DCA1" {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only DCA1 files have metadata to store the seek index in",
+        ));
+    }
+    let mut size = [0; 4];
+    reader.read_exact(&mut size)?;
+    let mut raw_json = vec![0; u32::from_le_bytes(size) as usize];
+    reader.read_exact(&mut raw_json)?;
+    let mut metadata: serde_json::Value = serde_json::from_slice(&raw_json)?;
+
+    let audio_start = reader.stream_position()?;
+    let index = DcaSeekIndex::build(&mut reader)?;
+    reader.seek(SeekFrom::Start(audio_start))?;
+
+    let Some(metadata_object) = metadata.as_object_mut() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed DCA1 metadata block",
+        ));
+    };
+    let extra = metadata_object
+        .entry("extra")
+        .or_insert_with(|| serde_json::Value::Object(Default::default()));
+    if !extra.is_object() {
+        *extra = serde_json::Value::Object(Default::default());
+    }
+    extra["seek_index"] = serde_json::to_value(&index)?;
+    let raw_json = serde_json::to_vec(&metadata)?;
+
+    let temp_path = path.with_extension("dca.tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writer.write_all(b"DCA1")?;
+    writer.write_all(&(raw_json.len() as u32).to_le_bytes())?;
+    writer.write_all(&raw_json)?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temp_path, path)?;
+
+    Ok(index)
+}
+
+/// Options of the Opus encoder used by [`write_dca`]
+#[derive(Clone, Copy, Debug)]
+pub struct DcaOptions {
+    /// Target bitrate, in bits per second
+    pub bitrate: u32,
+    pub vbr: bool,
+}
+
+impl Default for DcaOptions {
+    fn default() -> Self {
+        Self {
+            bitrate: 128000,
+            vbr: true,
+        }
+    }
+}
+
+/// Samples of every channel in a DCA frame (20 ms)
+const FRAME_SIZE: usize = 960;
+/// Largest Opus packet, as recommended by libopus
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// Reads the tags and the cover of `track` for the DCA1 metadata
+fn track_info(track: &MusicTrack) -> io::Result<Info> {
+    let mut info = Info {
+        title: None,
+        artist: None,
+        album: None,
+        genre: None,
+        cover: None,
+        comments: None,
+    };
+
+    let mut format = track.get_format()?;
+    if let Some(metadata) = format.metadata().skip_to_latest() {
+        for tag in metadata.tags() {
+            let field = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => &mut info.title,
+                Some(StandardTagKey::Artist) => &mut info.artist,
+                Some(StandardTagKey::Album) => &mut info.album,
+                Some(StandardTagKey::Genre) => &mut info.genre,
+                Some(StandardTagKey::Comment) => &mut info.comments,
+                _ => continue,
+            };
+            field.get_or_insert_with(|| tag.value.to_string());
+        }
+        info.cover = metadata
+            .visuals()
+            .first()
+            .map(|visual| base64(&visual.data));
+    }
+
+    // the title and the artist may also come from tags Symphonia doesn't read
+    let meta = track.get_meta()?;
+    if info.title.is_none() {
+        info.title = Some(meta.title);
+    }
+    if info.artist.is_none() && !meta.artist.is_empty() {
+        info.artist = Some(meta.artist);
+    }
+
+    Ok(info)
+}
+
+/// Opus encoder writing a DCA1 file one frame at a time
+///
+/// The encoder delay and padding and the seek index are only known at the end, so room is left for
+/// them in the metadata block, which is written again when the track is finished
+struct DcaEncoder<W: Write + Seek> {
+    encoder: Encoder,
+    writer: BufWriter<W>,
+    channels: usize,
+    metadata: DcaMetadata,
+    /// Position of the metadata block
+    metadata_start: u64,
+    /// Size of the metadata block, whitespace included
+    metadata_size: usize,
+    /// Interleaved samples of the next frame
+    pending: Vec<f32>,
+    /// Frames given to the encoder, without the delay and the padding
+    frames: u64,
+    seek_index: DcaSeekIndex,
+    /// Position of the next DCA frame, relative to the first one
+    position: u64,
+    packet: Vec<u8>,
+}
+
+impl<W: Write + Seek> DcaEncoder<W> {
+    /// Writes the metadata block, with room for the seek index of about `length` seconds
+    fn new(
+        writer: W,
+        channels: usize,
+        options: &DcaOptions,
+        mut metadata: DcaMetadata,
+        length: f64,
+    ) -> io::Result<Self> {
+        let mut encoder = Encoder::new(
+            SampleRate::Hz48000,
+            if channels == 1 {
+                Channels::Mono
+            } else {
+                Channels::Stereo
+            },
+            Application::Audio,
+        )
+        .map_err(io::Error::other)?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(options.bitrate as i32))
+            .map_err(io::Error::other)?;
+        encoder.set_vbr(options.vbr).map_err(io::Error::other)?;
+
+        metadata.opus.channels = channels as u8;
+        if let Some(origin) = &mut metadata.origin {
+            origin.channels = Some(channels as u8);
+        }
+        let mut dca = Self {
+            encoder,
+            writer: BufWriter::new(writer),
+            channels,
+            metadata,
+            metadata_start: 0,
+            metadata_size: 0,
+            pending: vec![],
+            frames: 0,
+            seek_index: DcaSeekIndex::default(),
+            position: 0,
+            packet: vec![0; MAX_PACKET_SIZE],
+        };
+
+        // the widest values take the most room
+        let entries = (length.max(0.0) * 48000.0) as u64 / SEEK_INDEX_INTERVAL + 1;
+        let gapless = DcaGapless {
+            delay: DEFAULT_DELAY,
+            padding: u32::MAX,
+        };
+        let seek_index = DcaSeekIndex {
+            length: u64::MAX,
+            frames: vec![(u64::MAX, u64::MAX); entries as usize],
+        };
+        let raw_json = dca.metadata_json(gapless, &seek_index)?;
+        dca.metadata_start = dca.writer.stream_position()?;
+        dca.metadata_size = raw_json.len();
+        dca.writer.write_all(b"DCA1")?;
+        dca.writer
+            .write_all(&(raw_json.len() as u32).to_le_bytes())?;
+        dca.writer.write_all(&raw_json)?;
+        Ok(dca)
+    }
+
+    fn metadata_json(
+        &mut self,
+        gapless: DcaGapless,
+        seek_index: &DcaSeekIndex,
+    ) -> io::Result<Vec<u8>> {
+        if let Some(serde_json::Value::Object(extra)) = &mut self.metadata.extra {
+            extra.insert("gapless".to_string(), serde_json::to_value(gapless)?);
+            extra.insert("seek_index".to_string(), serde_json::to_value(seek_index)?);
+        }
+        Ok(serde_json::to_vec(&self.metadata)?)
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.frames += (samples.len() / self.channels) as u64;
+        self.pending.extend_from_slice(samples);
+        self.encode_pending()
+    }
+
+    fn encode_pending(&mut self) -> io::Result<()> {
+        let frame_len = FRAME_SIZE * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= frame_len {
+            let len = self
+                .encoder
+                .encode_float(&self.pending[start..start + frame_len], &mut self.packet)
+                .map_err(io::Error::other)?;
+            self.writer.write_all(&(len as i16).to_le_bytes())?;
+            self.writer.write_all(&self.packet[..len])?;
+            self.seek_index.add_frame(self.position, FRAME_SIZE as u64);
+            self.position += 2 + len as u64;
+            start += frame_len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Encodes the rest of the samples and fills the room left in the metadata block
+    fn finish(mut self) -> io::Result<()> {
+        // the encoder delay is flushed with silence, then the last frame is filled
+        let frames = self.pending.len() / self.channels + DEFAULT_DELAY as usize;
+        self.pending.resize(
+            frames.div_ceil(FRAME_SIZE) * FRAME_SIZE * self.channels,
+            0.0,
+        );
+        self.encode_pending()?;
+
+        let gapless = DcaGapless {
+            delay: DEFAULT_DELAY,
+            padding: (self.seek_index.length - self.frames - DEFAULT_DELAY as u64) as u32,
+        };
+        let mut seek_index = mem::take(&mut self.seek_index);
+        let mut raw_json = self.metadata_json(gapless, &seek_index)?;
+        // a track longer than expected keeps every other frame of its seek index until it fits
+        while raw_json.len() > self.metadata_size && seek_index.frames.len() > 1 {
+            seek_index.frames = seek_index.frames.into_iter().step_by(2).collect();
+            raw_json = self.metadata_json(gapless, &seek_index)?;
+        }
+        if raw_json.len() > self.metadata_size {
+            return Err(io::Error::other(
+                "the DCA1 metadata doesn't fit in its room",
+            ));
+        }
+        // JSON ignores the whitespace after the value
+        raw_json.resize(self.metadata_size, b' ');
+
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.metadata_start + 8))?;
+        self.writer.write_all(&raw_json)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+}
+
+/// Encodes `track` with Opus and writes it as a DCA1 file to `writer`, one frame at a time
+///
+/// Tracks with more than two channels are downmixed to stereo, and resampled to 48 kHz if
+/// needed. The DCA1 metadata stores the tags, the cover, the chapters, the encoder delay and
+/// padding, and the seek index of the track.
+pub fn write_dca<W: Write + Seek>(
+    track: &MusicTrack,
+    options: &DcaOptions,
+    writer: W,
+) -> io::Result<()> {
+    let chapters = track
+        .get_chapters()?
+        .into_iter()
+        .map(|chapter| DcaChapter {
+            title: Some(chapter.title),
+            start: chapter.start,
+        })
+        .collect::<Vec<_>>();
+    let mut extra = serde_json::Map::new();
+    if !chapters.is_empty() {
+        extra.insert("chapters".to_string(), serde_json::to_value(chapters)?);
+    }
+
+    // the channels are set once the first samples are decoded
+    let metadata = DcaMetadata {
+        dca: DcaInfo {
+            version: 1,
+            tool: Tool {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                url: Some(env!("CARGO_PKG_HOMEPAGE").to_string()),
+                author: None,
+            },
+        },
+        opus: Opus {
+            mode: "audio".to_string(),
+            sample_rate: 48000,
+            frame_size: FRAME_SIZE as u64,
+            abr: Some(options.bitrate as u64),
+            vbr: options.vbr,
+            channels: 0,
+        },
+        info: Some(track_info(track)?),
+        origin: Some(Origin {
+            source: Some("file".to_string()),
+            abr: None,
+            channels: None,
+            encoding: None,
+            url: None,
+        }),
+        extra: Some(serde_json::Value::Object(extra)),
+    };
+    let length = track.get_length()?.length;
+
+    let mut start = Some((writer, metadata));
+    let mut encoder: Option<DcaEncoder<W>> = None;
+    let mut resampler: Option<Resampler> = None;
+    let mut result = Ok(());
+    let mut input = vec![];
+    let mut resampled = vec![];
+    track.decode_while(|decoded, decoded_channels, rate| {
+        if let Some((writer, metadata)) = start.take() {
+            let channels = decoded_channels.min(2);
+            if rate != 48000 {
+                resampler = Some(Resampler::new(rate, 48000, channels));
+            }
+            match DcaEncoder::new(writer, channels, options, metadata, length) {
+                Ok(new_encoder) => encoder = Some(new_encoder),
+                Err(e) => {
+                    result = Err(e);
+                    return ControlFlow::Break(());
+                }
+            }
+        }
+
+        input.clear();
+        for frame in decoded.chunks_exact(decoded_channels) {
+            downmix(frame, &mut input);
+        }
+        let samples = match &mut resampler {
+            Some(resampler) => {
+                resampled.clear();
+                resampler.process(&input, &mut resampled);
+                &resampled
+            }
+            None => &input,
+        };
+        if let Some(encoder) = &mut encoder {
+            result = encoder.encode(samples);
+        }
+        if result.is_err() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })?;
+    result?;
+
+    let Some(mut encoder) = encoder else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the track has no audio to encode",
+        ));
+    };
+    if let Some(resampler) = &mut resampler {
+        resampled.clear();
+        resampler.flush(&mut resampled);
+        encoder.encode(&resampled)?;
+    }
+    encoder.finish()
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Origin {
     pub source: Option<String>,
@@ -82,6 +557,32 @@ impl QueryDescriptor for DcaReader {
     }
 }
 
+/// Encoder delay of libopus, stored in the gapless metadata of the files written by [`write_dca`]
+pub const DEFAULT_DELAY: u32 = 312;
+/// Distance between the frames of a [`DcaSeekIndex`], in samples
+const SEEK_INDEX_INTERVAL: u64 = 5 * 48000;
+
+fn packet_samples(packet: &[u8]) -> SymphResult<u64> {
+    let checked_buf = packet.try_into().or_else(|_| {
+        symph_err::decode_error("Packet was not a valid Opus Packet: too large for audiopus.")
+    })?;
+
+    audiopus::packet::nb_samples(checked_buf, SampleRate::Hz48000)
+        .map(|samples| samples as u64)
+        .or_else(|_| {
+            symph_err::decode_error(
+                "Packet was not a valid Opus packet: couldn't read sample count.",
+            )
+        })
+}
+
+fn extra_field<T: DeserializeOwned>(extra: Option<&serde_json::Value>, key: &str) -> Option<T> {
+    extra
+        .and_then(|extra| extra.get(key))
+        .cloned()
+        .and_then(|value| serde_json::from_value(value).ok())
+}
+
 struct SeekAccel {
     frame_offsets: Vec<(TimeStamp, u64)>,
     seek_index_fill_rate: u16,
@@ -100,6 +601,19 @@ impl SeekAccel {
         }
     }
 
+    /// Uses a stored index, which already has all the frames needed
+    fn with_index(first_frame_byte_pos: u64, index: &DcaSeekIndex) -> Self {
+        Self {
+            frame_offsets: index
+                .frames
+                .iter()
+                .map(|&(ts, pos)| (ts, first_frame_byte_pos + pos))
+                .collect(),
+            seek_index_fill_rate: 0,
+            next_ts: TimeStamp::MAX,
+        }
+    }
+
     fn update(&mut self, ts: TimeStamp, pos: u64) {
         if ts >= self.next_ts {
             self.next_ts += (self.seek_index_fill_rate as u64) * (48000);
@@ -118,10 +632,68 @@ pub struct DcaReader {
     source: MediaSourceStream,
     track: Option<Track>,
     metas: MetadataLog,
+    cues: Vec<Cue>,
     seek_accel: SeekAccel,
     curr_ts: TimeStamp,
     max_ts: Option<TimeStamp>,
+    /// Encoder delay to trim, 0 if gapless playback is disabled
+    delay: u64,
+    /// Encoder padding to trim, 0 if gapless playback is disabled
+    padding: u64,
     held_packet: Option<Packet>,
+    /// Frame read ahead, untrimmed, to know which one is the last when the length isn't known
+    next_frame: Option<Packet>,
+}
+
+impl DcaReader {
+    /// Reads the next frame, without trimming it
+    fn read_frame(&mut self) -> SymphResult<Packet> {
+        let frame_pos = self.source.pos();
+
+        let p_len = match self.source.read_u16() {
+            Ok(len) => len as i16,
+            Err(eof) => {
+                self.max_ts = Some(self.curr_ts);
+                return Err(eof.into());
+            }
+        };
+
+        if p_len < 0 {
+            return symph_err::decode_error("DCA frame header had a negative length.");
+        }
+
+        let buf = self.source.read_boxed_slice_exact(p_len as usize)?;
+
+        let sample_ct = packet_samples(&buf)?;
+
+        let out = Packet::new_from_boxed_slice(0, self.curr_ts, sample_ct, buf);
+
+        self.seek_accel.update(self.curr_ts, frame_pos);
+
+        self.curr_ts += sample_ct;
+
+        Ok(out)
+    }
+
+    /// Trims the encoder delay and padding from a packet, moving the timestamps so that they
+    /// start after the delay
+    fn trim_packet(&self, packet: &mut Packet) {
+        let start = packet.ts;
+        let end = packet.ts + packet.dur;
+
+        let trim_start = self.delay.saturating_sub(start).min(packet.dur);
+        let trim_end = self
+            .max_ts
+            .map_or(0, |max_ts| {
+                end.saturating_sub(max_ts.saturating_sub(self.padding))
+            })
+            .min(packet.dur - trim_start);
+
+        packet.ts = start.saturating_sub(self.delay);
+        packet.dur -= trim_start + trim_end;
+        packet.trim_start = trim_start as u32;
+        packet.trim_end = trim_end as u32;
+    }
 }
 
 impl FormatReader for DcaReader {
@@ -129,8 +701,6 @@ impl FormatReader for DcaReader {
         // Read in the magic number to verify it's a DCA file.
         let magic = source.read_quad_bytes()?;
 
-        // FIXME: make use of the new options.enable_gapless to apply the opus coder delay.
-
         let read_meta = match &magic {
             b"DCA1" => true,
             _ if &magic[..3] == b"DCA" => {
@@ -152,6 +722,9 @@ impl FormatReader for DcaReader {
             .with_sample_format(SampleFormat::F32);
 
         let mut metas = MetadataLog::default();
+        let mut cues = vec![];
+        let mut gapless = DcaGapless::default();
+        let mut seek_index = None;
 
         if read_meta {
             let size = source.read_u32()?;
@@ -168,6 +741,15 @@ impl FormatReader for DcaReader {
             let metadata: DcaMetadata = serde_json::from_slice::<DcaMetadata>(&mut raw_json)
                 .map_err(|_| SymphError::DecodeError("malformed DCA1 metadata block"))?;
 
+            let extra = metadata.extra.as_ref();
+            // the padding isn't known, but the pre-skip of libopus is always the same
+            gapless = extra_field(extra, "gapless").unwrap_or(DcaGapless {
+                delay: DEFAULT_DELAY,
+                padding: 0,
+            });
+            seek_index = extra_field::<DcaSeekIndex>(extra, "seek_index")
+                .filter(|seek_index| seek_index.is_valid());
+
             let mut revision = MetadataBuilder::new();
 
             if let Some(info) = metadata.info {
@@ -211,6 +793,35 @@ impl FormatReader for DcaReader {
                 }
             }
 
+            if let Some(chapters) = metadata
+                .extra
+                .as_ref()
+                .and_then(|extra| extra.get("chapters"))
+                .cloned()
+                .and_then(|chapters| serde_json::from_value::<Vec<DcaChapter>>(chapters).ok())
+            {
+                let time_base = TimeBase::new(1, 48000);
+                cues = chapters
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, chapter)| Cue {
+                        index: index as u32 + 1,
+                        start_ts: time_base.calc_timestamp(Time::from(chapter.start.max(0.0))),
+                        tags: chapter
+                            .title
+                            .map(|title| {
+                                vec![Tag::new(
+                                    Some(StandardTagKey::TrackTitle),
+                                    "title",
+                                    Value::String(title),
+                                )]
+                            })
+                            .unwrap_or_default(),
+                        points: vec![],
+                    })
+                    .collect();
+            }
+
             if let Some(origin) = metadata.origin {
                 if let Some(t) = origin.url {
                     revision.add_tag(Tag::new(Some(StandardTagKey::Url), "url", Value::String(t)));
@@ -222,6 +833,23 @@ impl FormatReader for DcaReader {
 
         let bytes_read = source.pos();
 
+        let (delay, padding) = if options.enable_gapless {
+            codec_params
+                .with_delay(gapless.delay)
+                .with_padding(gapless.padding);
+            (gapless.delay as u64, gapless.padding as u64)
+        } else {
+            (0, 0)
+        };
+        let max_ts = seek_index.as_ref().map(|seek_index| seek_index.length);
+        if let Some(length) = max_ts {
+            codec_params.with_n_frames(length.saturating_sub(delay + padding));
+        }
+        let seek_accel = match &seek_index {
+            Some(seek_index) => SeekAccel::with_index(bytes_read, seek_index),
+            None => SeekAccel::new(*options, bytes_read),
+        };
+
         Ok(Self {
             source,
             track: Some(Track {
@@ -230,16 +858,20 @@ impl FormatReader for DcaReader {
                 codec_params,
             }),
             metas,
-            seek_accel: SeekAccel::new(*options, bytes_read),
+            cues,
+            seek_accel,
             curr_ts: 0,
-            max_ts: None,
+            max_ts,
+            delay,
+            padding,
             held_packet: None,
+            next_frame: None,
         })
     }
 
     fn cues(&self) -> &[Cue] {
-        // No cues in DCA...
-        &[]
+        // DCA has no cues by itself, these come from the `chapters` in the `extra` metadata
+        &self.cues
     }
 
     fn metadata(&mut self) -> SymphMetadata<'_> {
@@ -267,23 +899,32 @@ impl FormatReader for DcaReader {
             SeekTo::TimeStamp { ts, .. } => ts,
         };
 
+        // the frames are indexed without trimming the encoder delay
+        let raw_ts = ts + self.delay;
+
         if let Some(max_ts) = self.max_ts {
-            if ts > max_ts {
+            if raw_ts > max_ts {
                 return symph_err::seek_error(SeekErrorKind::OutOfRange);
             }
         }
 
-        let backseek_needed = self.curr_ts > ts;
+        // the frame read ahead hasn't been returned yet
+        let curr_ts = self
+            .next_frame
+            .as_ref()
+            .map_or(self.curr_ts, |frame| frame.ts);
+        let backseek_needed = curr_ts > raw_ts;
 
         if backseek_needed && !can_backseek {
             return symph_err::seek_error(SeekErrorKind::ForwardOnly);
         }
 
-        let (accel_seek_ts, accel_seek_pos) = self.seek_accel.get_seek_pos(ts);
+        let (accel_seek_ts, accel_seek_pos) = self.seek_accel.get_seek_pos(raw_ts);
 
         if backseek_needed || accel_seek_pos > self.source.pos() {
             self.source.seek(SeekFrom::Start(accel_seek_pos))?;
             self.curr_ts = accel_seek_ts;
+            self.next_frame = None;
         }
 
         while let Ok(pkt) = self.next_packet() {
@@ -324,43 +965,224 @@ impl FormatReader for DcaReader {
             return Ok(pkt);
         }
 
-        let frame_pos = self.source.pos();
+        let mut out = match self.next_frame.take() {
+            Some(frame) => frame,
+            None => self.read_frame()?,
+        };
 
-        let p_len = match self.source.read_u16() {
-            Ok(len) => len as i16,
-            Err(eof) => {
-                self.max_ts = Some(self.curr_ts);
-                return Err(eof.into());
+        // the padding is at the end of the last frame, which without the length of the stream
+        // is known only when there's no frame after it
+        if self.padding > 0 && self.max_ts.is_none() {
+            match self.read_frame() {
+                Ok(frame) => self.next_frame = Some(frame),
+                // `read_frame` has set `max_ts` to the end of `out`
+                Err(SymphError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+                Err(err) => return Err(err),
             }
-        };
+        }
 
-        if p_len < 0 {
-            return symph_err::decode_error("DCA frame header had a negative length.");
+        if self.delay > 0 || self.padding > 0 {
+            self.trim_packet(&mut out);
         }
 
-        let buf = self.source.read_boxed_slice_exact(p_len as usize)?;
+        Ok(out)
+    }
 
-        let checked_buf = buf[..].try_into().or_else(|_| {
-            symph_err::decode_error("Packet was not a valid Opus Packet: too large for audiopus.")
-        })?;
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.source
+    }
+}
 
-        let sample_ct =
-            audiopus::packet::nb_samples(checked_buf, SampleRate::Hz48000).or_else(|_| {
-                symph_err::decode_error(
-                    "Packet was not a valid Opus packet: couldn't read sample count.",
-                )
-            })? as u64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::TempDir;
 
-        let out = Packet::new_from_boxed_slice(0, self.curr_ts, sample_ct, buf);
+    const FRAMES: usize = 3;
 
-        self.seek_accel.update(self.curr_ts, frame_pos);
+    /// Frames of silence, encoded as the body of a DCA file
+    fn body() -> Vec<u8> {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Mono, Application::Audio).unwrap();
+        let mut body = vec![];
+        let mut packet = [0; MAX_PACKET_SIZE];
+        for _ in 0..FRAMES {
+            let len = encoder
+                .encode_float(&[0.0; FRAME_SIZE], &mut packet)
+                .unwrap();
+            body.extend_from_slice(&(len as i16).to_le_bytes());
+            body.extend_from_slice(&packet[..len]);
+        }
+        body
+    }
 
-        self.curr_ts += sample_ct;
+    fn dca1(extra: serde_json::Value) -> Vec<u8> {
+        let metadata = serde_json::json!({
+            "dca": { "version": 1, "tool": { "name": "test", "version": "0" } },
+            "opus": {
+                "mode": "audio",
+                "sample_rate": 48000,
+                "frame_size": FRAME_SIZE,
+                "vbr": true,
+                "channels": 1
+            },
+            "extra": extra
+        });
+        let raw_json = serde_json::to_vec(&metadata).unwrap();
 
-        Ok(out)
+        let mut file = b"DCA1".to_vec();
+        file.extend_from_slice(&(raw_json.len() as u32).to_le_bytes());
+        file.extend_from_slice(&raw_json);
+        file.extend_from_slice(&body());
+        file
     }
 
-    fn into_inner(self: Box<Self>) -> MediaSourceStream {
-        self.source
+    /// Timestamps and durations of the packets read with gapless playback
+    fn read_packets(file: Vec<u8>) -> Vec<(TimeStamp, u64)> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(file)), Default::default());
+        let options = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let mut reader = DcaReader::try_new(source, &options).unwrap();
+        let mut packets = vec![];
+        while let Ok(packet) = reader.next_packet() {
+            packets.push((packet.ts, packet.dur));
+        }
+        packets
+    }
+
+    #[test]
+    fn dca0_is_not_trimmed() {
+        let packets = read_packets(body());
+        assert_eq!(packets, [(0, 960), (960, 960), (1920, 960)]);
+    }
+
+    #[test]
+    fn dca1_without_gapless_skips_the_default_delay() {
+        let packets = read_packets(dca1(serde_json::json!({})));
+        assert_eq!(packets, [(0, 648), (648, 960), (1608, 960)]);
+    }
+
+    #[test]
+    fn padding_is_trimmed_without_seek_index() {
+        let packets = read_packets(dca1(serde_json::json!({
+            "gapless": { "delay": DEFAULT_DELAY, "padding": 100 }
+        })));
+        assert_eq!(packets, [(0, 648), (648, 960), (1608, 860)]);
+    }
+
+    #[test]
+    fn padding_is_trimmed_with_seek_index() {
+        let seek_index = DcaSeekIndex::build(&mut body().as_slice()).unwrap();
+        let packets = read_packets(dca1(serde_json::json!({
+            "gapless": { "delay": DEFAULT_DELAY, "padding": 100 },
+            "seek_index": seek_index
+        })));
+        assert_eq!(packets, [(0, 648), (648, 960), (1608, 860)]);
+    }
+
+    /// 16-bit mono WAVE file at 48 kHz of a sine of `frames` frames
+    fn wav(frames: usize) -> Vec<u8> {
+        let data = (0..frames)
+            .flat_map(|i| (((i as f32 * 0.05).sin() * 8000.0) as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b"WAVEfmt ");
+        file.extend_from_slice(&16_u32.to_le_bytes());
+        // PCM, 1 channel, rate, byte rate, block align, bits per sample
+        file.extend_from_slice(&1_u16.to_le_bytes());
+        file.extend_from_slice(&1_u16.to_le_bytes());
+        file.extend_from_slice(&48000_u32.to_le_bytes());
+        file.extend_from_slice(&96000_u32.to_le_bytes());
+        file.extend_from_slice(&2_u16.to_le_bytes());
+        file.extend_from_slice(&16_u16.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    /// The metadata block of a DCA1 file and its size
+    fn read_metadata(file: &[u8]) -> (serde_json::Value, usize) {
+        assert_eq!(&file[..4], b"DCA1");
+        let size = u32::from_le_bytes(file[4..8].try_into().unwrap()) as usize;
+        (serde_json::from_slice(&file[8..8 + size]).unwrap(), size)
+    }
+
+    fn seek_index(metadata: &serde_json::Value) -> DcaSeekIndex {
+        serde_json::from_value(metadata["extra"]["seek_index"].clone()).unwrap()
+    }
+
+    #[test]
+    fn written_files_are_trimmed_to_the_track() {
+        // a bit more than 6 s, so that the seek index has two frames
+        let frames = 6 * 48000 + 100;
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("track.wav");
+        fs::write(&path, wav(frames)).unwrap();
+
+        let mut file = Cursor::new(vec![]);
+        write_dca(
+            &MusicTrack::new(path.to_str().unwrap()).unwrap(),
+            &DcaOptions::default(),
+            &mut file,
+        )
+        .unwrap();
+        let file = file.into_inner();
+
+        let (metadata, _) = read_metadata(&file);
+        assert_eq!(metadata["opus"]["channels"], 1);
+        assert_eq!(metadata["extra"]["gapless"]["delay"], DEFAULT_DELAY);
+        let seek_index = seek_index(&metadata);
+        assert!(seek_index.is_valid());
+        assert_eq!(seek_index.frames.len(), 2);
+        assert_eq!(seek_index.length % FRAME_SIZE as u64, 0);
+
+        let packets = read_packets(file);
+        assert_eq!(
+            packets.iter().map(|(_, dur)| dur).sum::<u64>(),
+            frames as u64
+        );
+    }
+
+    #[test]
+    fn seek_index_is_thinned_to_fit_its_room() {
+        let metadata = serde_json::from_value::<DcaMetadata>(serde_json::json!({
+            "dca": { "version": 1, "tool": { "name": "test", "version": "0" } },
+            "opus": {
+                "mode": "audio",
+                "sample_rate": 48000,
+                "frame_size": FRAME_SIZE,
+                "vbr": true,
+                "channels": 0
+            },
+            "extra": {}
+        }))
+        .unwrap();
+        // the room is left for a track of unknown length, but a minute is encoded
+        let mut file = Cursor::new(vec![]);
+        let mut encoder =
+            DcaEncoder::new(&mut file, 1, &DcaOptions::default(), metadata, 0.0).unwrap();
+        for _ in 0..60 {
+            encoder.encode(&[0.0; 48000]).unwrap();
+        }
+        encoder.finish().unwrap();
+        let file = file.into_inner();
+
+        let (metadata, size) = read_metadata(&file);
+        let seek_index = seek_index(&metadata);
+        assert!(seek_index.is_valid());
+        // the audio still starts right after the metadata block
+        let mut body = &file[8 + size..];
+        let whole = DcaSeekIndex::build(&mut body).unwrap();
+        assert_eq!(whole.length, seek_index.length);
+        assert!(seek_index.frames.len() < whole.frames.len());
+        assert!(seek_index
+            .frames
+            .iter()
+            .all(|frame| whole.frames.contains(frame)));
     }
 }
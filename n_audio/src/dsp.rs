@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Center frequencies (in Hz) of the bands of the graphic equalizer
+pub const GRAPHIC_BANDS: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Q of the bands of the graphic equalizer, so that neighbouring bands (an octave apart) overlap
+const GRAPHIC_Q: f32 = 1.41;
+
+/// Built-in presets of the graphic equalizer, as the gains (in dB) of `GRAPHIC_BANDS`
+pub const PRESETS: [(&str, [f32; 10]); 8] = [
+    ("Flat", [0.0; 10]),
+    (
+        "Bass boost",
+        [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        "Treble boost",
+        [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 4.0, 5.0, 6.0],
+    ),
+    ("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+    ("Pop", [-1.0, 1.0, 3.0, 4.0, 3.0, 0.0, -1.0, -1.0, 0.0, 1.0]),
+    ("Jazz", [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+    (
+        "Classical",
+        [4.0, 3.0, 2.0, 1.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0],
+    ),
+    (
+        "Vocal",
+        [-2.0, -2.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0],
+    ),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilterKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+/// A band of the parametric equalizer
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EqBand {
+    pub kind: FilterKind,
+    /// Center (or corner, for shelves) frequency in Hz
+    pub frequency: f32,
+    /// Gain in dB
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl EqBand {
+    pub fn peaking(frequency: f32, gain: f32, q: f32) -> Self {
+        Self {
+            kind: FilterKind::Peaking,
+            frequency,
+            gain,
+            q,
+        }
+    }
+}
+
+/// Processing applied to the decoded samples before they're sent to the output
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DspSettings {
+    pub bands: Vec<EqBand>,
+    /// Gain (in dB) applied before the equalizer
+    pub preamp: f32,
+    /// From -1.0 (left channel only) to 1.0 (right channel only), only for stereo tracks
+    pub balance: f32,
+    /// Downmixes all the channels to mono
+    pub mono: bool,
+    /// Swaps the left and the right channel, only for stereo tracks
+    pub swap_channels: bool,
+}
+
+impl DspSettings {
+    /// Returns the settings of a graphic equalizer with the given gains (in dB) for `GRAPHIC_BANDS`
+    pub fn graphic(gains: &[f32]) -> Self {
+        Self {
+            bands: GRAPHIC_BANDS
+                .iter()
+                .zip(gains)
+                .map(|(frequency, gain)| EqBand::peaking(*frequency, *gain, GRAPHIC_Q))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether these settings leave the samples untouched
+    pub fn is_neutral(&self) -> bool {
+        self.bands.iter().all(|band| band.gain == 0.0)
+            && self.preamp == 0.0
+            && self.balance == 0.0
+            && !self.mono
+            && !self.swap_channels
+    }
+}
+
+/// Biquad filter from the [Audio EQ Cookbook](https://www.w3.org/TR/audio-eq-cookbook/),
+/// in transposed direct form II
+#[derive(Clone, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    state: Vec<[f32; 2]>,
+}
+
+impl Biquad {
+    fn new(band: &EqBand, rate: u32, channels: usize) -> Self {
+        let a = 10.0_f32.powf(band.gain / 40.0);
+        // the filter is unstable at (or above) the Nyquist frequency
+        let frequency = band.frequency.min(rate as f32 * 0.49);
+        let (sin, cos) = (2.0 * PI * frequency / rate as f32).sin_cos();
+        let alpha = sin / (2.0 * band.q.max(0.01));
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            FilterKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            state: vec![[0.0; 2]; channels],
+        }
+    }
+
+    fn process(&mut self, sample: f32, channel: usize) -> f32 {
+        let [z1, z2] = &mut self.state[channel];
+        let out = self.b0 * sample + *z1;
+        *z1 = self.b1 * sample - self.a1 * out + *z2;
+        *z2 = self.b2 * sample - self.a2 * out;
+        out
+    }
+
+    fn reset(&mut self) {
+        self.state.iter_mut().for_each(|state| *state = [0.0; 2]);
+    }
+}
+
+/// Applies `DspSettings` to interleaved samples, keeping the state of the filters between calls
+#[derive(Debug)]
+pub(crate) struct Dsp {
+    settings: DspSettings,
+    filters: Vec<Biquad>,
+    rate: u32,
+    channels: usize,
+}
+
+impl Dsp {
+    pub fn new(settings: DspSettings) -> Self {
+        Self {
+            settings,
+            filters: vec![],
+            rate: 0,
+            channels: 0,
+        }
+    }
+
+    pub fn configure(&mut self, settings: DspSettings) {
+        self.settings = settings;
+        // the filters are built again on the next call to `Dsp::process`
+        self.rate = 0;
+    }
+
+    /// Clears the state of the filters, to be called when the samples aren't contiguous anymore
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().for_each(Biquad::reset);
+    }
+
+    pub fn process(&mut self, samples: &mut [f32], channels: usize, rate: u32) {
+        if self.settings.is_neutral() || channels == 0 {
+            return;
+        }
+        if self.rate != rate || self.channels != channels {
+            self.rate = rate;
+            self.channels = channels;
+            self.filters = self
+                .settings
+                .bands
+                .iter()
+                .filter(|band| band.gain != 0.0)
+                .map(|band| Biquad::new(band, rate, channels))
+                .collect();
+        }
+
+        let preamp = 10.0_f32.powf(self.settings.preamp / 20.0);
+        let balance = self.settings.balance.clamp(-1.0, 1.0);
+        let (left, right) = ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0));
+
+        for frame in samples.chunks_exact_mut(channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self
+                    .filters
+                    .iter_mut()
+                    .fold(*sample * preamp, |sample, filter| {
+                        filter.process(sample, channel)
+                    });
+            }
+
+            if self.settings.mono && channels > 1 {
+                let mono = frame.iter().sum::<f32>() / channels as f32;
+                frame.iter_mut().for_each(|sample| *sample = mono);
+            }
+            if channels == 2 {
+                if self.settings.swap_channels {
+                    frame.swap(0, 1);
+                }
+                frame[0] *= left;
+                frame[1] *= right;
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::effect::{Effect, Parameter};
+use std::collections::VecDeque;
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+fn time_coefficient(ms: f32, rate: u32) -> f32 {
+    (-1.0 / (ms / 1000.0 * rate as f32)).exp()
+}
+
+/// Soft-knee compressor with a linked detector for all the channels
+#[derive(Clone, Debug)]
+pub struct Compressor {
+    /// Threshold in dB
+    threshold: f32,
+    ratio: f32,
+    /// Width of the knee in dB
+    knee: f32,
+    /// Attack time in ms
+    attack: f32,
+    /// Release time in ms
+    release: f32,
+    /// Gain applied after the compression in dB
+    makeup: f32,
+    rate: u32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    /// Current gain reduction in dB
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(
+        threshold: f32,
+        ratio: f32,
+        knee: f32,
+        attack: f32,
+        release: f32,
+        makeup: f32,
+    ) -> Self {
+        let mut compressor = Self {
+            threshold: threshold.clamp(-60.0, 0.0),
+            ratio: ratio.clamp(1.0, 20.0),
+            knee: knee.clamp(0.0, 24.0),
+            attack: attack.clamp(0.1, 200.0),
+            release: release.clamp(10.0, 2000.0),
+            makeup: makeup.clamp(0.0, 24.0),
+            rate: 44100,
+            attack_coefficient: 0.0,
+            release_coefficient: 0.0,
+            envelope: 0.0,
+        };
+        compressor.update_coefficients();
+        compressor
+    }
+
+    /// Strong compression that brings up quiet passages and tames loud ones, for listening at
+    /// low volume
+    pub fn night() -> Self {
+        Self::new(-30.0, 4.0, 12.0, 5.0, 250.0, 10.0)
+    }
+
+    fn update_coefficients(&mut self) {
+        self.attack_coefficient = time_coefficient(self.attack, self.rate);
+        self.release_coefficient = time_coefficient(self.release, self.rate);
+    }
+
+    /// Returns the gain reduction (in dB, negative) for a level (in dB)
+    fn gain_reduction(&self, level: f32) -> f32 {
+        let over = level - self.threshold;
+        let slope = 1.0 / self.ratio - 1.0;
+        if 2.0 * over < -self.knee {
+            0.0
+        } else if 2.0 * over.abs() <= self.knee {
+            slope * (over + self.knee / 2.0).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        }
+    }
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Self::new(-18.0, 3.0, 6.0, 10.0, 150.0, 0.0)
+    }
+}
+
+impl Effect for Compressor {
+    fn name(&self) -> &str {
+        "Compressor"
+    }
+
+    fn configure(&mut self, rate: u32, _channels: usize) {
+        self.rate = rate;
+        self.update_coefficients();
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        let makeup = self.makeup;
+        for frame in samples.chunks_exact_mut(channels) {
+            let peak = frame
+                .iter()
+                .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+            let level = 20.0 * peak.max(1e-9).log10();
+            let reduction = self.gain_reduction(level);
+            let coefficient = if reduction < self.envelope {
+                self.attack_coefficient
+            } else {
+                self.release_coefficient
+            };
+            self.envelope = coefficient * self.envelope + (1.0 - coefficient) * reduction;
+
+            let gain = db_to_gain(self.envelope + makeup);
+            frame.iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::new("threshold", self.threshold, -60.0, 0.0),
+            Parameter::new("ratio", self.ratio, 1.0, 20.0),
+            Parameter::new("knee", self.knee, 0.0, 24.0),
+            Parameter::new("attack", self.attack, 0.1, 200.0),
+            Parameter::new("release", self.release, 10.0, 2000.0),
+            Parameter::new("makeup", self.makeup, 0.0, 24.0),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.threshold = value.clamp(-60.0, 0.0),
+            "ratio" => self.ratio = value.clamp(1.0, 20.0),
+            "knee" => self.knee = value.clamp(0.0, 24.0),
+            "attack" => self.attack = value.clamp(0.1, 200.0),
+            "release" => self.release = value.clamp(10.0, 2000.0),
+            "makeup" => self.makeup = value.clamp(0.0, 24.0),
+            _ => {}
+        }
+        self.update_coefficients();
+    }
+}
+
+/// Look-ahead peak limiter, so that the samples never go over the ceiling
+///
+/// The gain starts going down before a peak reaches the output, instead of clipping it
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    /// Ceiling in dB
+    ceiling: f32,
+    /// Look-ahead time in ms
+    lookahead: f32,
+    /// Release time in ms
+    release: f32,
+    rate: u32,
+    channels: usize,
+    window: usize,
+    release_coefficient: f32,
+    /// Gain needed by the last frames, after the release
+    envelope: f32,
+    frame: u64,
+    /// Minimum of the envelope over the window, as (frame, gain) pairs with increasing gains
+    minimum: VecDeque<(u64, f32)>,
+    /// Last values of the minimum, averaged to smooth the gain changes
+    smoothing: Vec<f32>,
+    smoothing_sum: f64,
+    /// Delay line of the samples, as long as the look-ahead
+    delay: Vec<f32>,
+    position: usize,
+}
+
+impl Limiter {
+    pub fn new(ceiling: f32, lookahead: f32, release: f32) -> Self {
+        Self {
+            ceiling: ceiling.clamp(-24.0, 0.0),
+            lookahead: lookahead.clamp(0.1, 20.0),
+            release: release.clamp(1.0, 1000.0),
+            rate: 0,
+            channels: 0,
+            window: 0,
+            release_coefficient: 0.0,
+            envelope: 1.0,
+            frame: 0,
+            minimum: VecDeque::new(),
+            smoothing: vec![],
+            smoothing_sum: 0.0,
+            delay: vec![],
+            position: 0,
+        }
+    }
+
+    /// Keeps the peaks under 0 dBFS, to be used after anything that may raise the level
+    pub fn safety() -> Self {
+        Self::new(-0.1, 1.5, 60.0)
+    }
+
+    fn configure_window(&mut self) {
+        self.window = ((self.lookahead / 1000.0 * self.rate as f32) as usize).max(1);
+        self.release_coefficient = time_coefficient(self.release, self.rate);
+        self.reset();
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(-1.0, 5.0, 100.0)
+    }
+}
+
+impl Effect for Limiter {
+    fn name(&self) -> &str {
+        "Limiter"
+    }
+
+    fn configure(&mut self, rate: u32, channels: usize) {
+        self.rate = rate;
+        self.channels = channels;
+        self.configure_window();
+    }
+
+    fn process(&mut self, samples: &mut [f32], channels: usize) {
+        if channels != self.channels || self.window == 0 {
+            return;
+        }
+
+        let ceiling = db_to_gain(self.ceiling);
+        let window = self.window;
+        for frame in samples.chunks_exact_mut(channels) {
+            let peak = frame
+                .iter()
+                .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+            let required = if peak > ceiling { ceiling / peak } else { 1.0 };
+            // the gain drops immediately and recovers slowly
+            self.envelope = if required < self.envelope {
+                required
+            } else {
+                required + (self.envelope - required) * self.release_coefficient
+            };
+
+            while self
+                .minimum
+                .back()
+                .is_some_and(|(_, gain)| *gain >= self.envelope)
+            {
+                self.minimum.pop_back();
+            }
+            self.minimum.push_back((self.frame, self.envelope));
+            while self
+                .minimum
+                .front()
+                .is_some_and(|(frame, _)| frame + window as u64 <= self.frame)
+            {
+                self.minimum.pop_front();
+            }
+            let minimum = self.minimum.front().map_or(1.0, |(_, gain)| *gain);
+
+            let slot = (self.frame % window as u64) as usize;
+            self.smoothing_sum += minimum as f64 - self.smoothing[slot] as f64;
+            self.smoothing[slot] = minimum;
+            let gain = (self.smoothing_sum / window as f64) as f32;
+            self.frame += 1;
+
+            // the window is long enough for the gain to be low when the peak comes out
+            let delayed = &mut self.delay[self.position * channels..(self.position + 1) * channels];
+            for (sample, delayed) in frame.iter_mut().zip(delayed.iter_mut()) {
+                let input = *sample;
+                *sample = (*delayed * gain).clamp(-ceiling, ceiling);
+                *delayed = input;
+            }
+            self.position = (self.position + 1) % (window - 1).max(1);
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.window.saturating_sub(1).max(1)
+    }
+
+    fn reset(&mut self) {
+        self.envelope = 1.0;
+        self.frame = 0;
+        self.minimum.clear();
+        self.smoothing = vec![1.0; self.window];
+        self.smoothing_sum = self.window as f64;
+        self.delay = vec![0.0; self.window.saturating_sub(1).max(1) * self.channels];
+        self.position = 0;
+    }
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![
+            Parameter::new("ceiling", self.ceiling, -24.0, 0.0),
+            Parameter::new("lookahead", self.lookahead, 0.1, 20.0),
+            Parameter::new("release", self.release, 1.0, 1000.0),
+        ]
+    }
+
+    fn set_parameter(&mut self, name: &str, value: f32) {
+        match name {
+            "ceiling" => self.ceiling = value.clamp(-24.0, 0.0),
+            "lookahead" => self.lookahead = value.clamp(0.1, 20.0),
+            "release" => self.release = value.clamp(1.0, 1000.0),
+            _ => return,
+        }
+        if self.rate != 0 {
+            self.configure_window();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A parameter of an [`Effect`] that can be changed while playing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Parameter {
+    pub fn new<S: Into<String>>(name: S, value: f32, min: f32, max: f32) -> Self {
+        Self {
+            name: name.into(),
+            value,
+            min,
+            max,
+        }
+    }
+}
+
+/// A stage that processes the decoded samples before they're sent to the output
+///
+/// The samples are the frames of the decoded `AudioBufferRef`, converted to interleaved `f32`
+pub trait Effect: Send {
+    fn name(&self) -> &str;
+
+    /// Called before the first call to `Effect::process` and every time the rate or the number of
+    /// channels of the samples changes
+    fn configure(&mut self, rate: u32, channels: usize);
+
+    /// Processes the interleaved samples in place
+    fn process(&mut self, samples: &mut [f32], channels: usize);
+
+    /// Returns how many frames the output of this effect is delayed by
+    fn latency(&self) -> usize {
+        0
+    }
+
+    /// Clears the internal state, called when the next samples aren't contiguous with the previous
+    /// ones (e.g. after seeking)
+    fn reset(&mut self) {}
+
+    fn parameters(&self) -> Vec<Parameter> {
+        vec![]
+    }
+
+    /// Sets the parameter with the given name, values outside its range should be clamped
+    fn set_parameter(&mut self, _name: &str, _value: f32) {}
+}
+
+#[derive(Default)]
+struct Chain {
+    effects: Vec<Box<dyn Effect>>,
+    rate: u32,
+    channels: usize,
+}
+
+/// An ordered list of [`Effect`]s, shared between the `Player` and its track thread
+///
+/// Cloning it returns a handle to the same chain, so effects can be added, removed and tweaked
+/// while playing
+#[derive(Clone, Default)]
+pub struct EffectChain(Arc<Mutex<Chain>>);
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Chain> {
+        // an effect that panicked doesn't leave the chain itself in an invalid state
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Adds an effect at the end of the chain
+    pub fn push(&self, effect: Box<dyn Effect>) {
+        self.insert(usize::MAX, effect);
+    }
+
+    /// Inserts an effect at the given position of the chain, or at the end if it's out of bounds
+    pub fn insert(&self, index: usize, mut effect: Box<dyn Effect>) {
+        let mut chain = self.lock();
+        if chain.rate != 0 {
+            effect.configure(chain.rate, chain.channels);
+        }
+        let index = index.min(chain.effects.len());
+        chain.effects.insert(index, effect);
+    }
+
+    pub fn remove(&self, index: usize) -> Option<Box<dyn Effect>> {
+        let mut chain = self.lock();
+        (index < chain.effects.len()).then(|| chain.effects.remove(index))
+    }
+
+    pub fn clear(&self) {
+        self.lock().effects.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the names of the effects, in order
+    pub fn names(&self) -> Vec<String> {
+        self.lock()
+            .effects
+            .iter()
+            .map(|effect| effect.name().to_string())
+            .collect()
+    }
+
+    pub fn parameters(&self, index: usize) -> Vec<Parameter> {
+        self.lock()
+            .effects
+            .get(index)
+            .map(|effect| effect.parameters())
+            .unwrap_or_default()
+    }
+
+    pub fn set_parameter(&self, index: usize, name: &str, value: f32) {
+        if let Some(effect) = self.lock().effects.get_mut(index) {
+            effect.set_parameter(name, value);
+        }
+    }
+
+    /// Returns the total latency of the chain, in frames
+    pub fn latency(&self) -> usize {
+        self.lock()
+            .effects
+            .iter()
+            .map(|effect| effect.latency())
+            .sum()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.lock()
+            .effects
+            .iter_mut()
+            .for_each(|effect| effect.reset());
+    }
+
+    pub(crate) fn process(&self, samples: &mut [f32], channels: usize, rate: u32) {
+        let mut chain = self.lock();
+        if chain.effects.is_empty() {
+            return;
+        }
+        if chain.rate != rate || chain.channels != channels {
+            chain.rate = rate;
+            chain.channels = channels;
+            chain
+                .effects
+                .iter_mut()
+                .for_each(|effect| effect.configure(rate, channels));
+        }
+        for effect in chain.effects.iter_mut() {
+            effect.process(samples, channels);
+        }
+    }
+}
+
+impl Debug for EffectChain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("EffectChain").field(&self.names()).finish()
+    }
+}
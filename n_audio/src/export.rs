@@ -0,0 +1,589 @@
+use crate::dca::DEFAULT_DELAY;
+use crate::flac::{picture, vorbis_comments, FlacEncoder};
+use crate::music_track::MusicTrack;
+use crate::ogg::OggWriter;
+use crate::resample::Resampler;
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use symphonia_core::meta::StandardTagKey;
+
+/// Samples of every channel in an Opus packet (20 ms)
+const OPUS_FRAME_SIZE: usize = 960;
+/// Largest Opus packet, as recommended by libopus
+const MAX_PACKET_SIZE: usize = 4000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Opus in an Ogg container, with the bitrate in bits per second
+    Opus(u32),
+    Wav,
+    Flac,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Opus(_) => "opus",
+            ExportFormat::Wav => "wav",
+            ExportFormat::Flac => "flac",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Rate of the exported file, the rate of the track if `None`; Opus is always 48 kHz
+    pub rate: Option<u32>,
+    /// Applies the ReplayGain of the track (if it has one) to the samples
+    pub replay_gain: bool,
+}
+
+pub(crate) fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Appends `frame` to `output` with at most two channels: the channels after the front pair go
+/// to both sides, at half their level
+pub(crate) fn downmix(frame: &[f32], output: &mut Vec<f32>) {
+    if frame.len() <= 2 {
+        output.extend_from_slice(frame);
+        return;
+    }
+    let rest = frame[2..].iter().sum::<f32>() * 0.5;
+    let scale = 1.0 / (1.0 + 0.5 * (frame.len() - 2) as f32);
+    output.push((frame[0] + rest) * scale);
+    output.push((frame[1] + rest) * scale);
+}
+
+/// Name of the Vorbis comment for a tag
+fn comment_name(key: StandardTagKey) -> Option<&'static str> {
+    Some(match key {
+        StandardTagKey::TrackTitle => "TITLE",
+        StandardTagKey::Artist => "ARTIST",
+        StandardTagKey::Album => "ALBUM",
+        StandardTagKey::AlbumArtist => "ALBUMARTIST",
+        StandardTagKey::Genre => "GENRE",
+        StandardTagKey::Date => "DATE",
+        StandardTagKey::TrackNumber => "TRACKNUMBER",
+        StandardTagKey::TrackTotal => "TRACKTOTAL",
+        StandardTagKey::DiscNumber => "DISCNUMBER",
+        StandardTagKey::DiscTotal => "DISCTOTAL",
+        StandardTagKey::Composer => "COMPOSER",
+        StandardTagKey::Comment => "COMMENT",
+        StandardTagKey::Lyrics => "LYRICS",
+        StandardTagKey::ReplayGainTrackGain => "REPLAYGAIN_TRACK_GAIN",
+        StandardTagKey::ReplayGainTrackPeak => "REPLAYGAIN_TRACK_PEAK",
+        StandardTagKey::ReplayGainAlbumGain => "REPLAYGAIN_ALBUM_GAIN",
+        StandardTagKey::ReplayGainAlbumPeak => "REPLAYGAIN_ALBUM_PEAK",
+        _ => return None,
+    })
+}
+
+/// Reads the number at the start of a tag, like `-6.5 dB`
+fn parse_number(value: &str) -> Option<f32> {
+    value
+        .split_whitespace()
+        .next()
+        .and_then(|number| number.trim_end_matches("dB").parse().ok())
+}
+
+/// What is copied from the track to the exported file
+struct TrackInfo {
+    /// Vorbis comments, as (name, value) pairs
+    comments: Vec<(&'static str, String)>,
+    /// Media type and content of the cover
+    cover: Option<(String, Box<[u8]>)>,
+    /// Linear gain to apply to the samples
+    gain: f32,
+    bits_per_sample: Option<u32>,
+}
+
+impl TrackInfo {
+    fn read(track: &MusicTrack, replay_gain: bool) -> io::Result<Self> {
+        let mut format = track.get_format()?;
+        let mut info = Self {
+            comments: vec![],
+            cover: None,
+            gain: 1.0,
+            bits_per_sample: format
+                .default_track()
+                .and_then(|track| track.codec_params.bits_per_sample),
+        };
+
+        let mut track_gain = None;
+        let mut track_peak = None;
+        if let Some(metadata) = format.metadata().skip_to_latest() {
+            for tag in metadata.tags() {
+                let Some(key) = tag.std_key else {
+                    continue;
+                };
+                let value = tag.value.to_string();
+                let is_replay_gain = match key {
+                    StandardTagKey::ReplayGainTrackGain => {
+                        track_gain = parse_number(&value);
+                        true
+                    }
+                    StandardTagKey::ReplayGainTrackPeak => {
+                        track_peak = parse_number(&value);
+                        true
+                    }
+                    StandardTagKey::ReplayGainAlbumGain | StandardTagKey::ReplayGainAlbumPeak => {
+                        true
+                    }
+                    _ => false,
+                };
+                // the gain is part of the samples once applied
+                if is_replay_gain && replay_gain {
+                    continue;
+                }
+                if let Some(name) = comment_name(key) {
+                    info.comments.push((name, value));
+                }
+            }
+            info.cover = metadata
+                .visuals()
+                .first()
+                .map(|visual| (visual.media_type.clone(), visual.data.clone()));
+        }
+
+        // the title and the artist may also come from tags Symphonia doesn't read
+        let meta = track.get_meta()?;
+        if !info.comments.iter().any(|(name, _)| *name == "TITLE") {
+            info.comments.push(("TITLE", meta.title));
+        }
+        if !info.comments.iter().any(|(name, _)| *name == "ARTIST") && !meta.artist.is_empty() {
+            info.comments.push(("ARTIST", meta.artist));
+        }
+
+        if let (true, Some(gain)) = (replay_gain, track_gain) {
+            info.gain = 10.0_f32.powf(gain / 20.0);
+            // the peaks mustn't clip
+            if let Some(peak) = track_peak.filter(|peak| *peak > 0.0) {
+                info.gain = info.gain.min(1.0 / peak);
+            }
+        }
+
+        Ok(info)
+    }
+
+    fn vorbis_comments(&self) -> Vec<String> {
+        self.comments
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect()
+    }
+
+    fn picture(&self) -> Option<Vec<u8>> {
+        self.cover
+            .as_ref()
+            .map(|(media_type, data)| picture(media_type, data))
+    }
+}
+
+/// Opus encoder writing an Ogg Opus file (RFC 7845)
+struct OpusEncoder {
+    encoder: Encoder,
+    ogg: OggWriter<BufWriter<File>>,
+    channels: usize,
+    /// Interleaved samples of the next packet
+    pending: Vec<f32>,
+    /// Frames given to the encoder, without the delay and the padding
+    frames: u64,
+    /// Granule position of the last packet
+    granule: u64,
+    packet: Vec<u8>,
+}
+
+impl OpusEncoder {
+    fn new(
+        file: File,
+        channels: usize,
+        input_rate: u32,
+        bitrate: u32,
+        info: &TrackInfo,
+    ) -> io::Result<Self> {
+        let mut encoder = Encoder::new(
+            SampleRate::Hz48000,
+            if channels == 1 {
+                Channels::Mono
+            } else {
+                Channels::Stereo
+            },
+            Application::Audio,
+        )
+        .map_err(io::Error::other)?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(bitrate as i32))
+            .map_err(io::Error::other)?;
+
+        let mut ogg = OggWriter::new(BufWriter::new(file), rand::random());
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&(DEFAULT_DELAY as u16).to_le_bytes());
+        head.extend_from_slice(&input_rate.to_le_bytes());
+        // no output gain and mapping family 0
+        head.extend_from_slice(&[0, 0, 0]);
+        ogg.write_packet(&head, 0, true)?;
+
+        let mut comments = info.vorbis_comments();
+        if let Some(picture) = info.picture() {
+            comments.push(format!("METADATA_BLOCK_PICTURE={}", base64(&picture)));
+        }
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&vorbis_comments(&comments));
+        ogg.write_packet(&tags, 0, true)?;
+
+        Ok(Self {
+            encoder,
+            ogg,
+            channels,
+            pending: vec![],
+            frames: 0,
+            granule: 0,
+            packet: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.frames += (samples.len() / self.channels) as u64;
+        self.pending.extend_from_slice(samples);
+        self.encode_pending()
+    }
+
+    fn encode_pending(&mut self) -> io::Result<()> {
+        let packet_len = OPUS_FRAME_SIZE * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= packet_len {
+            let len = self
+                .encoder
+                .encode_float(&self.pending[start..start + packet_len], &mut self.packet)
+                .map_err(io::Error::other)?;
+            // the last packet ends with the audio, so that the padding is trimmed
+            self.granule =
+                (self.granule + OPUS_FRAME_SIZE as u64).min(DEFAULT_DELAY as u64 + self.frames);
+            self.ogg
+                .write_packet(&self.packet[..len], self.granule, false)?;
+            start += packet_len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        // the encoder delay is flushed with silence, then the last packet is filled
+        let frames = self.pending.len() / self.channels + DEFAULT_DELAY as usize;
+        self.pending.resize(
+            frames.div_ceil(OPUS_FRAME_SIZE) * OPUS_FRAME_SIZE * self.channels,
+            0.0,
+        );
+        self.encode_pending()?;
+        self.ogg.finish()?;
+        Ok(())
+    }
+}
+
+/// Name of the RIFF INFO chunk for a Vorbis comment
+fn info_chunk(name: &str) -> Option<&'static [u8; 4]> {
+    Some(match name {
+        "TITLE" => b"INAM",
+        "ARTIST" => b"IART",
+        "ALBUM" => b"IPRD",
+        "GENRE" => b"IGNR",
+        "DATE" => b"ICRD",
+        "TRACKNUMBER" => b"ITRK",
+        "COMMENT" => b"ICMT",
+        _ => return None,
+    })
+}
+
+/// Integer PCM WAVE writer, with the tags in a LIST INFO chunk (WAVE has no standard cover)
+struct WavEncoder {
+    writer: BufWriter<File>,
+    bits_per_sample: u32,
+    /// Position of the size of the data chunk
+    data_size_position: u64,
+    data_size: u64,
+    bytes: Vec<u8>,
+}
+
+impl WavEncoder {
+    fn new(
+        file: File,
+        rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        info: &TrackInfo,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(file);
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&[0; 4])?;
+        writer.write_all(b"WAVE")?;
+
+        let block_align = channels as u16 * (bits_per_sample / 8) as u16;
+        let mut fmt = vec![];
+        // WAVE_FORMAT_EXTENSIBLE for more than two channels
+        let extensible = channels > 2;
+        fmt.extend_from_slice(&(if extensible { 0xfffe_u16 } else { 1 }).to_le_bytes());
+        fmt.extend_from_slice(&(channels as u16).to_le_bytes());
+        fmt.extend_from_slice(&rate.to_le_bytes());
+        fmt.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&(bits_per_sample as u16).to_le_bytes());
+        if extensible {
+            fmt.extend_from_slice(&22_u16.to_le_bytes());
+            fmt.extend_from_slice(&(bits_per_sample as u16).to_le_bytes());
+            // the channels are in the standard order
+            fmt.extend_from_slice(&((1_u32 << channels) - 1).to_le_bytes());
+            // KSDATAFORMAT_SUBTYPE_PCM
+            fmt.extend_from_slice(&[
+                0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38,
+                0x9b, 0x71,
+            ]);
+        }
+        write_chunk(&mut writer, b"fmt ", &fmt)?;
+
+        let mut list = b"INFO".to_vec();
+        for (name, value) in &info.comments {
+            if let Some(id) = info_chunk(name) {
+                let mut text = value.as_bytes().to_vec();
+                text.push(0);
+                list.extend_from_slice(id);
+                list.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                list.extend_from_slice(&text);
+                if text.len() % 2 == 1 {
+                    list.push(0);
+                }
+            }
+        }
+        if list.len() > 4 {
+            write_chunk(&mut writer, b"LIST", &list)?;
+        }
+
+        writer.write_all(b"data")?;
+        let data_size_position = writer.stream_position()?;
+        writer.write_all(&[0; 4])?;
+
+        Ok(Self {
+            writer,
+            bits_per_sample,
+            data_size_position,
+            data_size: 0,
+            bytes: vec![],
+        })
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> io::Result<()> {
+        let max = ((1 << (self.bits_per_sample - 1)) - 1) as f32;
+        let size = self.bits_per_sample as usize / 8;
+        self.bytes.clear();
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * max).round() as i32;
+            self.bytes.extend_from_slice(&sample.to_le_bytes()[..size]);
+        }
+        self.data_size += self.bytes.len() as u64;
+        self.writer.write_all(&self.bytes)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let riff_size = self.writer.stream_position()? - 8;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(riff_size as u32).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(self.data_size_position))?;
+        self.writer
+            .write_all(&(self.data_size as u32).to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(id)?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    if data.len() % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+enum ExportEncoder {
+    Opus(OpusEncoder),
+    Wav(WavEncoder),
+    Flac(FlacEncoder<BufWriter<File>>),
+}
+
+impl ExportEncoder {
+    fn new(
+        format: ExportFormat,
+        path: &Path,
+        channels: usize,
+        input_rate: u32,
+        rate: u32,
+        info: &TrackInfo,
+    ) -> io::Result<Self> {
+        let file = File::create(path)?;
+        // lossy tracks have no bit depth, and 16 bits are enough for them
+        let bits_per_sample = if info.bits_per_sample.is_some_and(|bits| bits > 16) {
+            24
+        } else {
+            16
+        };
+        Ok(match format {
+            ExportFormat::Opus(bitrate) => {
+                Self::Opus(OpusEncoder::new(file, channels, input_rate, bitrate, info)?)
+            }
+            ExportFormat::Wav => Self::Wav(WavEncoder::new(
+                file,
+                rate,
+                channels,
+                bits_per_sample,
+                info,
+            )?),
+            ExportFormat::Flac => Self::Flac(FlacEncoder::new(
+                BufWriter::new(file),
+                rate,
+                channels,
+                bits_per_sample,
+                &info.vorbis_comments(),
+                info.picture().as_deref(),
+            )?),
+        })
+    }
+
+    fn encode(&mut self, samples: &[f32]) -> io::Result<()> {
+        match self {
+            ExportEncoder::Opus(encoder) => encoder.encode(samples),
+            ExportEncoder::Wav(encoder) => encoder.encode(samples),
+            ExportEncoder::Flac(encoder) => encoder.encode(samples),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ExportEncoder::Opus(encoder) => encoder.finish(),
+            ExportEncoder::Wav(encoder) => encoder.finish(),
+            ExportEncoder::Flac(encoder) => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+/// Decodes `track` and encodes it to `path`, copying its tags and its cover
+///
+/// Opus files have at most two channels. The file is removed if the export fails.
+pub fn export<P: AsRef<Path>>(
+    track: &MusicTrack,
+    options: &ExportOptions,
+    path: P,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let result = export_to(track, options, path);
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn export_to(track: &MusicTrack, options: &ExportOptions, path: &Path) -> io::Result<()> {
+    let info = TrackInfo::read(track, options.replay_gain)?;
+
+    let mut encoder: Option<ExportEncoder> = None;
+    let mut resampler: Option<Resampler> = None;
+    let mut result = Ok(());
+    let mut frames = vec![];
+    let mut resampled = vec![];
+    track.decode(|samples, channels, rate| {
+        if result.is_err() {
+            return;
+        }
+
+        let opus = matches!(options.format, ExportFormat::Opus(_));
+        if encoder.is_none() {
+            let output_channels = if opus { channels.min(2) } else { channels };
+            let output_rate = if opus {
+                48000
+            } else {
+                options.rate.unwrap_or(rate)
+            };
+            if output_rate != rate {
+                resampler = Some(Resampler::new(rate, output_rate, output_channels));
+            }
+            match ExportEncoder::new(
+                options.format,
+                path,
+                output_channels,
+                rate,
+                output_rate,
+                &info,
+            ) {
+                Ok(new_encoder) => encoder = Some(new_encoder),
+                Err(e) => {
+                    result = Err(e);
+                    return;
+                }
+            }
+        }
+
+        frames.clear();
+        if opus {
+            for frame in samples.chunks_exact(channels) {
+                downmix(frame, &mut frames);
+            }
+        } else {
+            frames.extend_from_slice(samples);
+        }
+        if info.gain != 1.0 {
+            frames.iter_mut().for_each(|sample| *sample *= info.gain);
+        }
+
+        let samples = match &mut resampler {
+            Some(resampler) => {
+                resampled.clear();
+                resampler.process(&frames, &mut resampled);
+                &resampled
+            }
+            None => &frames,
+        };
+        if let Some(encoder) = &mut encoder {
+            result = encoder.encode(samples);
+        }
+    })?;
+    result?;
+
+    let Some(mut encoder) = encoder else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the track has no audio to export",
+        ));
+    };
+    if let Some(resampler) = &mut resampler {
+        resampled.clear();
+        resampler.flush(&mut resampled);
+        encoder.encode(&resampled)?;
+    }
+    encoder.finish()
+}
@@ -0,0 +1,129 @@
+use std::f32::consts::PI;
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl AddAssign for Complex {
+    fn add_assign(&mut self, rhs: Self) {
+        self.re += rhs.re;
+        self.im += rhs.im;
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Mul<f32> for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+/// Iterative radix-2 FFT of a fixed size
+#[derive(Clone, Debug)]
+pub(crate) struct Fft {
+    size: usize,
+    twiddles: Vec<Complex>,
+    reversed: Vec<usize>,
+}
+
+impl Fft {
+    /// `size` must be a power of two
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two(), "FFT size must be a power of two");
+        let bits = size.trailing_zeros();
+        Self {
+            size,
+            twiddles: (0..size / 2)
+                .map(|k| {
+                    let (sin, cos) = (-2.0 * PI * k as f32 / size as f32).sin_cos();
+                    Complex::new(cos, sin)
+                })
+                .collect(),
+            reversed: (0..size)
+                .map(|i| i.reverse_bits() >> ((usize::BITS - bits) % usize::BITS))
+                .collect(),
+        }
+    }
+
+    pub fn forward(&self, buf: &mut [Complex]) {
+        self.transform(buf, false);
+    }
+
+    /// Inverse transform, already scaled by `1 / size`
+    pub fn inverse(&self, buf: &mut [Complex]) {
+        self.transform(buf, true);
+        let scale = 1.0 / self.size as f32;
+        buf.iter_mut().for_each(|value| *value = *value * scale);
+    }
+
+    fn transform(&self, buf: &mut [Complex], inverse: bool) {
+        assert_eq!(buf.len(), self.size);
+        for (i, j) in self.reversed.iter().enumerate() {
+            if i < *j {
+                buf.swap(i, *j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let half = len / 2;
+            let step = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let twiddle = self.twiddles[k * step];
+                    let twiddle = if inverse { twiddle.conj() } else { twiddle };
+                    let a = buf[start + k];
+                    let b = buf[start + k + half] * twiddle;
+                    buf[start + k] = a + b;
+                    buf[start + k + half] = a - b;
+                }
+            }
+            len *= 2;
+        }
+    }
+}
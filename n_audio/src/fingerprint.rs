@@ -0,0 +1,361 @@
+use crate::export::base64;
+use crate::fft::{Complex, Fft};
+use std::f32::consts::PI;
+
+/// Rate the tracks are downmixed and resampled to before being fingerprinted
+pub(crate) const FINGERPRINT_RATE: u32 = 11025;
+/// Length of audio that is fingerprinted from the start of a track, in seconds
+pub(crate) const FINGERPRINT_LENGTH: u32 = 120;
+const FRAME: usize = 4096;
+/// Consecutive frames overlap by two thirds
+const HOP: usize = FRAME / 3;
+/// Range of the frequencies used for the chroma
+const CHROMA_RANGE: (f32, f32) = (28.0, 3520.0);
+/// Weights of the consecutive chroma vectors that are blurred together
+const CHROMA_FILTER: [f32; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+/// Chroma vectors with a lower norm are considered silent
+const NORM_THRESHOLD: f32 = 0.01;
+/// Identifier of the algorithm in the compressed fingerprints
+const ALGORITHM: u8 = 1;
+/// Subfingerprints (of about 0.124 s each) the fingerprints are compared on
+const COMPARE_LENGTH: usize = 240;
+/// Fewest subfingerprints that have to overlap for two fingerprints to be compared
+const MIN_OVERLAP: usize = 40;
+
+/// Shape of the area of the chroma image compared by a classifier
+#[derive(Copy, Clone)]
+enum Filter {
+    Whole,
+    HalvesBands,
+    HalvesTime,
+    Quadrants,
+    ThirdsBands,
+    ThirdsTime,
+}
+
+/// Shape, first chroma band, bands and frames of the areas compared by the 16 classifiers of the
+/// default Chromaprint algorithm, trained by its authors
+///
+/// Each of them gives a pair of bits of the subfingerprints
+const CLASSIFIERS: [(Filter, usize, usize, usize); 16] = [
+    (Filter::Whole, 4, 3, 15),
+    (Filter::ThirdsBands, 4, 6, 15),
+    (Filter::HalvesBands, 0, 4, 16),
+    (Filter::Quadrants, 8, 2, 12),
+    (Filter::Quadrants, 4, 4, 8),
+    (Filter::ThirdsBands, 0, 3, 5),
+    (Filter::HalvesBands, 2, 2, 9),
+    (Filter::HalvesTime, 7, 3, 4),
+    (Filter::HalvesTime, 6, 2, 16),
+    (Filter::HalvesTime, 1, 3, 2),
+    (Filter::ThirdsTime, 10, 1, 15),
+    (Filter::Quadrants, 6, 2, 10),
+    (Filter::HalvesTime, 1, 1, 14),
+    (Filter::Quadrants, 5, 6, 4),
+    (Filter::HalvesBands, 9, 2, 12),
+    (Filter::Quadrants, 4, 2, 14),
+];
+/// Thresholds the comparisons of the classifiers are quantized with
+const THRESHOLDS: [[f32; 3]; 16] = [
+    [1.98215, 2.35817, 2.63523],
+    [-1.03809, -0.651211, -0.282167],
+    [-0.298702, 0.119262, 0.558497],
+    [-0.105439, 0.0153946, 0.135898],
+    [-0.142891, 0.0258736, 0.200632],
+    [-0.826319, -0.590612, -0.368214],
+    [-0.557409, -0.233035, 0.0534525],
+    [-0.0646826, 0.00620476, 0.0784847],
+    [-0.192387, -0.029699, 0.215855],
+    [-0.0397818, -0.00568076, 0.0292026],
+    [-0.53823, -0.369934, -0.190235],
+    [-0.124877, 0.0296483, 0.139239],
+    [-0.101475, 0.0225617, 0.231971],
+    [-0.0799915, -0.00729616, 0.063262],
+    [-0.272556, 0.019424, 0.302559],
+    [-0.164292, -0.0321188, 0.0846339],
+];
+
+/// Widest area compared by the classifiers, in frames
+const MAX_FRAMES: usize = 16;
+
+/// Summed-area table of the chroma vectors, to get the sum of any area in constant time
+struct IntegralImage {
+    sums: Vec<[f32; 13]>,
+}
+
+impl IntegralImage {
+    fn new(chroma: &[[f32; 12]]) -> Self {
+        let mut sums = vec![[0.0; 13]; chroma.len() + 1];
+        for (frame, vector) in chroma.iter().enumerate() {
+            for (band, value) in vector.iter().enumerate() {
+                sums[frame + 1][band + 1] =
+                    value + sums[frame][band + 1] + sums[frame + 1][band] - sums[frame][band];
+            }
+        }
+        Self { sums }
+    }
+
+    /// Sum of the frames from `frame` to `frame_end` and of the bands from `band` to `band_end`,
+    /// ends excluded
+    fn area(&self, frame: usize, band: usize, frame_end: usize, band_end: usize) -> f32 {
+        self.sums[frame_end][band_end] - self.sums[frame][band_end] - self.sums[frame_end][band]
+            + self.sums[frame][band]
+    }
+}
+
+/// Compares the areas of the classifier `index` starting at `frame`, returning its pair of bits
+fn classify(index: usize, image: &IntegralImage, frame: usize) -> u32 {
+    let (filter, y, h, w) = CLASSIFIERS[index];
+    let x = frame;
+    let area = |x0, y0, x1, y1| image.area(x0, y0, x1, y1);
+    let (a, b) = match filter {
+        Filter::Whole => (area(x, y, x + w, y + h), 0.0),
+        Filter::HalvesBands => (
+            area(x, y + h / 2, x + w, y + h),
+            area(x, y, x + w, y + h / 2),
+        ),
+        Filter::HalvesTime => (
+            area(x + w / 2, y, x + w, y + h),
+            area(x, y, x + w / 2, y + h),
+        ),
+        Filter::Quadrants => (
+            area(x, y + h / 2, x + w / 2, y + h) + area(x + w / 2, y, x + w, y + h / 2),
+            area(x, y, x + w / 2, y + h / 2) + area(x + w / 2, y + h / 2, x + w, y + h),
+        ),
+        Filter::ThirdsBands => (
+            area(x, y + h / 3, x + w, y + 2 * h / 3),
+            area(x, y, x + w, y + h / 3) + area(x, y + 2 * h / 3, x + w, y + h),
+        ),
+        Filter::ThirdsTime => (
+            area(x + w / 3, y, x + 2 * w / 3, y + h),
+            area(x, y, x + w / 3, y + h) + area(x + 2 * w / 3, y, x + w, y + h),
+        ),
+    };
+    let value = (1.0 + a).ln() - (1.0 + b).ln();
+
+    let quantized = THRESHOLDS[index]
+        .iter()
+        .take_while(|threshold| value >= **threshold)
+        .count();
+    // Gray code, so that neighbouring values differ by a single bit
+    [0, 1, 3, 2][quantized]
+}
+
+/// Acoustic fingerprint of the start of a track, compatible with the ones of Chromaprint (and
+/// AcoustID)
+///
+/// Every subfingerprint describes about 0.124 s of audio
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fingerprint(pub Vec<u32>);
+
+impl Fingerprint {
+    /// Computes the fingerprint of mono `samples` at [`FINGERPRINT_RATE`]
+    pub(crate) fn new(samples: &[f32]) -> Self {
+        let chroma = normalize(filter(chroma(samples)));
+        if chroma.len() < MAX_FRAMES {
+            return Self::default();
+        }
+
+        let image = IntegralImage::new(&chroma);
+        Self(
+            (0..=chroma.len() - MAX_FRAMES)
+                .map(|frame| {
+                    (0..CLASSIFIERS.len()).fold(0, |bits, index| {
+                        (bits << 2) | classify(index, &image, frame)
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the fingerprint compressed and encoded like the ones sent to AcoustID
+    pub fn compress(&self) -> String {
+        let mut normal = vec![];
+        let mut exceptional = vec![];
+        let mut previous = 0;
+        for subfingerprint in &self.0 {
+            // only the positions of the bits that changed are stored, as deltas
+            let mut changed = subfingerprint ^ previous;
+            previous = *subfingerprint;
+            let (mut bit, mut last_bit) = (1, 0);
+            while changed != 0 {
+                if changed & 1 != 0 {
+                    let delta = bit - last_bit;
+                    if delta >= 7 {
+                        normal.push(7);
+                        exceptional.push(delta - 7);
+                    } else {
+                        normal.push(delta);
+                    }
+                    last_bit = bit;
+                }
+                changed >>= 1;
+                bit += 1;
+            }
+            normal.push(0);
+        }
+
+        let len = self.0.len() as u32;
+        let mut data = vec![ALGORITHM];
+        data.extend_from_slice(&len.to_be_bytes()[1..]);
+        pack(&normal, 3, &mut data);
+        pack(&exceptional, 5, &mut data);
+        base64(&data)
+            .trim_end_matches('=')
+            .replace('+', "-")
+            .replace('/', "_")
+    }
+
+    /// Returns how similar the two fingerprints are, from 0 (unrelated) to 1 (identical), at the
+    /// best alignment within `max_offset` subfingerprints
+    pub fn similarity(&self, other: &Fingerprint, max_offset: usize) -> f32 {
+        let a = &self.0[..self.0.len().min(COMPARE_LENGTH)];
+        let b = &other.0[..other.0.len().min(COMPARE_LENGTH)];
+        let max_offset = max_offset as isize;
+
+        (-max_offset..=max_offset)
+            .filter_map(|offset| {
+                let (a, b) = if offset < 0 {
+                    (a, b.get(offset.unsigned_abs()..)?)
+                } else {
+                    (a.get(offset as usize..)?, b)
+                };
+                let overlap = a.len().min(b.len());
+                if overlap < MIN_OVERLAP {
+                    return None;
+                }
+                let errors = a
+                    .iter()
+                    .zip(b)
+                    .map(|(a, b)| (a ^ b).count_ones())
+                    .sum::<u32>();
+                Some(1.0 - errors as f32 / (32 * overlap) as f32)
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Packs `values` of `bits` bits each, from the least significant bit of the first byte
+fn pack(values: &[u32], bits: u32, output: &mut Vec<u8>) {
+    let mut buffer = 0_u32;
+    let mut buffered = 0;
+    for value in values {
+        buffer |= value << buffered;
+        buffered += bits;
+        while buffered >= 8 {
+            output.push(buffer as u8);
+            buffer >>= 8;
+            buffered -= 8;
+        }
+    }
+    if buffered > 0 {
+        output.push(buffer as u8);
+    }
+}
+
+fn hamming(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.54 - 0.46 * (2.0 * PI * i as f32 / (size - 1) as f32).cos())
+        .collect()
+}
+
+/// Returns the energy of every pitch class (A is the first one) of every frame of `samples`
+fn chroma(samples: &[f32]) -> Vec<[f32; 12]> {
+    if samples.len() < FRAME {
+        return vec![];
+    }
+
+    let fft = Fft::new(FRAME);
+    let window = hamming(FRAME);
+    let mut buf = vec![Complex::default(); FRAME];
+    let bin_width = FINGERPRINT_RATE as f32 / FRAME as f32;
+    let first = ((CHROMA_RANGE.0 / bin_width).round() as usize).max(1);
+    let last = ((CHROMA_RANGE.1 / bin_width).round() as usize).min(FRAME / 2);
+    let classes = (first..last)
+        .map(|bin| {
+            let octave = (bin as f32 * bin_width / (440.0 / 16.0)).log2();
+            ((12.0 * octave.fract()) as usize).min(11)
+        })
+        .collect::<Vec<_>>();
+
+    (0..=samples.len() - FRAME)
+        .step_by(HOP)
+        .map(|start| {
+            for ((value, sample), window) in buf
+                .iter_mut()
+                .zip(&samples[start..start + FRAME])
+                .zip(&window)
+            {
+                *value = Complex::new(sample * window, 0.0);
+            }
+            fft.forward(&mut buf);
+
+            let mut vector = [0.0; 12];
+            for (bin, class) in buf[first..last].iter().zip(&classes) {
+                vector[*class] += bin.norm().powi(2);
+            }
+            vector
+        })
+        .collect()
+}
+
+/// Blurs the chroma vectors over time
+fn filter(chroma: Vec<[f32; 12]>) -> Vec<[f32; 12]> {
+    chroma
+        .windows(CHROMA_FILTER.len())
+        .map(|vectors| {
+            let mut filtered = [0.0; 12];
+            for (vector, weight) in vectors.iter().zip(CHROMA_FILTER) {
+                for (filtered, value) in filtered.iter_mut().zip(vector) {
+                    *filtered += value * weight;
+                }
+            }
+            filtered
+        })
+        .collect()
+}
+
+fn normalize(mut chroma: Vec<[f32; 12]>) -> Vec<[f32; 12]> {
+    for vector in &mut chroma {
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm < NORM_THRESHOLD {
+            *vector = [0.0; 12];
+        } else {
+            vector.iter_mut().for_each(|value| *value /= norm);
+        }
+    }
+    chroma
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Silence at 11025 Hz long enough for `frames` chroma frames
+    fn silence(frames: usize) -> Vec<f32> {
+        vec![0.0; FRAME + (frames - 1) * HOP]
+    }
+
+    #[test]
+    fn silence_matches_chromaprint() {
+        // the fingerprint of 130 chunks of 1024 silent samples at 44100 Hz in the tests of
+        // Chromaprint, resampled to 11025 Hz
+        let fingerprint = Fingerprint::new(&[0.0; 130 * 1024 / 4]);
+        assert_eq!(fingerprint, Fingerprint(vec![627964279; 3]));
+        assert_eq!(fingerprint.compress(), "AQAAA0mUaEkSRZEGAA");
+    }
+
+    #[test]
+    fn frames_overlap_by_two_thirds() {
+        // 4 frames are lost to the chroma filter and 15 to the classifiers
+        assert_eq!(Fingerprint::new(&silence(23)).0.len(), 4);
+        assert_eq!(Fingerprint::new(&silence(23)[1..]).0.len(), 3);
+        assert_eq!(Fingerprint::new(&silence(19)).0.len(), 0);
+    }
+
+    #[test]
+    fn short_tracks_have_empty_fingerprints() {
+        assert_eq!(Fingerprint::new(&[]), Fingerprint::default());
+        assert_eq!(Fingerprint::new(&[0.0; FRAME]), Fingerprint::default());
+    }
+}
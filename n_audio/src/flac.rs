@@ -0,0 +1,415 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Frames in every FLAC frame but the last one
+const BLOCK_SIZE: usize = 4096;
+/// Highest order of the fixed predictors
+const MAX_ORDER: usize = 4;
+/// Highest partition order of the residuals
+const MAX_PARTITION_ORDER: u32 = 6;
+/// Highest Rice parameter with the 4-bit parameters
+const MAX_RICE_PARAMETER: u32 = 14;
+/// Position of the STREAMINFO block, after the signature and its header
+const STREAMINFO_POSITION: u64 = 8;
+
+const BLOCK_STREAMINFO: u8 = 0;
+const BLOCK_VORBIS_COMMENT: u8 = 4;
+const BLOCK_PICTURE: u8 = 6;
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Writes values with any number of bits, most significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `bits` (up to 32) bits of `value`
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Writes `value` zeros followed by a one
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as u32 + 1);
+    }
+
+    /// Pads the last byte with zeros
+    fn into_bytes(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+        self.bytes
+    }
+}
+
+/// Frame number coded like UTF-8, up to 36 bits
+fn write_utf8_number(bytes: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        bytes.push(value as u8);
+        return;
+    }
+    let len = (2..=7).find(|len| value < 1 << (5 * len + 1)).unwrap_or(7);
+    let prefix = !(0xffu8 >> len);
+    bytes.push(prefix | (value >> (6 * (len - 1))) as u8);
+    for i in (0..len - 1).rev() {
+        bytes.push(0x80 | ((value >> (6 * i)) & 0x3f) as u8);
+    }
+}
+
+fn zigzag(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Best Rice parameter for a partition and the bits it takes
+fn rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    let bits = |parameter: u32| {
+        residuals
+            .iter()
+            .map(|residual| (zigzag(*residual) >> parameter) + 1 + parameter as u64)
+            .sum::<u64>()
+    };
+    // the best parameter is close to the logarithm of the mean
+    let sum = residuals
+        .iter()
+        .map(|residual| zigzag(*residual))
+        .sum::<u64>();
+    let mean = sum / residuals.len().max(1) as u64;
+    let estimate = (64 - mean.leading_zeros()).min(MAX_RICE_PARAMETER);
+    (estimate.saturating_sub(1)..=(estimate + 1).min(MAX_RICE_PARAMETER))
+        .map(|parameter| (parameter, bits(parameter)))
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+/// Partition order and parameters of the residuals that take the fewest bits
+fn partition(residuals: &[i64], block_size: usize, order: usize) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+        let len = block_size >> partition_order;
+        if block_size % partitions != 0 || len <= order {
+            break;
+        }
+
+        let mut parameters = vec![];
+        let mut bits = 0;
+        let mut start = 0;
+        for i in 0..partitions {
+            // the first partition doesn't have the warm-up samples
+            let end = (i + 1) * len - order;
+            let (parameter, partition_bits) = rice_parameter(&residuals[start..end]);
+            parameters.push(parameter);
+            bits += 4 + partition_bits;
+            start = end;
+        }
+        if best
+            .as_ref()
+            .is_none_or(|(_, _, best_bits)| bits < *best_bits)
+        {
+            best = Some((partition_order as u32, parameters, bits));
+        }
+    }
+    best.unwrap_or((0, vec![0], u64::MAX))
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        writer.write(0, 8);
+        writer.write_signed(samples[0], bits_per_sample);
+        return;
+    }
+
+    // the order with the smallest residuals usually takes the fewest bits
+    let order = (0..=MAX_ORDER.min(samples.len() - 1))
+        .min_by_key(|order| {
+            fixed_residuals(samples, *order)
+                .iter()
+                .map(|residual| residual.unsigned_abs())
+                .sum::<u64>()
+        })
+        .unwrap_or(0);
+    let residuals = fixed_residuals(samples, order);
+    let (partition_order, parameters, residual_bits) = partition(&residuals, samples.len(), order);
+
+    let verbatim_bits = bits_per_sample as u64 * samples.len() as u64;
+    let fixed_bits = bits_per_sample as u64 * order as u64 + 6 + residual_bits;
+    if fixed_bits >= verbatim_bits {
+        writer.write(0b0000_0010, 8);
+        for sample in samples {
+            writer.write_signed(*sample, bits_per_sample);
+        }
+        return;
+    }
+
+    writer.write((0b00_1000 | order as u64) << 1, 8);
+    for sample in &samples[..order] {
+        writer.write_signed(*sample, bits_per_sample);
+    }
+    writer.write(0, 2);
+    writer.write(partition_order as u64, 4);
+    let len = samples.len() >> partition_order;
+    let mut start = 0;
+    for (i, parameter) in parameters.iter().enumerate() {
+        let end = (i + 1) * len - order;
+        writer.write(*parameter as u64, 4);
+        for residual in &residuals[start..end] {
+            let value = zigzag(*residual);
+            writer.write_unary(value >> parameter);
+            writer.write(value, *parameter);
+        }
+        start = end;
+    }
+}
+
+/// Lossless encoder with the fixed predictors of FLAC
+pub(crate) struct FlacEncoder<W: Write + Seek> {
+    writer: W,
+    rate: u32,
+    channels: usize,
+    bits_per_sample: u32,
+    /// Interleaved samples of the next frame
+    pending: Vec<i64>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl<W: Write + Seek> FlacEncoder<W> {
+    /// Writes the metadata blocks: the tags are `KEY=value` comments, and the cover is the
+    /// content of a picture block
+    pub fn new(
+        mut writer: W,
+        rate: u32,
+        channels: usize,
+        bits_per_sample: u32,
+        comments: &[String],
+        picture: Option<&[u8]>,
+    ) -> io::Result<Self> {
+        writer.write_all(b"fLaC")?;
+        write_block_header(&mut writer, BLOCK_STREAMINFO, false, 34)?;
+        writer.write_all(&[0; 34])?;
+
+        let comments = vorbis_comments(comments);
+        write_block_header(
+            &mut writer,
+            BLOCK_VORBIS_COMMENT,
+            picture.is_none(),
+            comments.len(),
+        )?;
+        writer.write_all(&comments)?;
+        if let Some(picture) = picture {
+            write_block_header(&mut writer, BLOCK_PICTURE, true, picture.len())?;
+            writer.write_all(picture)?;
+        }
+
+        let mut encoder = Self {
+            writer,
+            rate,
+            channels,
+            bits_per_sample,
+            pending: vec![],
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+        };
+        encoder.write_streaminfo()?;
+        Ok(encoder)
+    }
+
+    fn write_streaminfo(&mut self) -> io::Result<()> {
+        let mut streaminfo = BitWriter::default();
+        streaminfo.write(BLOCK_SIZE as u64, 16);
+        streaminfo.write(BLOCK_SIZE as u64, 16);
+        // 0 if unknown
+        streaminfo.write(
+            if self.max_frame_size == 0 {
+                0
+            } else {
+                self.min_frame_size as u64
+            },
+            24,
+        );
+        streaminfo.write(self.max_frame_size as u64, 24);
+        streaminfo.write(self.rate as u64, 20);
+        streaminfo.write(self.channels as u64 - 1, 3);
+        streaminfo.write(self.bits_per_sample as u64 - 1, 5);
+        streaminfo.write(self.total_frames >> 32, 4);
+        streaminfo.write(self.total_frames, 32);
+        // no MD5 signature
+        let mut streaminfo = streaminfo.into_bytes();
+        streaminfo.resize(34, 0);
+
+        let position = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(STREAMINFO_POSITION))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.seek(SeekFrom::Start(position))?;
+        Ok(())
+    }
+
+    /// Encodes interleaved samples between -1 and 1
+    pub fn encode(&mut self, samples: &[f32]) -> io::Result<()> {
+        let max = ((1 << (self.bits_per_sample - 1)) - 1) as f32;
+        self.pending.extend(
+            samples
+                .iter()
+                .map(|sample| (sample.clamp(-1.0, 1.0) * max).round() as i64),
+        );
+        while self.pending.len() >= BLOCK_SIZE * self.channels {
+            let block = self
+                .pending
+                .drain(..BLOCK_SIZE * self.channels)
+                .collect::<Vec<_>>();
+            self.write_frame(&block)?;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, block: &[i64]) -> io::Result<()> {
+        let block_size = block.len() / self.channels;
+
+        let mut header = vec![0xff, 0xf8];
+        // 4096 frames, or the size at the end of the header; the rate is in STREAMINFO
+        let size_code = if block_size == BLOCK_SIZE { 12 } else { 7 };
+        header.push(size_code << 4);
+        let sample_size_code = match self.bits_per_sample {
+            16 => 4,
+            24 => 6,
+            _ => 0,
+        };
+        header.push(((self.channels as u8 - 1) << 4) | (sample_size_code << 1));
+        write_utf8_number(&mut header, self.frame_number);
+        if size_code == 7 {
+            header.extend_from_slice(&(block_size as u16 - 1).to_be_bytes());
+        }
+        header.push(crc8(&header));
+
+        let mut subframes = BitWriter {
+            bytes: header,
+            ..Default::default()
+        };
+        for channel in 0..self.channels {
+            let samples = block
+                .iter()
+                .skip(channel)
+                .step_by(self.channels)
+                .copied()
+                .collect::<Vec<_>>();
+            write_subframe(&mut subframes, &samples, self.bits_per_sample);
+        }
+        let mut frame = subframes.into_bytes();
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        self.writer.write_all(&frame)?;
+
+        self.frame_number += 1;
+        self.total_frames += block_size as u64;
+        self.min_frame_size = self.min_frame_size.min(frame.len() as u32);
+        self.max_frame_size = self.max_frame_size.max(frame.len() as u32);
+        Ok(())
+    }
+
+    /// Encodes the last frame and completes STREAMINFO
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            let block = std::mem::take(&mut self.pending);
+            self.write_frame(&block)?;
+        }
+        self.write_streaminfo()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_block_header<W: Write>(
+    writer: &mut W,
+    kind: u8,
+    last: bool,
+    len: usize,
+) -> io::Result<()> {
+    writer.write_all(&[if last { 0x80 | kind } else { kind }])?;
+    writer.write_all(&(len as u32).to_be_bytes()[1..])
+}
+
+/// Content of a VORBIS_COMMENT block, which is also used by Opus
+pub(crate) fn vorbis_comments(comments: &[String]) -> Vec<u8> {
+    let vendor = concat!("n_audio ", env!("CARGO_PKG_VERSION"));
+    let mut block = vec![];
+    block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(vendor.as_bytes());
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        block.extend_from_slice(comment.as_bytes());
+    }
+    block
+}
+
+/// Content of a PICTURE block with a front cover, which is also used by Opus
+pub(crate) fn picture(media_type: &str, data: &[u8]) -> Vec<u8> {
+    let mut block = vec![];
+    block.extend_from_slice(&3_u32.to_be_bytes());
+    block.extend_from_slice(&(media_type.len() as u32).to_be_bytes());
+    block.extend_from_slice(media_type.as_bytes());
+    // no description, and the size and the colors of the picture are unknown
+    block.extend_from_slice(&[0; 20]);
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(data);
+    block
+}
@@ -1,21 +1,47 @@
+use flume::Sender;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::path::Path;
 use symphonia::core::codecs::CodecRegistry;
+use symphonia::core::formats::SeekMode;
 
+use crate::container::Location;
 use crate::dca::DcaReader;
+use crate::dsp::DspSettings;
 use crate::opus::OpusDecoder;
 use crate::raw::RawReader;
+#[cfg(feature = "tracker")]
+use crate::tracker::TrackerReader;
 use once_cell::sync::Lazy;
 use symphonia::core::units::Time;
 use symphonia::default::{register_enabled_codecs, register_enabled_formats};
 use symphonia_core::probe::Probe;
 
-mod dca;
+pub mod analysis;
+pub mod chapter;
+pub mod container;
+pub mod convolution;
+pub mod crossfeed;
+pub mod dca;
+pub mod dsp;
+pub mod dynamics;
+pub mod effect;
+pub mod export;
+mod fft;
+pub mod fingerprint;
+mod flac;
 pub mod music_track;
+mod ogg;
 mod opus;
 mod output;
 pub mod player;
 pub mod queue;
-mod raw;
+pub mod raw;
+mod resample;
+pub mod silence;
+pub mod stream;
+pub mod tap;
+pub mod tracker;
 
 /// Default Symphonia [`CodecRegistry`], including the (audiopus-backed) Opus codec.
 pub static CODEC_REGISTRY: Lazy<CodecRegistry> = Lazy::new(|| {
@@ -30,24 +56,52 @@ pub static PROBE: Lazy<Probe> = Lazy::new(|| {
     probe.register_all::<DcaReader>();
     probe.register_all::<RawReader>();
     register_enabled_formats(&mut probe);
+    #[cfg(feature = "tracker")]
+    probe.register_all::<TrackerReader>();
     probe
 });
 
 #[derive(Debug)]
 pub enum NError {
     NoTrack,
+    /// The requested position is past the end of the track
+    SeekOutOfRange,
+    /// The track couldn't seek for any other reason
+    Seek(String),
+    /// The track thread isn't running anymore
+    Disconnected,
 }
 
+impl Display for NError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NError::NoTrack => write!(f, "no track"),
+            NError::SeekOutOfRange => write!(f, "can't seek past the end of the track"),
+            NError::Seek(err) => write!(f, "can't seek: {err}"),
+            NError::Disconnected => write!(f, "the track thread isn't running"),
+        }
+    }
+}
+
+impl Error for NError {}
+
 /// Messages sent inside the `Player`
 pub enum Message {
     Play,
     Pause,
     End,
     Exit,
-    Seek(Time),
+    /// Seeks to the given time, replying with the position it landed on
+    Seek(Time, SeekMode, Sender<Result<f64, NError>>),
     Time(TrackTime),
     Volume(f32),
     PlaybackSpeed(f32),
+    /// Loops between the two positions (in seconds) until it's set to `None`
+    LoopRegion(Option<(f64, f64)>),
+    /// Length (in seconds) of the crossfade at the loop seam
+    LoopCrossfade(f64),
+    Dsp(DspSettings),
+    NightMode(bool),
 }
 
 /// Returns the file name without its extension
@@ -73,11 +127,10 @@ pub fn remove_ext<P: AsRef<Path>>(path: P) -> String {
 }
 
 pub fn strip_absolute_path(path: String) -> String {
-    let mut s = path
-        .split(std::path::MAIN_SEPARATOR)
-        .last()
-        .unwrap()
-        .to_string();
+    // the entries of an archive keep their folders inside it
+    let file = Location::parse(&path).file;
+    let start = file.rfind(std::path::MAIN_SEPARATOR).map_or(0, |i| i + 1);
+    let mut s = path[start..].to_string();
     s.shrink_to_fit();
 
     s
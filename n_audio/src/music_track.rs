@@ -1,11 +1,24 @@
-use crate::{remove_ext, Metadata, TrackTime, PROBE};
+use crate::analysis::{analyze, Analysis, ANALYSIS_RATE};
+use crate::chapter::{get_chapters, Chapter};
+use crate::container::{read_entry, select_track, Location};
+use crate::fingerprint::{Fingerprint, FINGERPRINT_LENGTH, FINGERPRINT_RATE};
+use crate::resample::Resampler;
+use crate::silence::{first_audible, last_audible, SilenceBounds};
+use crate::stream::{is_url, HttpStream, StreamInfo};
+#[cfg(feature = "tracker")]
+use crate::tracker::TrackerReader;
+use crate::{remove_ext, Metadata, TrackTime, CODEC_REGISTRY, PROBE};
 use multitag::Tag;
 use std::ffi::OsStr;
-use std::io::Cursor;
+use std::fs::{self, File};
+use std::io::{self, Cursor};
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::{fs, io};
-use symphonia::core::formats::{FormatOptions, FormatReader};
-use symphonia::core::io::MediaSourceStream;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 use symphonia_core::meta::StandardTagKey;
@@ -17,60 +30,108 @@ pub struct MusicTrack {
 }
 
 impl MusicTrack {
+    /// Creates a track from the path of a file, from the location of a track inside a container or
+    /// an archive (see [`Location`]), or from an `http(s)://` URL of a file, a live stream or a PLS
+    /// or M3U playlist
     pub fn new<P: AsRef<Path> + AsRef<OsStr> + Clone + Into<String>>(path: P) -> io::Result<Self> {
-        let p = path.clone();
-        let p = Path::new(&p);
-        Ok(MusicTrack {
-            path: path.into(),
-            ext: p
-                .extension()
-                .ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))?
-                .to_str()
-                .unwrap()
-                .to_string(),
-        })
+        let path: String = path.into();
+        // the format of a stream is known once connected
+        if is_url(&path) {
+            return Ok(MusicTrack {
+                path,
+                ext: String::new(),
+            });
+        }
+
+        let ext = Location::parse(&path)
+            .extension()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))?
+            .to_string();
+        Ok(MusicTrack { path, ext })
+    }
+
+    /// Whether the track is played from an `http(s)://` URL
+    pub fn is_stream(&self) -> bool {
+        is_url(&self.path)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
     }
 
     /// Returns the `FormatReader` provided by Symphonia
     pub fn get_format(&self) -> Result<Box<dyn FormatReader>, io::Error> {
-        let file = fs::read(&self.path)?;
-        let media_stream = MediaSourceStream::new(
-            Box::new(Cursor::new(file)),
-            std::default::Default::default(),
-        );
+        if self.is_stream() {
+            return self.open_stream().map(|(format, _)| format);
+        }
+
+        let location = Location::parse(&self.path);
+        let source: Box<dyn MediaSource> = match location.entry {
+            Some(entry) => Box::new(Cursor::new(read_entry(location.file, entry)?)),
+            // containers with more tracks can be videos, too big to be read at once
+            None if location.track_id.is_some() => Box::new(File::open(location.file)?),
+            None => Box::new(Cursor::new(fs::read(location.file)?)),
+        };
+        let media_stream = MediaSourceStream::new(source, std::default::Default::default());
+        // most modules don't start with a signature that the probe can find
+        #[cfg(feature = "tracker")]
+        if crate::tracker::is_module(&self.ext) {
+            return TrackerReader::try_new(media_stream, &FormatOptions::default())
+                .map(|format| Box::new(format) as Box<dyn FormatReader>)
+                .map_err(|_| io::Error::from(io::ErrorKind::Unsupported));
+        }
         let mut hint = Hint::new();
         hint.with_extension(self.ext.as_ref());
+        let format = Self::probe(&hint, media_stream)?;
+        match location.track_id {
+            Some(track_id) => Ok(Box::new(select_track(format, track_id)?)),
+            None => Ok(format),
+        }
+    }
+
+    /// Connects to the stream at the URL of the track, returning its `FormatReader` and the
+    /// information sent by its server while it's played
+    pub fn open_stream(&self) -> Result<(Box<dyn FormatReader>, StreamInfo), io::Error> {
+        let stream = HttpStream::open(&self.path)?;
+        let info = stream.info();
+        let mut hint = Hint::new();
+        if let Some(ext) = stream.extension() {
+            hint.with_extension(&ext);
+        }
+        let media_stream = MediaSourceStream::new(Box::new(stream), Default::default());
+        Ok((Self::probe(&hint, media_stream)?, info))
+    }
+
+    fn probe(hint: &Hint, media_stream: MediaSourceStream) -> io::Result<Box<dyn FormatReader>> {
         let meta_ops = MetadataOptions::default();
         let fmt_ops = FormatOptions {
             enable_gapless: true,
             ..Default::default()
         };
         let probed = PROBE
-            .format(&hint, media_stream, &fmt_ops, &meta_ops)
-            .expect("Format not supported");
+            .format(hint, media_stream, &fmt_ops, &meta_ops)
+            .map_err(|_| io::Error::from(io::ErrorKind::Unsupported))?;
         Ok(probed.format)
     }
 
     pub fn get_meta(&self) -> Result<Metadata, io::Error> {
-        let mut format = self.get_format()?;
+        let (mut format, stream) = if self.is_stream() {
+            let (format, info) = self.open_stream()?;
+            (format, Some(info))
+        } else {
+            (self.get_format()?, None)
+        };
         let track = format.default_track().expect("Can't load tracks");
-        let time_base = track
-            .codec_params
-            .time_base
+        if track.codec_params.time_base.is_none() {
             // TODO: add better error
-            .ok_or(io::Error::from(io::ErrorKind::Unsupported))?;
-
-        let duration = track
-            .codec_params
-            .n_frames
-            .map(|frames| track.codec_params.start_ts + frames)
-            .unwrap();
-        let time = time_base.calc_time(duration);
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
 
         let time = TrackTime {
             position: 0.0,
-            length: time.seconds as f64 + time.frac,
+            length: track_length(track),
         };
+        let language = track.language.clone();
 
         let mut artist = String::new();
         let mut title = String::new();
@@ -83,6 +144,8 @@ impl MusicTrack {
                     title = tag.value.to_string();
                 }
             }
+        } else if let Some(stream) = &stream {
+            title = stream.name().unwrap_or_default();
         } else if let Ok(tag) = Tag::read_from_path(&self.path) {
             if let Some(t) = tag.title() {
                 title = t.to_string();
@@ -92,8 +155,22 @@ impl MusicTrack {
             }
         }
 
-        if title.is_empty() {
-            title = remove_ext(&self.path);
+        if self.is_stream() {
+            if title.is_empty() {
+                title = self.path.clone();
+            }
+        } else {
+            let location = Location::parse(&self.path);
+            if title.is_empty() {
+                title = remove_ext(location.entry.unwrap_or(location.file));
+            }
+            // the tracks of a container share its tags
+            if let Some(track_id) = location.track_id {
+                title = match language {
+                    Some(language) => format!("{title} [{language}]"),
+                    None => format!("{title} [{track_id}]"),
+                };
+            }
         }
 
         title.shrink_to_fit();
@@ -106,21 +183,218 @@ impl MusicTrack {
         })
     }
 
+    /// Returns the chapters of the track, sorted by their start
+    pub fn get_chapters(&self) -> Result<Vec<Chapter>, io::Error> {
+        let mut format = self.get_format()?;
+        Ok(get_chapters(format.as_mut(), Some(&self.path)))
+    }
+
+    /// Returns the length of the track, 0 if it isn't known (like for live streams)
     pub fn get_length(&self) -> Result<TrackTime, io::Error> {
         let format = self.get_format()?;
         let track = format.default_track().expect("Can't load tracks");
-        let time_base = track.codec_params.time_base.unwrap();
-
-        let duration = track
-            .codec_params
-            .n_frames
-            .map(|frames| track.codec_params.start_ts + frames)
-            .unwrap();
-        let time = time_base.calc_time(duration);
 
         Ok(TrackTime {
             position: 0.0,
-            length: time.seconds as f64 + time.frac,
+            length: track_length(track),
+        })
+    }
+
+    /// Decodes the whole track, calling `f` with the interleaved samples of every packet, their
+    /// number of channels and their rate
+    pub fn decode<F: FnMut(&[f32], usize, u32)>(&self, mut f: F) -> Result<(), io::Error> {
+        self.decode_while(|samples, channels, rate| {
+            f(samples, channels, rate);
+            ControlFlow::Continue(())
         })
     }
+
+    /// Like `MusicTrack::decode`, but stops decoding as soon as `f` breaks
+    pub fn decode_while<F: FnMut(&[f32], usize, u32) -> ControlFlow<()>>(
+        &self,
+        mut f: F,
+    ) -> Result<(), io::Error> {
+        let mut format = self.get_format()?;
+        let track = format
+            .default_track()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))?;
+        let track_id = track.id;
+        let mut decoder = CODEC_REGISTRY
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|_| io::Error::from(io::ErrorKind::Unsupported))?;
+        let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    break
+                }
+                Err(err) => return Err(io::Error::other(err)),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            match decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    if sample_buf.as_ref().is_none_or(|buf| {
+                        buf.capacity() < decoded.capacity() * spec.channels.count()
+                    }) {
+                        sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                    }
+                    if let Some(sample_buf) = &mut sample_buf {
+                        sample_buf.copy_interleaved_ref(decoded);
+                        if f(sample_buf.samples(), spec.channels.count(), spec.rate).is_break() {
+                            break;
+                        }
+                    }
+                }
+                Err(SymphoniaError::DecodeError(err)) => {
+                    eprintln!("Decode error: {}", err);
+                }
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the whole track and estimates its tempo and its key
+    pub fn analyze(&self) -> Result<Analysis, io::Error> {
+        let mut samples = vec![];
+        let mut rate = 0;
+        // the frames are downmixed and averaged in groups of `factor`
+        let mut sum = 0.0;
+        let mut count = 0;
+        self.decode(|decoded, channels, decoded_rate| {
+            let factor = (decoded_rate / ANALYSIS_RATE).max(1);
+            rate = decoded_rate / factor;
+            for frame in decoded.chunks_exact(channels) {
+                sum += frame.iter().sum::<f32>() / channels as f32;
+                count += 1;
+                if count == factor {
+                    samples.push(sum / factor as f32);
+                    sum = 0.0;
+                    count = 0;
+                }
+            }
+        })?;
+
+        Ok(analyze(&samples, rate))
+    }
+
+    /// Decodes the start of the track and computes its acoustic fingerprint
+    pub fn fingerprint(&self) -> Result<Fingerprint, io::Error> {
+        let max_len = (FINGERPRINT_RATE * FINGERPRINT_LENGTH) as usize;
+        let mut samples = vec![];
+        let mut mono = vec![];
+        let mut resampler: Option<Resampler> = None;
+        self.decode_while(|decoded, channels, rate| {
+            let resampler =
+                resampler.get_or_insert_with(|| Resampler::new(rate, FINGERPRINT_RATE, 1));
+            mono.clear();
+            mono.extend(
+                decoded
+                    .chunks_exact(channels)
+                    .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+            );
+            resampler.process(&mono, &mut samples);
+            // the rest of the track isn't fingerprinted
+            if samples.len() >= max_len {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+        if let Some(resampler) = &mut resampler {
+            resampler.flush(&mut samples);
+        }
+        samples.truncate(max_len);
+
+        Ok(Fingerprint::new(&samples))
+    }
+
+    /// Decodes the whole track and returns where its audio starts and ends, ignoring the samples
+    /// under `threshold` (in dB)
+    /// Tracks that are silent from start to end aren't trimmed
+    pub fn silence_bounds(&self, threshold: f32) -> Result<SilenceBounds, io::Error> {
+        let threshold = 10.0_f32.powf(threshold / 20.0);
+        let mut frames = 0;
+        let mut audible: Option<(usize, usize)> = None;
+        let mut rate = 0;
+        self.decode(|samples, channels, samples_rate| {
+            rate = samples_rate;
+            if let Some(last) = last_audible(samples, channels, threshold) {
+                let first = match audible {
+                    Some((first, _)) => first,
+                    None => frames + first_audible(samples, channels, threshold).unwrap_or(last),
+                };
+                audible = Some((first, frames + last + 1));
+            }
+            frames += samples.len() / channels;
+        })?;
+
+        let (first, last) = audible.unwrap_or((0, frames));
+        let rate = rate.max(1) as f64;
+        Ok(SilenceBounds {
+            start: first as f64 / rate,
+            end: last as f64 / rate,
+        })
+    }
+
+    /// Decodes the whole track and returns the lowest and the highest sample of `points` equal
+    /// parts of it, to draw its waveform
+    pub fn waveform(&self, points: usize) -> Result<Vec<(f32, f32)>, io::Error> {
+        // the length isn't always known beforehand, so the peaks are collected for small blocks of
+        // frames and then merged into `points` parts
+        const BLOCK_FRAMES: usize = 256;
+        let mut blocks = vec![];
+        let mut block = (0.0_f32, 0.0_f32);
+        let mut frames = 0;
+        self.decode(|samples, channels, _| {
+            for frame in samples.chunks_exact(channels) {
+                for sample in frame {
+                    block = (block.0.min(*sample), block.1.max(*sample));
+                }
+                frames += 1;
+                if frames == BLOCK_FRAMES {
+                    blocks.push(block);
+                    block = (0.0, 0.0);
+                    frames = 0;
+                }
+            }
+        })?;
+        if frames > 0 {
+            blocks.push(block);
+        }
+        if blocks.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok((0..points)
+            .map(|i| {
+                let start = i * blocks.len() / points;
+                let end = ((i + 1) * blocks.len() / points).max(start + 1);
+                blocks[start..end]
+                    .iter()
+                    .fold((0.0_f32, 0.0_f32), |(min, max), block| {
+                        (min.min(block.0), max.max(block.1))
+                    })
+            })
+            .collect())
+    }
+}
+
+/// Length of `track` in seconds, 0 if it isn't known
+fn track_length(track: &Track) -> f64 {
+    let params = &track.codec_params;
+    match (params.time_base, params.n_frames) {
+        (Some(time_base), Some(frames)) => {
+            let time = time_base.calc_time(params.start_ts + frames);
+            time.seconds as f64 + time.frac
+        }
+        _ => 0.0,
+    }
 }
@@ -0,0 +1,135 @@
+use std::io::{self, Write};
+
+/// Largest number of segments in a page
+const MAX_SEGMENTS: usize = 255;
+/// Size after which a page is written once a packet ends on it
+const PAGE_SIZE: usize = 8192;
+
+const FLAG_CONTINUED: u8 = 0x01;
+const FLAG_FIRST: u8 = 0x02;
+const FLAG_LAST: u8 = 0x04;
+
+/// CRC-32 of the Ogg pages (polynomial 0x04c11db7, not reflected)
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u32) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Writes the packets of a single logical stream in Ogg pages
+pub(crate) struct OggWriter<W: Write> {
+    writer: W,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    /// Granule position of the last packet ending on the current page
+    granule: Option<u64>,
+    /// Granule position of the last page written
+    last_granule: u64,
+    first: bool,
+    /// The current page starts with the rest of a packet
+    continued: bool,
+}
+
+impl<W: Write> OggWriter<W> {
+    pub fn new(writer: W, serial: u32) -> Self {
+        Self {
+            writer,
+            serial,
+            sequence: 0,
+            segments: vec![],
+            data: vec![],
+            granule: None,
+            last_granule: 0,
+            first: true,
+            continued: false,
+        }
+    }
+
+    /// Adds a packet that ends at `granule`, and writes its page if `flush` (the header packets
+    /// have to end their pages)
+    pub fn write_packet(&mut self, packet: &[u8], granule: u64, flush: bool) -> io::Result<()> {
+        let mut rest = packet;
+        loop {
+            let len = rest.len().min(255);
+            self.segments.push(len as u8);
+            self.data.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            // a packet ends with a segment shorter than 255 bytes
+            let ended = len < 255;
+            if ended {
+                self.granule = Some(granule);
+            }
+            if self.segments.len() == MAX_SEGMENTS {
+                self.write_page(false)?;
+                self.continued = !ended;
+            }
+            if ended {
+                break;
+            }
+        }
+
+        if (flush || self.data.len() >= PAGE_SIZE) && !self.segments.is_empty() {
+            self.write_page(false)?;
+            self.continued = false;
+        }
+        Ok(())
+    }
+
+    fn write_page(&mut self, last: bool) -> io::Result<()> {
+        let mut flags = 0;
+        if self.continued {
+            flags |= FLAG_CONTINUED;
+        }
+        if self.first {
+            flags |= FLAG_FIRST;
+        }
+        if last {
+            flags |= FLAG_LAST;
+        }
+        // -1 if no packet ends on this page, but the last page always ends the stream
+        let granule = match self.granule.take() {
+            Some(granule) => {
+                self.last_granule = granule;
+                granule
+            }
+            None if last => self.last_granule,
+            None => u64::MAX,
+        };
+
+        let mut page = Vec::with_capacity(27 + self.segments.len() + self.data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0);
+        page.push(flags);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.segments.len() as u8);
+        page.extend_from_slice(&self.segments);
+        page.extend_from_slice(&self.data);
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.writer.write_all(&page)?;
+
+        self.sequence += 1;
+        self.first = false;
+        self.segments.clear();
+        self.data.clear();
+        Ok(())
+    }
+
+    /// Writes the last page, marking the end of the stream
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_page(true)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -1,76 +1,291 @@
-use audiopus::{
-    coder::{Decoder as AudiopusDecoder, GenericCtl},
-    Channels, Error as OpusError, ErrorCode, SampleRate,
-};
+use audiopus::ffi;
+use std::os::raw::c_int;
+use std::ptr::{self, NonNull};
 use symphonia_core::{
-    audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Layout, Signal, SignalSpec},
+    audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec},
     codecs::{
         CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult, CODEC_TYPE_OPUS,
     },
-    errors::{decode_error, Result as SymphResult},
+    errors::{decode_error, unsupported_error, Result as SymphResult},
     formats::Packet,
 };
 
 // Original code from the Songbird project
 
+/// Opus always decodes at 48 kHz
+const SAMPLE_RATE: u32 = 48000;
+/// Longest Opus packet (120 ms), in frames
+const MAX_FRAME_SIZE: usize = SAMPLE_RATE as usize * 120 / 1000;
+/// Length of the concealment of a lost packet (20 ms), in frames
+const LOST_FRAME_SIZE: usize = SAMPLE_RATE as usize / 50;
+
+/// Identification header of an Opus stream (RFC 7845, section 5.1)
+#[derive(Clone, Debug, PartialEq)]
+struct OpusHead {
+    channels: usize,
+    /// Frames to discard at the start of the stream
+    pre_skip: usize,
+    /// Gain to apply to the output, in dB
+    output_gain: f32,
+    mapping_family: u8,
+    streams: u8,
+    coupled_streams: u8,
+    /// Decoded channel (from the streams) of every output channel, 255 for silence
+    mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 19 || &data[..8] != b"OpusHead" || data[8] >> 4 != 0 {
+            return None;
+        }
+
+        let channels = data[9] as usize;
+        let pre_skip = u16::from_le_bytes([data[10], data[11]]) as usize;
+        // Q7.8 fixed point
+        let output_gain = i16::from_le_bytes([data[16], data[17]]) as f32 / 256.0;
+        let mapping_family = data[18];
+
+        let head = if mapping_family == 0 {
+            Self::family_zero(channels, pre_skip, output_gain)?
+        } else {
+            let table = data.get(19..21 + channels)?;
+            Self {
+                channels,
+                pre_skip,
+                output_gain,
+                mapping_family,
+                streams: table[0],
+                coupled_streams: table[1],
+                mapping: table[2..].to_vec(),
+            }
+        };
+        head.is_valid().then_some(head)
+    }
+
+    /// Header of a mono or stereo stream, used by family 0 and when there is no header at all
+    fn family_zero(channels: usize, pre_skip: usize, output_gain: f32) -> Option<Self> {
+        if !(1..=2).contains(&channels) {
+            return None;
+        }
+        Some(Self {
+            channels,
+            pre_skip,
+            output_gain,
+            mapping_family: 0,
+            streams: 1,
+            coupled_streams: channels as u8 - 1,
+            mapping: (0..channels as u8).collect(),
+        })
+    }
+
+    fn is_valid(&self) -> bool {
+        let decoded = self.streams as usize + self.coupled_streams as usize;
+        self.channels > 0
+            && self.streams > 0
+            && self.coupled_streams <= self.streams
+            && decoded <= 255
+            && self.mapping.len() == self.channels
+            && self
+                .mapping
+                .iter()
+                .all(|&index| index == 255 || (index as usize) < decoded)
+    }
+
+    /// Channels of the output and the plane of the [`AudioBuffer`] for every decoded channel, or
+    /// `None` if there are more channels than the positions Symphonia can represent
+    ///
+    /// Family 1 uses the Vorbis channel order, the other families have no defined positions
+    fn layout(&self) -> Option<(Channels, Vec<usize>)> {
+        let positions = match (self.mapping_family, self.channels) {
+            (0 | 1, 1) => vec![Channels::FRONT_LEFT],
+            (0 | 1, 2) => vec![Channels::FRONT_LEFT, Channels::FRONT_RIGHT],
+            (1, 3) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_CENTRE,
+                Channels::FRONT_RIGHT,
+            ],
+            (1, 4) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_RIGHT,
+                Channels::REAR_LEFT,
+                Channels::REAR_RIGHT,
+            ],
+            (1, 5) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_CENTRE,
+                Channels::FRONT_RIGHT,
+                Channels::REAR_LEFT,
+                Channels::REAR_RIGHT,
+            ],
+            (1, 6) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_CENTRE,
+                Channels::FRONT_RIGHT,
+                Channels::REAR_LEFT,
+                Channels::REAR_RIGHT,
+                Channels::LFE1,
+            ],
+            (1, 7) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_CENTRE,
+                Channels::FRONT_RIGHT,
+                Channels::SIDE_LEFT,
+                Channels::SIDE_RIGHT,
+                Channels::REAR_CENTRE,
+                Channels::LFE1,
+            ],
+            (1, 8) => vec![
+                Channels::FRONT_LEFT,
+                Channels::FRONT_CENTRE,
+                Channels::FRONT_RIGHT,
+                Channels::SIDE_LEFT,
+                Channels::SIDE_RIGHT,
+                Channels::REAR_LEFT,
+                Channels::REAR_RIGHT,
+                Channels::LFE1,
+            ],
+            // discrete channels, in the order they are decoded, each one with its own position
+            (_, channels) => {
+                let positions: Vec<Channels> = (0..u32::BITS)
+                    .map(|bit| Channels::from_bits_truncate(1 << bit))
+                    .filter(|position| !position.is_empty())
+                    .take(channels)
+                    .collect();
+                if positions.len() < channels {
+                    return None;
+                }
+                positions
+            }
+        };
+
+        let channels = positions
+            .iter()
+            .fold(Channels::empty(), |channels, position| channels | *position);
+        // the planes of an AudioBuffer are ordered by the bits of their position
+        let planes = positions
+            .iter()
+            .map(|position| (channels.bits() & (position.bits() - 1)).count_ones() as usize)
+            .collect();
+        Some((channels, planes))
+    }
+}
+
+/// libopus multistream decoder, which decodes every mapping family (a single stream included)
+struct MultistreamDecoder(NonNull<ffi::OpusMSDecoder>);
+
+impl MultistreamDecoder {
+    fn new(head: &OpusHead) -> SymphResult<Self> {
+        let mut error = 0;
+        let decoder = unsafe {
+            ffi::opus_multistream_decoder_create(
+                SAMPLE_RATE as i32,
+                head.channels as c_int,
+                head.streams as c_int,
+                head.coupled_streams as c_int,
+                head.mapping.as_ptr(),
+                &mut error,
+            )
+        };
+        // 0 is OPUS_OK
+        match NonNull::new(decoder) {
+            Some(decoder) if error == 0 => Ok(Self(decoder)),
+            Some(decoder) => {
+                unsafe { ffi::opus_multistream_decoder_destroy(decoder.as_ptr()) };
+                decode_error("Opus decoder could not be created.")
+            }
+            None => decode_error("Opus decoder could not be created."),
+        }
+    }
+
+    /// Decodes a packet (or conceals a lost one, if `None`) into `pcm`, interleaved, returning
+    /// the decoded frames
+    fn decode(
+        &mut self,
+        packet: Option<&[u8]>,
+        pcm: &mut [f32],
+        frame_size: usize,
+    ) -> SymphResult<usize> {
+        let (data, len) = match packet {
+            Some(packet) => match i32::try_from(packet.len()) {
+                Ok(len) => (packet.as_ptr(), len),
+                Err(_) => {
+                    return decode_error("Opus packet was too large (greater than i32::MAX bytes).")
+                }
+            },
+            None => (ptr::null(), 0),
+        };
+        let frames = unsafe {
+            ffi::opus_multistream_decode_float(
+                self.0.as_ptr(),
+                data,
+                len,
+                pcm.as_mut_ptr(),
+                frame_size as c_int,
+                0,
+            )
+        };
+        if frames < 0 {
+            decode_error("Opus packet could not be decoded.")
+        } else {
+            Ok(frames as usize)
+        }
+    }
+}
+
+impl Drop for MultistreamDecoder {
+    fn drop(&mut self) {
+        unsafe { ffi::opus_multistream_decoder_destroy(self.0.as_ptr()) }
+    }
+}
+
 /// Opus decoder for symphonia, based on libopus v1.3 (via [`audiopus`]).
 pub struct OpusDecoder {
-    inner: AudiopusDecoder,
+    inner: MultistreamDecoder,
+    head: OpusHead,
     params: CodecParameters,
+    /// Plane of [`Self::buf`] for every decoded channel
+    planes: Vec<usize>,
+    /// Linear gain from the header
+    gain: f32,
+    /// Frames still to discard from the start of the stream
+    skip: usize,
     buf: AudioBuffer<f32>,
     rawbuf: Vec<f32>,
 }
 
 /// # SAFETY
-/// The underlying Opus decoder (currently) requires only a `&self` parameter
-/// to decode given packets, which is likely a mistaken decision.
-///
-/// This struct makes stronger assumptions and only touches FFI decoder state with a
-/// `&mut self`, preventing data races via `&OpusDecoder` as required by `impl Sync`.
+/// The underlying Opus decoder state is only touched through FFI with a `&mut self`,
+/// preventing data races via `&OpusDecoder` as required by `impl Sync`, and libopus
+/// decoders are not bound to the thread that created them, as required by `impl Send`.
 /// No access to other internal state relies on unsafety or crosses FFI.
+unsafe impl Send for OpusDecoder {}
 unsafe impl Sync for OpusDecoder {}
 
 impl OpusDecoder {
     fn decode_inner(&mut self, packet: &Packet) -> SymphResult<()> {
-        let s_ct = loop {
-            let pkt = if packet.buf().is_empty() {
-                None
-            } else if let Ok(checked_pkt) = packet.buf().try_into() {
-                Some(checked_pkt)
-            } else {
-                return decode_error("Opus packet was too large (greater than i32::MAX bytes).");
-            };
-            let out_space = (&mut self.rawbuf[..]).try_into().expect("The following logic expands this buffer safely below i32::MAX, and we throw our own error.");
-
-            match self.inner.decode_float(pkt, out_space, false) {
-                Ok(v) => break v,
-                Err(OpusError::Opus(ErrorCode::BufferTooSmall)) => {
-                    // double the buffer size
-                    // correct behav would be to mirror the decoder logic in the udp_rx set.
-                    let new_size = (self.rawbuf.len() * 2).min(i32::MAX as usize);
-                    if new_size == self.rawbuf.len() {
-                        return decode_error("Opus frame too big: cannot expand opus frame decode buffer any further.");
-                    }
-
-                    self.rawbuf.resize(new_size, 0.0);
-                    self.buf = AudioBuffer::new(
-                        self.rawbuf.len() as u64 / 2,
-                        SignalSpec::new_with_layout(48000, Layout::Stereo),
-                    );
-                }
-                Err(_) => {
-                    return decode_error("Opus decode error: see 'tracing' logs.");
-                }
-            }
+        let channels = self.head.channels;
+        let frames = if packet.buf().is_empty() {
+            self.inner.decode(None, &mut self.rawbuf, LOST_FRAME_SIZE)?
+        } else {
+            self.inner
+                .decode(Some(packet.buf()), &mut self.rawbuf, MAX_FRAME_SIZE)?
         };
 
+        // the container may already trim the pre-skip (and the padding) with gapless playback
+        let skip = self.skip.max(packet.trim_start as usize).min(frames);
+        self.skip = self.skip.saturating_sub(skip);
+        let end = frames - (packet.trim_end as usize).min(frames - skip);
+
         self.buf.clear();
-        self.buf.render_reserved(Some(s_ct));
+        self.buf.render_reserved(Some(end - skip));
 
-        // Forcibly assuming stereo, for now.
-        for ch in 0..2 {
-            let iter = self.rawbuf.chunks_exact(2).map(|chunk| chunk[ch]);
-            for (tgt, src) in self.buf.chan_mut(ch).iter_mut().zip(iter) {
+        let decoded = &self.rawbuf[skip * channels..end * channels];
+        for (channel, plane) in self.planes.iter().enumerate() {
+            let iter = decoded
+                .chunks_exact(channels)
+                .map(|frame| frame[channel] * self.gain);
+            for (tgt, src) in self.buf.chan_mut(*plane).iter_mut().zip(iter) {
                 *tgt = src;
             }
         }
@@ -81,19 +296,40 @@ impl OpusDecoder {
 
 impl Decoder for OpusDecoder {
     fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> SymphResult<Self> {
-        let inner = AudiopusDecoder::new(SampleRate::Hz48000, Channels::Stereo).unwrap();
+        let head = match params.extra_data.as_deref() {
+            Some(extra_data) => OpusHead::parse(extra_data),
+            // without a header, only mono and stereo streams can be decoded, and the container
+            // trims the encoder delay
+            None => OpusHead::family_zero(
+                params.channels.map_or(2, |channels| channels.count()),
+                0,
+                0.0,
+            ),
+        };
+        let Some(head) = head else {
+            return unsupported_error("Opus header is invalid or has an unsupported mapping.");
+        };
+
+        let Some((channels, planes)) = head.layout() else {
+            return decode_error("Opus stream has more channels than the known positions.");
+        };
+        let inner = MultistreamDecoder::new(&head)?;
 
         let mut params = params.clone();
-        params.with_sample_rate(48000);
+        params.with_sample_rate(SAMPLE_RATE).with_channels(channels);
 
         Ok(Self {
             inner,
             params,
+            planes,
+            gain: 10.0_f32.powf(head.output_gain / 20.0),
+            skip: head.pre_skip,
             buf: AudioBuffer::new(
-                48000 / 50,
-                SignalSpec::new_with_layout(48000, Layout::Stereo),
+                MAX_FRAME_SIZE as u64,
+                SignalSpec::new(SAMPLE_RATE, channels),
             ),
-            rawbuf: vec![0.0f32; 2 * (48000 / 50)],
+            rawbuf: vec![0.0f32; head.channels * MAX_FRAME_SIZE],
+            head,
         })
     }
 
@@ -106,7 +342,12 @@ impl Decoder for OpusDecoder {
     }
 
     fn reset(&mut self) {
-        _ = self.inner.reset_state();
+        // a new decoder has a clean state, without going through the variadic ctl
+        if let Ok(inner) = MultistreamDecoder::new(&self.head) {
+            self.inner = inner;
+        }
+        // the pre-skip only applies to the start of the stream, and seeking never lands there
+        self.skip = 0;
     }
 
     fn codec_params(&self) -> &CodecParameters {
@@ -130,3 +371,269 @@ impl Decoder for OpusDecoder {
         self.buf.as_audio_buffer_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ogg::OggWriter;
+    use crate::{CODEC_REGISTRY, PROBE};
+    use std::io::Cursor;
+    use symphonia_core::audio::SampleBuffer;
+    use symphonia_core::errors::Error as SymphoniaError;
+    use symphonia_core::formats::FormatOptions;
+    use symphonia_core::io::MediaSourceStream;
+    use symphonia_core::meta::MetadataOptions;
+    use symphonia_core::probe::Hint;
+
+    const OPUS_APPLICATION_AUDIO: c_int = 2049;
+    const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+    /// Encoder delay of libopus at 48 kHz, written as the pre-skip
+    const LOOKAHEAD: usize = 312;
+    /// 20 ms packets
+    const PACKET_FRAMES: usize = 960;
+    /// Length of the reference PCM, half a second
+    const FRAMES: usize = PACKET_FRAMES * 25;
+
+    /// Frequency of the sine on the input channel `channel`: an offset of `LOOKAHEAD` frames
+    /// shifts it by half a period, so a wrong pre-skip inverts it
+    fn frequency(channel: usize) -> f32 {
+        SAMPLE_RATE as f32 * (2 * channel + 1) as f32 / (2 * LOOKAHEAD) as f32
+    }
+
+    /// Reference PCM, interleaved: a sine with its own frequency on every channel
+    fn reference(channels: usize) -> Vec<f32> {
+        (0..FRAMES)
+            .flat_map(|frame| {
+                (0..channels).map(move |channel| {
+                    let t = frame as f32 / SAMPLE_RATE as f32;
+                    0.25 * (std::f32::consts::TAU * frequency(channel) * t).sin()
+                })
+            })
+            .collect()
+    }
+
+    /// `OpusHead` packet, with the mapping table if `family` isn't 0
+    fn opus_head(channels: usize, family: u8, pre_skip: u16, gain: i16, coupled: u8) -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        head.extend_from_slice(&gain.to_le_bytes());
+        head.push(family);
+        if family != 0 {
+            head.push(channels as u8 - coupled);
+            head.push(coupled);
+            head.extend(0..channels as u8);
+        }
+        head
+    }
+
+    /// Encodes `pcm` to Opus packets of `PACKET_FRAMES`, with a coupled stream for stereo in
+    /// family 0 and a mono stream for every channel otherwise
+    fn encode(pcm: &[f32], channels: usize, coupled: u8) -> Vec<Vec<u8>> {
+        let mapping: Vec<u8> = (0..channels as u8).collect();
+        let mut error = 0;
+        let encoder = unsafe {
+            ffi::opus_multistream_encoder_create(
+                SAMPLE_RATE as i32,
+                channels as c_int,
+                channels as c_int - coupled as c_int,
+                coupled as c_int,
+                mapping.as_ptr(),
+                OPUS_APPLICATION_AUDIO,
+                &mut error,
+            )
+        };
+        assert_eq!(error, 0);
+        assert!(!encoder.is_null());
+        unsafe {
+            ffi::opus_multistream_encoder_ctl(
+                encoder,
+                OPUS_SET_BITRATE_REQUEST,
+                96000 * channels as c_int,
+            )
+        };
+
+        // the encoder delay is flushed with silence
+        let mut input = pcm.to_vec();
+        input.resize(input.len() + LOOKAHEAD * channels, 0.0);
+        let packet_len = PACKET_FRAMES * channels;
+        input.resize(input.len().div_ceil(packet_len) * packet_len, 0.0);
+
+        let packets = input
+            .chunks_exact(packet_len)
+            .map(|chunk| {
+                let mut packet = vec![0; 4000];
+                let len = unsafe {
+                    ffi::opus_multistream_encode_float(
+                        encoder,
+                        chunk.as_ptr(),
+                        PACKET_FRAMES as c_int,
+                        packet.as_mut_ptr(),
+                        packet.len() as i32,
+                    )
+                };
+                assert!(len > 0, "encoding failed: {len}");
+                packet.truncate(len as usize);
+                packet
+            })
+            .collect();
+        unsafe { ffi::opus_multistream_encoder_destroy(encoder) };
+        packets
+    }
+
+    /// Ogg Opus file of the reference PCM, the last granule position cutting the flushed delay
+    fn ogg_opus(channels: usize, family: u8, gain: i16) -> Vec<u8> {
+        let coupled = if family == 0 { channels as u8 - 1 } else { 0 };
+        let packets = encode(&reference(channels), channels, coupled);
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&4u32.to_le_bytes());
+        tags.extend_from_slice(b"test");
+        tags.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut writer = OggWriter::new(vec![], 1);
+        let head = opus_head(channels, family, LOOKAHEAD as u16, gain, coupled);
+        writer.write_packet(&head, 0, true).unwrap();
+        writer.write_packet(&tags, 0, true).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            let granule = (LOOKAHEAD + (i + 1) * PACKET_FRAMES).min(LOOKAHEAD + FRAMES);
+            writer.write_packet(packet, granule as u64, false).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Decodes a whole file through the probe, returning its interleaved samples and channels
+    fn decode_file(file: Vec<u8>) -> (Vec<f32>, usize) {
+        let mut hint = Hint::new();
+        hint.with_extension("opus");
+        let media_stream = MediaSourceStream::new(Box::new(Cursor::new(file)), Default::default());
+        let fmt_ops = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let mut format = PROBE
+            .format(&hint, media_stream, &fmt_ops, &MetadataOptions::default())
+            .unwrap()
+            .format;
+        let track = format.default_track().unwrap().clone();
+        let mut decoder = CODEC_REGISTRY
+            .make(&track.codec_params, &DecoderOptions::default())
+            .unwrap();
+
+        let mut samples = vec![];
+        let mut channels = 0;
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break
+                }
+                Err(e) => panic!("can't read the file: {e}"),
+            };
+            let decoded = decoder.decode(&packet).unwrap();
+            channels = decoded.spec().channels.count();
+            let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buf.samples());
+        }
+        (samples, channels)
+    }
+
+    /// Asserts that the output channel `output` is the input channel `input` of the reference,
+    /// multiplied by `gain`, comparing them sample by sample
+    fn assert_channel(decoded: &[f32], channels: usize, output: usize, input: usize, gain: f32) {
+        let reference = reference(input + 1);
+        let (mut signal, mut error) = (0.0, 0.0);
+        // the start and the end are left out, where the codec hasn't settled
+        for frame in PACKET_FRAMES..FRAMES - PACKET_FRAMES {
+            let expected = reference[frame * (input + 1) + input] * gain;
+            let sample = decoded[frame * channels + output];
+            signal += expected * expected;
+            error += (sample - expected) * (sample - expected);
+        }
+        assert!(
+            error < signal * 0.1,
+            "output channel {output} isn't input channel {input} with gain {gain}: \
+             error {error}, signal {signal}"
+        );
+    }
+
+    #[test]
+    fn family_zero_trims_pre_skip() {
+        let (decoded, channels) = decode_file(ogg_opus(2, 0, 0));
+        assert_eq!(channels, 2);
+        assert_eq!(decoded.len(), FRAMES * 2);
+        assert_channel(&decoded, 2, 0, 0, 1.0);
+        assert_channel(&decoded, 2, 1, 1, 1.0);
+    }
+
+    #[test]
+    fn family_zero_applies_header_gain() {
+        // +6 dB in Q7.8
+        let (decoded, _) = decode_file(ogg_opus(2, 0, 6 * 256));
+        let gain = 10.0_f32.powf(6.0 / 20.0);
+        assert_channel(&decoded, 2, 0, 0, gain);
+        assert_channel(&decoded, 2, 1, 1, gain);
+    }
+
+    #[test]
+    fn family_one_reorders_vorbis_channels() {
+        let (decoded, channels) = decode_file(ogg_opus(6, 1, -3 * 256));
+        assert_eq!(channels, 6);
+        assert_eq!(decoded.len(), FRAMES * 6);
+        let gain = 10.0_f32.powf(-3.0 / 20.0);
+        // Vorbis order (FL, FC, FR, RL, RR, LFE) to Symphonia's (FL, FR, FC, LFE, RL, RR)
+        for (output, input) in [0, 2, 1, 5, 3, 4].into_iter().enumerate() {
+            assert_channel(&decoded, 6, output, input, gain);
+        }
+    }
+
+    #[test]
+    fn family_255_keeps_discrete_order() {
+        let (decoded, channels) = decode_file(ogg_opus(3, 255, 0));
+        assert_eq!(channels, 3);
+        assert_eq!(decoded.len(), FRAMES * 3);
+        for channel in 0..3 {
+            assert_channel(&decoded, 3, channel, channel, 1.0);
+        }
+    }
+
+    #[test]
+    fn decoder_trims_pre_skip_without_container() {
+        let packets = encode(&reference(1), 1, 0);
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_OPUS)
+            .with_extra_data(opus_head(1, 0, LOOKAHEAD as u16, 0, 0).into_boxed_slice());
+        let mut decoder = OpusDecoder::try_new(&params, &DecoderOptions::default()).unwrap();
+
+        let mut decoded = vec![];
+        for (i, packet) in packets.into_iter().enumerate() {
+            let packet = Packet::new_from_slice(0, (i * PACKET_FRAMES) as u64, 0, &packet);
+            let buf = decoder.decode(&packet).unwrap();
+            let mut samples = SampleBuffer::<f32>::new(buf.capacity() as u64, *buf.spec());
+            samples.copy_interleaved_ref(buf);
+            decoded.extend_from_slice(samples.samples());
+        }
+        let packets = (FRAMES + LOOKAHEAD).div_ceil(PACKET_FRAMES);
+        assert_eq!(decoded.len(), packets * PACKET_FRAMES - LOOKAHEAD);
+        assert_channel(&decoded, 1, 0, 0, 1.0);
+    }
+
+    #[test]
+    fn discrete_layout_is_limited_to_known_positions() {
+        let positions = Channels::all().bits().count_ones() as usize;
+        let head = |channels: usize| {
+            OpusHead::parse(&opus_head(channels, 255, 0, 0, 0)).expect("valid header")
+        };
+        let (channels, planes) = head(positions).layout().unwrap();
+        assert_eq!(channels.count(), positions);
+        assert_eq!(planes, (0..positions).collect::<Vec<_>>());
+        assert!(head(positions + 1).layout().is_none());
+        assert!(head(255).layout().is_none());
+    }
+}
@@ -2,6 +2,7 @@
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::SampleRate;
+use dasp::sample::FromSample;
 use dasp::Sample;
 use rb::*;
 /// This is a modified version of [symphonia-play's `output.rs`](https://github.com/pdeljanov/Symphonia/blob/master/symphonia-play/src/output.rs)
@@ -9,13 +10,14 @@ use rb::*;
 /// Modifications: support for volume (for all platforms)
 /// Modifications: support for custom name app (only for PulseAudio)
 /// Modifications: completely removed pulseaudio in 1.3.0
+/// Modifications: takes interleaved f32 samples, so that they can be processed beforehand
+/// Modifications: the volume is applied (and limited) by the player
 use std::result;
-use symphonia::core::audio::{AudioBufferRef, RawSample, SampleBuffer, SignalSpec};
-use symphonia::core::conv::ConvertibleSample;
-use symphonia::core::units::Duration;
+use symphonia::core::audio::SignalSpec;
 
 pub trait AudioOutput {
-    fn write(&mut self, decoded: AudioBufferRef<'_>, volume: f32) -> Result<()>;
+    /// Writes interleaved samples, blocking until there's enough space in the ring buffer
+    fn write(&mut self, samples: &[f32]) -> Result<()>;
     #[allow(dead_code)]
     fn flush(&mut self);
 }
@@ -33,7 +35,7 @@ pub type Result<T> = result::Result<T, AudioOutputError>;
 
 pub struct CpalAudioOutput;
 
-trait AudioOutputSample: Sample + ConvertibleSample + RawSample + Send + 'static {}
+trait AudioOutputSample: Sample + FromSample<f32> + Send + 'static {}
 
 impl AudioOutputSample for f32 {}
 
@@ -44,7 +46,7 @@ impl AudioOutputSample for i16 {}
 impl AudioOutputSample for u16 {}
 
 impl CpalAudioOutput {
-    pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
+    pub fn try_open(spec: SignalSpec) -> Result<Box<dyn AudioOutput>> {
         // Get default host.
         let host = cpal::default_host();
 
@@ -70,18 +72,10 @@ impl CpalAudioOutput {
 
         // Select proper playback routine based on sample format.
         match config.sample_format() {
-            cpal::SampleFormat::F32 => {
-                CpalAudioOutputImpl::<f32>::try_open(spec, duration, &device)
-            }
-            cpal::SampleFormat::I32 => {
-                CpalAudioOutputImpl::<i32>::try_open(spec, duration, &device)
-            }
-            cpal::SampleFormat::I16 => {
-                CpalAudioOutputImpl::<i16>::try_open(spec, duration, &device)
-            }
-            cpal::SampleFormat::U16 => {
-                CpalAudioOutputImpl::<u16>::try_open(spec, duration, &device)
-            }
+            cpal::SampleFormat::F32 => CpalAudioOutputImpl::<f32>::try_open(spec, &device),
+            cpal::SampleFormat::I32 => CpalAudioOutputImpl::<i32>::try_open(spec, &device),
+            cpal::SampleFormat::I16 => CpalAudioOutputImpl::<i16>::try_open(spec, &device),
+            cpal::SampleFormat::U16 => CpalAudioOutputImpl::<u16>::try_open(spec, &device),
             _ => {
                 unimplemented!(
                     "sample format not yet implemented: {}",
@@ -97,16 +91,12 @@ where
     T: AudioOutputSample,
 {
     ring_buf_producer: Producer<T>,
-    sample_buf: SampleBuffer<T>,
+    sample_buf: Vec<T>,
     stream: cpal::Stream,
 }
 
 impl<T: AudioOutputSample + cpal::SizedSample> CpalAudioOutputImpl<T> {
-    pub fn try_open(
-        spec: SignalSpec,
-        duration: Duration,
-        device: &cpal::Device,
-    ) -> Result<Box<dyn AudioOutput>> {
+    pub fn try_open(spec: SignalSpec, device: &cpal::Device) -> Result<Box<dyn AudioOutput>> {
         let num_channels = spec.channels.count();
 
         // Output audio stream config.
@@ -129,7 +119,7 @@ impl<T: AudioOutputSample + cpal::SizedSample> CpalAudioOutputImpl<T> {
                 // output.
                 let written = ring_buf_consumer.read(data).unwrap_or(0);
                 // Mute any remaining samples.
-                data[written..].iter_mut().for_each(|s| *s = T::MID);
+                data[written..].iter_mut().for_each(|s| *s = T::EQUILIBRIUM);
             },
             move |err| eprintln!("audio output error: {:?}", err),
             None,
@@ -150,35 +140,30 @@ impl<T: AudioOutputSample + cpal::SizedSample> CpalAudioOutputImpl<T> {
             return Err(AudioOutputError::PlayStreamError);
         }
 
-        let sample_buf = SampleBuffer::<T>::new(duration, spec);
-
         Ok(Box::new(CpalAudioOutputImpl {
             ring_buf_producer,
-            sample_buf,
+            sample_buf: vec![],
             stream,
         }))
     }
 }
 
 impl<T: AudioOutputSample> AudioOutput for CpalAudioOutputImpl<T> {
-    fn write(&mut self, decoded: AudioBufferRef<'_>, volume: f32) -> Result<()> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
         // Do nothing if there are no audio frames.
-        if decoded.frames() == 0 {
+        if samples.is_empty() {
             return Ok(());
         }
 
-        // Audio samples must be interleaved for cpal. Interleave the samples in the audio
-        // buffer into the sample buffer.
-        self.sample_buf.copy_interleaved_ref(decoded);
+        // Convert the samples to the output format.
+        self.sample_buf.clear();
+        self.sample_buf
+            .extend(samples.iter().map(|sample| T::from_sample(*sample)));
 
         // Write all the interleaved samples to the ring buffer.
-        let mut samples: Vec<T> = self.sample_buf.samples().to_vec();
-        for sample in samples.iter_mut() {
-            *sample = sample.mul_amp(volume.to_sample());
-        }
-
-        while let Some(written) = self.ring_buf_producer.write_blocking(samples.as_slice()) {
-            samples = samples[written..].to_vec();
+        let mut samples = self.sample_buf.as_slice();
+        while let Some(written) = self.ring_buf_producer.write_blocking(samples) {
+            samples = &samples[written..];
         }
 
         Ok(())
@@ -190,6 +175,6 @@ impl<T: AudioOutputSample> AudioOutput for CpalAudioOutputImpl<T> {
     }
 }
 
-pub fn try_open(spec: SignalSpec, duration: Duration) -> Result<Box<dyn AudioOutput>> {
-    CpalAudioOutput::try_open(spec, duration)
+pub fn try_open(spec: SignalSpec) -> Result<Box<dyn AudioOutput>> {
+    CpalAudioOutput::try_open(spec)
 }
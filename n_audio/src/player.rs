@@ -1,13 +1,21 @@
+use crate::chapter::{chapter_at, get_chapters, Chapter};
+use crate::dsp::{Dsp, DspSettings};
+use crate::dynamics::{Compressor, Limiter};
+use crate::effect::{Effect, EffectChain};
 use crate::music_track::MusicTrack;
-use crate::{output, Message, TrackTime, CODEC_REGISTRY};
+use crate::silence::{first_audible, SilenceBounds, SilenceCache};
+use crate::tap::{Analyzer, Tap};
+use crate::{output, Message, NError, TrackTime, CODEC_REGISTRY};
 use flume::{Receiver, SendError, Sender};
 use std::ffi::OsStr;
 use std::path::Path;
 use std::thread::JoinHandle;
 use std::{io, thread};
+use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
-use symphonia::core::units::Time;
+use symphonia::core::errors::{Error as SymphoniaError, SeekErrorKind};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo, SeekedTo};
+use symphonia::core::units::{Time, TimeBase, TimeStamp};
 // TODO: update docs
 
 /// The main actor for everything.
@@ -18,7 +26,17 @@ pub struct Player {
     is_paused: bool,
     volume: f32,
     playback_speed: f32,
+    loop_region: Option<(f64, f64)>,
+    loop_crossfade: f64,
+    accurate_seek: bool,
+    dsp: DspSettings,
+    effects: EffectChain,
+    night_mode: bool,
+    tap: Tap,
+    silence_threshold: Option<f32>,
+    silence: SilenceCache,
     cached_get_time: Option<TrackTime>,
+    chapters: Vec<Chapter>,
     thread: Option<JoinHandle<()>>,
     tx: Option<Sender<Message>>,
     rx_t: Option<Receiver<Message>>,
@@ -28,11 +46,27 @@ pub struct Player {
 impl Player {
     /// Instance a new `Player`
     pub fn new(volume: f32, playback_speed: f32) -> Self {
+        Self::with_effects(volume, playback_speed, EffectChain::new())
+    }
+
+    /// Instance a new `Player` that runs the decoded samples through `effects`, after the
+    /// built-in equalizer
+    pub fn with_effects(volume: f32, playback_speed: f32, effects: EffectChain) -> Self {
         Player {
             is_paused: false,
             volume,
             playback_speed,
+            loop_region: None,
+            loop_crossfade: 0.0,
+            accurate_seek: false,
+            dsp: DspSettings::default(),
+            effects,
+            night_mode: false,
+            tap: Tap::new(),
+            silence_threshold: None,
+            silence: SilenceCache::new(),
             cached_get_time: None,
+            chapters: vec![],
             thread: None,
             tx: None,
             rx_t: None,
@@ -69,11 +103,55 @@ impl Player {
         false
     }
 
+    /// Returns the chapters of the current track, if any
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+
+    /// Replaces the chapters of the current track, like with the ones listed by a podcast feed
+    pub fn set_chapters(&mut self, chapters: Vec<Chapter>) {
+        self.chapters = chapters;
+    }
+
+    /// Returns the index of the chapter that is currently playing
+    pub fn current_chapter(&self) -> Option<usize> {
+        chapter_at(&self.chapters, self.cached_get_time?.position)
+    }
+
+    /// Seeks to the start of the next chapter, if any
+    pub async fn next_chapter(&mut self) -> Result<(), NError> {
+        let next = self.current_chapter().map_or(0, |chapter| chapter + 1);
+        if let Some(chapter) = self.chapters.get(next) {
+            let start = chapter.start;
+            self.seek_to(start.trunc() as u64, start.fract()).await?;
+        }
+        Ok(())
+    }
+
+    /// Seeks to the start of the current chapter, or to the previous one if the current chapter
+    /// has been playing for less than 3 seconds
+    pub async fn previous_chapter(&mut self) -> Result<(), NError> {
+        let position = self.cached_get_time.map_or(0.0, |time| time.position);
+        if let Some(mut chapter) = self.current_chapter() {
+            if position - self.chapters[chapter].start < 3.0 {
+                chapter = chapter.saturating_sub(1);
+            }
+            let start = self.chapters[chapter].start;
+            self.seek_to(start.trunc() as u64, start.fract()).await?;
+        }
+        Ok(())
+    }
+
     pub fn get_volume(&self) -> f32 {
         self.volume
     }
 
+    pub fn get_playback_speed(&self) -> f32 {
+        self.playback_speed
+    }
+
     /// Sets the output volume
+    /// Volumes above 1.0 amplify the track, the peaks are then limited instead of clipping
     /// It only errors if it can't send the message (so something serious may have happened)
     pub async fn set_volume(&mut self, volume: f32) -> Result<(), SendError<Message>> {
         if let Some(tx) = &self.tx {
@@ -92,30 +170,148 @@ impl Player {
         if let Some(tx) = &self.tx {
             tx.send_async(Message::PlaybackSpeed(playback_speed))
                 .await?;
-            self.playback_speed = playback_speed;
         }
+        self.playback_speed = playback_speed;
         Ok(())
     }
 
-    /// Seeks to the set timestamp
-    /// Be aware that if the timestamp isn't valid the track thread will panic
+    /// Returns the A-B loop region of the current track, if any
+    pub fn loop_region(&self) -> Option<(f64, f64)> {
+        self.loop_region
+    }
+
+    /// Sets the A-B loop region (in seconds) of the current track
+    /// Every time the playback reaches B it jumps back to A; `None` disables the loop
+    /// Regions where B isn't after A are ignored
     /// It only errors if it can't send the message (so something serious may have happened)
-    pub async fn seek_to(&self, seconds: u64, mut frac: f64) -> Result<(), SendError<Message>> {
+    pub async fn set_loop_region(
+        &mut self,
+        region: Option<(f64, f64)>,
+    ) -> Result<(), SendError<Message>> {
+        let region = region.filter(|(a, b)| a < b);
         if let Some(tx) = &self.tx {
-            if seconds == 0 && frac == 0.0 {
-                frac = 0.01;
-            }
+            tx.send_async(Message::LoopRegion(region)).await?;
+            self.loop_region = region;
+        }
+        Ok(())
+    }
 
-            let time = Time { seconds, frac };
-            if let Some(mut time) = self.cached_get_time {
-                time.position = seconds as f64 + frac;
-            }
+    pub fn get_loop_crossfade(&self) -> f64 {
+        self.loop_crossfade
+    }
+
+    /// Sets the length (in seconds) of the crossfade at the loop seam, 0 disables it
+    /// It only errors if it can't send the message (so something serious may have happened)
+    pub async fn set_loop_crossfade(&mut self, seconds: f64) -> Result<(), SendError<Message>> {
+        let seconds = seconds.max(0.0);
+        if let Some(tx) = &self.tx {
+            tx.send_async(Message::LoopCrossfade(seconds)).await?;
+        }
+        self.loop_crossfade = seconds;
+        Ok(())
+    }
+
+    pub fn dsp(&self) -> &DspSettings {
+        &self.dsp
+    }
+
+    /// Sets the equalizer and the other processing applied to the samples
+    /// It only errors if it can't send the message (so something serious may have happened)
+    pub async fn set_dsp(&mut self, dsp: DspSettings) -> Result<(), SendError<Message>> {
+        if let Some(tx) = &self.tx {
+            tx.send_async(Message::Dsp(dsp.clone())).await?;
+        }
+        self.dsp = dsp;
+        Ok(())
+    }
+
+    /// Returns the effect chain, effects can be added and removed from it even while playing
+    pub fn effects(&self) -> &EffectChain {
+        &self.effects
+    }
+
+    pub fn is_night_mode(&self) -> bool {
+        self.night_mode
+    }
 
-            tx.send_async(Message::Seek(time)).await?;
+    /// Enables or disables the night mode, a strong compression for listening at low volume
+    /// It only errors if it can't send the message (so something serious may have happened)
+    pub async fn set_night_mode(&mut self, night_mode: bool) -> Result<(), SendError<Message>> {
+        if let Some(tx) = &self.tx {
+            tx.send_async(Message::NightMode(night_mode)).await?;
         }
+        self.night_mode = night_mode;
         Ok(())
     }
 
+    /// Returns the spectrum and the levels of what is being played, for visualizers
+    pub fn tap(&self) -> &Tap {
+        &self.tap
+    }
+
+    pub fn silence_threshold(&self) -> Option<f32> {
+        self.silence_threshold
+    }
+
+    /// Enables the trimming of the leading and trailing silence under `threshold` (in dB), starting
+    /// from the next track, `None` disables it
+    /// Changing the threshold clears the silence cache
+    pub fn set_silence_threshold(&mut self, threshold: Option<f32>) {
+        if let (Some(old), Some(new)) = (self.silence_threshold, threshold) {
+            if old != new {
+                self.silence.clear();
+            }
+        }
+        self.silence_threshold = threshold;
+    }
+
+    /// Returns the silence detected in the tracks played with `Player::play_from_path`,
+    /// `Player::play_from_track` or `Player::play_with_path`
+    pub fn silence_cache(&self) -> &SilenceCache {
+        &self.silence
+    }
+
+    pub fn is_accurate_seek(&self) -> bool {
+        self.accurate_seek
+    }
+
+    /// Sets whether `Player::seek_to` lands exactly on the requested timestamp, decoding and
+    /// discarding the samples before it, instead of on the nearest packet
+    pub fn set_accurate_seek(&mut self, accurate_seek: bool) {
+        self.accurate_seek = accurate_seek;
+    }
+
+    /// Seeks to the set timestamp, returning the position (in seconds) it landed on
+    /// Seeking past the end of the track ends it, returning `NError::SeekOutOfRange`
+    pub async fn seek_to(&mut self, seconds: u64, mut frac: f64) -> Result<f64, NError> {
+        let Some(tx) = &self.tx else {
+            return Err(NError::NoTrack);
+        };
+        if seconds == 0 && frac == 0.0 {
+            frac = 0.01;
+        }
+
+        let time = Time { seconds, frac };
+        let mode = if self.accurate_seek {
+            SeekMode::Accurate
+        } else {
+            SeekMode::Coarse
+        };
+        let (tx_s, rx_s) = flume::bounded(1);
+        tx.send_async(Message::Seek(time, mode, tx_s))
+            .await
+            .map_err(|_| NError::Disconnected)?;
+        let position = rx_s
+            .recv_async()
+            .await
+            .map_err(|_| NError::Disconnected)??;
+
+        if let Some(time) = &mut self.cached_get_time {
+            time.position = position;
+        }
+        Ok(position)
+    }
+
     /// Returns the timestamp that was lastly sent by the track thread
     pub fn get_time(&mut self) -> Option<TrackTime> {
         let mut last = self.cached_get_time;
@@ -160,36 +356,107 @@ impl Player {
         Ok(())
     }
 
+    /// Ends the current track, if any, and forgets about it so that `Player::is_playing` returns `false`
+    pub async fn stop(&mut self) {
+        // the track thread may have already ended by itself
+        let _ = self.end_current().await;
+        self.is_paused = false;
+        self.cached_get_time = None;
+        self.loop_region = None;
+        self.chapters.clear();
+        self.thread = None;
+        self.tx = None;
+        self.rx_t = None;
+        self.rx_e = None;
+    }
+
     /// Plays a certain track given its file path
     pub fn play_from_path<P: AsRef<Path> + AsRef<OsStr> + Clone + Into<String>>(
         &mut self,
         path: P,
     ) -> io::Result<()> {
         let music_track = MusicTrack::new(path)?;
-        self.play(music_track.get_format()?);
-
-        Ok(())
+        self.play_from_track(&music_track)
     }
 
     /// Plays a certain track
     pub fn play_from_track(&mut self, track: &MusicTrack) -> io::Result<()> {
-        self.play(track.get_format()?);
+        self.start(track.get_format()?, Some(track.path().to_string()), false);
         Ok(())
     }
 
     /// Plays a certain track given its format
+    /// Only its leading silence can be trimmed, use `Player::play_with_path` to trim all of it
     pub fn play(&mut self, format: Box<dyn FormatReader>) {
-        let volume = self.volume;
-        let playback_speed = self.playback_speed;
+        self.start(format, None, false);
+    }
+
+    /// Loads a certain track given its format without starting the playback
+    /// Call `Player::unpause` to start it
+    pub fn play_paused(&mut self, format: Box<dyn FormatReader>) {
+        self.start(format, None, true);
+    }
+
+    /// Plays a certain track given its format and its path, which identifies it in the silence cache
+    pub fn play_with_path(&mut self, format: Box<dyn FormatReader>, path: String) {
+        self.start(format, Some(path), false);
+    }
+
+    /// Like `Player::play_paused`, with the path used by `Player::play_with_path`
+    pub fn play_paused_with_path(&mut self, format: Box<dyn FormatReader>, path: String) {
+        self.start(format, Some(path), true);
+    }
+
+    fn start(&mut self, mut format: Box<dyn FormatReader>, path: Option<String>, paused: bool) {
+        // the containers whose chapters aren't exposed by Symphonia are read from the file
+        self.chapters = get_chapters(format.as_mut(), path.as_deref());
+        // the time and the loop of the previous track are meaningless now
+        self.cached_get_time = None;
+        self.loop_region = None;
+        let trim = self.silence_threshold.map(|threshold| {
+            if let Some(path) = path
+                .as_ref()
+                .filter(|path| self.silence.get(path).is_none())
+            {
+                let (silence, path) = (self.silence.clone(), path.clone());
+                // it's done much faster than the track is played, meanwhile the leading silence is
+                // trimmed while decoding
+                thread::spawn(move || {
+                    match MusicTrack::new(path.clone())
+                        .and_then(|track| track.silence_bounds(threshold))
+                    {
+                        Ok(bounds) => silence.insert(path, bounds),
+                        Err(err) => {
+                            eprintln!("Couldn't detect the silence of {path}\nError: {err}")
+                        }
+                    }
+                });
+            }
+            SilenceTrim {
+                threshold: 10.0_f32.powf(threshold / 20.0),
+                cache: self.silence.clone(),
+                path,
+            }
+        });
+        let options = ThreadOptions {
+            volume: self.volume,
+            playback_speed: self.playback_speed,
+            loop_crossfade: self.loop_crossfade,
+            dsp: self.dsp.clone(),
+            effects: self.effects.clone(),
+            night_mode: self.night_mode,
+            tap: self.tap.clone(),
+            trim,
+            paused,
+        };
 
         let (tx, rx) = flume::unbounded();
         let (tx_t, rx_t) = flume::unbounded();
         let (tx_e, rx_e) = flume::unbounded();
 
-        let thread =
-            thread::spawn(move || Self::thread_fn(format, rx, tx_t, tx_e, volume, playback_speed));
+        let thread = thread::spawn(move || Self::thread_fn(format, rx, tx_t, tx_e, options));
 
-        self.is_paused = false;
+        self.is_paused = paused;
         self.rx_e = Some(rx_e);
         self.rx_t = Some(rx_t);
         self.tx = Some(tx);
@@ -201,29 +468,62 @@ impl Player {
         rx: Receiver<Message>,
         tx_t: Sender<Message>,
         tx_e: Sender<Message>,
-        mut volume: f32,
-        mut playback_speed: f32,
+        options: ThreadOptions,
     ) {
+        let ThreadOptions {
+            volume,
+            mut playback_speed,
+            mut loop_crossfade,
+            dsp,
+            effects,
+            night_mode,
+            tap,
+            trim,
+            paused: mut is_paused,
+        } = options;
+        let mut volume = Self::volume_gain(volume);
+        let mut dsp = Dsp::new(dsp);
+        let mut night_mode = night_mode.then(Compressor::night);
+        let mut limiter = Limiter::safety();
+        // the rate and the channels the compressor and the limiter are configured for
+        let mut dynamics_format = None;
+        let mut analyzer = Analyzer::new(tap);
+
         // Vars used for audio output
         let track = format.default_track().expect("Can't load tracks");
         let track_id = track.id;
         let time_base = track.codec_params.time_base;
+        let start_ts = track.codec_params.start_ts;
+        // unknown for live streams
         let duration = track
             .codec_params
             .n_frames
-            .map(|frames| track.codec_params.start_ts + frames)
-            .unwrap();
+            .map(|frames| track.codec_params.start_ts + frames);
 
         let mut decoder = CODEC_REGISTRY
             .make(&track.codec_params, &DecoderOptions::default())
             .expect("Can't load decoder");
         let mut audio_output = None;
+        let mut sample_buf: Option<SampleBuffer<f32>> = None;
 
         let mut spec = None;
+        let mut decoded_rate = None;
         let mut dur = None;
 
+        // Vars used for the A-B loop
+        let mut loop_region: Option<(TimeStamp, TimeStamp)> = None;
+        // frames before this timestamp are decoded but not played
+        let mut skip_until: Option<TimeStamp> = None;
+        // the end of the loop, faded out over the start of the next iteration
+        let mut seam: Vec<f32> = vec![];
+
+        // Vars used to trim the silence
+        // frames are dropped until the first audible one, while the bounds aren't detected yet
+        let mut leading_silence = trim.is_some();
+        // the timestamp after the last audible frame, which ends the track
+        let mut audible_end: Option<TimeStamp> = None;
+
         // Vars used to control audio output
-        let mut is_paused = false;
         let mut exit = false;
 
         loop {
@@ -234,31 +534,60 @@ impl Player {
             } {
                 match message {
                     Message::Play => is_paused = false,
-                    Message::Pause => is_paused = true,
-                    // from: https://stackoverflow.com/a/1165198
-                    Message::Volume(v) => volume = 1.0 - (1.0 - (v * v)).sqrt(),
+                    Message::Pause => {
+                        is_paused = true;
+                        analyzer.reset();
+                    }
+                    Message::Volume(v) => volume = Self::volume_gain(v),
                     Message::PlaybackSpeed(speed) => playback_speed = speed,
+                    Message::LoopRegion(region) => {
+                        loop_region = region
+                            .zip(time_base)
+                            .map(|(region, time_base)| Self::loop_timestamps(time_base, region));
+                        seam.clear();
+                    }
+                    Message::LoopCrossfade(seconds) => loop_crossfade = seconds,
+                    Message::Dsp(settings) => dsp.configure(settings),
+                    Message::NightMode(enabled) => {
+                        night_mode = enabled.then(Compressor::night);
+                        dynamics_format = None;
+                    }
                     Message::Exit => {
                         exit = true;
                         break;
                     }
-                    Message::Seek(time) => {
-                        if let Err(err) = format.seek(
-                            SeekMode::Coarse,
-                            SeekTo::Time {
-                                time,
-                                track_id: Some(track_id),
-                            },
-                        ) {
-                            println!("error seeking");
-                            if !err.to_string().contains("end of stream") {
-                                eprintln!(
-                                    "Couldn't seek to position {}+{}\nError: {}",
-                                    time.seconds, time.frac, err
-                                );
-                            } else {
-                                break;
+                    Message::Seek(time, mode, tx_s) => {
+                        seam.clear();
+                        skip_until = None;
+                        let result =
+                            Self::seek(format.as_mut(), track_id, time_base, duration, time, mode);
+                        if let Ok(seeked) = &result {
+                            decoder.reset();
+                            dsp.reset();
+                            effects.reset();
+                            if let Some(compressor) = &mut night_mode {
+                                compressor.reset();
+                            }
+                            limiter.reset();
+                            if mode == SeekMode::Accurate {
+                                skip_until = Some(seeked.required_ts);
                             }
+                            leading_silence = false;
+                        }
+                        let out_of_range = matches!(result, Err(NError::SeekOutOfRange));
+                        // the caller may have stopped waiting for the reply
+                        let _ = tx_s.send(result.map(|seeked| {
+                            let ts = if mode == SeekMode::Accurate {
+                                seeked.required_ts
+                            } else {
+                                seeked.actual_ts
+                            };
+                            time_base.map_or(time.seconds as f64 + time.frac, |time_base| {
+                                Self::seconds(time_base, ts)
+                            })
+                        }));
+                        if out_of_range {
+                            break;
                         }
                     }
                     _ => {}
@@ -284,12 +613,29 @@ impl Player {
                 while !format.metadata().is_latest() {
                     format.metadata().pop();
                 }
+                if let (Some(bounds), Some(time_base), None) = (
+                    trim.as_ref().and_then(SilenceTrim::bounds),
+                    time_base,
+                    audible_end,
+                ) {
+                    let ts =
+                        |seconds: f64| start_ts + time_base.calc_timestamp(Time::from(seconds));
+                    audible_end = Some(ts(bounds.end));
+                    if leading_silence {
+                        leading_silence = false;
+                        skip_until = Some(ts(bounds.start).max(skip_until.unwrap_or(0)));
+                    }
+                }
                 if let Some(time_base) = time_base {
-                    let position = time_base.calc_time(packet.ts());
-                    let length = time_base.calc_time(duration);
+                    let position = time_base.calc_time(packet.ts().max(skip_until.unwrap_or(0)));
+                    let length =
+                        duration.map_or(0.0, |duration| Self::seconds(time_base, duration));
+                    // what's playing now was decoded before the latency of the effects
+                    let latency =
+                        decoded_rate.map_or(0.0, |rate| effects.latency() as f64 / rate as f64);
                     if let Err(err) = tx_t.send(Message::Time(TrackTime {
-                        position: position.seconds as f64 + position.frac,
-                        length: length.seconds as f64 + length.frac,
+                        position: (position.seconds as f64 + position.frac - latency).max(0.0),
+                        length,
                     })) {
                         if let Ok(message) = rx.try_recv() {
                             if let Message::Exit = message {
@@ -305,6 +651,7 @@ impl Player {
                     }
                 }
 
+                let mut jump_to = None;
                 match decoder.decode(&packet) {
                     Ok(decoded) => {
                         if audio_output.is_none() {
@@ -312,8 +659,8 @@ impl Player {
                             tmp_spec.rate = (tmp_spec.rate as f32 * playback_speed).round() as u32;
                             spec = Some(tmp_spec);
                             dur = Some(decoded.capacity() as u64);
-                            audio_output =
-                                Some(output::try_open(spec.unwrap(), dur.unwrap()).unwrap());
+                            audio_output = Some(output::try_open(spec.unwrap()).unwrap());
+                            sample_buf = Some(SampleBuffer::new(dur.unwrap(), *decoded.spec()));
                         } else {
                             let mut new_spec = *decoded.spec();
                             new_spec.rate = (new_spec.rate as f32 * playback_speed).round() as u32;
@@ -328,13 +675,96 @@ impl Player {
                                 changed = true
                             }
                             if changed {
-                                audio_output =
-                                    Some(output::try_open(spec.unwrap(), dur.unwrap()).unwrap());
+                                audio_output = Some(output::try_open(spec.unwrap()).unwrap());
+                                sample_buf = Some(SampleBuffer::new(dur.unwrap(), *decoded.spec()));
+                                seam.clear();
+                            }
+                        }
+
+                        let decoded_spec = *decoded.spec();
+                        let channels = decoded_spec.channels.count();
+                        let Some(sample_buf) = &mut sample_buf else {
+                            continue;
+                        };
+                        sample_buf.copy_interleaved_ref(decoded);
+
+                        // Range of frames of this packet that will be played
+                        let ts = packet.ts();
+                        let frames = sample_buf.samples().len() / channels;
+                        let mut start = 0;
+                        let mut end = frames;
+
+                        if let Some(until) = skip_until {
+                            if ts + frames as u64 <= until {
+                                continue;
                             }
+                            start = until.saturating_sub(ts) as usize;
+                            skip_until = None;
                         }
 
+                        if let Some(trim) = trim.as_ref().filter(|_| leading_silence) {
+                            let samples = &sample_buf.samples()[start * channels..];
+                            match first_audible(samples, channels, trim.threshold) {
+                                Some(first) => {
+                                    start += first;
+                                    leading_silence = false;
+                                }
+                                None => continue,
+                            }
+                        }
+
+                        let mut trailing_silence = false;
+                        if let Some(audible_end) = audible_end {
+                            if ts + frames as u64 >= audible_end {
+                                end = (audible_end.saturating_sub(ts) as usize).clamp(start, end);
+                                trailing_silence = true;
+                            }
+                        }
+
+                        if let Some((a, b)) = loop_region {
+                            if ts + frames as u64 >= b {
+                                end = (b.saturating_sub(ts) as usize).max(start);
+                                jump_to = Some(a);
+                            }
+                        }
+
+                        let mut samples =
+                            sample_buf.samples()[start * channels..end * channels].to_vec();
+                        decoded_rate = Some(decoded_spec.rate);
+                        dsp.process(&mut samples, channels, decoded_spec.rate);
+                        effects.process(&mut samples, channels, decoded_spec.rate);
+
+                        if !seam.is_empty() {
+                            Self::crossfade(&mut samples, &seam, channels);
+                            seam.clear();
+                        }
+                        if jump_to.is_some() && loop_crossfade > 0.0 {
+                            let seam_frames = (loop_crossfade * decoded_spec.rate as f64) as usize;
+                            let seam_frames = seam_frames.min(samples.len() / channels);
+                            seam = samples.split_off(samples.len() - seam_frames * channels);
+                        }
+
+                        let format = Some((decoded_spec.rate, channels));
+                        if dynamics_format != format {
+                            dynamics_format = format;
+                            if let Some(compressor) = &mut night_mode {
+                                compressor.configure(decoded_spec.rate, channels);
+                            }
+                            limiter.configure(decoded_spec.rate, channels);
+                        }
+                        if let Some(compressor) = &mut night_mode {
+                            compressor.process(&mut samples, channels);
+                        }
+                        samples.iter_mut().for_each(|sample| *sample *= volume);
+                        limiter.process(&mut samples, channels);
+                        analyzer.process(&samples, channels, decoded_spec.rate);
+
                         if let Some(audio_output) = &mut audio_output {
-                            audio_output.write(decoded, volume).unwrap()
+                            audio_output.write(&samples).unwrap()
+                        }
+
+                        if trailing_silence && jump_to.is_none() {
+                            break;
                         }
                     }
                     Err(symphonia::core::errors::Error::DecodeError(err)) => {
@@ -345,12 +775,121 @@ impl Player {
                         break;
                     }
                 }
+
+                if let Some(a) = jump_to {
+                    decoder.reset();
+                    match format.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: a, track_id }) {
+                        Ok(seeked) => skip_until = Some(seeked.required_ts),
+                        Err(err) => {
+                            eprintln!("Couldn't loop back to {}\nError: {}", a, err);
+                            loop_region = None;
+                            seam.clear();
+                        }
+                    }
+                }
             }
         }
+        analyzer.reset();
         if !exit {
             tx_e.send(Message::End).expect("Can't send End message");
         }
     }
+
+    /// Seeks `format` to `time`, mapping the errors to the ones reported by `Player::seek_to`
+    fn seek(
+        format: &mut dyn FormatReader,
+        track_id: u32,
+        time_base: Option<TimeBase>,
+        duration: Option<TimeStamp>,
+        time: Time,
+        mode: SeekMode,
+    ) -> Result<SeekedTo, NError> {
+        if let (Some(time_base), Some(duration)) = (time_base, duration) {
+            if time_base.calc_timestamp(time) >= duration {
+                return Err(NError::SeekOutOfRange);
+            }
+        }
+
+        format
+            .seek(
+                mode,
+                SeekTo::Time {
+                    time,
+                    track_id: Some(track_id),
+                },
+            )
+            .map_err(|err| match err {
+                SymphoniaError::SeekError(SeekErrorKind::OutOfRange) => NError::SeekOutOfRange,
+                SymphoniaError::IoError(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    NError::SeekOutOfRange
+                }
+                err => NError::Seek(err.to_string()),
+            })
+    }
+
+    fn seconds(time_base: TimeBase, ts: TimeStamp) -> f64 {
+        let time = time_base.calc_time(ts);
+        time.seconds as f64 + time.frac
+    }
+
+    /// Timestamps of the points of a loop, given in seconds like the positions sent by
+    /// `Message::Time`, which already count from the start of the stream
+    fn loop_timestamps(time_base: TimeBase, (a, b): (f64, f64)) -> (TimeStamp, TimeStamp) {
+        let ts = |seconds: f64| time_base.calc_timestamp(Time::from(seconds));
+        (ts(a), ts(b))
+    }
+
+    /// Maps the volume set by the user to the gain applied to the samples
+    /// from: https://stackoverflow.com/a/1165198
+    fn volume_gain(volume: f32) -> f32 {
+        if volume <= 1.0 {
+            1.0 - (1.0 - (volume * volume)).sqrt()
+        } else {
+            volume
+        }
+    }
+
+    /// Mixes the end of the loop (`seam`) over the start of the next iteration, fading it out
+    fn crossfade(samples: &mut [f32], seam: &[f32], channels: usize) {
+        let seam_frames = seam.len() / channels;
+        for (frame, (samples, seam)) in samples
+            .chunks_mut(channels)
+            .zip(seam.chunks(channels))
+            .enumerate()
+        {
+            let fade_in = (frame + 1) as f32 / (seam_frames + 1) as f32;
+            for (sample, seam) in samples.iter_mut().zip(seam) {
+                *sample = *sample * fade_in + seam * (1.0 - fade_in);
+            }
+        }
+    }
+}
+
+/// Settings the track thread starts with
+struct ThreadOptions {
+    volume: f32,
+    playback_speed: f32,
+    loop_crossfade: f64,
+    dsp: DspSettings,
+    effects: EffectChain,
+    night_mode: bool,
+    tap: Tap,
+    trim: Option<SilenceTrim>,
+    paused: bool,
+}
+
+/// How the track thread trims the silence of the track
+struct SilenceTrim {
+    /// Threshold (linear) under which the samples are silent
+    threshold: f32,
+    cache: SilenceCache,
+    path: Option<String>,
+}
+
+impl SilenceTrim {
+    fn bounds(&self) -> Option<SilenceBounds> {
+        self.cache.get(self.path.as_deref()?)
+    }
 }
 
 impl Default for Player {
@@ -358,3 +897,19 @@ impl Default for Player {
         Self::new(1.0, 1.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loop_points_map_back_to_the_reported_positions() {
+        let time_base = TimeBase::new(1, 48000);
+        // a track whose first packet isn't at 0, like an MP4 with an edit list
+        let start_ts = 3000;
+        let (a, b) = (start_ts + 2 * 48000, start_ts + 3 * 48000);
+        // the positions are reported as the time of the packets
+        let region = (Player::seconds(time_base, a), Player::seconds(time_base, b));
+        assert_eq!(Player::loop_timestamps(time_base, region), (a, b));
+    }
+}
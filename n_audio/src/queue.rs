@@ -1,9 +1,11 @@
 use crate::music_track::MusicTrack;
 use crate::player::Player;
+use crate::stream::StreamInfo;
 use crate::{remove_ext, strip_absolute_path};
 use rand::prelude::SliceRandom;
 use rand::rng;
 use std::cmp::PartialEq;
+use std::collections::HashMap;
 use std::io;
 use std::io::ErrorKind;
 use std::ops::{Deref, DerefMut};
@@ -12,17 +14,52 @@ use std::sync::Arc;
 
 #[derive(Default, Eq, PartialEq, Debug, Clone)]
 pub enum LoopStatus {
+    /// Stops once the end of the queue is reached
+    None,
     #[default]
     Playlist,
     File,
 }
 
+impl From<LoopStatus> for i32 {
+    fn from(value: LoopStatus) -> Self {
+        match value {
+            LoopStatus::None => 0,
+            LoopStatus::Playlist => 1,
+            LoopStatus::File => 2,
+        }
+    }
+}
+
+impl TryFrom<i32> for LoopStatus {
+    type Error = String;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Playlist),
+            2 => Ok(Self::File),
+            _ => Err(format!("{value} is not a valid loop status")),
+        }
+    }
+}
+
 pub struct QueuePlayer {
     queue: Vec<Arc<str>>,
     path: String,
     player: Player,
+    /// Position in the queue, kept while the tracks of the up next queue are played
     index: usize,
+    /// Track of the up next queue being played, if any
+    queued: Option<usize>,
     loop_status: LoopStatus,
+    stop_after: Option<u32>,
+    up_next: Vec<usize>,
+    last_up_next: Option<Vec<usize>>,
+    shuffle: bool,
+    /// Path or URL of the track played outside of the queue, if any
+    outside: Option<String>,
+    stream: Option<StreamInfo>,
 }
 
 impl Default for QueuePlayer {
@@ -39,13 +76,21 @@ impl QueuePlayer {
             queue: vec![],
             player,
             index: usize::MAX - 1,
+            queued: None,
             path,
             loop_status: LoopStatus::Playlist,
+            stop_after: None,
+            up_next: vec![],
+            last_up_next: None,
+            shuffle: true,
+            outside: None,
+            stream: None,
         }
     }
 
+    /// Index of the track being played, which may come from the up next queue
     pub fn index(&self) -> usize {
-        self.index
+        self.queued.unwrap_or(self.index)
     }
 
     pub fn len(&self) -> usize {
@@ -72,6 +117,18 @@ impl QueuePlayer {
         self.loop_status.clone()
     }
 
+    /// Stops the playback after `tracks` tracks have ended by themselves
+    ///
+    /// `Some(1)` stops after the current track, `None` disables it
+    pub fn set_stop_after(&mut self, tracks: Option<u32>) {
+        self.stop_after = tracks.filter(|tracks| *tracks > 0);
+    }
+
+    /// Returns how many tracks are left before stopping, if set
+    pub fn stop_after(&self) -> Option<u32> {
+        self.stop_after
+    }
+
     pub async fn get_path_for_file(&self, i: usize) -> Option<PathBuf> {
         Some(PathBuf::from(&self.path).join(self.queue.get(i)?.as_ref()))
     }
@@ -80,6 +137,15 @@ impl QueuePlayer {
         &self.queue
     }
 
+    /// Sets whether `QueuePlayer::reorder` shuffles or sorts the queue
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    pub fn shuffle_enabled(&self) -> bool {
+        self.shuffle
+    }
+
     #[inline]
     pub fn shrink_to_fit(&mut self) {
         self.path.shrink_to_fit();
@@ -100,65 +166,306 @@ impl QueuePlayer {
         );
     }
 
-    #[inline]
+    /// Removes a track from the queue, keeping the current index and the up next queue pointing
+    /// to the same tracks
     pub fn remove(&mut self, index: usize) {
         self.queue.remove(index);
+
+        if index < self.index && self.index < usize::MAX - 1 {
+            self.index -= 1;
+        } else if index == self.index {
+            // the next track will be the one that took the place of the removed one
+            self.index = self.index.checked_sub(1).unwrap_or(usize::MAX - 1);
+        }
+
+        self.queued = match self.queued {
+            Some(queued) if queued == index => None,
+            Some(queued) if queued > index => Some(queued - 1),
+            queued => queued,
+        };
+
+        self.up_next.retain(|i| *i != index);
+        for i in self.up_next.iter_mut() {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        self.last_up_next = None;
     }
 
     #[inline]
     pub async fn clear(&mut self) {
         self.queue.clear();
+        self.up_next.clear();
+        self.last_up_next = None;
         self.index = usize::MAX - 1;
+        self.queued = None;
+    }
+
+    /// Returns the indexes of the tracks that will be played before continuing with the queue
+    pub fn up_next(&self) -> &[usize] {
+        &self.up_next
+    }
+
+    /// Adds a track to the start of the up next queue
+    pub fn play_after_current(&mut self, index: usize) {
+        if index < self.len() {
+            self.save_up_next();
+            self.up_next.insert(0, index);
+        }
     }
 
+    /// Adds a track to the end of the up next queue
+    pub fn enqueue(&mut self, index: usize) {
+        if index < self.len() {
+            self.save_up_next();
+            self.up_next.push(index);
+        }
+    }
+
+    /// Moves the entry at position `from` of the up next queue to position `to`
+    pub fn move_queued(&mut self, from: usize, to: usize) {
+        if from < self.up_next.len() && from != to {
+            self.save_up_next();
+            let index = self.up_next.remove(from);
+            self.up_next.insert(to.min(self.up_next.len()), index);
+        }
+    }
+
+    /// Removes the entries at the given positions of the up next queue
+    pub fn remove_queued(&mut self, positions: &[usize]) {
+        if positions.iter().any(|p| *p < self.up_next.len()) {
+            self.save_up_next();
+            let mut position = 0;
+            self.up_next.retain(|_| {
+                position += 1;
+                !positions.contains(&(position - 1))
+            });
+        }
+    }
+
+    /// Empties the up next queue
+    pub fn clear_queued(&mut self) {
+        if !self.up_next.is_empty() {
+            self.save_up_next();
+            self.up_next.clear();
+        }
+    }
+
+    /// Reverts the last edit made to the up next queue, returns `false` if there was nothing to revert
+    pub fn undo_queued(&mut self) -> bool {
+        if let Some(up_next) = self.last_up_next.take() {
+            self.up_next = up_next;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn save_up_next(&mut self) {
+        self.last_up_next = Some(self.up_next.clone());
+    }
+
+    /// Shuffles the queue, the up next queue is cleared as its indexes wouldn't be valid anymore
     #[inline]
     pub fn shuffle(&mut self) {
         self.queue.shuffle(&mut rng());
+        self.up_next.clear();
+        self.last_up_next = None;
+        self.queued = None;
+    }
+
+    /// Shuffles the queue if shuffle is enabled, otherwise sorts it by name
+    pub fn reorder(&mut self) {
+        if self.shuffle {
+            self.shuffle();
+        } else {
+            self.queue.sort();
+            self.up_next.clear();
+            self.last_up_next = None;
+            self.queued = None;
+        }
+    }
+
+    /// Reorders the queue following `order`, tracks that aren't in `order` are moved to the end
+    pub fn restore_order<S: AsRef<str>>(&mut self, order: &[S]) {
+        let positions = order
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (track.as_ref(), i))
+            .collect::<HashMap<&str, usize>>();
+        self.queue
+            .sort_by_key(|track| positions.get(track.as_ref()).copied().unwrap_or(usize::MAX));
+        self.up_next.clear();
+        self.last_up_next = None;
+        self.queued = None;
+    }
+
+    /// Replaces the up next queue without recording it as an edit
+    pub fn set_up_next(&mut self, up_next: Vec<usize>) {
+        self.up_next = up_next
+            .into_iter()
+            .filter(|index| *index < self.len())
+            .collect();
+        self.last_up_next = None;
+    }
+
+    pub fn get_index_from_file_name(&self, name: &str) -> Option<usize> {
+        self.queue.iter().position(|t| t.as_ref() == name)
     }
 
     pub async fn current_track_name(&self) -> Option<Arc<str>> {
-        self.queue.get(self.index).map(|t| t.clone())
+        self.queue.get(self.index()).map(|t| t.clone())
     }
 
     pub async fn play(&mut self) -> io::Result<()> {
+        self.start(false).await
+    }
+
+    async fn start(&mut self, paused: bool) -> io::Result<()> {
+        self.outside = None;
+        self.stream = None;
         let track = MusicTrack::new(
-            self.get_path_for_file(self.index)
+            self.get_path_for_file(self.index())
                 .await
                 .ok_or(io::Error::from(ErrorKind::NotFound))?
                 .to_str()
                 .unwrap(),
         )?;
+        let path = track.path().to_string();
         let format = tokio::task::spawn_blocking(move || track.get_format()).await??;
 
+        if paused {
+            self.player.play_paused_with_path(format, path);
+        } else {
+            self.player.play_with_path(format, path);
+        }
+        Ok(())
+    }
+
+    /// Plays the HTTP stream (or the first station of the playlist) at `url` outside of the queue,
+    /// until another track is played or `QueuePlayer::stop_outside` is called
+    pub async fn play_stream(&mut self, url: &str) -> io::Result<()> {
+        let track = MusicTrack::new(url)?;
+        let (format, info) = tokio::task::spawn_blocking(move || track.open_stream()).await??;
+
+        self.outside = Some(url.to_string());
+        self.stream = Some(info);
         self.player.play(format);
         Ok(())
     }
 
+    /// Plays the file at `path` outside of the queue, like `QueuePlayer::play_stream`
+    pub async fn play_file(&mut self, path: &str) -> io::Result<()> {
+        let track = MusicTrack::new(path)?;
+        let format = tokio::task::spawn_blocking(move || track.get_format()).await??;
+
+        self.outside = Some(path.to_string());
+        self.stream = None;
+        self.player.play_with_path(format, path.to_string());
+        Ok(())
+    }
+
+    /// Returns the path or the URL of the track played outside of the queue, if any
+    pub fn outside(&self) -> Option<&str> {
+        self.outside.as_deref()
+    }
+
+    /// Returns the ICY metadata of the stream being played, if any
+    pub fn stream_info(&self) -> Option<&StreamInfo> {
+        self.stream.as_ref()
+    }
+
+    /// Stops the track played outside of the queue, if any, keeping the queue where it was
+    pub async fn stop_outside(&mut self) {
+        self.stream = None;
+        if self.outside.take().is_some() {
+            self.player.stop().await;
+        }
+    }
+
     pub async fn play_index(&mut self, index: usize) -> io::Result<()> {
         self.index = index;
+        self.queued = None;
 
         self.play().await
     }
 
+    /// Loads the track at `index` without starting the playback
+    pub async fn play_index_paused(&mut self, index: usize) -> io::Result<()> {
+        self.index = index;
+        self.queued = None;
+
+        self.start(true).await
+    }
+
+    /// Plays the next track in the queue
+    ///
+    /// When `ignore_loop` is `false` the track has ended by itself, so the loop status and the
+    /// "stop after" counter are taken into account and the playback may be stopped instead
     pub async fn play_next(&mut self, ignore_loop: bool) -> io::Result<()> {
-        if ignore_loop || self.loop_status == LoopStatus::Playlist {
-            self.index += 1;
+        if !ignore_loop {
+            if let Some(tracks) = self.stop_after {
+                self.stop_after = (tracks > 1).then_some(tracks - 1);
+                if tracks == 1 {
+                    self.player.stop().await;
+                    return Ok(());
+                }
+            }
+        }
+
+        if self.advance(ignore_loop) {
+            self.play().await
+        } else {
+            self.player.stop().await;
+            Ok(())
+        }
+    }
+
+    /// Moves to the track played after the current one, returns `false` if the playback has to
+    /// stop instead because the end of the queue was reached
+    fn advance(&mut self, ignore_loop: bool) -> bool {
+        if !ignore_loop && self.loop_status == LoopStatus::File {
+            return true;
+        }
+
+        if !self.up_next.is_empty() {
+            // the queue goes on from its position once the up next queue is empty
+            self.queued = Some(self.up_next.remove(0));
+            return true;
+        }
+        self.queued = None;
+
+        self.index += 1;
 
-            if self.index >= self.len() {
-                self.index = 0;
+        if self.index >= self.len() {
+            if !ignore_loop && self.loop_status == LoopStatus::None {
+                self.index = self.len().saturating_sub(1);
+                return false;
             }
+            self.index = 0;
         }
-        self.play().await
+        true
     }
 
+    /// Plays the previous track in the queue, or the track of the queue that was playing before
+    /// the up next queue
     pub async fn play_previous(&mut self) -> io::Result<()> {
+        self.retreat();
+
+        self.play().await
+    }
+
+    fn retreat(&mut self) {
+        if self.queued.take().is_some() {
+            return;
+        }
+
         if self.index == 0 {
             self.index = self.len();
         }
 
         self.index -= 1;
-
-        self.play().await
     }
 
     pub fn get_index_from_track_name(&self, name: &str) -> Option<usize> {
@@ -184,3 +491,128 @@ impl DerefMut for QueuePlayer {
         &mut self.player
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queue of `len` tracks, at the first one
+    fn queue(len: usize) -> QueuePlayer {
+        let mut queue = QueuePlayer::default();
+        queue.queue = (0..len).map(|i| Arc::from(format!("{i}.flac"))).collect();
+        queue.index = 0;
+        queue
+    }
+
+    #[test]
+    fn tracks_are_queued_in_order() {
+        let mut queue = queue(6);
+        queue.enqueue(3);
+        queue.enqueue(4);
+        queue.play_after_current(5);
+        // out of the queue
+        queue.enqueue(6);
+        assert_eq!(queue.up_next(), [5, 3, 4]);
+
+        queue.move_queued(0, 2);
+        assert_eq!(queue.up_next(), [3, 4, 5]);
+        queue.move_queued(2, 10);
+        assert_eq!(queue.up_next(), [3, 4, 5]);
+        queue.move_queued(1, 0);
+        assert_eq!(queue.up_next(), [4, 3, 5]);
+
+        queue.remove_queued(&[0, 2, 7]);
+        assert_eq!(queue.up_next(), [3]);
+        queue.clear_queued();
+        assert!(queue.up_next().is_empty());
+    }
+
+    #[test]
+    fn removing_a_track_keeps_the_indices_pointing_to_the_same_tracks() {
+        let mut queue = queue(6);
+        queue.index = 3;
+        queue.enqueue(5);
+        queue.enqueue(2);
+        queue.enqueue(1);
+
+        queue.remove(2);
+        assert_eq!(queue.up_next(), [4, 1]);
+        assert_eq!(queue.index(), 2);
+        assert_eq!(queue.queue()[queue.index()].as_ref(), "3.flac");
+
+        queue.remove(0);
+        assert_eq!(queue.up_next(), [3, 0]);
+        assert_eq!(queue.index(), 1);
+        // the removal can't be undone
+        assert!(!queue.undo_queued());
+    }
+
+    #[test]
+    fn undo_restores_the_up_next_queue() {
+        let mut queue = queue(4);
+        assert!(!queue.undo_queued());
+        queue.enqueue(1);
+        queue.enqueue(2);
+        queue.move_queued(1, 0);
+        assert!(queue.undo_queued());
+        assert_eq!(queue.up_next(), [1, 2]);
+        // only the last edit is kept
+        assert!(!queue.undo_queued());
+
+        queue.clear_queued();
+        assert!(queue.undo_queued());
+        assert_eq!(queue.up_next(), [1, 2]);
+        queue.remove_queued(&[0]);
+        assert!(queue.undo_queued());
+        assert_eq!(queue.up_next(), [1, 2]);
+    }
+
+    #[test]
+    fn queue_goes_on_after_the_up_next_tracks() {
+        let mut queue = queue(6);
+        queue.index = 1;
+        queue.enqueue(4);
+        queue.enqueue(0);
+
+        assert!(queue.advance(false));
+        assert_eq!(queue.index(), 4);
+        assert!(queue.advance(false));
+        assert_eq!(queue.index(), 0);
+        assert!(queue.advance(false));
+        assert_eq!(queue.index(), 2);
+    }
+
+    #[test]
+    fn previous_goes_back_to_the_queue() {
+        let mut queue = queue(6);
+        queue.index = 2;
+        queue.enqueue(5);
+        queue.advance(true);
+        assert_eq!(queue.index(), 5);
+        queue.retreat();
+        assert_eq!(queue.index(), 2);
+        queue.retreat();
+        assert_eq!(queue.index(), 1);
+    }
+
+    #[test]
+    fn loop_status_decides_what_follows_the_end() {
+        let mut queue = queue(2);
+        queue.index = 1;
+        queue.set_loop_status(LoopStatus::File);
+        assert!(queue.advance(false));
+        assert_eq!(queue.index(), 1);
+
+        queue.set_loop_status(LoopStatus::Playlist);
+        assert!(queue.advance(false));
+        assert_eq!(queue.index(), 0);
+
+        queue.index = 1;
+        queue.set_loop_status(LoopStatus::None);
+        assert!(!queue.advance(false));
+        assert_eq!(queue.index(), 1);
+        // skipping by hand wraps around anyway
+        assert!(queue.advance(true));
+        assert_eq!(queue.index(), 0);
+    }
+}
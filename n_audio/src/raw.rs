@@ -1,12 +1,17 @@
-use std::io::{Seek, SeekFrom};
+use crate::music_track::MusicTrack;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
 use symphonia::core::{
     audio::Channels,
-    codecs::{CodecParameters, CODEC_TYPE_PCM_F32LE},
+    codecs::{
+        CodecParameters, CodecType, CODEC_TYPE_PCM_F32LE, CODEC_TYPE_PCM_F64LE,
+        CODEC_TYPE_PCM_S16LE, CODEC_TYPE_PCM_S24LE,
+    },
     errors::{self as symph_err, Result as SymphResult, SeekErrorKind},
     formats::prelude::*,
     io::{MediaSource, MediaSourceStream, ReadBytes, SeekBuffered},
     meta::{Metadata as SymphMetadata, MetadataLog},
     probe::{Descriptor, Instantiate, QueryDescriptor},
+    sample::SampleFormat,
     units::TimeStamp,
 };
 
@@ -16,10 +21,10 @@ impl QueryDescriptor for RawReader {
     fn query() -> &'static [Descriptor] {
         &[symphonia_core::support_format!(
             "raw",
-            "Raw arbitrary-length f32 audio container.",
-            &["rawf32"],
+            "Raw arbitrary-length PCM audio container.",
+            &["rawf32", "rawpcm"],
             &[],
-            &[b"SbirdRaw"]
+            &[b"SbirdRaw", b"SbirdRv2"]
         )]
     }
 
@@ -28,56 +33,204 @@ impl QueryDescriptor for RawReader {
     }
 }
 
-/// Symphonia support for a simple container for raw f32-PCM data of unknown duration.
+/// Encoding of the samples of a `SbirdRv2` file, stored after the channel count
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawSampleFormat {
+    F32 = 0,
+    I16 = 1,
+    I24 = 2,
+    F64 = 3,
+}
+
+impl RawSampleFormat {
+    fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Self::F32),
+            1 => Some(Self::I16),
+            2 => Some(Self::I24),
+            3 => Some(Self::F64),
+            _ => None,
+        }
+    }
+
+    /// Size of a sample, in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Self::F32 => 4,
+            Self::I16 => 2,
+            Self::I24 => 3,
+            Self::F64 => 8,
+        }
+    }
+
+    fn codec(self) -> CodecType {
+        match self {
+            Self::F32 => CODEC_TYPE_PCM_F32LE,
+            Self::I16 => CODEC_TYPE_PCM_S16LE,
+            Self::I24 => CODEC_TYPE_PCM_S24LE,
+            Self::F64 => CODEC_TYPE_PCM_F64LE,
+        }
+    }
+
+    fn sample_format(self) -> SampleFormat {
+        match self {
+            Self::F32 => SampleFormat::F32,
+            Self::I16 => SampleFormat::S16,
+            Self::I24 => SampleFormat::S24,
+            Self::F64 => SampleFormat::F64,
+        }
+    }
+}
+
+/// Standard layout for a channel count, with the samples interleaved in the order of the bits
+fn layout(channels: u32) -> Option<Channels> {
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let rear = Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    let side = Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+    Some(match channels {
+        1 => Channels::FRONT_LEFT,
+        2 => front,
+        3 => front | Channels::FRONT_CENTRE,
+        4 => front | rear,
+        5 => front | Channels::FRONT_CENTRE | rear,
+        6 => front | Channels::FRONT_CENTRE | Channels::LFE1 | rear,
+        7 => front | Channels::FRONT_CENTRE | Channels::LFE1 | Channels::REAR_CENTRE | side,
+        8 => front | Channels::FRONT_CENTRE | Channels::LFE1 | rear | side,
+        _ => return None,
+    })
+}
+
+/// Symphonia support for a simple container for raw PCM data of unknown duration.
 ///
 /// Contained files have a simple header:
-/// * the 8-byte signature `b"SbirdRaw"`,
+/// * the 8-byte signature `b"SbirdRaw"`, or `b"SbirdRv2"` for the second version,
 /// * the sample rate, as a little-endian `u32`,
-/// * the channel count, as a little-endian `u32`.
+/// * the channel count (up to 8), as a little-endian `u32`,
+/// * only in the second version, the [`RawSampleFormat`], as a little-endian `u32`.
 ///
-/// The remainder of the file is interleaved little-endian `f32` samples.
+/// The remainder of the file is interleaved little-endian samples, `f32` in the first version.
+/// The channels follow the WAVE order (front left, front right, front centre, LFE, rear left,
+/// rear right, rear centre, side left, side right).
 pub struct RawReader {
     source: MediaSourceStream,
     track: Track,
     meta: MetadataLog,
+    /// Position of the first sample, after the header
+    data_start: u64,
+    /// Size of a frame, in bytes
+    frame_size: usize,
     curr_ts: TimeStamp,
     max_ts: Option<TimeStamp>,
 }
 
+/// Decodes `track` and writes it as a `SbirdRaw` file to `writer`
+///
+/// The header can't describe more than 8 channels, nor a track whose rate or channel count changes
+pub fn write_raw<W: Write>(track: &MusicTrack, writer: W) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    // channels and rate written in the header
+    let mut format = None;
+    let mut bytes = vec![];
+    let mut result = Ok(());
+    track.decode(|samples, channels, rate| {
+        if result.is_err() {
+            return;
+        }
+
+        bytes.clear();
+        match format {
+            None if layout(channels as u32).is_none() => {
+                result = Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "a raw file can only hold between 1 and 8 channels",
+                ));
+                return;
+            }
+            None => {
+                bytes.extend_from_slice(b"SbirdRaw");
+                bytes.extend_from_slice(&rate.to_le_bytes());
+                bytes.extend_from_slice(&(channels as u32).to_le_bytes());
+                format = Some((channels, rate));
+            }
+            Some(format) if format != (channels, rate) => {
+                result = Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the channels or the rate change in the middle of the track",
+                ));
+                return;
+            }
+            Some(_) => {}
+        }
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        result = writer.write_all(&bytes);
+    })?;
+    result?;
+    if format.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the track has no audio to write",
+        ));
+    }
+    writer.flush()
+}
+
 impl FormatReader for RawReader {
     fn try_new(mut source: MediaSourceStream, _options: &FormatOptions) -> SymphResult<Self> {
         let mut magic = [0u8; 8];
         ReadBytes::read_buf_exact(&mut source, &mut magic[..])?;
 
-        if &magic != b"SbirdRaw" {
-            source.seek_buffered_rel(-(magic.len() as isize));
-            return symph_err::decode_error("rawf32: illegal magic byte sequence.");
-        }
+        let versioned = match &magic {
+            b"SbirdRaw" => false,
+            b"SbirdRv2" => true,
+            _ => {
+                source.seek_buffered_rel(-(magic.len() as isize));
+                return symph_err::decode_error("rawpcm: illegal magic byte sequence.");
+            }
+        };
 
         let sample_rate = source.read_u32()?;
         let n_chans = source.read_u32()?;
 
-        let chans = match n_chans {
-            1 => Channels::FRONT_LEFT,
-            2 => Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
-            _ => {
-                return symph_err::decode_error(
-                    "rawf32: channel layout is not stereo or mono for fmt_pcm",
-                )
+        let Some(chans) = layout(n_chans) else {
+            return symph_err::decode_error("rawpcm: channel count is not between 1 and 8");
+        };
+
+        let sample_format = if versioned {
+            match RawSampleFormat::from_id(source.read_u32()?) {
+                Some(sample_format) => sample_format,
+                None => return symph_err::decode_error("rawpcm: unknown sample format"),
             }
+        } else {
+            RawSampleFormat::F32
+        };
+        let data_start = source.pos();
+        let frame_size = sample_format.size() * n_chans as usize;
+
+        // the duration is only known if the whole file can be reached
+        let max_ts = if source.is_seekable() {
+            source
+                .byte_len()
+                .map(|len| len.saturating_sub(data_start) / frame_size as u64)
+        } else {
+            None
         };
 
         let mut codec_params = CodecParameters::new();
 
         codec_params
-            .for_codec(CODEC_TYPE_PCM_F32LE)
-            .with_bits_per_coded_sample((std::mem::size_of::<f32>() as u32) * 8)
-            .with_bits_per_sample((std::mem::size_of::<f32>() as u32) * 8)
+            .for_codec(sample_format.codec())
+            .with_bits_per_coded_sample((sample_format.size() as u32) * 8)
+            .with_bits_per_sample((sample_format.size() as u32) * 8)
             .with_sample_rate(sample_rate)
             .with_time_base(TimeBase::new(1, sample_rate))
-            .with_sample_format(symphonia_core::sample::SampleFormat::F32)
+            .with_sample_format(sample_format.sample_format())
             .with_max_frames_per_packet(sample_rate as u64 / 50)
             .with_channels(chans);
+        if let Some(max_ts) = max_ts {
+            codec_params.with_n_frames(max_ts);
+        }
 
         Ok(Self {
             source,
@@ -87,12 +240,15 @@ impl FormatReader for RawReader {
                 codec_params,
             },
             meta: MetadataLog::default(),
+            data_start,
+            frame_size,
             curr_ts: 0,
-            max_ts: None,
+            max_ts,
         })
     }
 
     fn cues(&self) -> &[Cue] {
+        // the header has no room for metadata, so there are no cues to expose
         &[]
     }
 
@@ -128,13 +284,7 @@ impl FormatReader for RawReader {
             return symph_err::seek_error(SeekErrorKind::ForwardOnly);
         }
 
-        let chan_count = track
-            .codec_params
-            .channels
-            .expect("Channel count is built into format.")
-            .count() as u64;
-
-        let seek_pos = 16 + (std::mem::size_of::<f32>() as u64) * (ts * chan_count);
+        let seek_pos = self.data_start + self.frame_size as u64 * ts;
 
         self.source.seek(SeekFrom::Start(seek_pos))?;
         self.curr_ts = ts;
@@ -161,19 +311,30 @@ impl FormatReader for RawReader {
             .sample_rate
             .expect("Sample rate is built into format.") as usize;
 
-        let chan_count = track
-            .codec_params
-            .channels
-            .expect("Channel count is built into format.")
-            .count();
-
-        let sample_unit = std::mem::size_of::<f32>() * chan_count;
+        let sample_unit = self.frame_size;
 
         // Aim for 20ms (50Hz).
-        let buf = self.source.read_boxed_slice((rate / 50) * sample_unit)?;
+        let mut buf = vec![0; (rate / 50) * sample_unit];
+        // a read can stop in the middle of a frame, so the packet is filled up to the end of the
+        // file, and only whole frames are kept
+        let mut len = 0;
+        while len < buf.len() {
+            match self.source.read_buf(&mut buf[len..]) {
+                Ok(read) => len += read,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && len > 0 => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        buf.truncate(len - len % sample_unit);
+        if buf.is_empty() {
+            return Err(symph_err::Error::IoError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "rawpcm: end of stream",
+            )));
+        }
 
         let sample_ct = (buf.len() / sample_unit) as u64;
-        let out = Packet::new_from_boxed_slice(0, self.curr_ts, sample_ct, buf);
+        let out = Packet::new_from_boxed_slice(0, self.curr_ts, sample_ct, buf.into_boxed_slice());
 
         self.curr_ts += sample_ct;
 
@@ -184,3 +345,215 @@ impl FormatReader for RawReader {
         self.source
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use symphonia::core::{
+        audio::SampleBuffer,
+        codecs::{Decoder, DecoderOptions},
+        io::ReadOnlySource,
+    };
+    use tempfile::TempDir;
+
+    const RATE: u32 = 48000;
+
+    /// A `SbirdRv2` file holding `samples`, encoded with `format`
+    fn v2(channels: u32, format: RawSampleFormat, samples: &[f64]) -> Vec<u8> {
+        let mut file = b"SbirdRv2".to_vec();
+        file.extend_from_slice(&RATE.to_le_bytes());
+        file.extend_from_slice(&channels.to_le_bytes());
+        file.extend_from_slice(&(format as u32).to_le_bytes());
+        for &sample in samples {
+            match format {
+                RawSampleFormat::F32 => file.extend_from_slice(&(sample as f32).to_le_bytes()),
+                RawSampleFormat::I16 => {
+                    file.extend_from_slice(&((sample * 32768.0) as i16).to_le_bytes())
+                }
+                RawSampleFormat::I24 => {
+                    file.extend_from_slice(&((sample * 8388608.0) as i32).to_le_bytes()[..3])
+                }
+                RawSampleFormat::F64 => file.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        file
+    }
+
+    fn open(file: Vec<u8>) -> SymphResult<RawReader> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(file)), Default::default());
+        RawReader::try_new(source, &FormatOptions::default())
+    }
+
+    /// Decodes every packet of `reader` to interleaved `f32` samples
+    fn decode(reader: &mut RawReader) -> Vec<f32> {
+        let mut decoder = crate::CODEC_REGISTRY
+            .make(&reader.track.codec_params, &DecoderOptions::default())
+            .unwrap();
+        let mut samples = vec![];
+        while let Ok(packet) = reader.next_packet() {
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        samples
+    }
+
+    /// 16-bit stereo WAVE file at 48 kHz of `frames` frames of two sines
+    fn wav(frames: usize) -> Vec<u8> {
+        let data = (0..frames)
+            .flat_map(|i| [(i as f32 * 0.05).sin(), (i as f32 * 0.03).cos()])
+            .flat_map(|sample| ((sample * 8000.0) as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b"WAVEfmt ");
+        file.extend_from_slice(&16_u32.to_le_bytes());
+        // PCM, 2 channels, rate, byte rate, block align, bits per sample
+        file.extend_from_slice(&1_u16.to_le_bytes());
+        file.extend_from_slice(&2_u16.to_le_bytes());
+        file.extend_from_slice(&RATE.to_le_bytes());
+        file.extend_from_slice(&(RATE * 4).to_le_bytes());
+        file.extend_from_slice(&4_u16.to_le_bytes());
+        file.extend_from_slice(&16_u16.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn first_version_holds_f32_samples() {
+        let mut file = b"SbirdRaw".to_vec();
+        file.extend_from_slice(&RATE.to_le_bytes());
+        file.extend_from_slice(&1_u32.to_le_bytes());
+        for sample in [0.25_f32, -0.5, 1.0] {
+            file.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let mut reader = open(file).unwrap();
+        let params = &reader.track.codec_params;
+        assert_eq!(params.codec, CODEC_TYPE_PCM_F32LE);
+        assert_eq!(params.sample_rate, Some(RATE));
+        assert_eq!(params.n_frames, Some(3));
+        assert_eq!(reader.data_start, 16);
+        assert_eq!(decode(&mut reader), [0.25, -0.5, 1.0]);
+    }
+
+    #[test]
+    fn second_version_reads_every_sample_format() {
+        let samples = [0.5, -0.5, 0.25, -0.25];
+        for format in [
+            RawSampleFormat::F32,
+            RawSampleFormat::I16,
+            RawSampleFormat::I24,
+            RawSampleFormat::F64,
+        ] {
+            let mut reader = open(v2(2, format, &samples)).unwrap();
+            let params = &reader.track.codec_params;
+            assert_eq!(params.codec, format.codec());
+            assert_eq!(params.sample_format, Some(format.sample_format()));
+            assert_eq!(params.bits_per_sample, Some(format.size() as u32 * 8));
+            assert_eq!(params.n_frames, Some(2));
+            assert_eq!(reader.data_start, 20);
+            assert_eq!(reader.frame_size, 2 * format.size());
+            assert_eq!(decode(&mut reader), [0.5, -0.5, 0.25, -0.25], "{format:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_headers_are_rejected() {
+        let mut file = v2(1, RawSampleFormat::F32, &[0.0]);
+        file[16..20].copy_from_slice(&4_u32.to_le_bytes());
+        assert!(open(file).is_err());
+
+        let mut file = v2(1, RawSampleFormat::F32, &[0.0]);
+        file[..8].copy_from_slice(b"SbirdRv3");
+        assert!(open(file).is_err());
+    }
+
+    #[test]
+    fn layouts_follow_the_channel_count() {
+        for channels in 1..=8 {
+            let samples = vec![0.0; channels as usize];
+            let reader = open(v2(channels, RawSampleFormat::I16, &samples)).unwrap();
+            let chans = reader.track.codec_params.channels.unwrap();
+            assert_eq!(chans.count(), channels as usize);
+            assert_eq!(Some(chans), layout(channels));
+        }
+        assert!(layout(6).unwrap().contains(Channels::LFE1));
+        assert!(layout(7).unwrap().contains(Channels::REAR_CENTRE));
+
+        for channels in [0, 9] {
+            assert!(open(v2(channels, RawSampleFormat::I16, &[])).is_err());
+        }
+    }
+
+    #[test]
+    fn length_counts_whole_frames() {
+        // 7 stereo 24-bit frames, and the start of an 8th one
+        let mut file = v2(2, RawSampleFormat::I24, &[0.0; 14]);
+        file.extend_from_slice(&[0; 4]);
+
+        let mut reader = open(file).unwrap();
+        assert_eq!(reader.max_ts, Some(7));
+        assert_eq!(reader.track.codec_params.n_frames, Some(7));
+        assert!(reader
+            .seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: 8, track_id: 0 })
+            .is_err());
+
+        let seeked = reader
+            .seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: 3, track_id: 0 })
+            .unwrap();
+        assert_eq!(seeked.actual_ts, 3);
+        let packet = reader.next_packet().unwrap();
+        assert_eq!((packet.ts, packet.dur), (3, 4));
+        assert!(reader.next_packet().is_err());
+    }
+
+    #[test]
+    fn unseekable_sources_have_no_length() {
+        let file = v2(1, RawSampleFormat::F32, &[0.0; 10]);
+        let source = MediaSourceStream::new(
+            Box::new(ReadOnlySource::new(Cursor::new(file))),
+            Default::default(),
+        );
+        let reader = RawReader::try_new(source, &FormatOptions::default()).unwrap();
+        assert_eq!(reader.max_ts, None);
+        assert_eq!(reader.track.codec_params.n_frames, None);
+    }
+
+    #[test]
+    fn written_files_hold_the_decoded_track() {
+        let frames = 10000;
+        let dir = TempDir::new().unwrap();
+        let wav_path = dir.path().join("track.wav");
+        fs::write(&wav_path, wav(frames)).unwrap();
+        let track = MusicTrack::new(wav_path.to_str().unwrap()).unwrap();
+
+        let raw_path = dir.path().join("track.rawpcm");
+        write_raw(&track, fs::File::create(&raw_path).unwrap()).unwrap();
+        let file = fs::read(&raw_path).unwrap();
+        assert_eq!(&file[..8], b"SbirdRaw");
+        assert_eq!(file[8..12], RATE.to_le_bytes());
+        assert_eq!(file[12..16], 2_u32.to_le_bytes());
+        assert_eq!(file.len(), 16 + frames * 2 * 4);
+
+        let mut expected = vec![];
+        track
+            .decode(|samples, _, _| expected.extend_from_slice(samples))
+            .unwrap();
+        let raw_track = MusicTrack::new(raw_path.to_str().unwrap()).unwrap();
+        let mut samples = vec![];
+        raw_track
+            .decode(|decoded, _, _| samples.extend_from_slice(decoded))
+            .unwrap();
+        assert_eq!(samples, expected);
+        assert_eq!(
+            raw_track.get_length().unwrap().length,
+            track.get_length().unwrap().length
+        );
+    }
+}
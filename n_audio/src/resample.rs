@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+
+/// Frames on every side of an output frame used to compute it
+const HALF_TAPS: usize = 16;
+
+/// Streaming resampler with a windowed sinc, for interleaved samples
+pub(crate) struct Resampler {
+    channels: usize,
+    /// Input frames for every output frame
+    step: f64,
+    /// Cutoff of the low-pass filter, relative to the input Nyquist frequency
+    cutoff: f64,
+    /// Input frames still needed, interleaved
+    buffer: Vec<f32>,
+    /// Position of the next output frame in `buffer`, in frames
+    position: f64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize) -> Self {
+        let step = from as f64 / to as f64;
+        Self {
+            channels,
+            step,
+            // a bit under the lower Nyquist frequency, to leave room to the transition band
+            cutoff: (1.0 / step).min(1.0) * 0.95,
+            // the first output frames need the ones before the start
+            buffer: vec![0.0; HALF_TAPS * channels],
+            position: HALF_TAPS as f64,
+        }
+    }
+
+    fn kernel(&self, x: f64) -> f64 {
+        let t = x / HALF_TAPS as f64;
+        if t.abs() >= 1.0 {
+            return 0.0;
+        }
+        let window = 0.5 * (1.0 + (PI * t).cos());
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * self.cutoff * x).sin() / (PI * self.cutoff * x)
+        };
+        sinc * window
+    }
+
+    /// Resamples `input`, appending to `output` the frames that can be computed
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        self.buffer.extend_from_slice(input);
+        let frames = self.buffer.len() / channels;
+
+        let mut sums = vec![0.0_f64; channels];
+        while (self.position as usize) + HALF_TAPS < frames {
+            let center = self.position as usize;
+            let frac = self.position - center as f64;
+
+            sums.iter_mut().for_each(|sum| *sum = 0.0);
+            let mut total = 0.0;
+            for index in center + 1 - HALF_TAPS..=center + HALF_TAPS {
+                let weight = self.kernel(index as f64 - center as f64 - frac);
+                total += weight;
+                let frame = &self.buffer[index * channels..(index + 1) * channels];
+                for (sum, sample) in sums.iter_mut().zip(frame) {
+                    *sum += *sample as f64 * weight;
+                }
+            }
+            // normalizing keeps the gain constant for every fractional position
+            output.extend(sums.iter().map(|sum| (sum / total) as f32));
+
+            self.position += self.step;
+        }
+
+        let consumed = (self.position as usize).saturating_sub(HALF_TAPS);
+        self.buffer.drain(..consumed * channels);
+        self.position -= consumed as f64;
+    }
+
+    /// Resamples the frames left, as if the input ended with silence
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let silence = vec![0.0; HALF_TAPS * self.channels];
+        self.process(&silence, output);
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Where the audio of a track starts and ends, in seconds, once its leading and trailing silence
+/// are removed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SilenceBounds {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// The [`SilenceBounds`] already detected, by the path of their track
+///
+/// Cloning it returns a handle to the same cache
+#[derive(Clone, Debug, Default)]
+pub struct SilenceCache(Arc<Mutex<HashMap<String, SilenceBounds>>>);
+
+impl SilenceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SilenceBounds>> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn get(&self, path: &str) -> Option<SilenceBounds> {
+        self.lock().get(path).copied()
+    }
+
+    pub fn insert(&self, path: String, bounds: SilenceBounds) {
+        self.lock().insert(path, bounds);
+    }
+
+    /// Adds previously detected bounds, e.g. loaded from disk
+    pub fn extend<I: IntoIterator<Item = (String, SilenceBounds)>>(&self, entries: I) {
+        self.lock().extend(entries);
+    }
+
+    /// Returns a copy of all the bounds, e.g. to save them
+    pub fn entries(&self) -> HashMap<String, SilenceBounds> {
+        self.lock().clone()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+}
+
+/// Returns the index of the first frame with a sample louder than `threshold` (linear)
+pub(crate) fn first_audible(samples: &[f32], channels: usize, threshold: f32) -> Option<usize> {
+    samples
+        .chunks_exact(channels)
+        .position(|frame| frame.iter().any(|sample| sample.abs() > threshold))
+}
+
+/// Returns the index of the last frame with a sample louder than `threshold` (linear)
+pub(crate) fn last_audible(samples: &[f32], channels: usize, threshold: f32) -> Option<usize> {
+    samples
+        .chunks_exact(channels)
+        .rposition(|frame| frame.iter().any(|sample| sample.abs() > threshold))
+}
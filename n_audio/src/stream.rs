@@ -0,0 +1,787 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use symphonia::core::io::MediaSource;
+use ureq::http::{Response, Uri};
+use ureq::{Agent, Body};
+
+/// Time allowed to connect to a server
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed to a server to answer a request, and then to send every part of the body
+pub const READ_TIMEOUT: Duration = Duration::from_secs(15);
+/// Bytes read at once from the body of a response
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Longest playlist read, which should be a few lines
+const MAX_PLAYLIST_SIZE: u64 = 1024 * 1024;
+/// Longest header read from a Shoutcast v1 server
+const MAX_ICY_HEADER_SIZE: u64 = 64 * 1024;
+/// Reconnections tried in a row before giving up, waiting twice as long after every failed one
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// How many playlists listing other playlists are followed
+const MAX_PLAYLIST_DEPTH: usize = 3;
+
+/// Returns whether `path` is an `http(s)://` URL instead of a file
+pub fn is_url(path: &str) -> bool {
+    let scheme = path.get(..8).unwrap_or(path).to_ascii_lowercase();
+    scheme.starts_with("http://") || scheme.starts_with("https://")
+}
+
+/// Information sent by the server of a stream, updated while it's played
+#[derive(Clone, Debug, Default)]
+pub struct StreamInfo(Arc<RwLock<IcyInfo>>);
+
+#[derive(Debug, Default)]
+struct IcyInfo {
+    name: Option<String>,
+    title: Option<String>,
+}
+
+impl StreamInfo {
+    /// Name of the station (`icy-name`)
+    pub fn name(&self) -> Option<String> {
+        self.0.read().unwrap().name.clone()
+    }
+
+    /// Title of what's being played (`StreamTitle`), usually as `Artist - Title`
+    pub fn title(&self) -> Option<String> {
+        self.0.read().unwrap().title.clone()
+    }
+}
+
+/// Entry of a PLS or M3U playlist
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlaylistEntry {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+/// Parses a PLS or M3U playlist, keeping only the entries that are URLs
+pub fn parse_playlist(content: &str) -> Vec<PlaylistEntry> {
+    let content = content.trim_start_matches('\u{feff}').trim_start();
+    if content.to_ascii_lowercase().starts_with("[playlist]") {
+        parse_pls(content)
+    } else {
+        parse_m3u(content)
+    }
+}
+
+fn parse_pls(content: &str) -> Vec<PlaylistEntry> {
+    // the entries are numbered, and their keys can be in any order
+    let mut entries: BTreeMap<u32, (Option<String>, Option<String>)> = BTreeMap::new();
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim().to_string();
+        if let Some(number) = key.strip_prefix("file").and_then(|n| n.parse().ok()) {
+            entries.entry(number).or_default().0 = Some(value);
+        } else if let Some(number) = key.strip_prefix("title").and_then(|n| n.parse().ok()) {
+            entries.entry(number).or_default().1 = Some(value);
+        }
+    }
+
+    entries
+        .into_values()
+        .filter_map(|(url, title)| {
+            Some(PlaylistEntry {
+                url: url.filter(|url| is_url(url))?,
+                title: title.filter(|title| !title.is_empty()),
+            })
+        })
+        .collect()
+}
+
+fn parse_m3u(content: &str) -> Vec<PlaylistEntry> {
+    let mut entries = vec![];
+    let mut title = None;
+    for line in content.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if is_url(line) {
+            entries.push(PlaylistEntry {
+                url: line.to_string(),
+                title: title.take(),
+            });
+        } else {
+            title = None;
+        }
+    }
+    entries
+}
+
+/// Returns the stations listed by the playlist at `url`, or the stream at `url` itself named by its
+/// server
+pub fn resolve_stations(url: &str) -> io::Result<Vec<PlaylistEntry>> {
+    let agent = agent();
+    let response = request(&agent, url, 0)?;
+    if is_playlist(&content_type(&response), url) {
+        return Ok(parse_playlist(&read_playlist(response)?));
+    }
+
+    Ok(vec![PlaylistEntry {
+        url: url.to_string(),
+        title: header(&response, "icy-name").filter(|name| !name.is_empty()),
+    }])
+}
+
+fn agent() -> Agent {
+    // the body of a live stream never ends, so it's read by a `TimeoutReader` instead of having a
+    // deadline
+    Agent::new_with_config(
+        Agent::config_builder()
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_recv_response(Some(READ_TIMEOUT))
+            .build(),
+    )
+}
+
+/// Reads the whole body of a playlist
+fn read_playlist(response: Response<Body>) -> io::Result<String> {
+    let mut playlist = vec![];
+    TimeoutReader::new(response.into_body().into_reader())
+        .take(MAX_PLAYLIST_SIZE)
+        .read_to_end(&mut playlist)?;
+    Ok(String::from_utf8_lossy(&playlist).into_owned())
+}
+
+/// Reader of the body of a response on its own thread, failing when the server sends nothing for
+/// [`READ_TIMEOUT`] instead of waiting forever
+pub struct TimeoutReader {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl TimeoutReader {
+    pub fn new(mut reader: impl Read + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::sync_channel(4);
+        // the thread ends with the body, or when the reader is dropped and the next chunk comes
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let result = match reader.read(&mut chunk) {
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Ok(chunk)
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+            let end = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+            if tx.send(result).is_err() || end {
+                break;
+            }
+        });
+
+        Self {
+            rx,
+            chunk: vec![],
+            read: 0,
+        }
+    }
+}
+
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read == self.chunk.len() {
+            match self.rx.recv_timeout(READ_TIMEOUT) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.read = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the server stopped sending data",
+                    ))
+                }
+                // the body has ended
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.read);
+        buf[..len].copy_from_slice(&self.chunk[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+/// Asks for `url` from the byte `from`, and for the ICY metadata of live streams
+fn request(agent: &Agent, url: &str, from: u64) -> io::Result<Response<Body>> {
+    let mut request = agent.get(url).header("Icy-MetaData", "1");
+    if from > 0 {
+        request = request.header("Range", format!("bytes={from}-"));
+    }
+    match request.call() {
+        // old Shoutcast servers answer `ICY 200 OK`, which isn't a valid HTTP status line
+        Err(ureq::Error::Protocol(err)) => {
+            icy_request(url).map_err(|_| io::Error::other(ureq::Error::Protocol(err)))
+        }
+        result => result.map_err(io::Error::other),
+    }
+}
+
+/// Asks for the live stream at the `http://` `url` of a Shoutcast v1 server, reading its
+/// `ICY 200 OK` response by hand
+fn icy_request(url: &str) -> io::Result<Response<Body>> {
+    let uri = url
+        .parse::<Uri>()
+        .ok()
+        .filter(|uri| uri.scheme_str() == Some("http"))
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let host = uri
+        .host()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let port = uri.port_u16().unwrap_or(80);
+
+    let mut last_err = io::Error::from(io::ErrorKind::NotFound);
+    let mut stream = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    let mut stream = stream.ok_or(last_err)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+    write!(
+        stream,
+        "GET {path} HTTP/1.0\r\nHost: {host}\r\nIcy-MetaData: 1\r\n\r\n"
+    )?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid ICY response");
+    let mut reader = BufReader::new(stream);
+    let mut header = (&mut reader).take(MAX_ICY_HEADER_SIZE);
+    let mut line = String::new();
+    header.read_line(&mut line)?;
+    let status = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["ICY", status, ..] => status.parse::<u16>().map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    if !(200..300).contains(&status) {
+        return Err(io::Error::other(format!("the server answered {status}")));
+    }
+
+    let mut response = Response::builder().status(status);
+    loop {
+        line.clear();
+        if header.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            response = response.header(name.trim(), value.trim());
+        }
+    }
+    // what was read past the header is already the audio
+    response
+        .body(Body::builder().reader(reader))
+        .map_err(io::Error::other)
+}
+
+fn header(response: &Response<Body>, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+}
+
+/// MIME type of the body of `response`, in lowercase and without its parameters
+fn content_type(response: &Response<Body>) -> String {
+    header(response, "content-type")
+        .and_then(|value| value.split(';').next().map(str::to_ascii_lowercase))
+        .unwrap_or_default()
+}
+
+fn url_extension(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next()?;
+    let path = path.split_once("://").map_or(path, |(_, rest)| rest);
+    let (_, path) = path.split_once('/')?;
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+fn is_playlist(content_type: &str, url: &str) -> bool {
+    matches!(
+        content_type,
+        "audio/x-scpls" | "audio/scpls" | "audio/x-mpegurl" | "audio/mpegurl"
+    ) || matches!(url_extension(url).as_deref(), Some("pls" | "m3u"))
+}
+
+/// Media source reading a file or a live stream over HTTP(S)
+///
+/// The ICY metadata interleaved in the audio by Shoutcast and Icecast servers is removed and kept in
+/// the [`StreamInfo`] of the stream. A lost connection is opened again, waiting longer after every
+/// failed attempt: files are resumed where they were left, and can be seeked if the server supports
+/// ranges
+pub struct HttpStream {
+    agent: Agent,
+    url: String,
+    content_type: String,
+    reader: Mutex<Box<dyn Read + Send>>,
+    /// Bytes of audio between two ICY metadata blocks
+    metaint: Option<usize>,
+    /// Bytes of audio left before the next ICY metadata block
+    until_meta: usize,
+    position: u64,
+    /// Length of the file, `None` for live streams
+    len: Option<u64>,
+    seekable: bool,
+    info: StreamInfo,
+}
+
+impl HttpStream {
+    /// Connects to `url`, following the PLS and M3U playlists to their first stream
+    pub fn open(url: &str) -> io::Result<Self> {
+        let agent = agent();
+        let mut url = url.to_string();
+        for _ in 0..=MAX_PLAYLIST_DEPTH {
+            let response = request(&agent, &url, 0)?;
+            let content_type = content_type(&response);
+            if !is_playlist(&content_type, &url) {
+                let mut stream = Self {
+                    agent,
+                    url,
+                    content_type,
+                    reader: Mutex::new(Box::new(io::empty())),
+                    metaint: None,
+                    until_meta: 0,
+                    position: 0,
+                    len: None,
+                    seekable: false,
+                    info: StreamInfo::default(),
+                };
+                stream.accept(response, 0)?;
+                return Ok(stream);
+            }
+
+            url = parse_playlist(&read_playlist(response)?)
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "the playlist has no streams")
+                })?
+                .url;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "too many nested playlists",
+        ))
+    }
+
+    pub fn info(&self) -> StreamInfo {
+        self.info.clone()
+    }
+
+    /// Extension of the format of the stream, guessed from its MIME type or its URL
+    pub fn extension(&self) -> Option<String> {
+        let ext = match self.content_type.as_str() {
+            "audio/mpeg" | "audio/mp3" => "mp3",
+            "audio/aac" | "audio/aacp" | "audio/x-aac" => "aac",
+            "audio/ogg" | "application/ogg" | "audio/opus" | "audio/vorbis" => "ogg",
+            "audio/flac" | "audio/x-flac" => "flac",
+            "audio/wav" | "audio/wave" | "audio/x-wav" => "wav",
+            "audio/mp4" | "audio/x-m4a" => "m4a",
+            _ => return url_extension(&self.url),
+        };
+        Some(ext.to_string())
+    }
+
+    /// Starts reading the body of `response`, asked from the byte `from`
+    fn accept(&mut self, response: Response<Body>, from: u64) -> io::Result<()> {
+        let partial = response.status().as_u16() == 206;
+        let metaint = header(&response, "icy-metaint")
+            .and_then(|value| value.parse().ok())
+            .filter(|metaint| *metaint > 0);
+        if let Some(name) = header(&response, "icy-name").filter(|name| !name.is_empty()) {
+            self.info.0.write().unwrap().name = Some(name);
+        }
+        if !partial {
+            // live streams have no length, even if some servers send a huge one
+            self.len = header(&response, "content-length")
+                .and_then(|value| value.parse().ok())
+                .filter(|_| metaint.is_none());
+        }
+        let ranges = header(&response, "accept-ranges").is_some_and(|value| value == "bytes");
+        self.seekable = self.len.is_some() && (partial || ranges);
+        self.metaint = metaint;
+        self.until_meta = metaint.unwrap_or(0);
+
+        let mut reader = TimeoutReader::new(response.into_body().into_reader());
+        if partial {
+            self.position = from;
+        } else if self.len.is_some() {
+            // the server sent the file from the start
+            io::copy(&mut (&mut reader).take(from), &mut io::sink())?;
+            self.position = from;
+        }
+        // otherwise a live stream goes on from what is being broadcast now
+        self.reader = Mutex::new(Box::new(reader));
+        Ok(())
+    }
+
+    /// Connects again after the connection was lost, resuming files from the current position and
+    /// live streams from what is being broadcast now
+    fn reconnect(&mut self, err: io::Error) -> io::Result<()> {
+        let mut last_err = err;
+        for attempt in 0..RECONNECT_ATTEMPTS {
+            thread::sleep(reconnect_delay(attempt));
+            let from = if self.len.is_some() { self.position } else { 0 };
+            match request(&self.agent, &self.url, from) {
+                Ok(response) => match self.accept(response, from) {
+                    Ok(()) => return Ok(()),
+                    Err(err) => last_err = err,
+                },
+                Err(err) => last_err = err,
+            }
+            eprintln!("Couldn't reconnect to {}\nError: {last_err}", self.url);
+        }
+        Err(last_err)
+    }
+
+    /// Reads the ICY metadata block that comes after every `metaint` bytes of audio
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let reader = self.reader.get_mut().unwrap();
+        let mut len = [0];
+        reader.read_exact(&mut len)?;
+        let mut block = vec![0; len[0] as usize * 16];
+        reader.read_exact(&mut block)?;
+
+        // an empty block means that nothing changed
+        if !block.is_empty() {
+            self.info.0.write().unwrap().title = stream_title(&block);
+        }
+        self.until_meta = self.metaint.unwrap_or(0);
+        Ok(())
+    }
+}
+
+/// Time waited before the reconnection `attempt`, counted from 0
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_DELAY * 2_u32.pow(attempt)
+}
+
+/// Returns the title of an ICY metadata block, like `StreamTitle='Artist - Title';StreamUrl='';`
+fn stream_title(block: &[u8]) -> Option<String> {
+    const KEY: &str = "StreamTitle='";
+    let block = String::from_utf8_lossy(block);
+    let start = block.find(KEY)? + KEY.len();
+    let rest = &block[start..];
+    // the title itself may contain quotes
+    let end = rest
+        .find("';")
+        .unwrap_or_else(|| rest.trim_end_matches('\0').trim_end_matches('\'').len());
+    Some(rest[..end].trim().to_string()).filter(|title| !title.is_empty())
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.metaint.is_some() && self.until_meta == 0 {
+                if let Err(err) = self.read_metadata() {
+                    self.reconnect(err)?;
+                    continue;
+                }
+            }
+
+            let len = match self.metaint {
+                Some(_) => buf.len().min(self.until_meta),
+                None => buf.len(),
+            };
+            match self.reader.get_mut().unwrap().read(&mut buf[..len]) {
+                Ok(0) if self.len.is_some_and(|len| self.position >= len) => return Ok(0),
+                Ok(0) => self.reconnect(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+                Ok(read) => {
+                    self.position += read as u64;
+                    if self.metaint.is_some() {
+                        self.until_meta -= read;
+                    }
+                    return Ok(read);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => self.reconnect(err)?,
+            }
+        }
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.and_then(|len| len.checked_add_signed(offset)),
+        }
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        if target == self.position {
+            return Ok(target);
+        }
+        if !self.seekable {
+            // live streams can only skip forward
+            if target > self.position {
+                io::copy(
+                    &mut self.by_ref().take(target - self.position),
+                    &mut io::sink(),
+                )?;
+                return Ok(self.position);
+            }
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
+
+        let response = request(&self.agent, &self.url, target)?;
+        self.accept(response, target)?;
+        Ok(self.position)
+    }
+}
+
+impl MediaSource for HttpStream {
+    fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    #[test]
+    fn pls_entries_are_ordered_by_number() {
+        let playlist = "[playlist]\n\
+            Title2=Second\n\
+            File2=http://example.com/2\n\
+            File1 = https://example.com/1 \n\
+            Title1=\n\
+            File3=relative/file.mp3\n\
+            NumberOfEntries=3\n";
+        assert_eq!(
+            parse_playlist(playlist),
+            [
+                PlaylistEntry {
+                    url: "https://example.com/1".to_string(),
+                    title: None,
+                },
+                PlaylistEntry {
+                    url: "http://example.com/2".to_string(),
+                    title: Some("Second".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn m3u_titles_belong_to_the_next_url() {
+        let playlist = "\u{feff}#EXTM3U\n\
+            #EXTINF:-1,First Radio\n\
+            http://example.com/first\n\
+            #EXTINF:-1,Local file\n\
+            music/file.mp3\n\
+            http://example.com/untitled\n";
+        assert_eq!(
+            parse_playlist(playlist),
+            [
+                PlaylistEntry {
+                    url: "http://example.com/first".to_string(),
+                    title: Some("First Radio".to_string()),
+                },
+                PlaylistEntry {
+                    url: "http://example.com/untitled".to_string(),
+                    title: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_title_is_read_from_the_block() {
+        assert_eq!(
+            stream_title(b"StreamTitle='Artist - Title';StreamUrl='';\0\0").as_deref(),
+            Some("Artist - Title")
+        );
+        assert_eq!(
+            stream_title(b"StreamTitle='It's here';").as_deref(),
+            Some("It's here")
+        );
+        assert_eq!(
+            stream_title(b"StreamTitle='Unterminated\0\0\0").as_deref(),
+            Some("Unterminated")
+        );
+        assert_eq!(stream_title(b"StreamTitle='';\0"), None);
+        assert_eq!(stream_title(b"StreamUrl='http://example.com';"), None);
+    }
+
+    /// ICY metadata block of `text`, padded to a multiple of 16 bytes
+    fn metadata_block(text: &str) -> Vec<u8> {
+        let len = text.len().div_ceil(16);
+        let mut block = vec![len as u8];
+        block.extend_from_slice(text.as_bytes());
+        block.resize(1 + len * 16, 0);
+        block
+    }
+
+    /// Accepts the next connection and reads its request, returning whether it asked for the ICY
+    /// metadata
+    fn accept_request(listener: &TcpListener) -> (TcpStream, bool) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut icy = false;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            icy |= line.to_ascii_lowercase().starts_with("icy-metadata: 1");
+            line.clear();
+        }
+        (stream, icy)
+    }
+
+    #[test]
+    fn icy_metadata_is_removed_from_the_audio() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/live", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, icy) = accept_request(&listener);
+            assert!(icy, "the client didn't ask for the ICY metadata");
+
+            let mut response = b"HTTP/1.1 200 OK\r\n\
+                Content-Type: audio/mpeg\r\n\
+                icy-name: Test Radio\r\n\
+                icy-metaint: 4\r\n\
+                Connection: close\r\n\r\n\
+                abcd"
+                .to_vec();
+            response.extend(metadata_block("StreamTitle='Artist - Title';"));
+            response.extend_from_slice(b"efgh");
+            // nothing changed
+            response.push(0);
+            response.extend_from_slice(b"ijkl");
+            (&stream).write_all(&response).unwrap();
+            stream
+        });
+
+        let mut stream = HttpStream::open(&url).unwrap();
+        let info = stream.info();
+        let mut audio = [0; 12];
+        stream.read_exact(&mut audio).unwrap();
+
+        assert_eq!(&audio, b"abcdefghijkl");
+        assert_eq!(info.name().as_deref(), Some("Test Radio"));
+        assert_eq!(info.title().as_deref(), Some("Artist - Title"));
+        assert_eq!(stream.extension().as_deref(), Some("mp3"));
+        assert!(!stream.is_seekable());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn shoutcast_v1_responses_are_read() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/;", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut response = b"ICY 200 OK\r\n\
+                icy-name: Old Radio\r\n\
+                content-type: audio/mpeg\r\n\
+                icy-metaint: 4\r\n\r\n\
+                abcd"
+                .to_vec();
+            response.extend(metadata_block("StreamTitle='Old Song';"));
+            response.extend_from_slice(b"efgh");
+            // the first request is the one rejected by ureq
+            let mut streams = vec![];
+            for _ in 0..2 {
+                let (stream, icy) = accept_request(&listener);
+                assert!(icy, "the client didn't ask for the ICY metadata");
+                (&stream).write_all(&response).unwrap();
+                streams.push(stream);
+            }
+            streams
+        });
+
+        let mut stream = HttpStream::open(&url).unwrap();
+        let mut audio = [0; 8];
+        stream.read_exact(&mut audio).unwrap();
+
+        assert_eq!(&audio, b"abcdefgh");
+        assert_eq!(stream.info().name().as_deref(), Some("Old Radio"));
+        assert_eq!(stream.info().title().as_deref(), Some("Old Song"));
+        assert_eq!(stream.extension().as_deref(), Some("mp3"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reconnection_delays_grow() {
+        assert_eq!(reconnect_delay(0), RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(1), RECONNECT_DELAY * 2);
+        assert_eq!(reconnect_delay(4), RECONNECT_DELAY * 16);
+    }
+
+    #[test]
+    fn lost_live_streams_are_resumed() {
+        const LIVE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+            Content-Type: audio/mpeg\r\n\
+            Connection: close\r\n\r\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/live", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            // a live stream without ICY metadata, whose connection is lost
+            let (stream, _) = accept_request(&listener);
+            (&stream).write_all(&[LIVE, b"abcd"].concat()).unwrap();
+            let lost = Instant::now();
+            drop(stream);
+
+            let (stream, _) = accept_request(&listener);
+            let first_attempt = Instant::now();
+            (&stream)
+                .write_all(
+                    b"HTTP/1.1 503 Service Unavailable\r\n\
+                    Content-Length: 0\r\n\
+                    Connection: close\r\n\r\n",
+                )
+                .unwrap();
+            drop(stream);
+
+            let (stream, _) = accept_request(&listener);
+            let second_attempt = Instant::now();
+            (&stream).write_all(&[LIVE, b"efgh"].concat()).unwrap();
+            (stream, first_attempt - lost, second_attempt - first_attempt)
+        });
+
+        let mut stream = HttpStream::open(&url).unwrap();
+        let mut audio = [0; 8];
+        stream.read_exact(&mut audio).unwrap();
+        assert_eq!(&audio, b"abcdefgh");
+
+        let (_stream, first_delay, second_delay) = server.join().unwrap();
+        assert!(first_delay >= reconnect_delay(0));
+        assert!(second_delay >= reconnect_delay(1));
+    }
+}
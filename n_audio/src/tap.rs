@@ -0,0 +1,201 @@
+use crate::fft::{Complex, Fft};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Number of bands of [`Tap::spectrum`]
+pub const SPECTRUM_BANDS: usize = 24;
+/// Lowest and highest frequencies shown by the spectrum, in Hz
+const SPECTRUM_RANGE: (f32, f32) = (40.0, 16000.0);
+/// Level (in dB) shown as 0.0 by the spectrum
+const SPECTRUM_FLOOR: f32 = -72.0;
+/// How much the bands of the spectrum fall at every update, so that they don't flicker
+const SPECTRUM_FALL: f32 = 0.03;
+const FFT_SIZE: usize = 2048;
+/// How many times per second the values are published
+const UPDATE_RATE: u32 = 60;
+
+/// RMS and peak levels (linear, 1.0 is 0 dBFS) of the first two channels
+///
+/// Mono tracks report the same levels for both channels
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Levels {
+    pub rms: [f32; 2],
+    pub peak: [f32; 2],
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    sequence: AtomicU64,
+    spectrum: [AtomicU32; SPECTRUM_BANDS],
+    rms: [AtomicU32; 2],
+    peak: [AtomicU32; 2],
+}
+
+/// The spectrum and the levels of the samples sent to the output, published by the track thread
+/// about 60 times per second
+///
+/// Every value is an atomic, so reading them never blocks the audio; a reader may see some values
+/// of an update mixed with the ones of the previous update, which is fine for visualizers
+/// Cloning it returns a handle to the same values
+#[derive(Clone, Debug, Default)]
+pub struct Tap(Arc<Shared>);
+
+impl Tap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a number that changes every time new values are published, so that readers can skip
+    /// the ones they already have
+    pub fn sequence(&self) -> u64 {
+        self.0.sequence.load(Ordering::Acquire)
+    }
+
+    /// Returns the magnitudes of log-spaced bands from 40 Hz to 16 kHz, from 0.0 (-72 dB or less)
+    /// to 1.0 (0 dB)
+    pub fn spectrum(&self) -> [f32; SPECTRUM_BANDS] {
+        let mut spectrum = [0.0; SPECTRUM_BANDS];
+        for (value, band) in spectrum.iter_mut().zip(&self.0.spectrum) {
+            *value = f32::from_bits(band.load(Ordering::Relaxed));
+        }
+        spectrum
+    }
+
+    pub fn levels(&self) -> Levels {
+        let load = |values: &[AtomicU32; 2]| {
+            [
+                f32::from_bits(values[0].load(Ordering::Relaxed)),
+                f32::from_bits(values[1].load(Ordering::Relaxed)),
+            ]
+        };
+        Levels {
+            rms: load(&self.0.rms),
+            peak: load(&self.0.peak),
+        }
+    }
+
+    fn publish(&self, spectrum: &[f32], levels: &Levels) {
+        for (band, value) in self.0.spectrum.iter().zip(spectrum) {
+            band.store(value.to_bits(), Ordering::Relaxed);
+        }
+        for channel in 0..2 {
+            self.0.rms[channel].store(levels.rms[channel].to_bits(), Ordering::Relaxed);
+            self.0.peak[channel].store(levels.peak[channel].to_bits(), Ordering::Relaxed);
+        }
+        self.0.sequence.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Computes the values published by a [`Tap`], owned by the track thread
+pub(crate) struct Analyzer {
+    tap: Tap,
+    fft: Fft,
+    /// Hann window
+    window: Vec<f32>,
+    /// The last `FFT_SIZE` frames downmixed to mono
+    history: Vec<f32>,
+    position: usize,
+    buf: Vec<Complex>,
+    spectrum: [f32; SPECTRUM_BANDS],
+    /// Frames since the last update
+    frames: u32,
+    sum_squares: [f32; 2],
+    peak: [f32; 2],
+}
+
+impl Analyzer {
+    pub fn new(tap: Tap) -> Self {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        Self {
+            tap,
+            fft: Fft::new(FFT_SIZE),
+            window,
+            history: vec![0.0; FFT_SIZE],
+            position: 0,
+            buf: vec![Complex::default(); FFT_SIZE],
+            spectrum: [0.0; SPECTRUM_BANDS],
+            frames: 0,
+            sum_squares: [0.0; 2],
+            peak: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32], channels: usize, rate: u32) {
+        if channels == 0 {
+            return;
+        }
+
+        for frame in samples.chunks_exact(channels) {
+            self.history[self.position] = frame.iter().sum::<f32>() / channels as f32;
+            self.position = (self.position + 1) % FFT_SIZE;
+
+            let stereo = [frame[0], frame[1.min(channels - 1)]];
+            for (channel, sample) in stereo.into_iter().enumerate() {
+                self.sum_squares[channel] += sample * sample;
+                self.peak[channel] = self.peak[channel].max(sample.abs());
+            }
+
+            self.frames += 1;
+            if self.frames >= (rate / UPDATE_RATE).max(1) {
+                self.update(rate);
+            }
+        }
+    }
+
+    /// Clears the published values, called when the output stops
+    pub fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.position = 0;
+        self.spectrum = [0.0; SPECTRUM_BANDS];
+        self.frames = 0;
+        self.sum_squares = [0.0; 2];
+        self.peak = [0.0; 2];
+        self.tap.publish(&self.spectrum, &Levels::default());
+    }
+
+    fn update(&mut self, rate: u32) {
+        let levels = Levels {
+            rms: self
+                .sum_squares
+                .map(|sum_squares| (sum_squares / self.frames as f32).sqrt()),
+            peak: self.peak,
+        };
+        self.frames = 0;
+        self.sum_squares = [0.0; 2];
+        self.peak = [0.0; 2];
+
+        // the oldest frame is at `position`
+        let (newest, oldest) = self.history.split_at(self.position);
+        for ((value, sample), window) in self
+            .buf
+            .iter_mut()
+            .zip(oldest.iter().chain(newest))
+            .zip(&self.window)
+        {
+            *value = Complex::new(sample * window, 0.0);
+        }
+        self.fft.forward(&mut self.buf);
+
+        // a full scale sine is shown at 0 dB
+        let scale = 4.0 / FFT_SIZE as f32;
+        let bin_width = rate as f32 / FFT_SIZE as f32;
+        let (low, high) = (SPECTRUM_RANGE.0, SPECTRUM_RANGE.1.min(rate as f32 / 2.0));
+        for (band, value) in self.spectrum.iter_mut().enumerate() {
+            let from = low * (high / low).powf(band as f32 / SPECTRUM_BANDS as f32);
+            let to = low * (high / low).powf((band + 1) as f32 / SPECTRUM_BANDS as f32);
+            let first = (from / bin_width).round() as usize;
+            let last = ((to / bin_width).round() as usize).clamp(first + 1, FFT_SIZE / 2);
+            let magnitude = self.buf[first.min(last - 1)..last]
+                .iter()
+                .fold(0.0_f32, |magnitude, bin| magnitude.max(bin.norm()))
+                * scale;
+            let db = 20.0 * magnitude.max(1e-9).log10();
+            let new = (1.0 - db / SPECTRUM_FLOOR).clamp(0.0, 1.0);
+            *value = new.max(*value - SPECTRUM_FALL);
+        }
+
+        self.tap.publish(&self.spectrum, &levels);
+    }
+}
@@ -0,0 +1,373 @@
+//! Tracker modules (MOD, XM, S3M, IT and the other formats of libopenmpt), rendered to PCM while
+//! they're played
+//!
+//! The renderer needs libopenmpt and is only built with the `tracker` feature; the options can be
+//! set either way and are used by the modules opened after they change
+
+use std::sync::RwLock;
+
+#[cfg(feature = "tracker")]
+pub use renderer::TrackerReader;
+
+/// Whether n_audio was built with the renderer of the modules
+pub const ENABLED: bool = cfg!(feature = "tracker");
+
+/// Extensions of the module formats played
+pub const EXTENSIONS: &[&str] = &[
+    "669", "amf", "dsm", "far", "it", "med", "mod", "mptm", "mtm", "okt", "s3m", "stm", "ult",
+    "umx", "xm",
+];
+
+static OPTIONS: RwLock<TrackerOptions> = RwLock::new(TrackerOptions {
+    loops: 0,
+    interpolation: Interpolation::Default,
+});
+
+/// How the samples of the instruments are interpolated
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The one recommended by libopenmpt
+    #[default]
+    Default,
+    /// Nearest sample, for the raw sound of the old trackers
+    None,
+    Linear,
+    Cubic,
+    /// Windowed sinc with 8 taps
+    Sinc,
+}
+
+impl Interpolation {
+    /// Length of the interpolation filter asked to libopenmpt
+    pub fn filter_length(self) -> i32 {
+        match self {
+            Self::Default => 0,
+            Self::None => 1,
+            Self::Linear => 2,
+            Self::Cubic => 4,
+            Self::Sinc => 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackerOptions {
+    /// How many times the modules are repeated after they're played once
+    pub loops: u32,
+    pub interpolation: Interpolation,
+}
+
+/// Sets the options of the modules opened from now on
+pub fn set_options(options: TrackerOptions) {
+    *OPTIONS.write().unwrap() = options;
+}
+
+pub fn options() -> TrackerOptions {
+    *OPTIONS.read().unwrap()
+}
+
+/// Whether `ext` is the extension of a module format
+pub fn is_module(ext: &str) -> bool {
+    EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e))
+}
+
+/// Renderer of the modules, built with libopenmpt
+#[cfg(feature = "tracker")]
+mod renderer {
+    use super::{options, EXTENSIONS};
+    use openmpt::module::{Logger, Module};
+    use std::io::{self, Read};
+    use symphonia::core::{
+        audio::Channels,
+        codecs::{CodecParameters, CODEC_TYPE_PCM_F32LE},
+        errors::{
+            self as symph_err, Error as SymphoniaError, Result as SymphResult, SeekErrorKind,
+        },
+        formats::prelude::*,
+        io::MediaSourceStream,
+        meta::{
+            Metadata as SymphMetadata, MetadataBuilder, MetadataLog, StandardTagKey, Tag, Value,
+        },
+        probe::{Descriptor, Instantiate, QueryDescriptor},
+        sample::SampleFormat,
+        units::TimeStamp,
+    };
+
+    /// Rate the modules are rendered at
+    pub(super) const RATE: u32 = 48000;
+
+    impl QueryDescriptor for TrackerReader {
+        fn query() -> &'static [Descriptor] {
+            // only XM and IT start with a signature, the other formats are opened by their
+            // extension
+            &[symphonia_core::support_format!(
+                "tracker",
+                "Tracker modules rendered by libopenmpt.",
+                EXTENSIONS,
+                &["audio/x-mod", "audio/x-xm", "audio/x-s3m", "audio/x-it"],
+                &[b"Extended Module:", b"IMPM"]
+            )]
+        }
+
+        fn score(_context: &[u8]) -> u8 {
+            255
+        }
+    }
+
+    /// libopenmpt module, only used through `&mut` by the reader that owns it
+    struct TrackerModule(Module);
+
+    // SAFETY: the module is never shared, so it's only used by one thread at a time
+    unsafe impl Send for TrackerModule {}
+    unsafe impl Sync for TrackerModule {}
+
+    /// Symphonia support for tracker modules, rendered by libopenmpt as stereo `f32` PCM
+    ///
+    /// The module is played once and then repeated `TrackerOptions::loops` times, restarting from
+    /// its beginning
+    pub struct TrackerReader {
+        source: MediaSourceStream,
+        module: TrackerModule,
+        track: Track,
+        meta: MetadataLog,
+        /// Frames of a single play of the module
+        song_frames: u64,
+        loops: u32,
+        /// Plays of the module already finished
+        played: u32,
+        curr_ts: TimeStamp,
+        buf: Vec<f32>,
+    }
+
+    impl FormatReader for TrackerReader {
+        fn try_new(mut source: MediaSourceStream, _options: &FormatOptions) -> SymphResult<Self> {
+            let mut data = vec![];
+            source.read_to_end(&mut data)?;
+            let Ok(mut module) = Module::create(&mut data.as_slice(), Logger::None, &[]) else {
+                return symph_err::decode_error("tracker: not a module supported by libopenmpt");
+            };
+
+            let options = options();
+            // the repetitions are handled here, so that seeking knows which one it's in
+            module.set_repeat_count(0);
+            module.set_render_interpolation_filter_length(options.interpolation.filter_length());
+            let song_frames = (module.get_duration_seconds().max(0.0) * RATE as f64) as u64;
+
+            let mut codec_params = CodecParameters::new();
+            codec_params
+                .for_codec(CODEC_TYPE_PCM_F32LE)
+                .with_bits_per_coded_sample(32)
+                .with_bits_per_sample(32)
+                .with_sample_rate(RATE)
+                .with_time_base(TimeBase::new(1, RATE))
+                .with_sample_format(SampleFormat::F32)
+                .with_max_frames_per_packet(RATE as u64 / 50)
+                .with_channels(Channels::FRONT_LEFT | Channels::FRONT_RIGHT)
+                .with_n_frames(song_frames * (options.loops as u64 + 1));
+
+            let mut revision = MetadataBuilder::new();
+            for (key, std_key) in [
+                ("title", StandardTagKey::TrackTitle),
+                ("artist", StandardTagKey::Artist),
+                ("message", StandardTagKey::Comment),
+            ] {
+                if let Some(value) = module.get_metadata(key) {
+                    if !value.trim().is_empty() {
+                        revision.add_tag(Tag::new(Some(std_key), key, Value::String(value)));
+                    }
+                }
+            }
+            let mut meta = MetadataLog::default();
+            meta.push(revision.metadata());
+
+            Ok(Self {
+                source,
+                module: TrackerModule(module),
+                track: Track {
+                    id: 0,
+                    language: None,
+                    codec_params,
+                },
+                meta,
+                song_frames,
+                loops: options.loops,
+                played: 0,
+                curr_ts: 0,
+                buf: vec![0.0; (RATE as usize / 50) * 2],
+            })
+        }
+
+        fn cues(&self) -> &[Cue] {
+            &[]
+        }
+
+        fn metadata(&mut self) -> SymphMetadata<'_> {
+            self.meta.metadata()
+        }
+
+        fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> SymphResult<SeekedTo> {
+            let ts = match to {
+                SeekTo::Time { time, .. } => TimeBase::new(1, RATE).calc_timestamp(time),
+                SeekTo::TimeStamp { ts, .. } => ts,
+            };
+            if ts > self.song_frames * (self.loops as u64 + 1) {
+                return symph_err::seek_error(SeekErrorKind::OutOfRange);
+            }
+
+            let played = ts
+                .checked_div(self.song_frames)
+                .unwrap_or_default()
+                .min(self.loops as u64);
+            let position = (ts - played * self.song_frames) as f64 / RATE as f64;
+            let actual = self.module.0.set_position_seconds(position);
+            self.played = played as u32;
+            self.curr_ts = played * self.song_frames + (actual.max(0.0) * RATE as f64) as u64;
+
+            Ok(SeekedTo {
+                track_id: self.track.id,
+                required_ts: ts,
+                actual_ts: self.curr_ts,
+            })
+        }
+
+        fn tracks(&self) -> &[Track] {
+            std::slice::from_ref(&self.track)
+        }
+
+        fn default_track(&self) -> Option<&Track> {
+            Some(&self.track)
+        }
+
+        fn next_packet(&mut self) -> SymphResult<Packet> {
+            loop {
+                let frames = self
+                    .module
+                    .0
+                    .read_interleaved_float_stereo(RATE as i32, &mut self.buf);
+                if frames > 0 {
+                    let buf: Box<[u8]> = self.buf[..frames * 2]
+                        .iter()
+                        .flat_map(|sample| sample.to_le_bytes())
+                        .collect();
+                    let packet = Packet::new_from_boxed_slice(0, self.curr_ts, frames as u64, buf);
+                    self.curr_ts += frames as u64;
+                    return Ok(packet);
+                }
+
+                if self.played >= self.loops {
+                    return Err(SymphoniaError::IoError(io::Error::from(
+                        io::ErrorKind::UnexpectedEof,
+                    )));
+                }
+                self.played += 1;
+                self.module.0.set_position_seconds(0.0);
+            }
+        }
+
+        fn into_inner(self: Box<Self>) -> MediaSourceStream {
+            self.source
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracker"))]
+mod tests {
+    use super::renderer::RATE;
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::probe::QueryDescriptor;
+
+    /// ProTracker module with a single empty pattern of 64 rows, 7.68 s at the default speed and
+    /// tempo
+    fn empty_mod() -> Vec<u8> {
+        let mut module = vec![0; 20 + 31 * 30];
+        // one pattern in the order list, which has 128 entries
+        module.extend_from_slice(&[1, 127]);
+        module.extend_from_slice(&[0; 128]);
+        module.extend_from_slice(b"M.K.");
+        module.extend_from_slice(&[0; 64 * 4 * 4]);
+        module
+    }
+
+    fn open(module: Vec<u8>) -> TrackerReader {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(module)), Default::default());
+        TrackerReader::try_new(source, &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn modules_are_recognized_by_extension() {
+        assert!(is_module("xm"));
+        assert!(is_module("MOD"));
+        assert!(is_module("669"));
+        assert!(!is_module("mp3"));
+        assert!(!is_module(""));
+    }
+
+    #[test]
+    fn the_probe_uses_the_same_extensions() {
+        assert_eq!(TrackerReader::query()[0].extensions, EXTENSIONS);
+    }
+
+    #[test]
+    fn interpolations_map_to_filter_lengths() {
+        let lengths = [
+            Interpolation::Default,
+            Interpolation::None,
+            Interpolation::Linear,
+            Interpolation::Cubic,
+            Interpolation::Sinc,
+        ]
+        .map(Interpolation::filter_length);
+        assert_eq!(lengths, [0, 1, 2, 4, 8]);
+        assert_eq!(Interpolation::default(), Interpolation::Default);
+    }
+
+    #[test]
+    fn modules_are_rendered_and_repeated() {
+        let mut reader = open(empty_mod());
+        let track = reader.default_track().unwrap();
+        let frames = track.codec_params.n_frames.unwrap();
+        assert_eq!(track.codec_params.sample_rate, Some(RATE));
+        assert!(frames.abs_diff(RATE as u64 * 768 / 100) < RATE as u64 / 100);
+
+        let mut rendered = 0;
+        while let Ok(packet) = reader.next_packet() {
+            assert_eq!(packet.ts, rendered);
+            rendered += packet.dur;
+        }
+        assert!(rendered.abs_diff(frames) < RATE as u64 / 100);
+
+        // the options are read when the module is opened
+        set_options(TrackerOptions {
+            loops: 2,
+            interpolation: Interpolation::Linear,
+        });
+        let mut repeated = open(empty_mod());
+        set_options(TrackerOptions::default());
+        let track = repeated.default_track().unwrap();
+        assert_eq!(track.codec_params.n_frames, Some(frames * 3));
+
+        // seeking into the last repetition
+        let seeked = repeated
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frames * 2 + RATE as u64,
+                    track_id: 0,
+                },
+            )
+            .unwrap();
+        assert!(seeked.actual_ts >= frames * 2);
+        assert!(repeated
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frames * 3 + 1,
+                    track_id: 0,
+                },
+            )
+            .is_err());
+    }
+}
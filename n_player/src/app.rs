@@ -1,30 +1,71 @@
+use crate::duplicates::duplicates_task;
+use crate::export::exporter;
 use crate::localization::{get_locale_denominator, localize};
-use crate::runner::{run, RunnerMessage, RunnerSeek};
+use crate::podcast::{download, download_cover, fetch_feed, format_date, Podcast};
+use crate::runner::{run, EpisodeTrack, RunnerMessage, RunnerSeek};
+use crate::settings::{CachedSilence, EqPreset, EqSettings, Session, Station, TrackAnalysis};
 use crate::{
-    add_all_tracks_to_player, bus_server, get_image_squared, AppData, FileTrack, Localization,
-    MainWindow, SettingsData, Theme, TrackData, WindowSize,
+    add_all_tracks_to_player, bus_server, format_length, get_image_squared, AppData, EpisodeData,
+    EqData, FileTrack, Localization, MainWindow, PodcastData, PodcastsData, QueueData,
+    SettingsData, StationData, Theme, TrackData, WindowSize,
 };
 use flume::{Receiver, Sender};
+use n_audio::dsp::{GRAPHIC_BANDS, PRESETS};
 use n_audio::music_track::MusicTrack;
-use n_audio::queue::QueuePlayer;
+use n_audio::queue::{LoopStatus, QueuePlayer};
 use n_audio::remove_ext;
+use n_audio::stream::resolve_stations;
+use n_audio::tap::Tap;
+use n_audio::tracker::{self, Interpolation, TrackerOptions};
 use pollster::FutureExt;
-use slint::{ComponentHandle, Model, VecModel, Weak};
+use slint::{ComponentHandle, Image, Model, ModelExt, ModelRc, SharedString, VecModel, Weak};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
 use std::mem;
 use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::NamedTempFile;
 use tokio::sync::{Mutex, RwLock};
 
+/// Number of parts of the track shown by the waveform of the seek bar
+const WAVEFORM_POINTS: usize = 200;
+/// How many tracks are analyzed before saving the analysis cache
+const ANALYSIS_SAVE_INTERVAL: usize = 20;
+/// How often the podcast feeds can be fetched, in hours, by the index chosen in the settings
+const PODCAST_REFRESH_HOURS: [u32; 5] = [0, 1, 6, 12, 24];
+/// Levels (in dB) under which the silence can be trimmed, by the index chosen in the settings
+const SILENCE_THRESHOLDS: [i8; 5] = [-40, -50, -60, -70, -80];
+/// How often it's checked whether the podcast feeds have to be fetched
+const PODCAST_REFRESH_CHECK: Duration = Duration::from_secs(60);
+
 pub type Runner = Arc<RwLock<crate::runner::Runner>>;
 pub type Settings = Arc<RwLock<crate::settings::Settings>>;
 #[allow(type_alias_bounds)]
 pub type Platform<P: crate::platform::Platform + Send + 'static> = Arc<RwLock<P>>;
 
+enum PodcastMessage {
+    Subscribe(String),
+    Unsubscribe(usize),
+    /// Fetches all the feeds
+    Refresh,
+    /// Shows the episodes of the podcast with the given index
+    Open(usize),
+    // the episodes are the ones of the open podcast, by their index
+    Play(usize),
+    Download(usize),
+    DeleteDownload(usize),
+    SetPlayed(usize, bool),
+    /// The enclosure at the given URL was downloaded to the given path
+    Downloaded(String, io::Result<String>),
+}
+
 enum Changes {
     Tracks(Vec<TrackData>),
     Metadata(usize, TrackData),
+    Analysis(usize, TrackAnalysis),
 }
 
 pub async fn run_app<P: crate::platform::Platform + Send + 'static + Sync>(
@@ -45,9 +86,40 @@ pub async fn run_app<P: crate::platform::Platform + Send + 'static + Sync>(
     let tmp = NamedTempFile::new().unwrap();
     let (tx, rx) = flume::unbounded();
 
-    let player = QueuePlayer::new(settings.read().await.path.clone());
+    let mut player = QueuePlayer::new(settings.read().await.path.clone());
+    let session = settings.write().await.session.take();
+    if let Some(session) = &session {
+        player.set_shuffle(session.shuffle);
+    }
 
-    let runner = Arc::new(RwLock::new(crate::runner::Runner::new(player)));
+    let mut runner = crate::runner::Runner::new(player);
+    runner.set_bookmarks(settings.read().await.bookmarks.clone());
+    let silence_threshold = settings.read().await.silence_threshold;
+    runner.set_silence_threshold(silence_threshold as f32);
+    runner.set_trim_silence(settings.read().await.trim_silence);
+    let silence = settings
+        .read()
+        .await
+        .read_silence(platform.read().await)
+        .await;
+    // the bounds detected under another threshold would trim too much or too little
+    if silence.threshold == silence_threshold {
+        runner.set_silence_bounds(silence.bounds);
+    }
+    runner
+        .set_loop_crossfade(settings.read().await.loop_crossfade)
+        .await;
+    runner.set_dsp(settings.read().await.eq.to_dsp()).await;
+    runner.set_crossfeed(settings.read().await.crossfeed);
+    runner
+        .set_night_mode(settings.read().await.night_mode)
+        .await;
+    runner
+        .set_impulse_response(settings.read().await.impulse_response.clone())
+        .await;
+    tracker::set_options(tracker_options(&settings.read().await));
+    let tap = runner.tap();
+    let runner = Arc::new(RwLock::new(runner));
 
     let r = runner.clone();
     let tx_t = tx.clone();
@@ -65,13 +137,35 @@ pub async fn run_app<P: crate::platform::Platform + Send + 'static + Sync>(
         .await
         .unwrap();
     let (tx_tracks, rx_tracks) = flume::unbounded();
+    let (tx_analyze, rx_analyze) = flume::unbounded();
+    let (tx_analysis, rx_analysis) = flume::unbounded();
+    let (tx_export, rx_export) = flume::unbounded();
+    let (tx_duplicates, rx_duplicates) = flume::unbounded();
+    let (tx_podcasts, rx_podcasts) = flume::unbounded();
     let s = settings.clone();
+    let t = tx_analyze.clone();
     let future = tokio::spawn(async move {
         let runner_future = tokio::task::spawn(run(r.clone(), rx));
         let bus_future = tokio::task::spawn(bus_server::run(p.clone(), r.clone(), tmp));
-        let loader_future = tokio::task::spawn(loader(r.clone(), s, p, tx_l, rx_path, tx_tracks));
+        let analysis_future = tokio::task::spawn(analysis_task(
+            r.clone(),
+            s.clone(),
+            p.clone(),
+            rx_analyze,
+            tx_analysis,
+        ));
+        let loader_future = tokio::task::spawn(loader(
+            r.clone(),
+            s,
+            p,
+            tx_l,
+            rx_path,
+            tx_tracks,
+            t,
+            session,
+        ));
 
-        let _ = tokio::join!(runner_future, bus_future, loader_future);
+        let _ = tokio::join!(runner_future, bus_future, analysis_future, loader_future);
     });
 
     let (tx_searching, rx_searching) = flume::unbounded();
@@ -85,6 +179,23 @@ pub async fn run_app<P: crate::platform::Platform + Send + 'static + Sync>(
         tx_searching,
         tx_changing,
         tx_path,
+        tx_analyze,
+        tx_export,
+        tx_duplicates,
+    )
+    .await;
+    setup_eq(
+        settings.clone(),
+        platform.clone(),
+        main_window.clone_strong(),
+        tx.clone(),
+    )
+    .await;
+    setup_podcasts(
+        settings.clone(),
+        platform.clone(),
+        main_window.clone_strong(),
+        tx_podcasts.clone(),
     )
     .await;
 
@@ -101,142 +212,1119 @@ pub async fn run_app<P: crate::platform::Platform + Send + 'static + Sync>(
         rx_changing,
         rx_searching,
         rx_l,
+        rx_analysis,
+    ));
+    let visualizer = tokio::task::spawn(visualizer_task(tap, main_window.as_weak()));
+    let waveform = tokio::task::spawn(waveform_task(
+        runner.clone(),
+        settings.clone(),
+        platform.clone(),
+        main_window.as_weak(),
+    ));
+
+    let exporter = tokio::task::spawn(exporter(
+        runner.clone(),
+        settings.clone(),
+        rx_export,
+        main_window.as_weak(),
+    ));
+    let duplicates = tokio::task::spawn(duplicates_task(
+        runner.clone(),
+        settings.clone(),
+        platform.clone(),
+        rx_duplicates,
+        main_window.as_weak(),
+    ));
+
+    let podcasts = tokio::task::spawn(podcast_task(
+        runner.clone(),
+        settings.clone(),
+        platform.clone(),
+        tx.clone(),
+        tx_podcasts,
+        rx_podcasts,
+        main_window.as_weak(),
     ));
 
-    tokio::task::block_in_place(|| main_window.run().unwrap());
+    tokio::task::block_in_place(|| main_window.run().unwrap());
+
+    podcasts.abort();
+    duplicates.abort();
+    exporter.abort();
+    waveform.abort();
+    visualizer.abort();
+    updater.abort();
+    future.abort();
+
+    settings.write().await.volume = runner.read().await.volume();
+    settings.write().await.session = runner.read().await.session().await;
+    settings.write().await.bookmarks = runner.read().await.bookmarks().clone();
+    let silence = CachedSilence {
+        threshold: settings.read().await.silence_threshold,
+        bounds: runner.read().await.silence_bounds(),
+    };
+    settings
+        .read()
+        .await
+        .save_silence(platform.read().await, &silence)
+        .await;
+    if settings.read().await.save_window_size {
+        let width = main_window.get_last_width() as usize;
+        let height = main_window.get_last_height() as usize;
+        settings.write().await.window_size = WindowSize { width, height };
+    } else {
+        settings.write().await.window_size = WindowSize::default();
+    }
+    settings.read().await.save(platform.read().await).await;
+}
+
+async fn setup_data<P: crate::platform::Platform + Send + 'static>(
+    settings: Settings,
+    platform: Platform<P>,
+    main_window: MainWindow,
+    tx: Sender<RunnerMessage>,
+    tx_searching: Sender<String>,
+    tx_changing: Sender<()>,
+    tx_path: Sender<(String, bool)>,
+    tx_analyze: Sender<Option<usize>>,
+    tx_export: Sender<PathBuf>,
+    tx_duplicates: Sender<()>,
+) {
+    localize(
+        settings.read().await.locale.clone(),
+        main_window.global::<Localization>(),
+    );
+
+    let settings_data = main_window.global::<SettingsData>();
+    let app_data = main_window.global::<AppData>();
+
+    #[cfg(target_os = "android")]
+    app_data.set_android(true);
+    app_data.set_version(env!("CARGO_PKG_VERSION").into());
+
+    {
+        let settings = settings.read().await;
+        settings_data.set_color_scheme(settings.theme.into());
+        settings_data.set_theme(i32::from(settings.theme));
+        settings_data.set_width(settings.window_size.width as f32);
+        settings_data.set_height(settings.window_size.height as f32);
+        settings_data.set_save_window_size(settings.save_window_size);
+        settings_data.set_auto_resume(settings.auto_resume);
+        settings_data.set_loop_crossfade(settings.loop_crossfade);
+        settings_data.set_crossfeed(settings.crossfeed);
+        settings_data.set_night_mode(settings.night_mode);
+        settings_data.set_trim_silence(settings.trim_silence);
+        let silence_threshold = SILENCE_THRESHOLDS
+            .iter()
+            .position(|threshold| *threshold == settings.silence_threshold)
+            .unwrap_or_default();
+        settings_data.set_silence_threshold(silence_threshold as i32);
+        settings_data.set_analyze_tracks(settings.analyze_tracks);
+        settings_data.set_export_format(settings.export_format as i32);
+        settings_data.set_export_replay_gain(settings.export_replay_gain);
+        settings_data.set_tracker(tracker::ENABLED);
+        settings_data.set_tracker_loops(settings.tracker_loops as i32);
+        settings_data.set_tracker_interpolation(settings.tracker_interpolation as i32);
+        if let Some(path) = &settings.impulse_response {
+            settings_data.set_impulse_response(remove_ext(path).into());
+        }
+        settings_data.set_current_path(settings.path.clone().into());
+        settings_data.set_stations(VecModel::from_slice(&station_data(&settings.stations)));
+    }
+
+    let p = platform.clone();
+    app_data.on_open_link(move |link| {
+        let p = p.clone();
+        slint::spawn_local(async move { p.read().await.open_link(link.into()).await }).unwrap();
+    });
+
+    let s = settings.clone();
+    let window = main_window.clone_strong();
+    let p = platform.clone();
+    main_window
+        .global::<Localization>()
+        .on_set_locale(move |locale_name| {
+            let denominator = get_locale_denominator(Some(locale_name.into()));
+            localize(
+                Some(denominator.to_string()),
+                window.global::<Localization>(),
+            );
+            let s = s.clone();
+            let p = p.clone();
+            slint::spawn_local(async move {
+                s.write().await.locale = Some(denominator);
+                s.read().await.save(p.read().await).await;
+            })
+            .unwrap();
+        });
+    let s = settings.clone();
+    let window = main_window.clone_strong();
+    let p = platform.clone();
+    settings_data.on_change_theme_callback(move |theme_name| {
+        if let Ok(theme) = Theme::try_from(theme_name) {
+            window
+                .global::<SettingsData>()
+                .set_color_scheme(theme.into());
+            let s = s.clone();
+            let p = p.clone();
+            slint::spawn_local(async move {
+                s.write().await.theme = theme;
+                s.read().await.save(p.read().await).await;
+            })
+            .unwrap();
+        }
+    });
+    let s = settings.clone();
+    settings_data.on_toggle_save_window_size(move |save| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            s.write().await.save_window_size = save;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_toggle_auto_resume(move |auto_resume| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            s.write().await.auto_resume = auto_resume;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_toggle_loop_crossfade(move |crossfade| {
+        let s = s.clone();
+        t.send(RunnerMessage::LoopCrossfade(crossfade)).unwrap();
+        slint::spawn_local(async move {
+            s.write().await.loop_crossfade = crossfade;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_toggle_crossfeed(move |crossfeed| {
+        let s = s.clone();
+        t.send(RunnerMessage::Crossfeed(crossfeed)).unwrap();
+        slint::spawn_local(async move {
+            s.write().await.crossfeed = crossfeed;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_toggle_night_mode(move |night_mode| {
+        let s = s.clone();
+        t.send(RunnerMessage::NightMode(night_mode)).unwrap();
+        slint::spawn_local(async move {
+            s.write().await.night_mode = night_mode;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_toggle_trim_silence(move |trim| {
+        let s = s.clone();
+        t.send(RunnerMessage::TrimSilence(trim)).unwrap();
+        slint::spawn_local(async move {
+            s.write().await.trim_silence = trim;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_change_silence_threshold(move |index| {
+        let Some(&threshold) = SILENCE_THRESHOLDS.get(index as usize) else {
+            return;
+        };
+        let s = s.clone();
+        t.send(RunnerMessage::SilenceThreshold(threshold as f32))
+            .unwrap();
+        slint::spawn_local(async move {
+            s.write().await.silence_threshold = threshold;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_toggle_analyze_tracks(move |analyze| {
+        let s = s.clone();
+        let tx_analyze = tx_analyze.clone();
+        slint::spawn_local(async move {
+            s.write().await.analyze_tracks = analyze;
+            if analyze {
+                tx_analyze.send_async(None).await.unwrap();
+            }
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_change_export_format(move |format| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            s.write().await.export_format = format as u8;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_change_tracker_loops(move |loops| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            let mut settings = s.write().await;
+            settings.tracker_loops = loops as u32;
+            tracker::set_options(tracker_options(&settings));
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_change_tracker_interpolation(move |interpolation| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            let mut settings = s.write().await;
+            settings.tracker_interpolation = interpolation as u8;
+            tracker::set_options(tracker_options(&settings));
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_toggle_export_replay_gain(move |replay_gain| {
+        let s = s.clone();
+        slint::spawn_local(async move {
+            s.write().await.export_replay_gain = replay_gain;
+        })
+        .unwrap();
+    });
+    let p = platform.clone();
+    settings_data.on_export_library(move || {
+        let p = p.clone();
+        let tx_export = tx_export.clone();
+        slint::spawn_local(async move {
+            let destination = p.read().await.ask_music_dir().await;
+            if !destination.as_os_str().is_empty() {
+                tx_export.send_async(destination).await.unwrap();
+            }
+        })
+        .unwrap();
+    });
+    settings_data.on_find_duplicates(move || tx_duplicates.send(()).unwrap());
+    let s = settings.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    settings_data.on_add_station(move |url| {
+        let s = s.clone();
+        let p = p.clone();
+        let window = window.clone();
+        let url = url.trim().to_string();
+        if let Some(window) = window.upgrade() {
+            window.global::<SettingsData>().set_adding_station(true);
+        }
+        slint::spawn_local(async move {
+            let entries = match tokio::task::spawn_blocking(move || resolve_stations(&url)).await {
+                Ok(Ok(entries)) => entries,
+                Ok(Err(e)) => {
+                    eprintln!("can't add the station: {e}");
+                    vec![]
+                }
+                Err(e) => {
+                    eprintln!("error happened while adding the station: {e}");
+                    vec![]
+                }
+            };
+            let stations = {
+                let mut settings = s.write().await;
+                for entry in entries {
+                    if settings
+                        .stations
+                        .iter()
+                        .any(|station| station.url == entry.url)
+                    {
+                        continue;
+                    }
+                    settings.stations.push(Station {
+                        name: entry.title.unwrap_or_else(|| entry.url.clone()),
+                        url: entry.url,
+                    });
+                }
+                station_data(&settings.stations)
+            };
+            s.read().await.save(p.read().await).await;
+            if let Some(window) = window.upgrade() {
+                let settings_data = window.global::<SettingsData>();
+                settings_data.set_stations(VecModel::from_slice(&stations));
+                settings_data.set_adding_station(false);
+            }
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    settings_data.on_remove_station(move |index| {
+        let s = s.clone();
+        let p = p.clone();
+        let window = window.clone();
+        slint::spawn_local(async move {
+            let stations = {
+                let mut settings = s.write().await;
+                let index = index as usize;
+                if index >= settings.stations.len() {
+                    return;
+                }
+                settings.stations.remove(index);
+                station_data(&settings.stations)
+            };
+            s.read().await.save(p.read().await).await;
+            if let Some(window) = window.upgrade() {
+                window
+                    .global::<SettingsData>()
+                    .set_stations(VecModel::from_slice(&stations));
+            }
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    settings_data.on_play_station(move |index| {
+        let s = s.clone();
+        let t = t.clone();
+        slint::spawn_local(async move {
+            let url = s
+                .read()
+                .await
+                .stations
+                .get(index as usize)
+                .map(|station| station.url.clone());
+            if let Some(url) = url {
+                t.send_async(RunnerMessage::PlayStream(url)).await.unwrap();
+            }
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    settings_data.on_pick_impulse_response(move || {
+        let s = s.clone();
+        let t = t.clone();
+        let p = p.clone();
+        let window = window.clone();
+        slint::spawn_local(async move {
+            let Some(path) = p.read().await.ask_file().await.into_iter().next() else {
+                return;
+            };
+            let path = path.to_string_lossy().to_string();
+            t.send_async(RunnerMessage::ImpulseResponse(Some(path.clone())))
+                .await
+                .unwrap();
+            if let Some(window) = window.upgrade() {
+                window
+                    .global::<SettingsData>()
+                    .set_impulse_response(remove_ext(&path).into());
+            }
+            s.write().await.impulse_response = Some(path);
+            s.read().await.save(p.read().await).await;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let t = tx.clone();
+    let p = platform.clone();
+    settings_data.on_clear_impulse_response(move || {
+        let s = s.clone();
+        let p = p.clone();
+        t.send(RunnerMessage::ImpulseResponse(None)).unwrap();
+        slint::spawn_local(async move {
+            s.write().await.impulse_response = None;
+            s.read().await.save(p.read().await).await;
+        })
+        .unwrap();
+    });
+    let path = tx_path.clone();
+    settings_data.on_path(move || {
+        let tx_path = path.clone();
+        slint::spawn_local(async move {
+            tx_path.send_async((String::new(), false)).await.unwrap();
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    settings_data.on_scan(move || {
+        let tx_path = tx_path.clone();
+        let settings = s.clone();
+        slint::spawn_local(async move {
+            tx_path
+                .send_async((settings.read().await.path.clone(), false))
+                .await
+                .unwrap();
+        })
+        .unwrap();
+    });
+    let window = main_window.as_weak();
+    app_data.on_sort_tracks(move |sort| {
+        let window = window.unwrap();
+        let app_data = window.global::<AppData>();
+        app_data.set_sort(sort);
+        sort_tracks(&app_data);
+    });
+    let t = tx.clone();
+    app_data.on_clicked(move |i| t.send(RunnerMessage::PlayTrack(i as usize)).unwrap());
+    let t = tx.clone();
+    app_data.on_play_previous(move || t.send(RunnerMessage::PlayPrevious).unwrap());
+    let t = tx.clone();
+    app_data.on_toggle_pause(move || t.send(RunnerMessage::TogglePause).unwrap());
+    let t = tx.clone();
+    app_data.on_play_next(move || t.send(RunnerMessage::PlayNext).unwrap());
+    let t = tx.clone();
+    app_data.on_seek(move |time| {
+        t.send(RunnerMessage::Seek(RunnerSeek::Absolute(time as f64)))
+            .unwrap()
+    });
+    let t = tx.clone();
+    app_data.on_set_volume(move |volume| t.send(RunnerMessage::SetVolume(volume as f64)).unwrap());
+    let t = tx.clone();
+    app_data.on_set_loop_status(move |loop_status| {
+        if let Ok(loop_status) = LoopStatus::try_from(loop_status) {
+            t.send(RunnerMessage::LoopStatus(loop_status)).unwrap()
+        }
+    });
+    let t = tx.clone();
+    app_data.on_set_stop_after(move |tracks| {
+        t.send(RunnerMessage::StopAfter(u32::try_from(tracks).ok()))
+            .unwrap()
+    });
+    let t = tx.clone();
+    app_data.on_play_after_current(move |i| {
+        t.send(RunnerMessage::PlayAfterCurrent(i as usize)).unwrap()
+    });
+    let t = tx.clone();
+    app_data.on_enqueue(move |i| t.send(RunnerMessage::Enqueue(i as usize)).unwrap());
+    let t = tx.clone();
+    app_data.on_move_queued(move |from, to| {
+        t.send(RunnerMessage::MoveQueued(from as usize, to as usize))
+            .unwrap()
+    });
+    let window = main_window.as_weak();
+    app_data.on_toggle_queued(move |i| {
+        if let Some(window) = window.upgrade() {
+            let up_next = window.global::<AppData>().get_up_next();
+            if let Some(mut entry) = up_next.row_data(i as usize) {
+                entry.selected = !entry.selected;
+                up_next.set_row_data(i as usize, entry);
+            }
+        }
+    });
+    let t = tx.clone();
+    let window = main_window.as_weak();
+    app_data.on_remove_selected_queued(move || {
+        if let Some(window) = window.upgrade() {
+            let positions = window
+                .global::<AppData>()
+                .get_up_next()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.selected)
+                .map(|(position, _)| position)
+                .collect::<Vec<_>>();
+            t.send(RunnerMessage::RemoveQueued(positions)).unwrap();
+        }
+    });
+    let t = tx.clone();
+    app_data.on_clear_queued(move || t.send(RunnerMessage::ClearQueued).unwrap());
+    let t = tx.clone();
+    app_data.on_undo_queued(move || t.send(RunnerMessage::UndoQueued).unwrap());
+    let t = tx.clone();
+    app_data.on_set_speed(move |speed| t.send(RunnerMessage::SetSpeed(speed)).unwrap());
+    let t = tx.clone();
+    app_data.on_next_chapter(move || t.send(RunnerMessage::NextChapter).unwrap());
+    let t = tx.clone();
+    app_data.on_previous_chapter(move || t.send(RunnerMessage::PreviousChapter).unwrap());
+    let t = tx.clone();
+    app_data
+        .on_set_finished(move |finished| t.send(RunnerMessage::MarkFinished(finished)).unwrap());
+    let t = tx.clone();
+    app_data.on_loop_point(move || t.send(RunnerMessage::LoopPoint).unwrap());
+    let t = tx.clone();
+    let s = settings.clone();
+    let p = platform.clone();
+    settings_data.on_toggle_audiobook(move |audiobook| {
+        let t = t.clone();
+        let s = s.clone();
+        let p = p.clone();
+        slint::spawn_local(async move {
+            {
+                let mut settings = s.write().await;
+                let path = settings.path.clone();
+                settings.audiobook_paths.retain(|p| p != &path);
+                if audiobook {
+                    settings.audiobook_paths.push(path);
+                }
+            }
+            s.read().await.save(p.read().await).await;
+            t.send_async(RunnerMessage::Audiobook(audiobook))
+                .await
+                .unwrap();
+        })
+        .unwrap();
+    });
+    app_data.on_searching(move |searching| tx_searching.send(searching.to_string()).unwrap());
+    app_data.on_changing(move || tx_changing.send(()).unwrap());
+}
+
+fn eq_settings(eq_data: &EqData) -> EqSettings {
+    EqSettings {
+        enabled: eq_data.get_enabled(),
+        gains: eq_data.get_gains().iter().collect(),
+        preamp: eq_data.get_preamp(),
+        balance: eq_data.get_balance(),
+        mono: eq_data.get_mono(),
+        swap_channels: eq_data.get_swap_channels(),
+    }
+}
+
+async fn setup_podcasts<P: crate::platform::Platform + Send + 'static>(
+    settings: Settings,
+    platform: Platform<P>,
+    main_window: MainWindow,
+    tx: Sender<PodcastMessage>,
+) {
+    let settings_data = main_window.global::<SettingsData>();
+    let podcasts_data = main_window.global::<PodcastsData>();
+
+    {
+        let refresh = settings.read().await.podcast_refresh;
+        let index = PODCAST_REFRESH_HOURS
+            .iter()
+            .position(|hours| *hours == refresh)
+            .unwrap_or_default();
+        settings_data.set_podcast_refresh(index as i32);
+        let path = podcast_folder(&settings, &platform).await;
+        settings_data.set_podcast_path(path.to_string_lossy().to_string().into());
+    }
+
+    let s = settings.clone();
+    settings_data.on_change_podcast_refresh(move |index| {
+        let Some(&hours) = PODCAST_REFRESH_HOURS.get(index as usize) else {
+            return;
+        };
+        let s = s.clone();
+        slint::spawn_local(async move {
+            s.write().await.podcast_refresh = hours;
+        })
+        .unwrap();
+    });
+    let s = settings.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    settings_data.on_pick_podcast_path(move || {
+        let s = s.clone();
+        let p = p.clone();
+        let window = window.clone();
+        slint::spawn_local(async move {
+            let path = p.read().await.ask_music_dir().await;
+            if path.as_os_str().is_empty() {
+                return;
+            }
+            let path = path.to_string_lossy().to_string();
+            s.write().await.podcast_path = Some(path.clone());
+            s.read().await.save(p.read().await).await;
+            if let Some(window) = window.upgrade() {
+                window
+                    .global::<SettingsData>()
+                    .set_podcast_path(path.into());
+            }
+        })
+        .unwrap();
+    });
+
+    let t = tx.clone();
+    podcasts_data.on_subscribe(move |url| {
+        t.send(PodcastMessage::Subscribe(url.trim().to_string()))
+            .unwrap()
+    });
+    let t = tx.clone();
+    podcasts_data
+        .on_unsubscribe(move |index| t.send(PodcastMessage::Unsubscribe(index as usize)).unwrap());
+    let t = tx.clone();
+    podcasts_data.on_refresh(move || t.send(PodcastMessage::Refresh).unwrap());
+    let t = tx.clone();
+    podcasts_data.on_open(move |index| t.send(PodcastMessage::Open(index as usize)).unwrap());
+    let t = tx.clone();
+    podcasts_data.on_play(move |index| t.send(PodcastMessage::Play(index as usize)).unwrap());
+    let t = tx.clone();
+    podcasts_data
+        .on_download(move |index| t.send(PodcastMessage::Download(index as usize)).unwrap());
+    let t = tx.clone();
+    podcasts_data.on_delete_download(move |index| {
+        t.send(PodcastMessage::DeleteDownload(index as usize))
+            .unwrap()
+    });
+    podcasts_data.on_set_played(move |index, played| {
+        tx.send(PodcastMessage::SetPlayed(index as usize, played))
+            .unwrap()
+    });
+}
+
+/// Folder of the downloaded podcast episodes, the one chosen by the user or one inside the
+/// internal directory
+async fn podcast_folder<P: crate::platform::Platform + Send + 'static>(
+    s: &Settings,
+    p: &Platform<P>,
+) -> PathBuf {
+    match &s.read().await.podcast_path {
+        Some(path) => PathBuf::from(path),
+        None => p.read().await.internal_dir().await.join("episodes"),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Fetches the feed of `podcast` again, and its cover if it isn't downloaded yet
+async fn refresh_podcast(podcast: &mut Podcast, folder: &Path) {
+    let url = podcast.url.clone();
+    match tokio::task::spawn_blocking(move || fetch_feed(&url)).await {
+        Ok(Ok(feed)) => {
+            podcast.update(feed);
+            podcast.refreshed = now();
+        }
+        Ok(Err(e)) => eprintln!("can't fetch the feed {}: {e}", podcast.url),
+        Err(e) => eprintln!(
+            "error happened while fetching the feed {}: {e}",
+            podcast.url
+        ),
+    }
+
+    if let (None, Some(image)) = (&podcast.cover, podcast.image.clone()) {
+        let folder = folder.join(podcast.folder_name());
+        match tokio::task::spawn_blocking(move || download_cover(&image, &folder)).await {
+            Ok(Ok(path)) => podcast.cover = Some(path.to_string_lossy().to_string()),
+            Ok(Err(e)) => eprintln!("can't download the cover of {}: {e}", podcast.title),
+            Err(e) => eprintln!("error happened while downloading a cover: {e}"),
+        }
+    }
+}
+
+/// Shows the podcasts, and the episodes of the `open` one, played or not by their bookmarks
+async fn show_podcasts(
+    r: &Runner,
+    window: &Weak<MainWindow>,
+    podcasts: &[Podcast],
+    open: Option<usize>,
+    downloading: &HashSet<String>,
+) {
+    let runner = r.read().await;
+    let bookmarks = runner.bookmarks();
+    let played = |url: &String| bookmarks.get(url).is_some_and(|bookmark| bookmark.finished);
+
+    // the covers are loaded in the event loop, `Image` can't be sent to it
+    let podcast_data = podcasts
+        .iter()
+        .map(|podcast| {
+            let unplayed = podcast
+                .episodes
+                .iter()
+                .filter(|episode| !played(&episode.url))
+                .count();
+            (
+                SharedString::from(podcast.title.as_str()),
+                SharedString::from(podcast.author.as_str()),
+                podcast.cover.clone(),
+                unplayed as i32,
+            )
+        })
+        .collect::<Vec<_>>();
+    let episodes = open.and_then(|open| podcasts.get(open)).map(|podcast| {
+        podcast
+            .episodes
+            .iter()
+            .map(|episode| EpisodeData {
+                title: episode.title.as_str().into(),
+                date: episode
+                    .published
+                    .map(format_date)
+                    .unwrap_or_default()
+                    .into(),
+                time: episode.length.map(format_length).unwrap_or_default().into(),
+                played: played(&episode.url),
+                downloaded: episode.downloaded.is_some(),
+                downloading: downloading.contains(&episode.url),
+            })
+            .collect::<Vec<_>>()
+    });
+    window
+        .upgrade_in_event_loop(move |window| {
+            let podcasts_data = window.global::<PodcastsData>();
+            let podcasts = podcast_data
+                .into_iter()
+                .map(|(title, author, cover, unplayed)| PodcastData {
+                    title,
+                    author,
+                    cover: cover
+                        .and_then(|cover| Image::load_from_path(Path::new(&cover)).ok())
+                        .unwrap_or_default(),
+                    unplayed,
+                })
+                .collect::<Vec<_>>();
+            podcasts_data.set_podcasts(VecModel::from_slice(&podcasts));
+            if let Some(episodes) = episodes {
+                podcasts_data.set_episodes(VecModel::from_slice(&episodes));
+            }
+        })
+        .unwrap();
+}
+
+fn set_refreshing(window: &Weak<MainWindow>, refreshing: bool) {
+    window
+        .upgrade_in_event_loop(move |window| {
+            window.global::<PodcastsData>().set_refreshing(refreshing)
+        })
+        .unwrap();
+}
+
+async fn podcast_task<P: crate::platform::Platform + Send + 'static>(
+    r: Runner,
+    s: Settings,
+    p: Platform<P>,
+    tx: Sender<RunnerMessage>,
+    tx_podcasts: Sender<PodcastMessage>,
+    rx: Receiver<PodcastMessage>,
+    window: Weak<MainWindow>,
+) {
+    let mut podcasts = s.read().await.read_podcasts(p.read().await).await;
+    let mut open = None;
+    // URLs of the enclosures being downloaded
+    let mut downloading = HashSet::new();
+    show_podcasts(&r, &window, &podcasts, open, &downloading).await;
+
+    let mut interval = tokio::time::interval(PODCAST_REFRESH_CHECK);
+    loop {
+        let message = tokio::select! {
+            _ = interval.tick() => {
+                let hours = s.read().await.podcast_refresh as u64;
+                let now = now();
+                if hours == 0
+                    || podcasts
+                        .iter()
+                        .all(|podcast| now < podcast.refreshed + hours * 3600)
+                {
+                    continue;
+                }
+                PodcastMessage::Refresh
+            }
+            message = rx.recv_async() => match message {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+
+        let folder = podcast_folder(&s, &p).await;
+        // whether the podcasts have to be saved
+        let mut changed = true;
+        match message {
+            PodcastMessage::Subscribe(url) => {
+                if url.is_empty() || podcasts.iter().any(|podcast| podcast.url == url) {
+                    continue;
+                }
+                set_refreshing(&window, true);
+                let mut podcast = Podcast {
+                    url,
+                    ..Default::default()
+                };
+                refresh_podcast(&mut podcast, &folder).await;
+                // the feed couldn't be fetched
+                if podcast.refreshed > 0 {
+                    podcasts.push(podcast);
+                }
+                set_refreshing(&window, false);
+            }
+            PodcastMessage::Unsubscribe(index) => {
+                if index >= podcasts.len() {
+                    continue;
+                }
+                let podcast = podcasts.remove(index);
+                let files = podcast
+                    .episodes
+                    .iter()
+                    .filter_map(|episode| episode.downloaded.clone())
+                    .chain(podcast.cover.clone())
+                    .collect::<Vec<_>>();
+                let folder = folder.join(podcast.folder_name());
+                let _ = tokio::task::spawn_blocking(move || {
+                    for file in files {
+                        let _ = std::fs::remove_file(file);
+                    }
+                    // only if nothing else is inside
+                    let _ = std::fs::remove_dir(folder);
+                })
+                .await;
+                open = None;
+            }
+            PodcastMessage::Refresh => {
+                set_refreshing(&window, true);
+                for podcast in &mut podcasts {
+                    refresh_podcast(podcast, &folder).await;
+                }
+                set_refreshing(&window, false);
+            }
+            PodcastMessage::Open(index) => {
+                open = Some(index);
+                changed = false;
+            }
+            PodcastMessage::Play(index) => {
+                changed = false;
+                let Some((podcast, episode)) = open
+                    .and_then(|open| podcasts.get(open))
+                    .and_then(|podcast| Some((podcast, podcast.episodes.get(index)?)))
+                else {
+                    continue;
+                };
+                // streamed if it isn't downloaded
+                let path = episode
+                    .downloaded
+                    .clone()
+                    .filter(|path| Path::new(path).exists())
+                    .unwrap_or_else(|| episode.url.clone());
+                tx.send_async(RunnerMessage::PlayEpisode(EpisodeTrack {
+                    key: episode.url.clone(),
+                    path,
+                    title: episode.title.clone(),
+                    podcast: podcast.title.clone(),
+                    chapters: episode.chapters.iter().map(Into::into).collect(),
+                }))
+                .await
+                .unwrap();
+            }
+            PodcastMessage::Download(index) => {
+                changed = false;
+                let Some((podcast, episode)) = open
+                    .and_then(|open| podcasts.get(open))
+                    .and_then(|podcast| Some((podcast, podcast.episodes.get(index)?)))
+                else {
+                    continue;
+                };
+                if episode.downloaded.is_some() || !downloading.insert(episode.url.clone()) {
+                    continue;
+                }
+                let url = episode.url.clone();
+                let destination = folder.join(podcast.folder_name()).join(episode.file_name());
+                let tx = tx_podcasts.clone();
+                tokio::task::spawn(async move {
+                    let u = url.clone();
+                    let result = tokio::task::spawn_blocking(move || {
+                        download(&u, &destination)
+                            .map(|()| destination.to_string_lossy().to_string())
+                    })
+                    .await
+                    .unwrap_or_else(|e| Err(io::Error::other(e)));
+                    let _ = tx.send_async(PodcastMessage::Downloaded(url, result)).await;
+                });
+            }
+            PodcastMessage::Downloaded(url, result) => {
+                downloading.remove(&url);
+                match result {
+                    Ok(path) => {
+                        for episode in podcasts
+                            .iter_mut()
+                            .flat_map(|podcast| podcast.episodes.iter_mut())
+                            .filter(|episode| episode.url == url)
+                        {
+                            episode.downloaded = Some(path.clone());
+                        }
+                    }
+                    Err(e) => eprintln!("can't download {url}: {e}"),
+                }
+            }
+            PodcastMessage::DeleteDownload(index) => {
+                let Some(episode) = open
+                    .and_then(|open| podcasts.get_mut(open))
+                    .and_then(|podcast| podcast.episodes.get_mut(index))
+                else {
+                    continue;
+                };
+                if let Some(path) = episode.downloaded.take() {
+                    if let Err(e) = std::fs::remove_file(&path) {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            eprintln!("can't delete {path}: {e}");
+                        }
+                    }
+                }
+            }
+            PodcastMessage::SetPlayed(index, played) => {
+                changed = false;
+                let Some(episode) = open
+                    .and_then(|open| podcasts.get(open))
+                    .and_then(|podcast| podcast.episodes.get(index))
+                else {
+                    continue;
+                };
+                r.write().await.set_finished(episode.url.clone(), played);
+            }
+        }
+
+        if changed {
+            s.read()
+                .await
+                .save_podcasts(p.read().await, &podcasts)
+                .await;
+        }
+        show_podcasts(&r, &window, &podcasts, open, &downloading).await;
+    }
+}
 
-    updater.abort();
-    future.abort();
+fn station_data(stations: &[Station]) -> Vec<StationData> {
+    stations
+        .iter()
+        .map(|station| StationData {
+            name: station.name.clone().into(),
+            url: station.url.clone().into(),
+        })
+        .collect()
+}
 
-    settings.write().await.volume = runner.read().await.volume();
-    if settings.read().await.save_window_size {
-        let width = main_window.get_last_width() as usize;
-        let height = main_window.get_last_height() as usize;
-        settings.write().await.window_size = WindowSize { width, height };
-    } else {
-        settings.write().await.window_size = WindowSize::default();
-    }
-    settings.read().await.save(platform.read().await).await;
+fn eq_preset_names(presets: &[EqPreset]) -> Vec<SharedString> {
+    PRESETS
+        .iter()
+        .map(|(name, _)| SharedString::from(*name))
+        .chain(presets.iter().map(|preset| preset.name.as_str().into()))
+        .collect()
 }
 
-async fn setup_data<P: crate::platform::Platform + Send + 'static>(
+async fn setup_eq<P: crate::platform::Platform + Send + 'static>(
     settings: Settings,
     platform: Platform<P>,
     main_window: MainWindow,
     tx: Sender<RunnerMessage>,
-    tx_searching: Sender<String>,
-    tx_changing: Sender<()>,
-    tx_path: Sender<(String, bool)>,
 ) {
-    localize(
-        settings.read().await.locale.clone(),
-        main_window.global::<Localization>(),
-    );
-
-    let settings_data = main_window.global::<SettingsData>();
-    let app_data = main_window.global::<AppData>();
-
-    #[cfg(target_os = "android")]
-    app_data.set_android(true);
-    app_data.set_version(env!("CARGO_PKG_VERSION").into());
+    let eq_data = main_window.global::<EqData>();
 
+    let bands = GRAPHIC_BANDS
+        .iter()
+        .map(|frequency| {
+            if *frequency >= 1000.0 {
+                format!("{}k", frequency / 1000.0).into()
+            } else {
+                format!("{frequency}").into()
+            }
+        })
+        .collect::<Vec<SharedString>>();
+    eq_data.set_bands(VecModel::from_slice(&bands));
     {
         let settings = settings.read().await;
-        settings_data.set_color_scheme(settings.theme.into());
-        settings_data.set_theme(i32::from(settings.theme));
-        settings_data.set_width(settings.window_size.width as f32);
-        settings_data.set_height(settings.window_size.height as f32);
-        settings_data.set_save_window_size(settings.save_window_size);
-        settings_data.set_current_path(settings.path.clone().into());
+        eq_data.set_enabled(settings.eq.enabled);
+        eq_data.set_gains(VecModel::from_slice(&settings.eq.gains));
+        eq_data.set_preamp(settings.eq.preamp);
+        eq_data.set_balance(settings.eq.balance);
+        eq_data.set_mono(settings.eq.mono);
+        eq_data.set_swap_channels(settings.eq.swap_channels);
+        eq_data.set_presets(VecModel::from_slice(&eq_preset_names(&settings.eq_presets)));
+        eq_data.set_preset(-1);
     }
 
-    let p = platform.clone();
-    app_data.on_open_link(move |link| {
-        let p = p.clone();
-        slint::spawn_local(async move { p.read().await.open_link(link.into()).await }).unwrap();
-    });
-
-    let s = settings.clone();
-    let window = main_window.clone_strong();
-    let p = platform.clone();
-    main_window
-        .global::<Localization>()
-        .on_set_locale(move |locale_name| {
-            let denominator = get_locale_denominator(Some(locale_name.into()));
-            localize(
-                Some(denominator.to_string()),
-                window.global::<Localization>(),
-            );
-            let s = s.clone();
-            let p = p.clone();
-            slint::spawn_local(async move {
-                s.write().await.locale = Some(denominator);
-                s.read().await.save(p.read().await).await;
-            })
-            .unwrap();
-        });
     let s = settings.clone();
-    let window = main_window.clone_strong();
-    let p = platform.clone();
-    settings_data.on_change_theme_callback(move |theme_name| {
-        if let Ok(theme) = Theme::try_from(theme_name) {
-            window
-                .global::<SettingsData>()
-                .set_color_scheme(theme.into());
+    let window = main_window.as_weak();
+    eq_data.on_changed(move || {
+        if let Some(window) = window.upgrade() {
+            let eq = eq_settings(&window.global::<EqData>());
+            tx.send(RunnerMessage::Dsp(eq.to_dsp())).unwrap();
             let s = s.clone();
-            let p = p.clone();
             slint::spawn_local(async move {
-                s.write().await.theme = theme;
-                s.read().await.save(p.read().await).await;
+                s.write().await.eq = eq;
             })
             .unwrap();
         }
     });
+    let window = main_window.as_weak();
+    eq_data.on_set_gain(move |index, gain| {
+        if let Some(window) = window.upgrade() {
+            let eq_data = window.global::<EqData>();
+            eq_data.get_gains().set_row_data(index as usize, gain);
+            eq_data.invoke_changed();
+        }
+    });
     let s = settings.clone();
-    settings_data.on_toggle_save_window_size(move |save| {
+    let window = main_window.as_weak();
+    eq_data.on_apply_preset(move |index| {
         let s = s.clone();
+        let window = window.clone();
         slint::spawn_local(async move {
-            s.write().await.save_window_size = save;
+            let index = index as usize;
+            let gains = match PRESETS.get(index) {
+                Some((_, gains)) => Some(gains.to_vec()),
+                None => s
+                    .read()
+                    .await
+                    .eq_presets
+                    .get(index - PRESETS.len())
+                    .map(|preset| preset.gains.clone()),
+            };
+            if let (Some(gains), Some(window)) = (gains, window.upgrade()) {
+                let eq_data = window.global::<EqData>();
+                eq_data.set_enabled(true);
+                eq_data.set_gains(VecModel::from_slice(&gains));
+                eq_data.invoke_changed();
+            }
         })
         .unwrap();
     });
-    let path = tx_path.clone();
-    settings_data.on_path(move || {
-        let tx_path = path.clone();
+    let s = settings.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    eq_data.on_save_preset(move |name| {
+        let Some(window) = window.upgrade() else {
+            return;
+        };
+        let name = name.to_string();
+        let gains = eq_settings(&window.global::<EqData>()).gains;
+        let s = s.clone();
+        let p = p.clone();
+        let window = window.as_weak();
         slint::spawn_local(async move {
-            tx_path.send_async((String::new(), false)).await.unwrap();
+            let names = {
+                let mut settings = s.write().await;
+                settings.eq_presets.retain(|preset| preset.name != name);
+                settings.eq_presets.push(EqPreset { name, gains });
+                eq_preset_names(&settings.eq_presets)
+            };
+            s.read().await.save(p.read().await).await;
+            if let Some(window) = window.upgrade() {
+                let eq_data = window.global::<EqData>();
+                eq_data.set_presets(VecModel::from_slice(&names));
+                eq_data.set_preset(names.len() as i32 - 1);
+            }
         })
         .unwrap();
     });
     let s = settings.clone();
-    settings_data.on_scan(move || {
-        let tx_path = tx_path.clone();
-        let settings = s.clone();
+    let p = platform.clone();
+    let window = main_window.as_weak();
+    eq_data.on_delete_preset(move |index| {
+        // the built-in presets can't be deleted
+        let Some(index) = (index as usize).checked_sub(PRESETS.len()) else {
+            return;
+        };
+        let s = s.clone();
+        let p = p.clone();
+        let window = window.clone();
         slint::spawn_local(async move {
-            tx_path
-                .send_async((settings.read().await.path.clone(), false))
-                .await
-                .unwrap();
+            let names = {
+                let mut settings = s.write().await;
+                if index >= settings.eq_presets.len() {
+                    return;
+                }
+                settings.eq_presets.remove(index);
+                eq_preset_names(&settings.eq_presets)
+            };
+            s.read().await.save(p.read().await).await;
+            if let Some(window) = window.upgrade() {
+                let eq_data = window.global::<EqData>();
+                eq_data.set_presets(VecModel::from_slice(&names));
+                eq_data.set_preset(-1);
+            }
         })
         .unwrap();
     });
-    let t = tx.clone();
-    app_data.on_clicked(move |i| t.send(RunnerMessage::PlayTrack(i as usize)).unwrap());
-    let t = tx.clone();
-    app_data.on_play_previous(move || t.send(RunnerMessage::PlayPrevious).unwrap());
-    let t = tx.clone();
-    app_data.on_toggle_pause(move || t.send(RunnerMessage::TogglePause).unwrap());
-    let t = tx.clone();
-    app_data.on_play_next(move || t.send(RunnerMessage::PlayNext).unwrap());
-    let t = tx.clone();
-    app_data.on_seek(move |time| {
-        t.send(RunnerMessage::Seek(RunnerSeek::Absolute(time as f64)))
-            .unwrap()
-    });
-    let t = tx.clone();
-    app_data.on_set_volume(move |volume| t.send(RunnerMessage::SetVolume(volume as f64)).unwrap());
-    app_data.on_searching(move |searching| tx_searching.send(searching.to_string()).unwrap());
-    app_data.on_changing(move || tx_changing.send(()).unwrap());
 }
 
 async fn updater_task<P: crate::platform::Platform + Send + 'static>(
@@ -248,10 +1336,13 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
     rx_changing: Receiver<()>,
     rx_searching: Receiver<String>,
     rx_l: Receiver<Option<(usize, FileTrack)>>,
+    rx_analysis: Receiver<(usize, TrackAnalysis)>,
 ) {
     let mut interval = tokio::time::interval(Duration::from_millis(250));
     let mut searching = String::new();
     let mut old_index = usize::MAX;
+    let mut old_up_next = vec![];
+    let mut old_chapters = vec![];
     let mut loaded = 0;
     let mut saved = false;
     let mut changes = vec![];
@@ -261,6 +1352,11 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
     }
     loop {
         interval.tick().await;
+        let bookmarks = r.write().await.bookmarks_to_save();
+        if let Some(bookmarks) = bookmarks {
+            s.write().await.bookmarks = bookmarks;
+            s.read().await.save(p.read().await).await;
+        }
         let guard = r.read().await;
         let mut index = guard.index();
         let len = guard.len();
@@ -268,11 +1364,54 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
             index = 0;
         }
         let playback = guard.playback();
+        let outside = guard.outside_names();
+        let streaming = outside.is_some();
+        let (stream_name, stream_title) = outside.unwrap_or_default();
+        let (stream_name, stream_title) = (
+            stream_name.unwrap_or_default(),
+            stream_title.unwrap_or_default(),
+        );
+        let stream_url = guard.stream_url().unwrap_or_default().to_string();
         let time = guard.time();
         let length = time.length;
         let time_float = time.position;
         let volume = guard.volume();
+        let loop_status = i32::from(guard.loop_status());
+        let stop_after = guard.stop_after().unwrap_or(0) as i32;
+        let audiobook = guard.audiobook();
+        let speed = guard.speed();
+        let chapter = guard.chapter().unwrap_or_default();
+        let finished = guard.finished().await;
+        let chapters = guard.chapter_starts();
+        let (loop_a, loop_b) = guard.loop_points();
+        let loop_a = loop_a.map_or(-1.0, |a| a as f32);
+        let loop_b = loop_b.map_or(-1.0, |b| b as f32);
+        let chapters = if chapters != old_chapters {
+            old_chapters = chapters.clone();
+            Some(chapters)
+        } else {
+            None
+        };
+        let up_next = if guard.up_next() != old_up_next.as_slice() {
+            old_up_next = guard.up_next().to_vec();
+            Some(
+                old_up_next
+                    .iter()
+                    .map(|index| QueueData {
+                        index: *index as i32,
+                        selected: false,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            None
+        };
         let position = time.format_pos();
+        let length_time = if length > 0.0 {
+            time.format_len()
+        } else {
+            String::new()
+        };
 
         let change_time = if let Ok(()) = rx_changing.try_recv() {
             false
@@ -311,6 +1450,9 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
                 new_loaded = true;
             }
         }
+        while let Ok((index, analysis)) = rx_analysis.try_recv() {
+            changes.push(Changes::Analysis(index, analysis));
+        }
         let progress = loaded as f64 / len as f64;
         if old_index != index || new_loaded {
             old_index = index;
@@ -339,8 +1481,27 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
                     app_data.set_time(time_float as f32);
                 }
                 app_data.set_length(length as f32);
+                app_data.set_length_time(length_time.into());
                 app_data.set_playback(playback);
+                app_data.set_streaming(streaming);
+                app_data.set_stream_url(stream_url.into());
+                app_data.set_stream_name(stream_name.into());
+                app_data.set_stream_title(stream_title.into());
                 app_data.set_volume(volume as f32);
+                app_data.set_loop_status(loop_status);
+                app_data.set_stop_after(stop_after);
+                app_data.set_audiobook(audiobook);
+                app_data.set_speed(speed);
+                app_data.set_chapter(chapter.into());
+                app_data.set_finished(finished);
+                app_data.set_loop_a(loop_a);
+                app_data.set_loop_b(loop_b);
+                if let Some(up_next) = up_next {
+                    app_data.set_up_next(VecModel::from_slice(&up_next));
+                }
+                if let Some(chapters) = chapters {
+                    app_data.set_chapters(VecModel::from_slice(&chapters));
+                }
 
                 if new_loaded {
                     let progress = if progress == 1.0 {
@@ -355,9 +1516,24 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
                     match change {
                         Changes::Tracks(tracks) => {
                             app_data.set_tracks(VecModel::from_slice(&tracks));
+                            sort_tracks(&app_data);
+                        }
+                        Changes::Metadata(index, mut track) => {
+                            let tracks = app_data.get_tracks();
+                            // the analysis may have been loaded before the metadata
+                            if let Some(old) = tracks.row_data(index) {
+                                track.bpm = old.bpm;
+                                track.key = old.key;
+                                track.key_order = old.key_order;
+                            }
+                            tracks.set_row_data(index, track);
                         }
-                        Changes::Metadata(index, track) => {
-                            app_data.get_tracks().set_row_data(index, track);
+                        Changes::Analysis(index, analysis) => {
+                            let tracks = app_data.get_tracks();
+                            if let Some(mut track) = tracks.row_data(index) {
+                                set_analysis(&mut track, analysis);
+                                tracks.set_row_data(index, track);
+                            }
                         }
                     }
                 }
@@ -376,12 +1552,10 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
                         app_data.set_saved_y(app_data.get_viewport_y());
                     }
                     for (index, mut track) in tracks.iter().enumerate() {
-                        let title = track.title.to_lowercase();
-                        let artist = track.artist.to_lowercase();
                         if search.is_empty() && !track.visible {
                             track.visible = true;
                         } else if !search.is_empty() {
-                            if title.contains(&search) || artist.contains(&search) {
+                            if matches_search(&track, &search) {
                                 counter += 1;
                                 if track.visible {
                                     continue;
@@ -411,6 +1585,283 @@ async fn updater_task<P: crate::platform::Platform + Send + 'static>(
     }
 }
 
+/// Pushes the spectrum and the levels of what is being played to the UI, at display rate
+async fn visualizer_task(tap: Tap, window: Weak<MainWindow>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(33));
+    let mut sequence = tap.sequence();
+    loop {
+        interval.tick().await;
+        let new_sequence = tap.sequence();
+        if new_sequence == sequence {
+            continue;
+        }
+        sequence = new_sequence;
+
+        let spectrum = tap.spectrum();
+        let levels = tap.levels();
+        window
+            .upgrade_in_event_loop(move |window| {
+                let app_data = window.global::<AppData>();
+                let model = app_data.get_spectrum();
+                if model.row_count() == spectrum.len() {
+                    for (index, value) in spectrum.into_iter().enumerate() {
+                        model.set_row_data(index, value);
+                    }
+                } else {
+                    app_data.set_spectrum(VecModel::from_slice(&spectrum));
+                }
+                app_data.set_level_left(meter(levels.rms[0]));
+                app_data.set_level_right(meter(levels.rms[1]));
+                app_data.set_peak_left(meter(levels.peak[0]));
+                app_data.set_peak_right(meter(levels.peak[1]));
+            })
+            .unwrap();
+    }
+}
+
+/// Shows the tempo and the key of a track
+fn set_analysis(track: &mut TrackData, analysis: TrackAnalysis) {
+    track.bpm = analysis.bpm.unwrap_or_default();
+    match analysis.key() {
+        Some(key) => {
+            let (number, letter) = key.camelot();
+            track.key = format!("{key} ({number}{letter})").into();
+            track.key_order = (number as i32 - 1) * 2 + i32::from(letter == 'B');
+        }
+        None => {
+            track.key = SharedString::new();
+            track.key_order = -1;
+        }
+    }
+}
+
+/// Returns whether a track matches the search (in lowercase) by its title or its artist, by its key
+/// (like `am` or `8a`) or by its tempo (like `128` or `120-130`)
+fn matches_search(track: &TrackData, search: &str) -> bool {
+    if track.title.to_lowercase().contains(search) || track.artist.to_lowercase().contains(search) {
+        return true;
+    }
+    if track
+        .key
+        .to_lowercase()
+        .split([' ', '(', ')'])
+        .any(|key| !key.is_empty() && key == search)
+    {
+        return true;
+    }
+
+    let (from, to) = search.split_once('-').unwrap_or((search, search));
+    match (from.trim().parse::<f32>(), to.trim().parse::<f32>()) {
+        (Ok(from), Ok(to)) if track.bpm > 0.0 => (from..=to).contains(&track.bpm.round()),
+        _ => false,
+    }
+}
+
+/// Shows the tracks in the order chosen by the user
+/// 0: library order, 1: title, 2: artist, 3: tempo, 4: key (on the Camelot wheel, then tempo)
+fn sort_tracks(app_data: &AppData) {
+    let tracks = app_data.get_tracks();
+    // unknown tempos and keys are sorted last
+    let by_bpm = |a: &TrackData, b: &TrackData| {
+        (a.bpm <= 0.0)
+            .cmp(&(b.bpm <= 0.0))
+            .then(a.bpm.total_cmp(&b.bpm))
+    };
+    let sorted = match app_data.get_sort() {
+        1 => {
+            ModelRc::new(tracks.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase())))
+        }
+        2 => ModelRc::new(tracks.sort_by(|a, b| {
+            a.artist
+                .to_lowercase()
+                .cmp(&b.artist.to_lowercase())
+                .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        })),
+        3 => ModelRc::new(tracks.sort_by(by_bpm)),
+        4 => ModelRc::new(tracks.sort_by(move |a, b| {
+            (a.key_order < 0)
+                .cmp(&(b.key_order < 0))
+                .then(a.key_order.cmp(&b.key_order))
+                .then_with(|| by_bpm(a, b))
+        })),
+        _ => tracks,
+    };
+    app_data.set_sorted_tracks(sorted);
+}
+
+/// Sends the tempo and the key of the tracks to the UI, analyzing in the background the ones that
+/// aren't cached yet if enabled
+/// A new library is announced with its number of tracks, `None` goes through the current one again
+async fn analysis_task<P: crate::platform::Platform + Send + 'static>(
+    r: Runner,
+    s: Settings,
+    p: Platform<P>,
+    rx: Receiver<Option<usize>>,
+    tx: Sender<(usize, TrackAnalysis)>,
+) {
+    let mut len = 0;
+    while let Ok(message) = rx.recv_async().await {
+        if let Some(new_len) = message {
+            len = new_len;
+        }
+
+        let mut cache = s.read().await.read_analysis(p.read().await).await;
+        let mut unsaved = 0;
+        for index in 0..len {
+            // starts again with the new library
+            if !rx.is_empty() {
+                break;
+            }
+            let Some(path) = r.read().await.get_path_for_file(index).await else {
+                continue;
+            };
+            let path = path.to_string_lossy().to_string();
+            let analysis = match cache.get(&path) {
+                Some(analysis) => *analysis,
+                None if s.read().await.analyze_tracks => {
+                    let track_path = path.clone();
+                    let analysis = match tokio::task::spawn_blocking(move || {
+                        MusicTrack::new(track_path)?.analyze()
+                    })
+                    .await
+                    {
+                        Ok(Ok(analysis)) => analysis.into(),
+                        Ok(Err(e)) => {
+                            // so that it isn't analyzed again
+                            eprintln!("can't analyze {path}: {e}");
+                            TrackAnalysis {
+                                bpm: None,
+                                key: None,
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("error happened while analyzing {path}: {e}");
+                            continue;
+                        }
+                    };
+                    cache.insert(path, analysis);
+                    unsaved += 1;
+                    if unsaved == ANALYSIS_SAVE_INTERVAL {
+                        unsaved = 0;
+                        s.read().await.save_analysis(p.read().await, &cache).await;
+                    }
+                    analysis
+                }
+                None => continue,
+            };
+            if tx.send_async((index, analysis)).await.is_err() {
+                return;
+            }
+        }
+        if unsaved > 0 {
+            s.read().await.save_analysis(p.read().await, &cache).await;
+        }
+    }
+}
+
+/// Pushes the waveform of the current track to the seek bar, computing it the first time the track is
+/// played
+async fn waveform_task<P: crate::platform::Platform + Send + 'static>(
+    r: Runner,
+    s: Settings,
+    p: Platform<P>,
+    window: Weak<MainWindow>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+    let mut waveforms = s.read().await.read_waveforms(p.read().await).await;
+    let mut current = None;
+    loop {
+        interval.tick().await;
+        let path = {
+            let guard = r.read().await;
+            guard
+                .get_path_for_file(guard.index())
+                .await
+                .map(|path| path.to_string_lossy().to_string())
+        };
+        if path == current {
+            continue;
+        }
+        current = path.clone();
+
+        let waveform = match path {
+            Some(path) if waveforms.contains_key(&path) => waveforms.get(&path).cloned(),
+            Some(path) => {
+                // the seek bar goes back to a plain slider while the waveform is computed
+                window
+                    .upgrade_in_event_loop(|window| {
+                        window.global::<AppData>().set_waveform(SharedString::new())
+                    })
+                    .unwrap();
+                let track_path = path.clone();
+                match tokio::task::spawn_blocking(move || {
+                    MusicTrack::new(track_path)?.waveform(WAVEFORM_POINTS)
+                })
+                .await
+                {
+                    Ok(Ok(peaks)) => {
+                        let waveform = peaks
+                            .into_iter()
+                            .map(|(min, max)| ((min * 127.0) as i8, (max * 127.0) as i8))
+                            .collect::<Vec<_>>();
+                        waveforms.insert(path, waveform.clone());
+                        s.read()
+                            .await
+                            .save_waveforms(p.read().await, &waveforms)
+                            .await;
+                        Some(waveform)
+                    }
+                    Ok(Err(e)) => {
+                        eprintln!("can't compute the waveform: {e}");
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("error happened while computing the waveform: {e}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let commands = waveform
+            .map(|waveform| waveform_commands(&waveform))
+            .unwrap_or_default();
+        window
+            .upgrade_in_event_loop(move |window| {
+                window.global::<AppData>().set_waveform(commands.into())
+            })
+            .unwrap();
+    }
+}
+
+/// Returns the SVG path of a waveform, in a 1000x2 viewbox centered on 0
+fn waveform_commands(waveform: &[(i8, i8)]) -> String {
+    if waveform.len() < 2 {
+        return String::new();
+    }
+
+    let x = |i: usize| i as f32 * 1000.0 / (waveform.len() - 1) as f32;
+    let mut commands = String::new();
+    // the highest peaks from left to right, then the lowest ones back, at least a line thick
+    for (i, (_, max)) in waveform.iter().enumerate() {
+        let command = if i == 0 { 'M' } else { 'L' };
+        let y = -((*max).max(1) as f32) / 127.0;
+        let _ = write!(commands, "{command} {:.1} {y:.3} ", x(i));
+    }
+    for (i, (min, _)) in waveform.iter().enumerate().rev() {
+        let y = -((*min).min(-1) as f32) / 127.0;
+        let _ = write!(commands, "L {:.1} {y:.3} ", x(i));
+    }
+    commands.push('Z');
+    commands
+}
+
+/// Maps a linear level to the height of a meter, showing the last 60 dB
+fn meter(level: f32) -> f32 {
+    (1.0 + 20.0 * level.max(1e-9).log10() / 60.0).clamp(0.0, 1.0)
+}
+
 async fn loader_task(
     runner: Runner,
     tx: Sender<Option<(usize, FileTrack)>>,
@@ -456,6 +1907,20 @@ async fn loader_task(
     }
 }
 
+/// Options of the tracker modules from the settings, applied to the ones opened after they change
+fn tracker_options(settings: &crate::settings::Settings) -> TrackerOptions {
+    TrackerOptions {
+        loops: settings.tracker_loops,
+        interpolation: match settings.tracker_interpolation {
+            1 => Interpolation::None,
+            2 => Interpolation::Linear,
+            3 => Interpolation::Cubic,
+            4 => Interpolation::Sinc,
+            _ => Interpolation::Default,
+        },
+    }
+}
+
 async fn loader<P: crate::platform::Platform + Send + 'static>(
     runner: Runner,
     settings: Settings,
@@ -463,6 +1928,8 @@ async fn loader<P: crate::platform::Platform + Send + 'static>(
     tx: Sender<Option<(usize, FileTrack)>>,
     rx: Receiver<(String, bool)>,
     tx_tracks: Sender<Vec<TrackData>>,
+    tx_analyze: Sender<Option<usize>>,
+    mut session: Option<Session>,
 ) {
     loop {
         if let Ok((mut path, check_cache)) = rx.recv_async().await {
@@ -482,7 +1949,12 @@ async fn loader<P: crate::platform::Platform + Send + 'static>(
                 let mut guard = runner.write().await;
                 guard.clear().await;
                 guard.set_path(path.clone());
-                add_all_tracks_to_player(guard.deref_mut(), path).await;
+                let audiobook = settings.read().await.audiobook_paths.contains(&path);
+                guard.set_audiobook(audiobook).await;
+                add_all_tracks_to_player(guard.deref_mut(), path.clone()).await;
+                if let Some(session) = session.as_ref().filter(|session| session.path == path) {
+                    guard.restore_order(&session.order);
+                }
                 guard.len()
             };
 
@@ -516,13 +1988,22 @@ async fn loader<P: crate::platform::Platform + Send + 'static>(
                         title: remove_ext(track_path).into(),
                         index: i as i32,
                         visible: true,
+                        bpm: 0.0,
+                        key: Default::default(),
+                        key_order: -1,
                     });
                 }
             }
             tracks.shrink_to_fit();
             tx_tracks.send_async(tracks).await.unwrap();
 
+            if let Some(session) = session.take().filter(|session| session.path == path) {
+                let auto_resume = settings.read().await.auto_resume;
+                runner.write().await.restore(session, auto_resume).await;
+            }
+
             if is_cached {
+                tx_analyze.send_async(Some(len)).await.unwrap();
                 continue;
             }
 
@@ -545,6 +2026,7 @@ async fn loader<P: crate::platform::Platform + Send + 'static>(
             for task in tasks {
                 task.await.unwrap();
             }
+            tx_analyze.send_async(Some(len)).await.unwrap();
         }
     }
 }
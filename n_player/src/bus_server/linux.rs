@@ -9,8 +9,9 @@ use mpris_server::{
 };
 use n_audio::music_track::MusicTrack;
 use n_audio::remove_ext;
+use n_audio::stream::is_url;
 use std::io::{Seek, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tempfile::NamedTempFile;
 use tokio::sync::RwLock;
@@ -68,7 +69,11 @@ impl RootInterface for MPRISBridge {
     }
 
     async fn supported_uri_schemes(&self) -> fdo::Result<Vec<String>> {
-        Ok(vec![])
+        Ok(vec![
+            String::from("file"),
+            String::from("http"),
+            String::from("https"),
+        ])
     }
 
     async fn supported_mime_types(&self) -> fdo::Result<Vec<String>> {
@@ -132,8 +137,42 @@ impl PlayerInterface for MPRISBridge {
         Ok(())
     }
 
-    async fn open_uri(&self, _uri: String) -> fdo::Result<()> {
-        Ok(())
+    async fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        if is_url(&uri) {
+            self.tx
+                .send_async(RunnerMessage::PlayStream(uri))
+                .await
+                .unwrap();
+            return Ok(());
+        }
+
+        let Some(path) = uri.strip_prefix("file://") else {
+            return Err(fdo::Error::NotSupported(format!("can't open {uri}")));
+        };
+        let path = PathBuf::from(percent_decode(path));
+        // only the tracks of the library can be played
+        let index = {
+            let runner = self.runner.read().await;
+            path.strip_prefix(runner.path()).ok().and_then(|name| {
+                runner
+                    .queue()
+                    .iter()
+                    .position(|track| Path::new(track.as_ref()) == name)
+            })
+        };
+        match index {
+            Some(index) => {
+                self.tx
+                    .send_async(RunnerMessage::PlayTrack(index))
+                    .await
+                    .unwrap();
+                Ok(())
+            }
+            None => Err(fdo::Error::FileNotFound(format!(
+                "{} is not in the library",
+                path.display()
+            ))),
+        }
     }
 
     async fn playback_status(&self) -> fdo::Result<PlaybackStatus> {
@@ -148,6 +187,7 @@ impl PlayerInterface for MPRISBridge {
     async fn loop_status(&self) -> fdo::Result<LoopStatus> {
         let loop_status = self.runner.read().await.loop_status();
         match loop_status {
+            n_audio::queue::LoopStatus::None => Ok(LoopStatus::None),
             n_audio::queue::LoopStatus::Playlist => Ok(LoopStatus::Playlist),
             n_audio::queue::LoopStatus::File => Ok(LoopStatus::Track),
         }
@@ -155,8 +195,7 @@ impl PlayerInterface for MPRISBridge {
 
     async fn set_loop_status(&self, loop_status: LoopStatus) -> zbus::Result<()> {
         let loop_status = match loop_status {
-            // defaults to playlist
-            LoopStatus::None => n_audio::queue::LoopStatus::Playlist,
+            LoopStatus::None => n_audio::queue::LoopStatus::None,
             LoopStatus::Track => n_audio::queue::LoopStatus::File,
             LoopStatus::Playlist => n_audio::queue::LoopStatus::Playlist,
         };
@@ -178,14 +217,28 @@ impl PlayerInterface for MPRISBridge {
     }
 
     async fn shuffle(&self) -> fdo::Result<bool> {
-        Ok(true)
+        Ok(self.runner.read().await.shuffle_enabled())
     }
 
-    async fn set_shuffle(&self, _shuffle: bool) -> zbus::Result<()> {
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()> {
+        self.tx
+            .send_async(RunnerMessage::Shuffle(shuffle))
+            .await
+            .expect("can't modify shuffle from mpris");
         Ok(())
     }
 
     async fn metadata(&self) -> fdo::Result<Metadata> {
+        {
+            let runner = self.runner.read().await;
+            if let Some((name, title)) = runner.outside_names() {
+                let mut metadata = Metadata::new();
+                metadata.set_artist(title.as_ref().and(name.clone()).map(|name| vec![name]));
+                metadata.set_title(title.or(name));
+                metadata.set_trackid(Some(ObjectPath::from_static_str_unchecked("/n_music")));
+                return Ok(metadata);
+            }
+        }
         let path = self.runner.read().await.path();
         let track_name = self.runner.read().await.current_track().await;
         if let None = track_name {
@@ -283,3 +336,27 @@ impl PlayerInterface for MPRISBridge {
         Ok(true)
     }
 }
+
+/// Decodes the `%XX` escapes of the path of a `file://` URI
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
@@ -43,6 +43,8 @@ pub async fn run<P: Platform + Send + Sync>(
     let mut loop_status = LoopStatus::default();
     let mut index = runner.read().await.index();
     let mut time = TrackTime::default();
+    // name and title of the stream or of the episode being played, if any
+    let mut stream = None;
     let path = runner.read().await.path();
 
     loop {
@@ -68,7 +70,24 @@ pub async fn run<P: Platform + Send + Sync>(
             properties.push(Property::PositionChanged(time.position));
         }
 
-        if index != guard.index() {
+        let guard_stream = guard.outside_names();
+        if stream != guard_stream {
+            if let Some((name, title)) = &guard_stream {
+                properties.push(Property::Metadata(Metadata {
+                    id: String::from("/n_music"),
+                    title: title.clone().or(name.clone()),
+                    artists: title.as_ref().and(name.clone()).map(|name| vec![name]),
+                    length: 0.0,
+                    image_path: None,
+                }));
+            } else {
+                // the metadata of the track that was playing before has to be sent again
+                index = usize::MAX;
+            }
+            stream = guard_stream;
+        }
+
+        if stream.is_none() && index != guard.index() {
             index = guard.index();
             let track_name = match guard.current_track().await {
                 Some(track) => track,
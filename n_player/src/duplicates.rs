@@ -0,0 +1,404 @@
+use crate::app::{Platform, Runner, Settings};
+use crate::settings::CachedFingerprint;
+use crate::{format_length, DuplicateData, MainWindow, SettingsData};
+use flume::Receiver;
+use n_audio::container::Location;
+use n_audio::fingerprint::Fingerprint;
+use n_audio::music_track::MusicTrack;
+use slint::{ComponentHandle, SharedString, VecModel, Weak};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::time::UNIX_EPOCH;
+
+/// How many tracks are fingerprinted before saving the fingerprint cache
+const FINGERPRINT_SAVE_INTERVAL: usize = 20;
+/// Largest difference between the lengths of two duplicates, in seconds
+const DUPLICATE_LENGTH_TOLERANCE: f64 = 5.0;
+/// Lowest similarity of the fingerprints of two duplicates
+const DUPLICATE_SIMILARITY: f32 = 0.8;
+/// Lowest similarity of the fingerprints of two duplicates with the same title and artist
+const DUPLICATE_SIMILARITY_SAME_TAGS: f32 = 0.65;
+/// How far apart (in subfingerprints, about 3 s) the starts of two duplicates can be
+const DUPLICATE_MAX_OFFSET: usize = 24;
+
+/// A track considered by the duplicate finder
+struct DuplicateCandidate {
+    index: usize,
+    path: String,
+    title: String,
+    artist: String,
+    length: f64,
+    /// Empty if the track couldn't be fingerprinted
+    fingerprint: Fingerprint,
+}
+
+/// Fingerprints the tracks that aren't cached yet and shows the groups of tracks that are probably
+/// the same song
+pub async fn duplicates_task<P: crate::platform::Platform + Send + 'static>(
+    r: Runner,
+    s: Settings,
+    p: Platform<P>,
+    rx: Receiver<()>,
+    window: Weak<MainWindow>,
+) {
+    let set_progress = |progress: String| {
+        window
+            .upgrade_in_event_loop(move |window| {
+                window
+                    .global::<SettingsData>()
+                    .set_duplicates_progress(progress.into())
+            })
+            .unwrap();
+    };
+
+    while rx.recv_async().await.is_ok() {
+        let len = r.read().await.len();
+        set_progress(format!("0/{len}"));
+
+        let mut fingerprints = s.read().await.read_fingerprints(p.read().await).await;
+        let mut unsaved = 0;
+        let mut candidates = vec![];
+        for index in 0..len {
+            let Some(path) = r.read().await.get_path_for_file(index).await else {
+                continue;
+            };
+            let path = path.to_string_lossy().to_string();
+            let cached = fingerprints.get(&path).map(|cached| cached.modified);
+            let track_path = path.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let modified = modified(&track_path);
+                let track = MusicTrack::new(track_path)?;
+                let meta = track.get_meta()?;
+                // the tracks that can't be fingerprinted are cached too, so that they aren't
+                // decoded again until their file changes
+                let fingerprint = (cached != Some(modified)).then(|| CachedFingerprint {
+                    modified,
+                    fingerprint: track.fingerprint().map(|f| f.0).unwrap_or_default(),
+                });
+                Ok::<_, io::Error>((meta, fingerprint))
+            })
+            .await;
+            set_progress(format!("{}/{len}", index + 1));
+
+            let (meta, fingerprint) = match result {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => {
+                    eprintln!("can't fingerprint {path}: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("error happened while fingerprinting {path}: {e}");
+                    continue;
+                }
+            };
+            if let Some(fingerprint) = fingerprint {
+                fingerprints.insert(path.clone(), fingerprint);
+                unsaved += 1;
+                if unsaved == FINGERPRINT_SAVE_INTERVAL {
+                    unsaved = 0;
+                    s.read()
+                        .await
+                        .save_fingerprints(p.read().await, &fingerprints)
+                        .await;
+                }
+            }
+            candidates.push(DuplicateCandidate {
+                index,
+                fingerprint: Fingerprint(
+                    fingerprints
+                        .get(&path)
+                        .map(|cached| cached.fingerprint.clone())
+                        .unwrap_or_default(),
+                ),
+                path,
+                title: meta.title,
+                artist: meta.artist,
+                length: meta.time.length,
+            });
+        }
+        if unsaved > 0 {
+            s.read()
+                .await
+                .save_fingerprints(p.read().await, &fingerprints)
+                .await;
+        }
+
+        let duplicates = group_duplicates(candidates)
+            .into_iter()
+            .flat_map(|group| {
+                group
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, track)| DuplicateData {
+                        title: track.title.into(),
+                        artist: track.artist.into(),
+                        path: track.path.into(),
+                        time: format_length(track.length).into(),
+                        index: track.index as i32,
+                        first: position == 0,
+                    })
+            })
+            .collect::<Vec<_>>();
+        // the searches asked in the meantime are answered by this one
+        while rx.try_recv().is_ok() {}
+        window
+            .upgrade_in_event_loop(move |window| {
+                let settings_data = window.global::<SettingsData>();
+                settings_data.set_duplicates(VecModel::from_slice(&duplicates));
+                settings_data.set_duplicates_progress(SharedString::new());
+            })
+            .unwrap();
+    }
+}
+
+/// Modification time of the file holding the track at `path`, in nanoseconds since the Unix epoch,
+/// 0 if it can't be read
+fn modified(path: &str) -> u64 {
+    fs::metadata(Location::parse(path).file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+/// Lowercase title or artist without punctuation and spaces, to compare the tags of two tracks
+fn normalize_tag(tag: &str) -> String {
+    tag.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Whether two tracks are probably the same song: their lengths have to be close and their
+/// fingerprints similar, less so if they have the same tags
+/// Tracks that couldn't be fingerprinted are compared by their tags only
+fn is_duplicate(a: &DuplicateCandidate, b: &DuplicateCandidate) -> bool {
+    if (a.length - b.length).abs() > DUPLICATE_LENGTH_TOLERANCE {
+        return false;
+    }
+    let title = normalize_tag(&a.title);
+    let same_tags = !title.is_empty()
+        && title == normalize_tag(&b.title)
+        && normalize_tag(&a.artist) == normalize_tag(&b.artist);
+    if a.fingerprint.0.is_empty() || b.fingerprint.0.is_empty() {
+        return same_tags;
+    }
+
+    let threshold = if same_tags {
+        DUPLICATE_SIMILARITY_SAME_TAGS
+    } else {
+        DUPLICATE_SIMILARITY
+    };
+    a.fingerprint
+        .similarity(&b.fingerprint, DUPLICATE_MAX_OFFSET)
+        >= threshold
+}
+
+/// Returns the groups of at least two tracks that are duplicates, sorted by title
+///
+/// Every group is built around its shortest track, and holds only the tracks that are duplicates
+/// of that one: two tracks that are both similar to a third aren't grouped through it, so a chain
+/// of slightly different versions doesn't become a single group
+fn group_duplicates(mut candidates: Vec<DuplicateCandidate>) -> Vec<Vec<DuplicateCandidate>> {
+    // only the tracks with close lengths have to be compared
+    candidates.sort_by(|a, b| a.length.total_cmp(&b.length));
+    // every track starts alone, as the representative of its own group
+    let mut representatives = (0..candidates.len()).collect::<Vec<_>>();
+    let mut grouped = vec![false; candidates.len()];
+    for i in 0..candidates.len() {
+        if grouped[i] {
+            continue;
+        }
+        for j in i + 1..candidates.len() {
+            if candidates[j].length - candidates[i].length > DUPLICATE_LENGTH_TOLERANCE {
+                break;
+            }
+            if !grouped[j] && is_duplicate(&candidates[i], &candidates[j]) {
+                grouped[j] = true;
+                representatives[j] = i;
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<DuplicateCandidate>> = HashMap::new();
+    for (candidate, representative) in candidates.into_iter().zip(representatives) {
+        groups.entry(representative).or_default().push(candidate);
+    }
+    let mut groups = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect::<Vec<_>>();
+    for group in &mut groups {
+        group.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    groups.sort_by_cached_key(|group| group[0].title.to_lowercase());
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    /// Pseudo-random fingerprint, different for every seed
+    fn fingerprint(seed: u32) -> Fingerprint {
+        let mut state = seed.wrapping_mul(0x9e3779b9) | 1;
+        Fingerprint(
+            (0..240)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state
+                })
+                .collect(),
+        )
+    }
+
+    /// `fingerprint` with the bits of `mask` flipped in every subfingerprint
+    fn flipped(fingerprint: &Fingerprint, mask: u32) -> Fingerprint {
+        Fingerprint(fingerprint.0.iter().map(|value| value ^ mask).collect())
+    }
+
+    fn candidate(
+        index: usize,
+        title: &str,
+        artist: &str,
+        length: f64,
+        fingerprint: Fingerprint,
+    ) -> DuplicateCandidate {
+        DuplicateCandidate {
+            index,
+            path: format!("{index}.flac"),
+            title: title.to_string(),
+            artist: artist.to_string(),
+            length,
+            fingerprint,
+        }
+    }
+
+    #[test]
+    fn same_audio_with_close_lengths_is_duplicate() {
+        let a = candidate(0, "Song", "Artist", 200.0, fingerprint(1));
+        let b = candidate(1, "Other", "Someone", 203.0, fingerprint(1));
+        assert!(is_duplicate(&a, &b));
+
+        let far = candidate(2, "Song", "Artist", 210.0, fingerprint(1));
+        assert!(!is_duplicate(&a, &far));
+        let different = candidate(3, "Song", "Artist", 200.0, fingerprint(2));
+        assert!(!is_duplicate(&a, &different));
+    }
+
+    #[test]
+    fn same_tags_lower_the_similarity_needed() {
+        // 8 bits out of 32 are different, a similarity of 0.75
+        let a = candidate(0, "Song (Live)", "The Artist", 200.0, fingerprint(1));
+        let tagged = candidate(
+            1,
+            "song live",
+            "the artist",
+            200.0,
+            flipped(&a.fingerprint, 0xff),
+        );
+        let untagged = candidate(
+            2,
+            "Other",
+            "The Artist",
+            200.0,
+            flipped(&a.fingerprint, 0xff),
+        );
+        assert!(is_duplicate(&a, &tagged));
+        assert!(!is_duplicate(&a, &untagged));
+    }
+
+    #[test]
+    fn tracks_without_fingerprints_are_compared_by_tags() {
+        let a = candidate(0, "Song", "Artist", 200.0, Fingerprint(vec![]));
+        let same = candidate(1, "SONG!", "artist", 201.0, fingerprint(1));
+        let other = candidate(2, "Another", "Artist", 200.0, Fingerprint(vec![]));
+        let untitled = candidate(3, "", "", 200.0, Fingerprint(vec![]));
+        assert!(is_duplicate(&a, &same));
+        assert!(!is_duplicate(&a, &other));
+        assert!(!is_duplicate(&untitled, &untitled));
+    }
+
+    #[test]
+    fn groups_are_sorted_and_unique_tracks_left_out() {
+        let groups = group_duplicates(vec![
+            candidate(3, "Zeta", "", 100.0, fingerprint(1)),
+            candidate(0, "Unique", "", 150.0, fingerprint(2)),
+            candidate(2, "alpha", "", 300.0, fingerprint(3)),
+            candidate(1, "Zeta", "", 101.0, fingerprint(1)),
+            candidate(4, "Alpha", "", 299.0, fingerprint(3)),
+        ]);
+        assert_eq!(indices(&groups), [vec![2, 4], vec![1, 3]]);
+    }
+
+    fn indices(groups: &[Vec<DuplicateCandidate>]) -> Vec<Vec<usize>> {
+        groups
+            .iter()
+            .map(|group| group.iter().map(|track| track.index).collect())
+            .collect()
+    }
+
+    #[test]
+    fn similar_tracks_are_not_chained() {
+        // 6 bits apart from each other, but 12 bits apart from the first
+        let first = fingerprint(1);
+        let groups = group_duplicates(vec![
+            candidate(0, "A", "", 200.0, first.clone()),
+            candidate(1, "B", "", 201.0, flipped(&first, 0x3f)),
+            candidate(2, "C", "", 202.0, flipped(&first, 0xfff)),
+        ]);
+        assert_eq!(indices(&groups), [vec![0, 1]]);
+    }
+
+    #[test]
+    fn lengths_are_compared_with_the_representative() {
+        let groups = group_duplicates(vec![
+            candidate(0, "A", "", 200.0, fingerprint(1)),
+            candidate(1, "B", "", 204.0, fingerprint(1)),
+            candidate(2, "C", "", 208.0, fingerprint(1)),
+        ]);
+        assert_eq!(indices(&groups), [vec![0, 1]]);
+    }
+
+    #[test]
+    fn modified_time_follows_the_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("song.flac");
+        let path_str = path.to_str().unwrap();
+        assert_eq!(modified(path_str), 0);
+
+        fs::write(&path, b"").unwrap();
+        let set_modified = |time| {
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        set_modified(UNIX_EPOCH + Duration::from_secs(60));
+        assert_eq!(modified(path_str), 60_000_000_000);
+        set_modified(SystemTime::now());
+        assert!(modified(path_str) > 60_000_000_000);
+    }
+
+    #[test]
+    fn entries_use_the_time_of_their_archive() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("album.zip");
+        fs::write(&archive, b"").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&archive)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1))
+            .unwrap();
+        let entry = format!("{}#song.flac", archive.to_str().unwrap());
+        assert_eq!(modified(&entry), 1_000_000_000);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::app::{Runner, Settings};
+use crate::{MainWindow, SettingsData};
+use flume::{Receiver, Sender};
+use n_audio::container::{Location, TRACK_SEPARATOR};
+use n_audio::export::{export, ExportFormat, ExportOptions};
+use n_audio::music_track::MusicTrack;
+use slint::{ComponentHandle, Weak};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Bitrate of the tracks exported with Opus, in bits per second
+const EXPORT_BITRATE: u32 = 160000;
+
+/// Format of the exported tracks for its index in the settings
+fn export_format(index: u8) -> ExportFormat {
+    match index {
+        1 => ExportFormat::Flac,
+        2 => ExportFormat::Wav,
+        _ => ExportFormat::Opus(EXPORT_BITRATE),
+    }
+}
+
+/// Whether `target` was exported after the last change of `path`
+fn is_exported(path: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let path = path.to_string_lossy();
+    let file = Path::new(Location::parse(&path).file);
+    matches!((modified(file), modified(target)), (Some(source), Some(target)) if target >= source)
+}
+
+/// Where the track at `path` is exported with the extension `ext`, relative to the export folder:
+/// the folders of the library in `root` are kept, archives become folders and the tracks of a
+/// container are told apart by their id
+///
+/// Returns `None` for the archive entries whose names would leave their folder
+fn export_path(path: &Path, root: &Path, ext: &str) -> Option<PathBuf> {
+    let path = path.to_string_lossy();
+    let location = Location::parse(&path);
+    let file = Path::new(location.file);
+    let mut relative = file
+        .strip_prefix(root)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| file.file_name().map(PathBuf::from).unwrap_or_default());
+    if let Some(entry) = location.entry {
+        // the names of the entries come from the archive
+        let entry = Path::new(entry);
+        if !entry
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        relative = relative.with_extension("").join(entry);
+    }
+    Some(match location.track_id {
+        Some(track_id) => PathBuf::from(format!(
+            "{} {TRACK_SEPARATOR}{track_id}.{ext}",
+            relative.with_extension("").display()
+        )),
+        None => relative.with_extension(ext),
+    })
+}
+
+async fn export_task(
+    runner: Runner,
+    root: Arc<Path>,
+    destination: Arc<Path>,
+    options: ExportOptions,
+    tx: Sender<()>,
+    rx_e: Arc<Mutex<Receiver<usize>>>,
+) {
+    loop {
+        let Ok(index) = rx_e.lock().await.recv_async().await else {
+            return;
+        };
+        if index == usize::MAX {
+            return;
+        }
+        let path = runner.read().await.get_path_for_file(index).await;
+        let relative = path
+            .as_ref()
+            .and_then(|path| export_path(path, &root, options.format.extension()));
+        if let (Some(path), None) = (&path, &relative) {
+            eprintln!(
+                "can't export {}: it would be outside the export folder",
+                path.display()
+            );
+        }
+        if let (Some(path), Some(relative)) = (path, relative) {
+            let target = destination.join(relative);
+            let name = path.to_string_lossy().to_string();
+            match tokio::task::spawn_blocking(move || {
+                if is_exported(&path, &target) {
+                    return Ok(());
+                }
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                export(
+                    &MusicTrack::new(path.to_string_lossy().to_string())?,
+                    &options,
+                    &target,
+                )
+            })
+            .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("can't export {name}: {e}"),
+                Err(e) => eprintln!("error happened while exporting {name}: {e}"),
+            }
+        }
+        if let Err(e) = tx.send_async(()).await {
+            eprintln!("error happened when signaling an exported track, probably because the app was closed: {e}");
+            return;
+        }
+    }
+}
+
+/// Exports the library to the folders it receives, with a task for every CPU
+pub async fn exporter(
+    runner: Runner,
+    settings: Settings,
+    rx: Receiver<PathBuf>,
+    window: Weak<MainWindow>,
+) {
+    let set_progress = |progress: String| {
+        window
+            .upgrade_in_event_loop(move |window| {
+                window
+                    .global::<SettingsData>()
+                    .set_export_progress(progress.into())
+            })
+            .unwrap();
+    };
+
+    while let Ok(destination) = rx.recv_async().await {
+        let (root, options) = {
+            let settings = settings.read().await;
+            (
+                Arc::<Path>::from(Path::new(&settings.path)),
+                ExportOptions {
+                    format: export_format(settings.export_format),
+                    rate: None,
+                    replay_gain: settings.export_replay_gain,
+                },
+            )
+        };
+        let destination: Arc<Path> = Arc::from(destination);
+        let len = runner.read().await.len();
+        set_progress(format!("0/{len}"));
+
+        let (tx_e, rx_e) = flume::unbounded();
+        let rx_e = Arc::new(Mutex::new(rx_e));
+        let (tx_done, rx_done) = flume::unbounded();
+        let cpus = num_cpus::get();
+        for _ in 0..cpus {
+            tokio::task::spawn(export_task(
+                runner.clone(),
+                Arc::clone(&root),
+                destination.clone(),
+                options,
+                tx_done.clone(),
+                rx_e.clone(),
+            ));
+        }
+        drop(tx_done);
+        for i in 0..len {
+            tx_e.send_async(i).await.unwrap();
+        }
+        for _ in 0..cpus {
+            tx_e.send_async(usize::MAX).await.unwrap();
+        }
+
+        let mut exported = 0;
+        while rx_done.recv_async().await.is_ok() {
+            exported += 1;
+            set_progress(format!("{exported}/{len}"));
+        }
+        set_progress(String::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+    use tempfile::TempDir;
+
+    /// Library in a temporary folder, with an archive and a container so that their locations are
+    /// split
+    fn library() -> TempDir {
+        let root = TempDir::new().unwrap();
+        fs::write(root.path().join("album.zip"), b"").unwrap();
+        fs::write(root.path().join("movie.mkv"), b"").unwrap();
+        root
+    }
+
+    fn relative(root: &Path, path: &str) -> Option<PathBuf> {
+        let path = format!("{}{}{path}", root.display(), std::path::MAIN_SEPARATOR);
+        export_path(Path::new(&path), root, "opus")
+    }
+
+    #[test]
+    fn library_folders_are_kept() {
+        let root = library();
+        assert_eq!(
+            relative(root.path(), "artist/song.flac"),
+            Some(PathBuf::from("artist/song.opus"))
+        );
+        assert_eq!(
+            export_path(Path::new("/elsewhere/song.flac"), root.path(), "wav"),
+            Some(PathBuf::from("song.wav"))
+        );
+    }
+
+    #[test]
+    fn archives_become_folders() {
+        let root = library();
+        assert_eq!(
+            relative(root.path(), "album.zip#disc 1/01.flac"),
+            Some(PathBuf::from("album/disc 1/01.opus"))
+        );
+    }
+
+    #[test]
+    fn container_tracks_are_named_by_id() {
+        let root = library();
+        assert_eq!(
+            relative(root.path(), "movie.mkv#2"),
+            Some(PathBuf::from("movie #2.opus"))
+        );
+    }
+
+    #[test]
+    fn entries_leaving_the_folder_are_rejected() {
+        let root = library();
+        assert_eq!(relative(root.path(), "album.zip#../../evil.flac"), None);
+        assert_eq!(relative(root.path(), "album.zip#/etc/evil.flac"), None);
+    }
+
+    #[test]
+    fn only_newer_exports_are_kept() {
+        let root = library();
+        let source = root.path().join("song.flac");
+        let target = root.path().join("song.opus");
+        fs::write(&source, b"").unwrap();
+        assert!(!is_exported(&source, &target));
+
+        fs::write(&target, b"").unwrap();
+        let now = SystemTime::now();
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(60))
+            .unwrap();
+        assert!(is_exported(&source, &target));
+
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(now + Duration::from_secs(60))
+            .unwrap();
+        assert!(!is_exported(&source, &target));
+    }
+
+    #[test]
+    fn export_format_falls_back_to_opus() {
+        assert_eq!(export_format(1).extension(), "flac");
+        assert_eq!(export_format(2).extension(), "wav");
+        assert_eq!(export_format(0).extension(), "opus");
+        assert_eq!(export_format(9).extension(), "opus");
+    }
+}
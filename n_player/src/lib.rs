@@ -4,6 +4,8 @@ use bitcode::{Decode, Encode};
 use flume::{Receiver, RecvError, SendError, Sender, TryRecvError};
 use multitag::data::Picture;
 use multitag::Tag;
+use n_audio::container::{archive_cover, list_tracks, Location};
+use n_audio::tracker;
 #[cfg(target_os = "android")]
 use once_cell::sync::Lazy;
 use rimage::codecs::webp::WebPDecoder;
@@ -25,8 +27,11 @@ slint::include_modules!();
 
 pub mod app;
 pub mod bus_server;
+pub mod duplicates;
+pub mod export;
 pub mod localization;
 pub mod platform;
+pub mod podcast;
 pub mod runner;
 pub mod settings;
 
@@ -170,6 +175,11 @@ pub async fn get_image_squared<P: AsRef<Path> + Debug + Send + 'static>(
 }
 
 pub fn get_image<P: AsRef<Path> + Debug>(path: P) -> Vec<u8> {
+    if let Some(location) = path.as_ref().to_str().map(Location::parse) {
+        if location.entry.is_some() {
+            return archive_cover(&location).unwrap_or_default();
+        }
+    }
     if let Ok(tag) = Tag::read_from_path(path.as_ref()) {
         if let Some(album) = tag.get_album_info() {
             if let Some(cover) = album.cover {
@@ -197,6 +207,18 @@ pub fn get_image<P: AsRef<Path> + Debug>(path: P) -> Vec<u8> {
     vec![]
 }
 
+/// MIME types of the audio files whose container can hold more than one audio track
+const CONTAINER_MIME_TYPES: &[&str] = &[
+    "audio/x-matroska",
+    "audio/webm",
+    "audio/mp4",
+    "audio/m4a",
+    "audio/x-m4a",
+    "audio/ogg",
+    "audio/opus",
+    "application/ogg",
+];
+
 pub async fn add_all_tracks_to_player<P: AsRef<Path> + AsRef<OsStr> + From<String>>(
     runner: &mut Runner,
     path: P,
@@ -205,9 +227,36 @@ pub async fn add_all_tracks_to_player<P: AsRef<Path> + AsRef<OsStr> + From<Strin
         let mut paths = vec![];
         while let Ok(Some(file)) = dir.next_entry().await {
             if file.file_type().await.unwrap().is_file() {
-                if let Ok(Some(mime)) = infer::get_from_path(&file.path()) {
-                    if mime.mime_type().contains("audio") {
-                        let mut p = file.path().to_str().unwrap().to_string();
+                // tracker modules have no MIME type that can be detected
+                let module = file
+                    .path()
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(tracker::is_module);
+                if module && tracker::ENABLED {
+                    let mut p = file.path().to_str().unwrap().to_string();
+                    p.shrink_to_fit();
+                    paths.push(p);
+                } else if let Ok(Some(mime)) = infer::get_from_path(&file.path()) {
+                    let mut p = file.path().to_str().unwrap().to_string();
+                    let mime = mime.mime_type();
+                    if mime.contains("video")
+                        || mime == "application/zip"
+                        || CONTAINER_MIME_TYPES.contains(&mime)
+                    {
+                        // the audio tracks of containers and the audio files of archives are
+                        // added one by one
+                        match tokio::task::spawn_blocking(move || list_tracks(&p)).await {
+                            Ok(Ok(tracks)) => paths.extend(tracks),
+                            Ok(Err(e)) => {
+                                eprintln!("can't list the tracks of {:?}: {e}", file.path())
+                            }
+                            Err(e) => eprintln!(
+                                "error happened while listing the tracks of {:?}: {e}",
+                                file.path()
+                            ),
+                        }
+                    } else if mime.contains("audio") {
                         p.shrink_to_fit();
                         paths.push(p);
                     }
@@ -216,7 +265,7 @@ pub async fn add_all_tracks_to_player<P: AsRef<Path> + AsRef<OsStr> + From<Strin
         }
         runner.add_all(paths).await;
         runner.shrink_to_fit();
-        runner.shuffle();
+        runner.reorder();
     }
 }
 
@@ -313,6 +362,15 @@ pub struct FileTrack {
     pub image: Vec<u8>,
 }
 
+/// Formats a length in seconds as minutes and seconds
+pub fn format_length(length: f64) -> String {
+    format!(
+        "{:02}:{:02}",
+        (length / 60.0).floor() as u64,
+        length.floor() as u64 % 60
+    )
+}
+
 impl From<FileTrack> for TrackData {
     fn from(mut value: FileTrack) -> Self {
         value.artist.shrink_to_fit();
@@ -326,14 +384,12 @@ impl From<FileTrack> for TrackData {
                 Default::default()
             },
             index: 0,
-            time: format!(
-                "{:02}:{:02}",
-                (value.length / 60.0).floor() as u64,
-                value.length.floor() as u64 % 60
-            )
-            .into(),
+            time: format_length(value.length).into(),
             title: value.title.into(),
             visible: true,
+            bpm: 0.0,
+            key: Default::default(),
+            key_order: -1,
         }
     }
 }
@@ -40,6 +40,59 @@ pub struct Locale {
     check_update: Option<String>,
     update: Option<String>,
     rescan: Option<String>,
+    up_next: Option<String>,
+    auto_resume: Option<String>,
+    audiobook_folder: Option<String>,
+    loop_crossfade: Option<String>,
+    equalizer: Option<String>,
+    preset: Option<String>,
+    save_preset: Option<String>,
+    preamp: Option<String>,
+    balance: Option<String>,
+    mono: Option<String>,
+    swap_channels: Option<String>,
+    impulse_response: Option<String>,
+    crossfeed: Option<String>,
+    night_mode: Option<String>,
+    trim_silence: Option<String>,
+    analyze_tracks: Option<String>,
+    sort_library: Option<String>,
+    sort_title: Option<String>,
+    sort_artist: Option<String>,
+    sort_bpm: Option<String>,
+    sort_key: Option<String>,
+    export_library: Option<String>,
+    export_format: Option<String>,
+    export_replay_gain: Option<String>,
+    duplicate_tracks: Option<String>,
+    find_duplicates: Option<String>,
+    no_duplicates: Option<String>,
+    radio_stations: Option<String>,
+    open_stations: Option<String>,
+    station_url: Option<String>,
+    add_station: Option<String>,
+    remove_station: Option<String>,
+    no_stations: Option<String>,
+    podcasts: Option<String>,
+    open_podcasts: Option<String>,
+    podcast_refresh: Option<String>,
+    podcast_refresh_manual: Option<String>,
+    podcast_folder: Option<String>,
+    refresh: Option<String>,
+    unsubscribe: Option<String>,
+    feed_url: Option<String>,
+    subscribe: Option<String>,
+    no_podcasts: Option<String>,
+    download: Option<String>,
+    downloading: Option<String>,
+    delete_download: Option<String>,
+    tracker_loops: Option<String>,
+    tracker_interpolation: Option<String>,
+    interpolation_default: Option<String>,
+    interpolation_none: Option<String>,
+    interpolation_linear: Option<String>,
+    interpolation_cubic: Option<String>,
+    silence_threshold: Option<String>,
 }
 
 pub fn localize(denominator: Option<String>, localization: Localization) {
@@ -81,7 +134,60 @@ pub fn localize(denominator: Option<String>, localization: Localization) {
         update_text,
         check_update,
         update,
-        rescan
+        rescan,
+        up_next,
+        auto_resume,
+        audiobook_folder,
+        loop_crossfade,
+        equalizer,
+        preset,
+        save_preset,
+        preamp,
+        balance,
+        mono,
+        swap_channels,
+        impulse_response,
+        crossfeed,
+        night_mode,
+        trim_silence,
+        analyze_tracks,
+        sort_library,
+        sort_title,
+        sort_artist,
+        sort_bpm,
+        sort_key,
+        export_library,
+        export_format,
+        export_replay_gain,
+        duplicate_tracks,
+        find_duplicates,
+        no_duplicates,
+        radio_stations,
+        open_stations,
+        station_url,
+        add_station,
+        remove_station,
+        no_stations,
+        podcasts,
+        open_podcasts,
+        podcast_refresh,
+        podcast_refresh_manual,
+        podcast_folder,
+        refresh,
+        unsubscribe,
+        feed_url,
+        subscribe,
+        no_podcasts,
+        download,
+        downloading,
+        delete_download,
+        tracker_loops,
+        tracker_interpolation,
+        interpolation_default,
+        interpolation_none,
+        interpolation_linear,
+        interpolation_cubic,
+        silence_threshold
     );
 }
 
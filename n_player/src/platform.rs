@@ -184,6 +184,7 @@ impl Platform for LinuxPlatform {
                     Property::Volume(volume) => mpris_server::Property::Volume(volume),
                     Property::LoopStatus(loop_status) => {
                         let loop_status = match loop_status {
+                            n_audio::queue::LoopStatus::None => mpris_server::LoopStatus::None,
                             n_audio::queue::LoopStatus::Playlist => {
                                 mpris_server::LoopStatus::Playlist
                             }
@@ -0,0 +1,844 @@
+use bitcode::{Decode, Encode};
+use n_audio::chapter::Chapter;
+use n_audio::stream::{TimeoutReader, READ_TIMEOUT};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::NsReader;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use ureq::Agent;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest feed that is downloaded, in bytes
+const FEED_LIMIT: u64 = 64 * 1024 * 1024;
+/// Largest episode that is downloaded, in bytes
+const EPISODE_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+/// Extensions of the enclosures that are kept in the name of the downloaded episodes
+const AUDIO_EXTENSIONS: [&str; 9] = [
+    "mp3", "m4a", "m4b", "mp4", "aac", "ogg", "opus", "flac", "wav",
+];
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const ITUNES: &[u8] = b"http://www.itunes.com/dtds/podcast-1.0.dtd";
+const PODLOVE: &[u8] = b"http://podlove.org/simple-chapters";
+const ATOM: &[u8] = b"http://www.w3.org/2005/Atom";
+
+/// A podcast the user is subscribed to
+#[derive(Clone, Debug, Default, Decode, Encode)]
+pub struct Podcast {
+    /// URL of the feed
+    pub url: String,
+    pub title: String,
+    pub author: String,
+    /// URL of the cover
+    pub image: Option<String>,
+    /// Path of the downloaded cover
+    pub cover: Option<String>,
+    /// Newest first
+    pub episodes: Vec<Episode>,
+    /// When the feed was last fetched, in seconds since the Unix epoch
+    pub refreshed: u64,
+}
+
+#[derive(Clone, Debug, Default, Decode, Encode)]
+pub struct Episode {
+    /// Identifies the episode across the refreshes of the feed, the URL of the enclosure if the
+    /// feed doesn't have one
+    pub guid: String,
+    pub title: String,
+    /// When the episode was published, in seconds since the Unix epoch
+    pub published: Option<u64>,
+    /// URL of the enclosure, also identifies the bookmark of the episode
+    pub url: String,
+    pub mime_type: String,
+    /// Length stated by the feed, in seconds
+    pub length: Option<f64>,
+    /// URL of the image of the episode, if different from the podcast's
+    pub image: Option<String>,
+    pub chapters: Vec<EpisodeChapter>,
+    /// Path of the downloaded enclosure
+    pub downloaded: Option<String>,
+}
+
+/// A chapter listed by a feed with the Podlove Simple Chapters namespace
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct EpisodeChapter {
+    pub title: String,
+    /// Where the chapter starts, in seconds
+    pub start: f64,
+}
+
+impl From<&EpisodeChapter> for Chapter {
+    fn from(value: &EpisodeChapter) -> Self {
+        Self {
+            title: value.title.clone(),
+            start: value.start,
+        }
+    }
+}
+
+/// What's read from a feed
+#[derive(Debug, Default)]
+pub struct Feed {
+    pub title: String,
+    pub author: String,
+    pub image: Option<String>,
+    pub episodes: Vec<Episode>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Ns {
+    None,
+    Itunes,
+    Podlove,
+    Atom,
+    Other,
+}
+
+impl From<ResolveResult<'_>> for Ns {
+    fn from(value: ResolveResult) -> Self {
+        match value {
+            ResolveResult::Unbound => Ns::None,
+            ResolveResult::Bound(Namespace(ITUNES)) => Ns::Itunes,
+            ResolveResult::Bound(Namespace(PODLOVE)) => Ns::Podlove,
+            ResolveResult::Bound(Namespace(ATOM)) => Ns::Atom,
+            _ => Ns::Other,
+        }
+    }
+}
+
+impl Podcast {
+    /// Replaces the podcast with what's read from its feed, keeping the downloaded episodes
+    pub fn update(&mut self, feed: Feed) {
+        self.title = feed.title;
+        self.author = feed.author;
+        if self.image != feed.image {
+            self.image = feed.image;
+            self.cover = None;
+        }
+
+        let mut old: HashMap<String, Episode> = mem::take(&mut self.episodes)
+            .into_iter()
+            .map(|episode| (episode.guid.clone(), episode))
+            .collect();
+        self.episodes = feed.episodes;
+        for episode in &mut self.episodes {
+            if let Some(old) = old.remove(&episode.guid) {
+                episode.downloaded = old.downloaded;
+            }
+        }
+        // the episodes removed from the feed are kept while they're downloaded
+        self.episodes.extend(
+            old.into_values()
+                .filter(|episode| episode.downloaded.is_some()),
+        );
+        sort_episodes(&mut self.episodes);
+    }
+
+    /// Name of the folder of the downloaded episodes, inside the podcast folder
+    pub fn folder_name(&self) -> String {
+        let name = sanitize(&self.title);
+        if name.is_empty() {
+            String::from("Podcast")
+        } else {
+            name
+        }
+    }
+}
+
+impl Episode {
+    /// Name of the downloaded enclosure, from the date and the title of the episode, and a hash of
+    /// its GUID so that the episodes with the same date and title don't overwrite each other
+    pub fn file_name(&self) -> String {
+        let title = sanitize(&self.title);
+        let name = match self.published {
+            Some(published) if title.is_empty() => format_date(published),
+            Some(published) => format!("{} {title}", format_date(published)),
+            None if title.is_empty() => String::from("Episode"),
+            None => title,
+        };
+        format!("{name} [{:08x}].{}", fnv1a(&self.guid), self.extension())
+    }
+
+    fn extension(&self) -> String {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        if let Some(ext) = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase())
+            .filter(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()))
+        {
+            return ext;
+        }
+
+        let ext = match self.mime_type.as_str() {
+            "audio/mp4" | "audio/x-m4a" | "audio/aac" => "m4a",
+            "audio/ogg" | "audio/vorbis" => "ogg",
+            "audio/opus" => "opus",
+            "audio/flac" | "audio/x-flac" => "flac",
+            "audio/wav" | "audio/x-wav" => "wav",
+            "video/mp4" => "mp4",
+            _ => "mp3",
+        };
+        ext.to_string()
+    }
+}
+
+/// 32-bit FNV-1a hash of `text`, which unlike the hasher of the standard library is the same on
+/// every version
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Newest first, the episodes without a date last
+fn sort_episodes(episodes: &mut [Episode]) {
+    episodes.sort_by(|a, b| b.published.cmp(&a.published));
+}
+
+/// Keeps only the characters that are allowed in file names on every platform
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "<>:\"/\\|?*".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    name.trim()
+        .trim_end_matches('.')
+        .chars()
+        .take(100)
+        .collect()
+}
+
+fn agent() -> Agent {
+    // a whole episode can take long to download, so the bodies are read by a `TimeoutReader`
+    // instead of having a deadline
+    Agent::new_with_config(
+        Agent::config_builder()
+            .timeout_connect(Some(CONNECT_TIMEOUT))
+            .timeout_recv_response(Some(READ_TIMEOUT))
+            .build(),
+    )
+}
+
+/// Asks for `url`, returning a reader of at most `limit` bytes of its body that fails when the
+/// server stops sending it
+fn get(url: &str, limit: u64) -> io::Result<TimeoutReader> {
+    let body = agent()
+        .get(url)
+        .call()
+        .map_err(io::Error::other)?
+        .into_body();
+    Ok(TimeoutReader::new(
+        body.into_with_config().limit(limit).reader(),
+    ))
+}
+
+/// Downloads and parses the feed at `url`
+pub fn fetch_feed(url: &str) -> io::Result<Feed> {
+    let mut xml = vec![];
+    get(url, FEED_LIMIT)?.read_to_end(&mut xml)?;
+    parse_feed(&String::from_utf8_lossy(&xml))
+}
+
+/// Downloads `url` to `destination` through a temporary file, so that an interrupted download is
+/// never taken for a whole one
+pub fn download(url: &str, destination: &Path) -> io::Result<()> {
+    download_limited(url, destination, EPISODE_LIMIT)
+}
+
+fn download_limited(url: &str, destination: &Path, limit: u64) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = destination.with_extension("part");
+    match download_to(url, &partial, limit) {
+        Ok(()) => fs::rename(partial, destination),
+        Err(err) => {
+            let _ = fs::remove_file(partial);
+            Err(err)
+        }
+    }
+}
+
+fn download_to(url: &str, path: &Path, limit: u64) -> io::Result<()> {
+    // a bigger body fails the download instead of filling the disk
+    let mut reader = get(url, limit)?;
+    let mut file = BufWriter::new(File::create(path)?);
+    io::copy(&mut reader, &mut file)?;
+    file.flush()
+}
+
+/// Downloads the cover of a podcast from `url` into `folder`, returning its path
+pub fn download_cover(url: &str, folder: &Path) -> io::Result<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let ext = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => "png",
+        Some(ext) if ext.eq_ignore_ascii_case("webp") => "webp",
+        _ => "jpg",
+    };
+    let destination = folder.join(format!("cover.{ext}"));
+    download(url, &destination)?;
+    Ok(destination)
+}
+
+/// Parses an RSS 2.0 feed, with the iTunes and the Podlove Simple Chapters namespaces, or an Atom
+/// feed, keeping only the entries with an enclosure
+pub fn parse_feed(xml: &str) -> io::Result<Feed> {
+    let mut reader = NsReader::from_str(xml);
+    reader.config_mut().trim_text(true);
+
+    let mut feed = Feed::default();
+    let mut found = false;
+    // the elements from the root to the current one
+    let mut path: Vec<(Ns, String)> = vec![];
+    let mut text = String::new();
+    let mut episode: Option<Episode> = None;
+    loop {
+        match reader.read_resolved_event().map_err(invalid_data)? {
+            (ns, Event::Start(element)) => {
+                let ns = Ns::from(ns);
+                let name = local_name(&element);
+                found |= matches!((ns, name.as_str()), (Ns::None, "rss") | (Ns::Atom, "feed"));
+                if matches!(
+                    (ns, name.as_str()),
+                    (Ns::None, "item") | (Ns::Atom, "entry")
+                ) {
+                    episode = Some(Episode::default());
+                }
+                read_attributes(&mut feed, episode.as_mut(), ns, &name, &element)?;
+                path.push((ns, name));
+                text.clear();
+            }
+            (ns, Event::Empty(element)) => {
+                let name = local_name(&element);
+                read_attributes(&mut feed, episode.as_mut(), Ns::from(ns), &name, &element)?;
+            }
+            (_, Event::Text(t)) => text.push_str(&t.unescape().map_err(invalid_data)?),
+            (_, Event::CData(t)) => text.push_str(&String::from_utf8_lossy(&t)),
+            (_, Event::End(_)) => {
+                let Some((ns, name)) = path.pop() else {
+                    continue;
+                };
+                let parent = path.last().map_or("", |(_, name)| name.as_str());
+                let text = mem::take(&mut text);
+                let text = text.trim();
+                match (ns, name.as_str()) {
+                    (Ns::None, "item") | (Ns::Atom, "entry") => {
+                        if let Some(mut episode) = episode.take().filter(|e| !e.url.is_empty()) {
+                            if episode.guid.is_empty() {
+                                episode.guid = episode.url.clone();
+                            }
+                            episode.chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+                            feed.episodes.push(episode);
+                        }
+                    }
+                    _ => read_text(&mut feed, episode.as_mut(), ns, &name, parent, text),
+                }
+            }
+            (_, Event::Eof) => break,
+            _ => {}
+        }
+    }
+
+    if !found {
+        return Err(invalid_data("not an RSS or Atom feed"));
+    }
+    sort_episodes(&mut feed.episodes);
+    Ok(feed)
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).to_string()
+}
+
+fn attribute(element: &BytesStart, name: &str) -> io::Result<Option<String>> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(invalid_data)?;
+        if attribute.key.local_name().as_ref() == name.as_bytes() {
+            let value = attribute.unescape_value().map_err(invalid_data)?;
+            return Ok(Some(value.trim().to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads what's stored in the attributes of `element`
+fn read_attributes(
+    feed: &mut Feed,
+    episode: Option<&mut Episode>,
+    ns: Ns,
+    name: &str,
+    element: &BytesStart,
+) -> io::Result<()> {
+    match (ns, name, episode) {
+        (Ns::None, "enclosure", Some(episode)) => {
+            if let Some(url) = attribute(element, "url")? {
+                episode.url = url;
+                episode.mime_type = attribute(element, "type")?.unwrap_or_default();
+            }
+        }
+        (Ns::Atom, "link", Some(episode)) => {
+            if attribute(element, "rel")?.as_deref() == Some("enclosure") {
+                if let Some(url) = attribute(element, "href")? {
+                    episode.url = url;
+                    episode.mime_type = attribute(element, "type")?.unwrap_or_default();
+                }
+            }
+        }
+        (Ns::Itunes, "image", episode) => {
+            let href = attribute(element, "href")?.filter(|href| !href.is_empty());
+            match episode {
+                Some(episode) => episode.image = href,
+                // preferred to the image of RSS, that is often smaller
+                None if href.is_some() => feed.image = href,
+                None => {}
+            }
+        }
+        (Ns::Podlove, "chapter", Some(episode)) => {
+            if let Some(start) = attribute(element, "start")?.and_then(|start| parse_time(&start)) {
+                episode.chapters.push(EpisodeChapter {
+                    title: attribute(element, "title")?.unwrap_or_default(),
+                    start,
+                });
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reads what's stored in the text of the element `name`, child of `parent`
+fn read_text(
+    feed: &mut Feed,
+    episode: Option<&mut Episode>,
+    ns: Ns,
+    name: &str,
+    parent: &str,
+    text: &str,
+) {
+    if text.is_empty() {
+        return;
+    }
+    match (ns, name, episode) {
+        (Ns::None | Ns::Atom, "title", Some(episode)) if matches!(parent, "item" | "entry") => {
+            episode.title = text.to_string();
+        }
+        (Ns::None, "guid", Some(episode)) | (Ns::Atom, "id", Some(episode)) => {
+            episode.guid = text.to_string();
+        }
+        (Ns::None, "pubDate", Some(episode)) => episode.published = parse_rfc2822(text),
+        (Ns::Atom, "published", Some(episode)) => episode.published = parse_rfc3339(text),
+        (Ns::Atom, "updated", Some(episode)) if episode.published.is_none() => {
+            episode.published = parse_rfc3339(text);
+        }
+        (Ns::Itunes, "duration", Some(episode)) => episode.length = parse_time(text),
+        (Ns::None | Ns::Atom, "title", None) if matches!(parent, "channel" | "feed") => {
+            feed.title = text.to_string();
+        }
+        (Ns::Itunes, "author", None) => feed.author = text.to_string(),
+        (Ns::Atom, "name", None) if parent == "author" && feed.author.is_empty() => {
+            feed.author = text.to_string();
+        }
+        (Ns::None, "url", None) if parent == "image" && feed.image.is_none() => {
+            feed.image = Some(text.to_string());
+        }
+        (Ns::Atom, "logo" | "icon", None) if feed.image.is_none() => {
+            feed.image = Some(text.to_string());
+        }
+        _ => {}
+    }
+}
+
+/// Parses a time like `01:02:03.5`, `62:03` or `3723`, in seconds
+pub fn parse_time(time: &str) -> Option<f64> {
+    time.trim().split(':').try_fold(0.0, |seconds, part| {
+        let part: f64 = part.trim().parse().ok()?;
+        (part >= 0.0).then_some(seconds * 60.0 + part)
+    })
+}
+
+/// Parses a date like `Tue, 10 Jun 2003 04:00:00 GMT`, in seconds since the Unix epoch
+pub fn parse_rfc2822(date: &str) -> Option<u64> {
+    let date = date.split_once(',').map_or(date, |(_, date)| date);
+    let mut parts = date.split_whitespace();
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let year = match parts.next()?.parse().ok()? {
+        year @ 0..50 => year + 2000,
+        year @ 50..100 => year + 1900,
+        year => year,
+    };
+    let seconds = parse_clock(parts.next()?)?;
+    let offset = parts.next().map_or(0, zone_offset);
+    timestamp(year, month, day, seconds - offset)
+}
+
+/// Parses a date like `2003-12-13T18:30:02Z`, in seconds since the Unix epoch
+pub fn parse_rfc3339(date: &str) -> Option<u64> {
+    let (day, time) = date
+        .trim()
+        .split_once(['T', 't', ' '])
+        .unwrap_or((date, "00:00"));
+    let mut parts = day.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    // the offset follows the seconds
+    let (clock, zone) = time.split_at(time.find(['Z', 'z', '+', '-']).unwrap_or(time.len()));
+    timestamp(year, month, day, parse_clock(clock)? - zone_offset(zone))
+}
+
+/// Parses `hh:mm[:ss[.fraction]]`, in seconds
+fn parse_clock(clock: &str) -> Option<i64> {
+    let mut parts = clock.split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds = match parts.next() {
+        Some(seconds) => seconds.parse::<f64>().ok()?.floor() as i64,
+        None => 0,
+    };
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Offset of a time zone like `+0200`, `-05:00`, `Z` or `EST` from UTC, in seconds
+fn zone_offset(zone: &str) -> i64 {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        zone => {
+            let sign = match zone.chars().next() {
+                Some('+') => 1,
+                Some('-') => -1,
+                _ => return 0,
+            };
+            let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+            let hours: i64 = digits.get(..2).and_then(|h| h.parse().ok()).unwrap_or(0);
+            let minutes: i64 = digits.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+            return sign * (hours * 3600 + minutes * 60);
+        }
+    };
+    hours * 3600
+}
+
+/// Seconds since the Unix epoch of `seconds` after the start of the day, `None` before the epoch
+fn timestamp(year: i64, month: u32, day: u32, seconds: i64) -> Option<u64> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    u64::try_from(days_from_civil(year, month, day) * 86400 + seconds).ok()
+}
+
+/// Days since the Unix epoch of a date of the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD`
+pub fn format_date(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+    use tempfile::TempDir;
+
+    const RSS_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"
+     xmlns:psc="http://podlove.org/simple-chapters">
+  <channel>
+    <title>Test Podcast</title>
+    <itunes:author>Test Author</itunes:author>
+    <image>
+      <url>http://example.com/small.jpg</url>
+      <title>Not the title</title>
+    </image>
+    <itunes:image href="http://example.com/cover.jpg"/>
+    <item>
+      <title>Older &amp; wiser</title>
+      <guid isPermaLink="false">guid-1</guid>
+      <pubDate>Tue, 10 Jun 2003 04:00:00 GMT</pubDate>
+      <enclosure url="http://example.com/1.mp3?token=1" length="1000" type="audio/mpeg"/>
+      <itunes:duration>01:02:03</itunes:duration>
+      <psc:chapters version="1.2">
+        <psc:chapter start="00:10:00.5" title="Second"/>
+        <psc:chapter start="0" title="First"/>
+      </psc:chapters>
+    </item>
+    <item>
+      <title><![CDATA[Newer]]></title>
+      <pubDate>Thu, 02 Oct 03 08:00:00 EST</pubDate>
+      <enclosure url="http://example.com/2" type="audio/x-m4a"/>
+      <itunes:image href="http://example.com/2.jpg"/>
+    </item>
+    <item>
+      <title>Without enclosure</title>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM_FEED: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom Cast</title>
+  <author><name>Atom Author</name></author>
+  <logo>http://example.com/logo.png</logo>
+  <entry>
+    <title>Updated</title>
+    <id>urn:uuid:1</id>
+    <updated>2003-12-13T18:30:02+01:00</updated>
+    <link rel="alternate" href="http://example.com/page"/>
+    <link rel="enclosure" type="audio/ogg" href="http://example.com/1.ogg"/>
+  </entry>
+  <entry>
+    <title>Published</title>
+    <id>urn:uuid:2</id>
+    <published>2003-12-13T18:30:02Z</published>
+    <updated>2024-02-29T00:00:00Z</updated>
+    <link rel="enclosure" type="audio/opus" href="http://example.com/2.opus"/>
+  </entry>
+</feed>"#;
+
+    #[test]
+    fn rss_feed_is_parsed() {
+        let feed = parse_feed(RSS_FEED).unwrap();
+        assert_eq!(feed.title, "Test Podcast");
+        assert_eq!(feed.author, "Test Author");
+        assert_eq!(feed.image.as_deref(), Some("http://example.com/cover.jpg"));
+        assert_eq!(feed.episodes.len(), 2);
+
+        let newer = &feed.episodes[0];
+        assert_eq!(newer.title, "Newer");
+        assert_eq!(newer.published, Some(1065099600));
+        // without a GUID, the episode is identified by its enclosure
+        assert_eq!(newer.guid, "http://example.com/2");
+        assert_eq!(newer.mime_type, "audio/x-m4a");
+        assert_eq!(newer.image.as_deref(), Some("http://example.com/2.jpg"));
+        assert_eq!(newer.extension(), "m4a");
+
+        let older = &feed.episodes[1];
+        assert_eq!(older.title, "Older & wiser");
+        assert_eq!(older.guid, "guid-1");
+        assert_eq!(older.published, Some(1055217600));
+        assert_eq!(older.url, "http://example.com/1.mp3?token=1");
+        assert_eq!(older.length, Some(3723.0));
+        assert_eq!(older.extension(), "mp3");
+        let chapters: Vec<_> = older
+            .chapters
+            .iter()
+            .map(|chapter| (chapter.title.as_str(), chapter.start))
+            .collect();
+        assert_eq!(chapters, [("First", 0.0), ("Second", 600.5)]);
+    }
+
+    #[test]
+    fn atom_feed_is_parsed() {
+        let feed = parse_feed(ATOM_FEED).unwrap();
+        assert_eq!(feed.title, "Atom Cast");
+        assert_eq!(feed.author, "Atom Author");
+        assert_eq!(feed.image.as_deref(), Some("http://example.com/logo.png"));
+
+        let episodes: Vec<_> = feed
+            .episodes
+            .iter()
+            .map(|episode| {
+                (
+                    episode.title.as_str(),
+                    episode.guid.as_str(),
+                    episode.url.as_str(),
+                    episode.published,
+                )
+            })
+            .collect();
+        assert_eq!(
+            episodes,
+            [
+                // the publication date is preferred to the update
+                (
+                    "Published",
+                    "urn:uuid:2",
+                    "http://example.com/2.opus",
+                    Some(1071340202)
+                ),
+                (
+                    "Updated",
+                    "urn:uuid:1",
+                    "http://example.com/1.ogg",
+                    Some(1071336602)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn other_documents_are_not_feeds() {
+        assert!(parse_feed("<html><body>Not a feed</body></html>").is_err());
+    }
+
+    #[test]
+    fn rfc2822_dates_are_parsed() {
+        assert_eq!(
+            parse_rfc2822("Tue, 10 Jun 2003 04:00:00 GMT"),
+            Some(1055217600)
+        );
+        assert_eq!(parse_rfc2822("10 Jun 2003 04:00 +0000"), Some(1055217600));
+        assert_eq!(
+            parse_rfc2822("Tue, 10 June 2003 06:00:00 +0200"),
+            Some(1055217600)
+        );
+        assert_eq!(
+            parse_rfc2822("Mon, 09 Jun 2003 23:00:00 EST"),
+            Some(1055217600)
+        );
+        assert_eq!(parse_rfc2822("Tue, 10 Foo 2003 04:00:00 GMT"), None);
+        assert_eq!(parse_rfc2822("not a date"), None);
+    }
+
+    #[test]
+    fn rfc3339_dates_are_parsed() {
+        assert_eq!(parse_rfc3339("2003-12-13T18:30:02Z"), Some(1071340202));
+        assert_eq!(parse_rfc3339("2003-12-13T18:30:02.25Z"), Some(1071340202));
+        assert_eq!(parse_rfc3339("2003-12-13T19:30:02+01:00"), Some(1071340202));
+        assert_eq!(parse_rfc3339("2003-12-13T13:30:02-05:00"), Some(1071340202));
+        assert_eq!(parse_rfc3339("2024-02-29"), Some(1709164800));
+        assert_eq!(parse_rfc3339("2003-13-13T18:30:02Z"), None);
+        assert_eq!(format_date(1709164800), "2024-02-29");
+    }
+
+    #[test]
+    fn file_names_of_episodes_are_unique() {
+        let episode = Episode {
+            guid: String::from("guid-1"),
+            title: String::from("Title: part 1/2"),
+            published: Some(1709164800),
+            url: String::from("http://example.com/episode.mp3"),
+            ..Default::default()
+        };
+        assert_eq!(
+            episode.file_name(),
+            "2024-02-29 Title_ part 1_2 [199e9166].mp3"
+        );
+
+        let same = Episode {
+            guid: String::from("guid-2"),
+            ..episode.clone()
+        };
+        assert_ne!(episode.file_name(), same.file_name());
+    }
+
+    /// Serves the bodies of `files` by their path, ignoring the queries, to the next `requests`
+    /// requests, returning the URL of the server
+    fn serve(files: Vec<(&'static str, Vec<u8>)>, requests: usize) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..requests {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let path = path.split('?').next().unwrap_or_default();
+                let response = match files.iter().find(|(file, _)| *file == path) {
+                    Some((_, body)) => [
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes(),
+                        body.clone(),
+                    ]
+                    .concat(),
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                };
+                (&stream).write_all(&response).unwrap();
+            }
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn episodes_are_downloaded_to_their_file_name() {
+        let audio = b"not really an mp3".to_vec();
+        let (url, server) = serve(
+            vec![
+                ("/feed.xml", RSS_FEED.as_bytes().to_vec()),
+                ("/1.mp3", audio.clone()),
+            ],
+            2,
+        );
+        let feed = fetch_feed(&format!("{url}/feed.xml")).unwrap();
+        assert_eq!(feed.title, "Test Podcast");
+
+        let mut episode = feed.episodes[1].clone();
+        episode.url = episode.url.replace("http://example.com", &url);
+        let folder = TempDir::new().unwrap();
+        let destination = folder.path().join(episode.file_name());
+        download(&episode.url, &destination).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(fs::read(&destination).unwrap(), audio);
+        let name = destination.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with(&format!("[{:08x}].mp3", fnv1a("guid-1"))));
+        // only the whole episode is left
+        assert_eq!(fs::read_dir(folder.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn downloads_over_the_limit_fail() {
+        let (url, server) = serve(vec![("/big.mp3", vec![0; 1024])], 2);
+        let folder = TempDir::new().unwrap();
+        let destination = folder.path().join("big.mp3");
+
+        assert!(download_limited(&format!("{url}/big.mp3"), &destination, 1000).is_err());
+        assert_eq!(fs::read_dir(folder.path()).unwrap().count(), 0);
+
+        download_limited(&format!("{url}/big.mp3"), &destination, 1024).unwrap();
+        assert_eq!(fs::read(&destination).unwrap().len(), 1024);
+        server.join().unwrap();
+    }
+}
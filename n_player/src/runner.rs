@@ -1,9 +1,19 @@
+use crate::settings::{Bookmark, Session};
 use flume::Receiver;
+use n_audio::chapter::Chapter;
+use n_audio::convolution::{Convolver, ImpulseResponse};
+use n_audio::crossfeed::Crossfeed;
+use n_audio::dsp::DspSettings;
+use n_audio::music_track::MusicTrack;
 use n_audio::queue::{LoopStatus, QueuePlayer};
-use n_audio::TrackTime;
+use n_audio::silence::SilenceBounds;
+use n_audio::stream::is_url;
+use n_audio::tap::Tap;
+use n_audio::{NError, TrackTime};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 pub async fn run(runner: Arc<RwLock<Runner>>, rx: Receiver<RunnerMessage>) {
@@ -32,8 +42,58 @@ pub enum RunnerMessage {
     Play,
     SetVolume(f64),
     PlayTrack(usize),
+    /// Plays the internet radio or HTTP stream at the given URL instead of the queue
+    PlayStream(String),
+    /// Plays the episode of a podcast instead of the queue, resuming it from its bookmark
+    PlayEpisode(EpisodeTrack),
     Seek(RunnerSeek),
     LoopStatus(LoopStatus),
+    StopAfter(Option<u32>),
+    /// Plays the track with the given index right after the current one
+    PlayAfterCurrent(usize),
+    /// Adds the track with the given index to the end of the up next queue
+    Enqueue(usize),
+    /// Moves an entry of the up next queue from a position to another
+    MoveQueued(usize, usize),
+    /// Removes the entries at the given positions from the up next queue
+    RemoveQueued(Vec<usize>),
+    ClearQueued,
+    UndoQueued,
+    Shuffle(bool),
+    /// Enables or disables the audiobook mode
+    Audiobook(bool),
+    SetSpeed(f32),
+    NextChapter,
+    PreviousChapter,
+    /// Marks the current track as finished (or not) in audiobook mode
+    MarkFinished(bool),
+    /// Sets point A, then point B at the current position, and clears the A-B loop the third time
+    LoopPoint,
+    LoopRegion(Option<(f64, f64)>),
+    LoopCrossfade(bool),
+    Dsp(DspSettings),
+    Crossfeed(bool),
+    /// Enables or disables the compression for listening at low volume
+    NightMode(bool),
+    /// Enables or disables the trimming of the silence at the start and at the end of the tracks
+    TrimSilence(bool),
+    /// Sets the level (in dB) under which the silence is trimmed
+    SilenceThreshold(f32),
+    /// Loads the impulse response at the given path, `None` disables the convolution
+    ImpulseResponse(Option<String>),
+}
+
+/// Episode of a podcast played outside of the queue
+#[derive(Clone, Debug)]
+pub struct EpisodeTrack {
+    /// Key of the bookmark of the episode, the URL of its enclosure
+    pub key: String,
+    /// Downloaded file or URL of the enclosure
+    pub path: String,
+    pub title: String,
+    pub podcast: String,
+    /// Chapters listed by the feed, used if the file doesn't have any
+    pub chapters: Vec<Chapter>,
 }
 
 #[derive(Debug)]
@@ -42,31 +102,198 @@ pub enum RunnerSeek {
     Relative(f64),
 }
 
+/// How often the bookmarks are saved while playing in audiobook mode
+const BOOKMARKS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Length of the crossfade at the seam of the A-B loop, in seconds
+const LOOP_CROSSFADE: f64 = 0.05;
+
 pub struct Runner {
     player: QueuePlayer,
     current_time: TrackTime,
+    audiobook: bool,
+    bookmarks: HashMap<String, Bookmark>,
+    bookmarks_changed: bool,
+    bookmarks_urgent: bool,
+    bookmarks_saved: Instant,
+    /// Point A of the A-B loop while waiting for point B
+    loop_start: Option<f64>,
+    crossfeed: bool,
+    trim_silence: bool,
+    /// Level (in dB) under which the start and the end of the tracks are trimmed
+    silence_threshold: f32,
+    impulse_response: Option<ImpulseResponse>,
+    /// Last episode played, only meaningful while it's played outside of the queue
+    episode: Option<EpisodeTrack>,
 }
 
 impl Runner {
-    pub fn new(player: QueuePlayer) -> Self {
+    pub fn new(mut player: QueuePlayer) -> Self {
+        player.set_accurate_seek(true);
         Self {
             player,
             current_time: TrackTime::default(),
+            audiobook: false,
+            bookmarks: HashMap::new(),
+            bookmarks_changed: false,
+            bookmarks_urgent: false,
+            bookmarks_saved: Instant::now(),
+            loop_start: None,
+            crossfeed: false,
+            trim_silence: false,
+            silence_threshold: -60.0,
+            impulse_response: None,
+            episode: None,
         }
     }
 
     pub async fn update(&mut self) {
         if let Some(time) = self.player.get_time() {
             self.current_time = time;
+            if self.bookmarked() {
+                let position = time.position;
+                if let Some(bookmark) = self.current_bookmark().await {
+                    if bookmark.position != position {
+                        bookmark.position = position;
+                        self.bookmarks_changed = true;
+                    }
+                }
+            }
         }
 
         if self.player.has_ended() {
+            if self.player.outside().is_some() {
+                if self.episode().is_some() {
+                    self.mark_finished(true).await;
+                }
+                // there's nothing to play next outside of the queue, that stays where it was
+                self.player.stop_outside().await;
+                return;
+            }
+            if self.audiobook {
+                self.mark_finished(true).await;
+            }
             if let Err(err) = self.player.play_next(false).await {
                 eprintln!("error happened: {err}");
             }
+            self.track_changed().await;
+        }
+    }
+
+    /// Whether the position of what's playing is saved, like for the tracks in audiobook mode and
+    /// the episodes of the podcasts
+    fn bookmarked(&self) -> bool {
+        match self.player.outside() {
+            Some(_) => self.episode().is_some(),
+            None => self.audiobook,
+        }
+    }
+
+    async fn current_key(&self) -> Option<String> {
+        if self.player.outside().is_some() {
+            return self.episode().map(|episode| episode.key.clone());
+        }
+        self.player
+            .get_path_for_file(self.player.index())
+            .await
+            .map(|path| path.to_string_lossy().to_string())
+    }
+
+    async fn current_bookmark(&mut self) -> Option<&mut Bookmark> {
+        let key = self.current_key().await?;
+        Some(self.bookmarks.entry(key).or_default())
+    }
+
+    async fn mark_finished(&mut self, finished: bool) {
+        if let Some(key) = self.current_key().await {
+            self.set_finished(key, finished);
+        }
+    }
+
+    /// Marks the track or the episode with the bookmark `key` as finished (or not)
+    pub fn set_finished(&mut self, key: String, finished: bool) {
+        let bookmark = self.bookmarks.entry(key).or_default();
+        bookmark.finished = finished;
+        if finished {
+            bookmark.position = 0.0;
+        }
+        self.bookmarks_changed = true;
+        self.bookmarks_urgent = true;
+    }
+
+    /// Must be called every time a new track starts, in audiobook mode (and for the episodes) it
+    /// restores the track's speed and resume position
+    async fn track_changed(&mut self) {
+        self.loop_start = None;
+        let bookmark = if self.bookmarked() {
+            self.bookmarks_urgent = true;
+            match self.current_key().await {
+                Some(key) => self.bookmarks.get(&key).cloned(),
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let speed = bookmark.as_ref().map_or(1.0, |bookmark| bookmark.speed);
+        if self.player.get_playback_speed() != speed {
+            if let Err(e) = self.player.set_playback_speed(speed).await {
+                eprintln!("error happened while setting the playback speed: {e}");
+            }
+        }
+
+        if let Some(bookmark) = bookmark.filter(|b| !b.finished && b.position > 0.0) {
+            let position = bookmark.position;
+            if let Err(e) = self
+                .player
+                .seek_to(position.trunc() as u64, position.fract())
+                .await
+            {
+                eprintln!("error happened while resuming the track: {e}");
+            }
+        }
+    }
+
+    /// Returns the bookmarks if they need to be saved
+    pub fn bookmarks_to_save(&mut self) -> Option<HashMap<String, Bookmark>> {
+        if self.bookmarks_changed
+            && (self.bookmarks_urgent || self.bookmarks_saved.elapsed() >= BOOKMARKS_SAVE_INTERVAL)
+        {
+            self.bookmarks_changed = false;
+            self.bookmarks_urgent = false;
+            self.bookmarks_saved = Instant::now();
+            Some(self.bookmarks.clone())
+        } else {
+            None
         }
     }
 
+    pub fn set_bookmarks(&mut self, bookmarks: HashMap<String, Bookmark>) {
+        self.bookmarks = bookmarks;
+    }
+
+    pub fn bookmarks(&self) -> &HashMap<String, Bookmark> {
+        &self.bookmarks
+    }
+
+    /// Adds the silence already detected in the tracks, as (start, end) by their path
+    pub fn set_silence_bounds(&mut self, bounds: HashMap<String, (f64, f64)>) {
+        self.player.silence_cache().extend(
+            bounds
+                .into_iter()
+                .map(|(path, (start, end))| (path, SilenceBounds { start, end })),
+        );
+    }
+
+    /// Returns the silence detected in the tracks, as (start, end) by their path
+    pub fn silence_bounds(&self) -> HashMap<String, (f64, f64)> {
+        self.player
+            .silence_cache()
+            .entries()
+            .into_iter()
+            .map(|(path, bounds)| (path, (bounds.start, bounds.end)))
+            .collect()
+    }
+
     async fn parse_command(&mut self, message: RunnerMessage) {
         println!("{message:?}");
         match message {
@@ -75,15 +302,19 @@ impl Runner {
                 if let Err(err) = self.player.play_next(true).await {
                     eprintln!("error happened: {err}");
                 }
+                self.track_changed().await;
             }
             RunnerMessage::PlayPrevious => {
                 if self.current_time.position > 3.0 {
-                    self.player.seek_to(0, 0.0).await.unwrap();
+                    if let Err(e) = self.player.seek_to(0, 0.0).await {
+                        eprintln!("error happened while asking to seek: {e}");
+                    }
                 } else {
                     self.player.end_current().await.unwrap();
                     if let Err(err) = self.player.play_previous().await {
                         eprintln!("error happened: {err}");
                     }
+                    self.track_changed().await;
                 }
             }
             RunnerMessage::TogglePause => {
@@ -96,6 +327,7 @@ impl Runner {
                     if let Err(err) = self.player.play_next(true).await {
                         eprintln!("error happened: {err}");
                     }
+                    self.track_changed().await;
                 }
             }
             RunnerMessage::Pause => {
@@ -107,6 +339,7 @@ impl Runner {
                     if let Err(err) = self.player.play_next(true).await {
                         eprintln!("error happened: {err}");
                     }
+                    self.track_changed().await;
                 }
             }
             RunnerMessage::SetVolume(volume) => {
@@ -117,19 +350,395 @@ impl Runner {
                 if let Err(err) = self.player.play_index(index).await {
                     eprintln!("error happened: {err}");
                 }
+                self.track_changed().await;
+            }
+            RunnerMessage::PlayStream(url) => {
+                self.player.end_current().await.unwrap();
+                self.loop_start = None;
+                self.current_time = TrackTime::default();
+                if let Err(err) = self.player.play_stream(&url).await {
+                    eprintln!("can't play the stream: {err}");
+                }
+            }
+            RunnerMessage::PlayEpisode(episode) => {
+                self.player.end_current().await.unwrap();
+                let result = if is_url(&episode.path) {
+                    self.player.play_stream(&episode.path).await
+                } else {
+                    self.player.play_file(&episode.path).await
+                };
+                if let Err(err) = result {
+                    eprintln!("can't play the episode: {err}");
+                    return;
+                }
+                if self.player.chapters().is_empty() {
+                    self.player.set_chapters(episode.chapters.clone());
+                }
+                self.episode = Some(episode);
+                self.track_changed().await;
             }
             RunnerMessage::Seek(seek) => {
                 let seek = match seek {
                     RunnerSeek::Absolute(value) => value,
                     RunnerSeek::Relative(value) => self.current_time.position + value,
                 };
-                if let Err(e) = self.player.seek_to(seek.trunc() as u64, seek.fract()).await {
-                    eprintln!("error happened while asking to seek: {e}");
+                match self.player.seek_to(seek.trunc() as u64, seek.fract()).await {
+                    Ok(position) => self.current_time.position = position,
+                    // the track has ended, `Runner::update` will play the next one
+                    Err(NError::SeekOutOfRange) => {}
+                    Err(e) => eprintln!("error happened while asking to seek: {e}"),
                 }
             }
             RunnerMessage::LoopStatus(loop_status) => {
                 self.player.set_loop_status(loop_status);
             }
+            RunnerMessage::StopAfter(tracks) => {
+                self.player.set_stop_after(tracks);
+            }
+            RunnerMessage::PlayAfterCurrent(index) => {
+                self.player.play_after_current(index);
+            }
+            RunnerMessage::Enqueue(index) => {
+                self.player.enqueue(index);
+            }
+            RunnerMessage::MoveQueued(from, to) => {
+                self.player.move_queued(from, to);
+            }
+            RunnerMessage::RemoveQueued(positions) => {
+                self.player.remove_queued(&positions);
+            }
+            RunnerMessage::ClearQueued => {
+                self.player.clear_queued();
+            }
+            RunnerMessage::UndoQueued => {
+                self.player.undo_queued();
+            }
+            RunnerMessage::Shuffle(shuffle) => {
+                self.player.set_shuffle(shuffle);
+            }
+            RunnerMessage::Audiobook(audiobook) => {
+                self.set_audiobook(audiobook).await;
+            }
+            RunnerMessage::SetSpeed(speed) => {
+                if let Err(e) = self.player.set_playback_speed(speed).await {
+                    eprintln!("error happened while setting the playback speed: {e}");
+                }
+                if self.audiobook {
+                    if let Some(bookmark) = self.current_bookmark().await {
+                        bookmark.speed = speed;
+                        self.bookmarks_changed = true;
+                        self.bookmarks_urgent = true;
+                    }
+                }
+            }
+            RunnerMessage::NextChapter => {
+                if let Err(e) = self.player.next_chapter().await {
+                    eprintln!("error happened while asking to seek: {e}");
+                }
+            }
+            RunnerMessage::PreviousChapter => {
+                if let Err(e) = self.player.previous_chapter().await {
+                    eprintln!("error happened while asking to seek: {e}");
+                }
+            }
+            RunnerMessage::MarkFinished(finished) => {
+                if self.bookmarked() {
+                    self.mark_finished(finished).await;
+                }
+            }
+            RunnerMessage::LoopPoint => {
+                let position = self.current_time.position;
+                let region = match self.loop_start.take() {
+                    _ if self.player.loop_region().is_some() => None,
+                    Some(start) => Some((start.min(position), start.max(position))),
+                    None => {
+                        self.loop_start = Some(position);
+                        None
+                    }
+                };
+                if let Err(e) = self.player.set_loop_region(region).await {
+                    eprintln!("error happened while setting the loop region: {e}");
+                }
+            }
+            RunnerMessage::LoopRegion(region) => {
+                self.loop_start = None;
+                if let Err(e) = self.player.set_loop_region(region).await {
+                    eprintln!("error happened while setting the loop region: {e}");
+                }
+            }
+            RunnerMessage::LoopCrossfade(crossfade) => {
+                self.set_loop_crossfade(crossfade).await;
+            }
+            RunnerMessage::Dsp(dsp) => {
+                self.set_dsp(dsp).await;
+            }
+            RunnerMessage::Crossfeed(crossfeed) => {
+                self.set_crossfeed(crossfeed);
+            }
+            RunnerMessage::NightMode(night_mode) => {
+                self.set_night_mode(night_mode).await;
+            }
+            RunnerMessage::TrimSilence(trim) => {
+                self.set_trim_silence(trim);
+            }
+            RunnerMessage::SilenceThreshold(threshold) => {
+                self.set_silence_threshold(threshold);
+            }
+            RunnerMessage::ImpulseResponse(path) => {
+                self.set_impulse_response(path).await;
+            }
+        }
+    }
+
+    /// Restores the playback state saved in `session`, the queue order must be already restored
+    /// with `Runner::restore_order`
+    pub async fn restore(&mut self, session: Session, auto_resume: bool) {
+        self.player
+            .set_loop_status(LoopStatus::try_from(session.loop_status).unwrap_or_default());
+        self.player.set_stop_after(session.stop_after);
+        self.player.set_up_next(
+            session
+                .up_next
+                .iter()
+                .filter_map(|name| self.player.get_index_from_file_name(name))
+                .collect(),
+        );
+
+        let Some(index) = session
+            .current
+            .and_then(|name| self.player.get_index_from_file_name(&name))
+        else {
+            return;
+        };
+
+        if let Err(err) = self.player.play_index_paused(index).await {
+            eprintln!("can't restore the last track: {err}");
+            return;
+        }
+        if let Some(path) = self.player.get_path_for_file(index).await {
+            if let Ok(track) = MusicTrack::new(path.to_string_lossy().to_string()) {
+                if let Ok(Ok(time)) = tokio::task::spawn_blocking(move || track.get_length()).await
+                {
+                    self.current_time.length = time.length;
+                }
+            }
+        }
+        if session.position > 0.0 {
+            let position = session.position;
+            match self
+                .player
+                .seek_to(position.trunc() as u64, position.fract())
+                .await
+            {
+                Ok(position) => self.current_time.position = position,
+                Err(e) => eprintln!("error happened while restoring the position: {e}"),
+            }
+        }
+        if auto_resume && !session.paused {
+            self.player.unpause().await.unwrap();
+        }
+    }
+
+    /// Returns the current playback state, to be restored with `Runner::restore` on the next start
+    pub async fn session(&self) -> Option<Session> {
+        if self.player.is_empty() {
+            return None;
+        }
+
+        let queue = self.player.queue();
+        Some(Session {
+            path: self.player.path(),
+            order: queue.iter().map(|track| track.to_string()).collect(),
+            current: if self.player.is_playing() && self.player.outside().is_none() {
+                self.player
+                    .current_track_name()
+                    .await
+                    .map(|track| track.to_string())
+            } else {
+                None
+            },
+            up_next: self
+                .player
+                .up_next()
+                .iter()
+                .filter_map(|index| queue.get(*index))
+                .map(|track| track.to_string())
+                .collect(),
+            position: self.current_time.position,
+            paused: self.player.is_paused(),
+            loop_status: i32::from(self.player.loop_status()),
+            stop_after: self.player.stop_after(),
+            shuffle: self.player.shuffle_enabled(),
+        })
+    }
+
+    pub fn restore_order<S: AsRef<str>>(&mut self, order: &[S]) {
+        self.player.restore_order(order)
+    }
+
+    /// Enables or disables the audiobook mode, applying the bookmark of the current track
+    pub async fn set_audiobook(&mut self, audiobook: bool) {
+        if self.audiobook != audiobook {
+            self.audiobook = audiobook;
+            if self.player.is_playing() && self.player.outside().is_none() {
+                self.track_changed().await;
+            }
+        }
+    }
+
+    pub fn audiobook(&self) -> bool {
+        self.audiobook
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.player.get_playback_speed()
+    }
+
+    /// Returns the title of the chapter that is currently playing
+    pub fn chapter(&self) -> Option<String> {
+        self.player
+            .current_chapter()
+            .map(|chapter| self.player.chapters()[chapter].title.clone())
+    }
+
+    /// Returns where the chapters of the current track start, in seconds
+    pub fn chapter_starts(&self) -> Vec<f32> {
+        self.player
+            .chapters()
+            .iter()
+            .map(|chapter| chapter.start as f32)
+            .collect()
+    }
+
+    /// Returns whether the current track was marked as finished
+    pub async fn finished(&self) -> bool {
+        match self.current_key().await {
+            Some(key) => self
+                .bookmarks
+                .get(&key)
+                .is_some_and(|bookmark| bookmark.finished),
+            None => false,
+        }
+    }
+
+    /// Returns the points of the A-B loop, in seconds
+    pub fn loop_points(&self) -> (Option<f64>, Option<f64>) {
+        match self.player.loop_region() {
+            Some((a, b)) => (Some(a), Some(b)),
+            None => (self.loop_start, None),
+        }
+    }
+
+    pub async fn set_loop_crossfade(&mut self, crossfade: bool) {
+        let seconds = if crossfade { LOOP_CROSSFADE } else { 0.0 };
+        if let Err(e) = self.player.set_loop_crossfade(seconds).await {
+            eprintln!("error happened while setting the loop crossfade: {e}");
+        }
+    }
+
+    pub async fn set_dsp(&mut self, dsp: DspSettings) {
+        if let Err(e) = self.player.set_dsp(dsp).await {
+            eprintln!("error happened while setting the equalizer: {e}");
+        }
+    }
+
+    pub fn set_crossfeed(&mut self, crossfeed: bool) {
+        self.crossfeed = crossfeed;
+        self.update_effects();
+    }
+
+    pub async fn set_night_mode(&mut self, night_mode: bool) {
+        if let Err(e) = self.player.set_night_mode(night_mode).await {
+            eprintln!("error happened while setting the night mode: {e}");
+        }
+    }
+
+    /// Trims the silence of the tracks from the next one
+    pub fn set_trim_silence(&mut self, trim: bool) {
+        self.trim_silence = trim;
+        self.player
+            .set_silence_threshold(trim.then_some(self.silence_threshold));
+    }
+
+    /// Trims the silence under `threshold` (in dB) from the next track, detecting it again in the
+    /// tracks where it was detected under another threshold
+    pub fn set_silence_threshold(&mut self, threshold: f32) {
+        if threshold != self.silence_threshold {
+            // the bounds are cleared even while the silence isn't trimmed
+            self.player.silence_cache().clear();
+            self.silence_threshold = threshold;
+        }
+        self.set_trim_silence(self.trim_silence);
+    }
+
+    /// Loads the impulse response at `path` for the convolution, `None` disables it
+    pub async fn set_impulse_response(&mut self, path: Option<String>) {
+        self.impulse_response = match path {
+            Some(path) => {
+                match tokio::task::spawn_blocking(move || ImpulseResponse::from_file(path)).await {
+                    Ok(Ok(ir)) => Some(ir),
+                    Ok(Err(e)) => {
+                        eprintln!("can't load the impulse response: {e}");
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("error happened while loading the impulse response: {e}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        self.update_effects();
+    }
+
+    fn update_effects(&self) {
+        let effects = self.player.effects();
+        effects.clear();
+        if self.crossfeed {
+            effects.push(Box::new(Crossfeed::bauer()));
+        }
+        if let Some(ir) = &self.impulse_response {
+            effects.push(Box::new(Convolver::new(ir.clone())));
+        }
+    }
+
+    /// Returns whether an internet radio or HTTP stream is playing instead of the queue
+    pub fn streaming(&self) -> bool {
+        self.player.stream_info().is_some() && self.episode().is_none()
+    }
+
+    /// Returns the URL of the stream being played, if any
+    pub fn stream_url(&self) -> Option<&str> {
+        self.player.outside().filter(|_| self.streaming())
+    }
+
+    /// Returns the name of the station being streamed, if it sends one
+    pub fn stream_name(&self) -> Option<String> {
+        self.player.stream_info().and_then(|info| info.name())
+    }
+
+    /// Returns the title of what the station is streaming now, if it sends one
+    pub fn stream_title(&self) -> Option<String> {
+        self.player.stream_info().and_then(|info| info.title())
+    }
+
+    /// Returns the episode of a podcast being played, if any
+    pub fn episode(&self) -> Option<&EpisodeTrack> {
+        self.episode
+            .as_ref()
+            .filter(|episode| self.player.outside() == Some(episode.path.as_str()))
+    }
+
+    /// Returns the name and the title of what's played outside of the queue: the station and what
+    /// it's streaming, or the podcast and the episode
+    pub fn outside_names(&self) -> Option<(Option<String>, Option<String>)> {
+        if let Some(episode) = self.episode() {
+            Some((Some(episode.podcast.clone()), Some(episode.title.clone())))
+        } else if self.streaming() {
+            Some((self.stream_name(), self.stream_title()))
+        } else {
+            None
         }
     }
 
@@ -137,6 +746,10 @@ impl Runner {
         !self.player.is_paused() && self.player.is_playing()
     }
 
+    pub fn tap(&self) -> Tap {
+        self.player.tap().clone()
+    }
+
     pub fn volume(&self) -> f64 {
         self.player.get_volume() as f64
     }
@@ -157,6 +770,10 @@ impl Runner {
         self.player.index()
     }
 
+    pub fn up_next(&self) -> &[usize] {
+        self.player.up_next()
+    }
+
     pub fn len(&self) -> usize {
         self.player.len()
     }
@@ -169,6 +786,10 @@ impl Runner {
         self.player.loop_status()
     }
 
+    pub fn stop_after(&self) -> Option<u32> {
+        self.player.stop_after()
+    }
+
     pub async fn get_path_for_file(&self, i: usize) -> Option<PathBuf> {
         self.player.get_path_for_file(i).await
     }
@@ -196,4 +817,16 @@ impl Runner {
     pub fn shuffle(&mut self) {
         self.player.shuffle()
     }
+
+    pub fn reorder(&mut self) {
+        self.player.reorder()
+    }
+
+    pub fn shuffle_enabled(&self) -> bool {
+        self.player.shuffle_enabled()
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.player.set_shuffle(shuffle)
+    }
 }
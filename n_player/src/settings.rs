@@ -1,6 +1,10 @@
 use crate::platform::Platform;
+use crate::podcast::Podcast;
 use crate::{FileTrack, Theme, WindowSize};
-use bitcode::{Decode, Encode};
+use bitcode::{Decode, DecodeOwned, Encode};
+use n_audio::analysis::{Analysis, Key};
+use n_audio::dsp::{DspSettings, GRAPHIC_BANDS};
+use std::collections::HashMap;
 use std::fs::File;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::{BufReader, BufWriter, Cursor};
@@ -16,6 +20,176 @@ pub struct Settings {
     pub save_window_size: bool,
     pub locale: Option<String>,
     pub timestamp: Option<u64>,
+    pub auto_resume: bool,
+    pub session: Option<Session>,
+    /// Library folders that are played in audiobook mode
+    pub audiobook_paths: Vec<String>,
+    /// Resume positions of the tracks played in audiobook mode, by their path
+    pub bookmarks: HashMap<String, Bookmark>,
+    /// Whether the seam of the A-B loop is crossfaded
+    pub loop_crossfade: bool,
+    pub eq: EqSettings,
+    /// Equalizer presets saved by the user
+    pub eq_presets: Vec<EqPreset>,
+    pub crossfeed: bool,
+    /// Whether the dynamic range is compressed for listening at low volume
+    pub night_mode: bool,
+    pub trim_silence: bool,
+    /// Level (in dB) under which the start and the end of the tracks are trimmed
+    pub silence_threshold: i8,
+    /// Whether the tempo and the key of the tracks are estimated in the background
+    pub analyze_tracks: bool,
+    /// Path of the impulse response used for the convolution
+    pub impulse_response: Option<String>,
+    /// Format of the exported library: 0 for Opus, 1 for FLAC, 2 for WAV
+    pub export_format: u8,
+    /// Whether the ReplayGain of the tracks is applied when exporting them
+    pub export_replay_gain: bool,
+    /// Internet radio stations saved by the user
+    pub stations: Vec<Station>,
+    /// Folder of the downloaded podcast episodes, `None` for the one inside the internal directory
+    pub podcast_path: Option<String>,
+    /// How often the podcast feeds are fetched, in hours, 0 to only fetch them when asked
+    pub podcast_refresh: u32,
+    /// How many times the tracker modules are repeated after they're played once
+    pub tracker_loops: u32,
+    /// Interpolation of the tracker modules: 0 for the default one, 1 for none, 2 for linear, 3 for
+    /// cubic, 4 for sinc
+    pub tracker_interpolation: u8,
+}
+
+/// Tempo and key of a track, see `n_audio::analysis::Analysis`
+#[derive(Copy, Clone, Debug, PartialEq, Decode, Encode)]
+pub struct TrackAnalysis {
+    pub bpm: Option<f32>,
+    /// Pitch class of the tonic, plus 12 for minor keys
+    pub key: Option<u8>,
+}
+
+impl From<Analysis> for TrackAnalysis {
+    fn from(value: Analysis) -> Self {
+        Self {
+            bpm: value.bpm,
+            key: value
+                .key
+                .map(|key| key.tonic + if key.minor { 12 } else { 0 }),
+        }
+    }
+}
+
+/// Silence detected in the tracks, recomputed when the threshold changes
+#[derive(Debug, Default, Decode, Encode)]
+pub struct CachedSilence {
+    /// Level (in dB) the silence was detected under
+    pub threshold: i8,
+    /// Where the audio of the tracks starts and ends without the silence, by their path
+    pub bounds: HashMap<String, (f64, f64)>,
+}
+
+/// Cached acoustic fingerprint of a track, recomputed when its file changes
+#[derive(Clone, Debug, Default, Decode, Encode)]
+pub struct CachedFingerprint {
+    /// Modification time of the file when it was fingerprinted, in nanoseconds since the Unix
+    /// epoch
+    pub modified: u64,
+    /// Empty if the track couldn't be fingerprinted
+    pub fingerprint: Vec<u32>,
+}
+
+impl TrackAnalysis {
+    pub fn key(&self) -> Option<Key> {
+        self.key.map(|key| Key {
+            tonic: key % 12,
+            minor: key >= 12,
+        })
+    }
+}
+
+/// Equalizer and the other processing of the samples, see `n_audio::dsp::DspSettings`
+#[derive(Clone, Debug, PartialEq, Decode, Encode)]
+pub struct EqSettings {
+    pub enabled: bool,
+    /// Gains (in dB) of the bands of the graphic equalizer
+    pub gains: Vec<f32>,
+    pub preamp: f32,
+    pub balance: f32,
+    pub mono: bool,
+    pub swap_channels: bool,
+}
+
+impl EqSettings {
+    pub fn to_dsp(&self) -> DspSettings {
+        let mut dsp = if self.enabled {
+            DspSettings {
+                preamp: self.preamp,
+                ..DspSettings::graphic(&self.gains)
+            }
+        } else {
+            DspSettings::default()
+        };
+        dsp.balance = self.balance;
+        dsp.mono = self.mono;
+        dsp.swap_channels = self.swap_channels;
+        dsp
+    }
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gains: vec![0.0; GRAPHIC_BANDS.len()],
+            preamp: 0.0,
+            balance: 0.0,
+            mono: false,
+            swap_channels: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct EqPreset {
+    pub name: String,
+    pub gains: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+/// Per-track state used in audiobook mode
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct Bookmark {
+    pub position: f64,
+    pub speed: f32,
+    pub finished: bool,
+}
+
+impl Default for Bookmark {
+    fn default() -> Self {
+        Self {
+            position: 0.0,
+            speed: 1.0,
+            finished: false,
+        }
+    }
+}
+
+/// The state of the playback when the app was closed
+#[derive(Clone, Debug, Default, Decode, Encode)]
+pub struct Session {
+    pub path: String,
+    /// Names of the tracks in the order they were in the queue
+    pub order: Vec<String>,
+    pub current: Option<String>,
+    pub up_next: Vec<String>,
+    pub position: f64,
+    pub paused: bool,
+    pub loop_status: i32,
+    pub stop_after: Option<u32>,
+    pub shuffle: bool,
 }
 
 impl Settings {
@@ -149,6 +323,93 @@ impl Settings {
         .unwrap()
     }
 
+    /// Reads the cached waveforms of the tracks, by their path
+    ///
+    /// They're kept apart from the tracks so that they can be added one at a time, when a track is
+    /// played for the first time, without rewriting the covers of the whole library
+    pub async fn read_waveforms<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+    ) -> HashMap<String, Vec<(i8, i8)>> {
+        read_cache(platform.internal_dir().await.join("waveforms")).await
+    }
+
+    pub async fn save_waveforms<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+        waveforms: &HashMap<String, Vec<(i8, i8)>>,
+    ) {
+        save_cache(platform.internal_dir().await.join("waveforms"), waveforms).await
+    }
+
+    /// Reads the silence detected in the tracks
+    pub async fn read_silence<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+    ) -> CachedSilence {
+        read_cache(platform.internal_dir().await.join("silence")).await
+    }
+
+    pub async fn save_silence<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+        silence: &CachedSilence,
+    ) {
+        save_cache(platform.internal_dir().await.join("silence"), silence).await
+    }
+
+    /// Reads the cached tempo and key of the tracks, by their path
+    pub async fn read_analysis<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+    ) -> HashMap<String, TrackAnalysis> {
+        read_cache(platform.internal_dir().await.join("analysis")).await
+    }
+
+    pub async fn save_analysis<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+        analysis: &HashMap<String, TrackAnalysis>,
+    ) {
+        save_cache(platform.internal_dir().await.join("analysis"), analysis).await
+    }
+
+    /// Reads the cached acoustic fingerprints of the tracks, by their path
+    pub async fn read_fingerprints<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+    ) -> HashMap<String, CachedFingerprint> {
+        read_cache(platform.internal_dir().await.join("fingerprints")).await
+    }
+
+    pub async fn save_fingerprints<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+        fingerprints: &HashMap<String, CachedFingerprint>,
+    ) {
+        save_cache(
+            platform.internal_dir().await.join("fingerprints"),
+            fingerprints,
+        )
+        .await
+    }
+
+    /// Reads the podcasts the user is subscribed to
+    pub async fn read_podcasts<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+    ) -> Vec<Podcast> {
+        read_cache(platform.internal_dir().await.join("podcasts")).await
+    }
+
+    pub async fn save_podcasts<P: Deref<Target = impl Platform>>(
+        &self,
+        platform: P,
+        podcasts: &[Podcast],
+    ) {
+        save_cache(platform.internal_dir().await.join("podcasts"), podcasts).await
+    }
+
     pub async fn save<P: Deref<Target = impl Platform>>(&self, platform: P) {
         self.save_and_compress(platform.internal_dir().await).await
     }
@@ -182,6 +443,53 @@ impl Default for Settings {
             save_window_size: false,
             locale: None,
             timestamp: None,
+            auto_resume: false,
+            session: None,
+            audiobook_paths: vec![],
+            bookmarks: HashMap::new(),
+            loop_crossfade: false,
+            eq: EqSettings::default(),
+            eq_presets: vec![],
+            crossfeed: false,
+            night_mode: false,
+            trim_silence: false,
+            silence_threshold: -60,
+            analyze_tracks: false,
+            impulse_response: None,
+            export_format: 0,
+            export_replay_gain: false,
+            stations: vec![],
+            podcast_path: None,
+            podcast_refresh: 6,
+            tracker_loops: 0,
+            tracker_interpolation: 0,
         }
     }
 }
+
+/// Reads a cache compressed like the tracks, returning the default value if it can't be read
+async fn read_cache<T: DecodeOwned + Default + Send + 'static>(file: PathBuf) -> T {
+    tokio::task::spawn_blocking(|| {
+        let mut data = vec![];
+        File::open(file)
+            .and_then(|file| {
+                zstd::stream::copy_decode(file, BufWriter::new(Cursor::new(&mut data)))
+            })
+            .ok()
+            .and_then(|_| bitcode::decode(&data).ok())
+            .unwrap_or_default()
+    })
+    .await
+    .unwrap()
+}
+
+async fn save_cache<T: Encode + ?Sized>(file: PathBuf, value: &T) {
+    let data = bitcode::encode(value);
+    tokio::task::spawn_blocking(move || {
+        if let Ok(file) = File::create(file) {
+            zstd::stream::copy_encode(BufReader::new(Cursor::new(data)), file, 9).unwrap();
+        }
+    })
+    .await
+    .unwrap();
+}